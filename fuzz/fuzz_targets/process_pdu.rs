@@ -0,0 +1,33 @@
+#![no_main]
+
+use std::sync::{Arc, RwLock};
+
+use libfuzzer_sys::fuzz_target;
+use modsim::config::{BoolItemConfig, RegisterItemConfig};
+use modsim::sim::SimState;
+use modsim::transport::tcp::ModbusService;
+
+// Feeds arbitrary request PDUs through the service; any panic is a finding.
+// Malformed PDUs may fail, but must not crash.
+fuzz_target!(|pdu: &[u8]| {
+    let coil = BoolItemConfig {
+        address: 0xFFFF,
+        ..Default::default()
+    };
+    let register = RegisterItemConfig {
+        address: 0xFFFE,
+        initial: 7,
+        ..Default::default()
+    };
+    let state = SimState::new(
+        500,
+        false,
+        vec![coil],
+        vec![],
+        vec![register.clone()],
+        vec![register],
+    )
+    .unwrap();
+    let service = ModbusService::new(Arc::new(RwLock::new(state)));
+    let _ = service.process_pdu(pdu);
+});
@@ -0,0 +1,132 @@
+//! Millisecond fields that also accept human-friendly durations such as
+//! `"500ms"`, `"30s"`, `"1h"` or `"1h30m"`. Bare numbers are milliseconds.
+
+use serde::{Deserialize, Deserializer};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Millis<T> {
+    Number(T),
+    Text(String),
+}
+
+/// Parses a duration string into milliseconds. Units are `ms`, `s`, `m`, `h`
+/// and `d`; several may be chained (`"1m30s"`) and numbers may be fractional
+/// (`"1.5s"`). A bare number is milliseconds.
+pub fn parse_millis(text: &str) -> Result<f64, String> {
+    let text = text.trim();
+    let invalid = || format!("invalid duration `{text}` (e.g. \"500ms\", \"30s\", \"1h\")");
+    if let Ok(ms) = text.parse::<f64>() {
+        return if ms.is_finite() {
+            Ok(ms)
+        } else {
+            Err(invalid())
+        };
+    }
+    if text.is_empty() {
+        return Err(invalid());
+    }
+    let mut rest = text;
+    let mut total = 0.0;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .ok_or_else(invalid)?;
+        let number: f64 = rest[..number_len].parse().map_err(|_| invalid())?;
+        rest = &rest[number_len..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_len] {
+            "ms" => 1.0,
+            "s" => 1_000.0,
+            "m" => 60_000.0,
+            "h" => 3_600_000.0,
+            "d" => 86_400_000.0,
+            _ => return Err(invalid()),
+        };
+        total += number * scale;
+        rest = &rest[unit_len..];
+    }
+    Ok(total)
+}
+
+fn whole_millis(ms: f64) -> Result<u64, String> {
+    if ms < 0.0 || ms.fract() != 0.0 || ms > u64::MAX as f64 {
+        return Err(format!(
+            "duration must be a whole number of milliseconds, got {ms}"
+        ));
+    }
+    Ok(ms as u64)
+}
+
+/// Whole milliseconds from a TOML value, as stored in dynamics tables.
+pub fn value_millis(value: &toml::Value) -> Option<u64> {
+    match value {
+        toml::Value::Integer(ms) => u64::try_from(*ms).ok(),
+        toml::Value::String(text) => parse_millis(text).and_then(whole_millis).ok(),
+        _ => None,
+    }
+}
+
+pub fn millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match Millis::<u64>::deserialize(deserializer)? {
+        Millis::Number(ms) => Ok(ms),
+        Millis::Text(text) => parse_millis(&text)
+            .and_then(whole_millis)
+            .map_err(serde::de::Error::custom),
+    }
+}
+
+pub fn optional_millis<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    millis(deserializer).map(Some)
+}
+
+/// Like [`millis`], keeping fractions of a millisecond.
+pub fn fractional_millis<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    match Millis::<f64>::deserialize(deserializer)? {
+        Millis::Number(ms) => Ok(ms),
+        Millis::Text(text) => parse_millis(&text).map_err(serde::de::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_units() {
+        assert_eq!(parse_millis("500ms"), Ok(500.0));
+        assert_eq!(parse_millis("30s"), Ok(30_000.0));
+        assert_eq!(parse_millis("1h"), Ok(3_600_000.0));
+        assert_eq!(parse_millis("1m30s"), Ok(90_000.0));
+        assert_eq!(parse_millis("1.5s"), Ok(1_500.0));
+        assert_eq!(parse_millis("250"), Ok(250.0));
+        assert!(parse_millis("10 parsecs").is_err());
+        assert!(parse_millis("s").is_err());
+        assert!(parse_millis("").is_err());
+        assert!(parse_millis("nan").is_err());
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Timing {
+        #[serde(deserialize_with = "millis")]
+        period_ms: u64,
+        #[serde(default, deserialize_with = "optional_millis")]
+        update_ms: Option<u64>,
+    }
+
+    #[test]
+    fn accepts_integers_and_strings() {
+        let timing: Timing = toml::from_str("period_ms = 3600000").unwrap();
+        assert_eq!((timing.period_ms, timing.update_ms), (3_600_000, None));
+        let timing: Timing = toml::from_str("period_ms = \"1h\"\nupdate_ms = \"2s\"").unwrap();
+        assert_eq!(
+            (timing.period_ms, timing.update_ms),
+            (3_600_000, Some(2_000))
+        );
+        assert!(toml::from_str::<Timing>("period_ms = \"0.5ms\"").is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! `${VAR}` and `${VAR:-default}` interpolation of the raw config text.
+
+use anyhow::{Context, Result, bail};
+
+/// Replaces every `${VAR}` with the value of `VAR` and every
+/// `${VAR:-default}` with it or, when unset or empty, `default`. `$${` is a
+/// literal `${`. Comments, whole-line or trailing, are left untouched; a `#`
+/// inside a string does not start one.
+pub fn expand(text: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut quote = None;
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        let (code, comment) = line.split_at(comment_start(line, &mut quote).unwrap_or(line.len()));
+        expand_line(code, &lookup, &mut out).with_context(|| format!("line {}", index + 1))?;
+        out.push_str(comment);
+    }
+    Ok(out)
+}
+
+/// Where the comment of `line` starts, if it has one. `quote` is the
+/// multi-line string the line starts in, and is left at the one it ends in.
+fn comment_start(line: &str, quote: &mut Option<&'static str>) -> Option<usize> {
+    let bytes = line.as_bytes();
+    let mut index = 0;
+    while index < bytes.len() {
+        let rest = &bytes[index..];
+        match *quote {
+            None => {
+                if let Some(open) = ["\"\"\"", "'''", "\"", "'"]
+                    .into_iter()
+                    .find(|open| rest.starts_with(open.as_bytes()))
+                {
+                    *quote = Some(open);
+                    index += open.len();
+                    continue;
+                }
+                if bytes[index] == b'#' {
+                    return Some(index);
+                }
+            }
+            Some(open) => {
+                // Only basic strings have escapes.
+                if bytes[index] == b'\\' && open.starts_with('"') {
+                    index += 2;
+                    continue;
+                }
+                if rest.starts_with(open.as_bytes()) {
+                    *quote = None;
+                    index += open.len();
+                    continue;
+                }
+            }
+        }
+        index += 1;
+    }
+    // Single-line strings end with their line.
+    if quote.is_some_and(|open| open.len() == 1) {
+        *quote = None;
+    }
+    None
+}
+
+fn expand_line(
+    mut line: &str,
+    lookup: &impl Fn(&str) -> Option<String>,
+    out: &mut String,
+) -> Result<()> {
+    while let Some(start) = line.find("${") {
+        if line[..start].ends_with('$') {
+            out.push_str(&line[..start - 1]);
+            out.push_str("${");
+            line = &line[start + 2..];
+            continue;
+        }
+        out.push_str(&line[..start]);
+        let Some(len) = line[start..].find('}') else {
+            bail!("unterminated `${{` in config");
+        };
+        let reference = &line[start + 2..start + len];
+        let (name, default) = match reference.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (reference, None),
+        };
+        if name.is_empty() {
+            bail!("empty variable name in `${{{reference}}}`");
+        }
+        match (lookup(name).filter(|value| !value.is_empty()), default) {
+            (Some(value), _) => out.push_str(&value),
+            (None, Some(default)) => out.push_str(default),
+            (None, None) => {
+                bail!("environment variable {name} is not set and `${{{name}}}` has no default")
+            }
+        }
+        line = &line[start + len + 1..];
+    }
+    out.push_str(line);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        (name == "MODSIM_BIND").then(|| "127.0.0.1:1502".to_string())
+    }
+
+    #[test]
+    fn expands_variables_and_defaults() {
+        let text = "bind = \"${MODSIM_BIND:-0.0.0.0:5020}\"\nother = \"${MISSING:-x}\"\n";
+        assert_eq!(
+            expand(text, lookup).unwrap(),
+            "bind = \"127.0.0.1:1502\"\nother = \"x\"\n"
+        );
+        assert_eq!(
+            expand("expr = \"$${t}\"\n# ${MISSING}\n", lookup).unwrap(),
+            "expr = \"${t}\"\n# ${MISSING}\n"
+        );
+    }
+
+    #[test]
+    fn comments_are_not_expanded() {
+        let text = "bind = \"${MODSIM_BIND}\" # was ${MISSING}\n  # ${MISSING}\n";
+        assert_eq!(
+            expand(text, lookup).unwrap(),
+            "bind = \"127.0.0.1:1502\" # was ${MISSING}\n  # ${MISSING}\n"
+        );
+        // A `#` inside a string, even a multi-line one, starts no comment.
+        let text = "a = \"#${MODSIM_BIND}\"\nb = \"\"\"\n# ${MODSIM_BIND}\n\"\"\" # ${MISSING}\n";
+        assert_eq!(
+            expand(text, lookup).unwrap(),
+            "a = \"#127.0.0.1:1502\"\nb = \"\"\"\n# 127.0.0.1:1502\n\"\"\" # ${MISSING}\n"
+        );
+        let text = "c = 'a\\' # ${MISSING}\nd = \"\\\"#\" # ${MISSING}\n";
+        assert_eq!(expand(text, lookup).unwrap(), text);
+    }
+
+    #[test]
+    fn unset_variable_without_default_is_an_error() {
+        let err = expand("a = 1\nbind = \"${MISSING}\"", lookup).unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "line 2: environment variable MISSING is not set and `${MISSING}` has no default"
+        );
+        assert!(expand("bind = \"${MODSIM_BIND\"", lookup).is_err());
+    }
+}
@@ -0,0 +1,177 @@
+//! Merging of several config files into one, before deserialization.
+
+use anyhow::{Result, bail};
+use toml::{Table, Value};
+
+/// Item lists of a device that are concatenated rather than replaced.
+const ITEM_LISTS: [&str; 5] = [
+    "coils",
+    "discrete_inputs",
+    "holding_registers",
+    "input_registers",
+    "fifos",
+];
+
+/// Marks an item that replaces one with the same address from an earlier file.
+const OVERRIDE_KEY: &str = "override";
+
+/// Merges the config `overlay` into `base`:
+///
+/// - tables merge key by key, recursively; other values in `overlay` replace
+///   those in `base`
+/// - `[[devices]]` entries with the same `name` merge like `[device]`; new
+///   names are appended
+/// - device item lists are concatenated. An item whose address is already
+///   defined is an error unless it sets `override = true`, which replaces the
+///   earlier item
+pub fn merge(base: &mut Table, overlay: Table) -> Result<()> {
+    for (key, value) in overlay {
+        match (key.as_str(), base.get_mut(&key), value) {
+            ("device", Some(Value::Table(device)), Value::Table(overlay)) => {
+                merge_device(device, overlay)?
+            }
+            ("devices", Some(Value::Array(devices)), Value::Array(overlay)) => {
+                merge_devices(devices, overlay)?
+            }
+            (_, Some(Value::Table(table)), Value::Table(overlay)) => merge_tables(table, overlay),
+            (_, _, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn merge_tables(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(table)), Value::Table(overlay)) => merge_tables(table, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn merge_devices(base: &mut Vec<Value>, overlay: Vec<Value>) -> Result<()> {
+    for device in overlay {
+        let existing = base
+            .iter_mut()
+            .find(|existing| device_name(existing) == device_name(&device));
+        match (existing, device) {
+            (Some(Value::Table(existing)), Value::Table(device)) => merge_device(existing, device)?,
+            (_, device) => base.push(device),
+        }
+    }
+    Ok(())
+}
+
+fn device_name(device: &Value) -> &str {
+    device
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or("default")
+}
+
+fn merge_device(base: &mut Table, overlay: Table) -> Result<()> {
+    let name = overlay
+        .get("name")
+        .and_then(Value::as_str)
+        .or(base.get("name").and_then(Value::as_str))
+        .unwrap_or("default")
+        .to_string();
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Array(items)), Value::Array(overlay))
+                if ITEM_LISTS.contains(&key.as_str()) =>
+            {
+                merge_items(&name, &key, items, overlay)?
+            }
+            (Some(Value::Table(table)), Value::Table(overlay)) => merge_tables(table, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn merge_items(device: &str, list: &str, base: &mut Vec<Value>, overlay: Vec<Value>) -> Result<()> {
+    for mut item in overlay {
+        let address = item.get("address").and_then(Value::as_integer);
+        let replaces = item
+            .as_table_mut()
+            .and_then(|item| item.remove(OVERRIDE_KEY))
+            .and_then(|value| value.as_bool())
+            .unwrap_or(false);
+        let existing = address.and_then(|address| {
+            base.iter()
+                .position(|item| item.get("address").and_then(Value::as_integer) == Some(address))
+        });
+        match existing {
+            Some(index) if replaces => base[index] = item,
+            Some(_) => bail!(
+                "device {device}: {list} address {} is defined in more than one file; \
+                 set `override = true` on the later one to replace it",
+                address.unwrap_or_default()
+            ),
+            None => base.push(item),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merged(files: &[&str]) -> Result<Table> {
+        let mut base = Table::new();
+        for file in files {
+            merge(&mut base, toml::from_str(file).unwrap())?;
+        }
+        Ok(base)
+    }
+
+    #[test]
+    fn concatenates_items_and_overrides_settings() {
+        let table = merged(&[
+            "[global]\nupdate_ms = 500\nseed = 1\n\n[device]\nunit_id = 2\n\
+             holding_registers = [{ address = 0 }]",
+            "[global]\nupdate_ms = 100\n\n[device]\n\
+             holding_registers = [{ address = 1 }, { address = 0, initial = 9, override = true }]",
+        ])
+        .unwrap();
+        assert_eq!(
+            table.to_string(),
+            toml::from_str::<Table>(
+                "[global]\nupdate_ms = 100\nseed = 1\n\n[device]\nunit_id = 2\n\
+                 holding_registers = [{ address = 0, initial = 9 }, { address = 1 }]"
+            )
+            .unwrap()
+            .to_string()
+        );
+    }
+
+    #[test]
+    fn merges_named_devices() {
+        let table = merged(&[
+            "[[devices]]\nname = \"a\"\ncoils = [{ address = 0 }]",
+            "[[devices]]\nname = \"a\"\ncoils = [{ address = 1 }]\n\n[[devices]]\nname = \"b\"",
+        ])
+        .unwrap();
+        let devices = table["devices"].as_array().unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0]["coils"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn conflicting_address_without_override_is_an_error() {
+        let err = merged(&[
+            "[device]\ninput_registers = [{ address = 4 }]",
+            "[device]\ninput_registers = [{ address = 4 }]",
+        ])
+        .unwrap_err();
+        assert!(err.to_string().contains("input_registers address 4"));
+    }
+}
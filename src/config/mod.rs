@@ -1,4 +1,116 @@
-use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::num::NonZeroU32;
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Deserializer};
+
+use crate::sim::Space;
+
+pub mod duration;
+pub mod env;
+pub mod merge;
+
+pub fn load(path: &str) -> Result<Config> {
+    load_all(&[path])
+}
+
+/// Loads several config files, each merged into the ones before it as
+/// described by [`merge::merge`].
+pub fn load_all<S: AsRef<str>>(paths: &[S]) -> Result<Config> {
+    let merged = merged_table(paths)?;
+    let config: Config = merged.try_into().context("failed to parse merged config")?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// The config files merged into one TOML table, environment variables
+/// expanded, before it is parsed as a [`Config`]. Items are counted on the
+/// tables, so a config over `global.max_items` is refused before it is
+/// deserialized: each file against the limit it or a file before it sets,
+/// then the merged one.
+pub fn merged_table<S: AsRef<str>>(paths: &[S]) -> Result<toml::Table> {
+    let mut merged = toml::Table::new();
+    for path in paths {
+        let path = path.as_ref();
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("failed to read {path}"))?;
+        let content = env::expand(&content, |name| std::env::var(name).ok())
+            .with_context(|| format!("failed to expand environment variables in {path}"))?;
+        let table: toml::Table =
+            toml::from_str(&content).with_context(|| format!("failed to parse TOML in {path}"))?;
+        let max_items = table_max_items(&table)
+            .or_else(|| table_max_items(&merged))
+            .unwrap_or_else(default_max_items);
+        check_item_count(table_item_count(&table), max_items)?;
+        // Each file is a valid config on its own. Parsing the text again only
+        // when it is not reports the error with its location in that file.
+        if table.clone().try_into::<Config>().is_err() {
+            toml::from_str::<Config>(&content)
+                .with_context(|| format!("failed to parse TOML in {path}"))?;
+        }
+        merge::merge(&mut merged, table).with_context(|| format!("failed to merge {path}"))?;
+    }
+    let max_items = table_max_items(&merged).unwrap_or_else(default_max_items);
+    check_item_count(table_item_count(&merged), max_items)?;
+    Ok(merged)
+}
+
+/// Lists of a device table whose entries are items.
+const ITEM_LISTS: [&str; 4] = [
+    "coils",
+    "discrete_inputs",
+    "holding_registers",
+    "input_registers",
+];
+
+/// Items declared in a config table, over `[device]` and `[[devices]]`.
+fn table_item_count(table: &toml::Table) -> usize {
+    let devices = table.get("devices").and_then(toml::Value::as_array);
+    table
+        .get("device")
+        .into_iter()
+        .chain(devices.into_iter().flatten())
+        .filter_map(toml::Value::as_table)
+        .flat_map(|device| ITEM_LISTS.iter().filter_map(|list| device.get(*list)))
+        .filter_map(toml::Value::as_array)
+        .map(Vec::len)
+        .sum()
+}
+
+/// The `global.max_items` a config table sets, if any.
+fn table_max_items(table: &toml::Table) -> Option<usize> {
+    let max_items = table.get("global")?.get("max_items")?.as_integer()?;
+    usize::try_from(max_items).ok()
+}
+
+fn check_item_count(items: usize, max_items: usize) -> Result<()> {
+    if items > max_items {
+        bail!(
+            "config has {items} items, more than global.max_items ({max_items}); \
+             raise the limit if the config is meant to be this large"
+        );
+    }
+    Ok(())
+}
+
+/// Fingerprint of the config files as read from disk: a 64-bit FNV-1a hash
+/// of their contents, in hex. Equal fingerprints mean the same files.
+pub fn fingerprint<S: AsRef<str>>(paths: &[S]) -> Result<String> {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for path in paths {
+        let path = path.as_ref();
+        let content = std::fs::read(path).with_context(|| format!("failed to read {path}"))?;
+        // The separator keeps `a` + `bc` apart from `ab` + `c`.
+        for byte in content.iter().chain(&[0]) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x100_0000_01b3);
+        }
+    }
+    Ok(format!("{hash:016x}"))
+}
+
+/// Item count from which loading logs its progress and duration.
+pub const LARGE_CONFIG_ITEMS: usize = 10_000;
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Config {
@@ -6,38 +118,527 @@ pub struct Config {
     pub global: Option<GlobalConfig>,
     pub tcp: Option<TcpConfig>,
     pub rtu: Option<RtuConfig>,
-    pub device: DeviceConfig,
+    pub fault: Option<FaultConfig>,
+    pub control: Option<ControlConfig>,
+    pub persistence: Option<PersistenceConfig>,
+    pub power: Option<PowerConfig>,
+    pub mirror: Option<MirrorConfig>,
+    pub replay: Option<ReplayConfig>,
+    pub device: Option<DeviceConfig>,
+    #[serde(default)]
+    pub devices: Vec<DeviceConfig>,
+}
+
+impl Config {
+    /// Checks what the types alone cannot: global settings in range, and a
+    /// device layout that serves every device as written.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(global) = &self.global
+            && !(global.clock_drift_ppm.is_finite() && global.clock_drift_ppm > -1_000_000.0)
+        {
+            bail!(
+                "global.clock_drift_ppm must be above -1000000, got {}",
+                global.clock_drift_ppm
+            );
+        }
+        if let Some(global) = &self.global
+            && !(global.noise_floor.is_finite() && global.noise_floor >= 0.0)
+        {
+            bail!(
+                "global.noise_floor must be a non-negative number, got {}",
+                global.noise_floor
+            );
+        }
+        // Only the legacy `[device]` inherits the top-level transports.
+        if self.device.is_none() && !self.devices.is_empty() {
+            for (section, set) in [("tcp", self.tcp.is_some()), ("rtu", self.rtu.is_some())] {
+                if set {
+                    bail!(
+                        "top-level [{section}] is only used by [device]; \
+                         give each [[devices]] entry its own `{section}`"
+                    );
+                }
+            }
+        }
+        let devices = self.device.iter().chain(&self.devices);
+        for rtu in self
+            .rtu
+            .iter()
+            .chain(devices.filter_map(|device| device.rtu.as_ref()))
+        {
+            if rtu.pty && rtu.device.is_some() {
+                bail!("rtu.pty and rtu.device are exclusive; a pty replaces the serial device");
+            }
+        }
+        let mut names = std::collections::BTreeSet::new();
+        for device in self.device.iter().chain(&self.devices) {
+            if !names.insert(device.name.as_str()) {
+                bail!("device name `{}` is used more than once", device.name);
+            }
+        }
+        Ok(())
+    }
+
+    /// Items declared over all devices.
+    pub fn item_count(&self) -> usize {
+        self.device
+            .iter()
+            .chain(&self.devices)
+            .map(DeviceConfig::item_count)
+            .sum()
+    }
+
+    /// Returns every configured device with its transports resolved.
+    ///
+    /// The legacy single `[device]` table inherits the top-level `[tcp]`,
+    /// `[rtu]` and `[mirror]` sections unless it declares its own. The
+    /// top-level `[fault]` section applies to every device without its own.
+    /// Items of mirrored devices and of standbys have their dynamics removed.
+    pub fn resolved_devices(&self) -> Vec<DeviceConfig> {
+        let mut devices = Vec::with_capacity(self.devices.len() + 1);
+        if let Some(device) = &self.device {
+            let mut device = device.clone();
+            if device.tcp.is_none() {
+                device.tcp = self.tcp.clone();
+            }
+            if device.rtu.is_none() {
+                device.rtu = self.rtu.clone();
+            }
+            if device.mirror.is_none() {
+                device.mirror = self.mirror.clone();
+            }
+            if device.replay.is_none() {
+                device.replay = self.replay.clone();
+            }
+            devices.push(device);
+        }
+        devices.extend(self.devices.iter().cloned());
+        for device in &mut devices {
+            if device.fault.is_none() {
+                device.fault = self.fault.clone();
+            }
+            let standby = device
+                .standby
+                .as_ref()
+                .is_some_and(|standby| standby.primary.is_some());
+            if device.mirror.is_some() || standby {
+                device
+                    .coils
+                    .iter_mut()
+                    .for_each(|item| item.dynamics = None);
+                device
+                    .discrete_inputs
+                    .iter_mut()
+                    .for_each(|item| item.dynamics = None);
+                device
+                    .holding_registers
+                    .iter_mut()
+                    .for_each(|item| item.dynamics = None);
+                device
+                    .input_registers
+                    .iter_mut()
+                    .for_each(|item| item.dynamics = None);
+            }
+        }
+        devices
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct LoggingConfig {
     #[serde(default)]
     pub log_value_updates: bool,
+    /// Record every successful client write.
+    #[serde(default)]
+    pub audit_writes: bool,
+    /// File audit records are appended to as JSON lines, besides the log.
+    pub audit_file: Option<String>,
+    /// Log every Modbus request and its response as one line at info level.
+    #[serde(default)]
+    pub log_transactions: bool,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct GlobalConfig {
-    #[serde(default = "default_update_ms")]
+    #[serde(default = "default_update_ms", deserialize_with = "duration::millis")]
     pub update_ms: u64,
+    pub seed: Option<u64>,
+    /// Absolute floor for the simulator tick interval.
+    #[serde(default = "default_min_tick_ms", deserialize_with = "duration::millis")]
+    pub min_tick_ms: u64,
+    /// Items updating faster than this are reported at startup.
+    #[serde(
+        default = "default_warn_update_ms",
+        deserialize_with = "duration::millis"
+    )]
+    pub warn_update_ms: u64,
+    /// Estimated item updates per second above which a startup warning is logged.
+    #[serde(default = "default_max_updates_per_sec")]
+    pub max_updates_per_sec: u64,
+    /// Delay before Modbus transports bind and serve, modelling a device that
+    /// is powering up.
+    #[serde(default, deserialize_with = "duration::millis")]
+    pub startup_delay_ms: u64,
+    /// Warmup window for items without their own `warmup_ms`; `0` disables it.
+    #[serde(default, deserialize_with = "duration::millis")]
+    pub warmup_ms: u64,
+    /// Give periodic items without `phase_ms` a random phase.
+    #[serde(default)]
+    pub randomize_phase: bool,
+    /// What the tick loop does when a tick finishes after the next was due.
+    #[serde(default)]
+    pub tick_overrun_policy: TickOverrunPolicy,
+    /// Order in which a tick evaluates the items of a device.
+    #[serde(default)]
+    pub tick_order: TickOrder,
+    /// What items reading other items see of them during a tick.
+    #[serde(default)]
+    pub tick_evaluation: TickEvaluation,
+    /// Largest number of items, over all devices, a config may declare.
+    #[serde(default = "default_max_items")]
+    pub max_items: usize,
+    /// Rate, in parts per million, at which the simulation clock runs ahead
+    /// of (or, when negative, behind) the wall clock.
+    #[serde(default)]
+    pub clock_drift_ppm: f64,
+    /// Amplitude, in raw units, of the uniform noise added to the output of
+    /// every register with dynamics on each update; `0` disables it.
+    #[serde(default)]
+    pub noise_floor: f64,
+    /// How a device's tick loop slows down while no client is connected;
+    /// full rate when unset.
+    pub idle: Option<IdleConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct IdleConfig {
+    pub mode: IdleMode,
+    /// Tick interval of a device in `slow` mode while it is idle.
+    #[serde(
+        default = "default_idle_tick_ms",
+        deserialize_with = "duration::millis"
+    )]
+    pub tick_ms: u64,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdleMode {
+    /// Tick every `tick_ms`, with the simulation clock running on.
+    Slow,
+    /// Stop ticking and stop the simulation clock until a client connects.
+    Pause,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TickOverrunPolicy {
+    /// Log a warning and run missed ticks back to back to catch up.
+    #[default]
+    Warn,
+    /// Log a warning and drop missed ticks instead of catching up.
+    Skip,
+    /// Lengthen the tick interval to what the ticks actually take.
+    Widen,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TickOrder {
+    /// Coils, discrete inputs, holding registers, then input registers, each
+    /// by ascending address.
+    #[default]
+    PerSpace,
+    /// By ascending address across the spaces, in the order above at one
+    /// address.
+    ByAddress,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum TickEvaluation {
+    /// Snapshot the values items read from others, then compute every item
+    /// from it, so the order cannot change any value.
+    #[default]
+    TwoPass,
+    /// Compute the items one after another, each seeing the updates made
+    /// before it in the same tick.
+    Sequential,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WriteConflictPolicy {
+    /// Writes apply in the order they arrive; the last one wins.
+    #[default]
+    LastWriterWins,
+    /// A write to an address another client wrote within the busy window
+    /// answers ServerDeviceBusy and changes nothing.
+    RejectIfBusy,
+}
+
+impl Default for GlobalConfig {
+    fn default() -> Self {
+        Self {
+            update_ms: default_update_ms(),
+            seed: None,
+            min_tick_ms: default_min_tick_ms(),
+            warn_update_ms: default_warn_update_ms(),
+            max_updates_per_sec: default_max_updates_per_sec(),
+            startup_delay_ms: 0,
+            warmup_ms: 0,
+            randomize_phase: false,
+            tick_overrun_policy: TickOverrunPolicy::default(),
+            tick_order: TickOrder::default(),
+            tick_evaluation: TickEvaluation::default(),
+            max_items: default_max_items(),
+            clock_drift_ppm: 0.0,
+            noise_floor: 0.0,
+            idle: None,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct TcpConfig {
     #[serde(default = "default_tcp_bind")]
     pub bind: String,
+    /// TCP keepalive for accepted connections; OS defaults when absent.
+    pub keepalive: Option<KeepaliveConfig>,
+    /// Connections accepted at once from one client IP; unlimited when unset.
+    pub max_connections_per_ip: Option<usize>,
+    /// How requests with a nonzero MBAP protocol identifier are treated.
+    #[serde(default)]
+    pub protocol_id: ProtocolIdPolicy,
+    /// How multiple-write requests whose byte count disagrees with their
+    /// quantity are treated.
+    #[serde(default)]
+    pub byte_count: ByteCountPolicy,
+    /// How Write Single Coil requests with a value other than 0xFF00 or
+    /// 0x0000 are treated.
+    #[serde(default)]
+    pub coil_value: CoilValuePolicy,
+    /// Requests in a row answered with an exception after which the
+    /// connection is closed; never closed when unset.
+    pub max_consecutive_exceptions: Option<NonZeroU32>,
+}
+
+impl TcpConfig {
+    pub fn new(bind: impl Into<String>) -> Self {
+        Self {
+            bind: bind.into(),
+            keepalive: None,
+            max_connections_per_ip: None,
+            protocol_id: ProtocolIdPolicy::default(),
+            byte_count: ByteCountPolicy::default(),
+            coil_value: CoilValuePolicy::default(),
+            max_consecutive_exceptions: None,
+        }
+    }
+}
+
+/// Handling of Modbus/TCP requests whose MBAP protocol identifier is not 0.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProtocolIdPolicy {
+    /// Answer them as if the identifier were 0.
+    #[default]
+    Lenient,
+    /// Drop them unanswered, keeping the connection open.
+    Strict,
+}
+
+/// Handling of Write Multiple Coils/Registers requests whose byte count is
+/// not the size of the quantity they write.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ByteCountPolicy {
+    /// Answer them IllegalDataValue, changing nothing.
+    #[default]
+    Strict,
+    /// Leave them to tokio-modbus, which writes the quantity when the frame
+    /// holds enough data and closes the connection otherwise.
+    Lenient,
+}
+
+/// Handling of Write Single Coil requests whose value is neither 0xFF00 (ON)
+/// nor 0x0000 (OFF), which the protocol does not allow.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CoilValuePolicy {
+    /// Leave them to tokio-modbus, which closes the connection.
+    #[default]
+    Close,
+    /// Answer them IllegalDataValue, changing nothing.
+    Strict,
+    /// Write any nonzero value as ON.
+    Lenient,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct KeepaliveConfig {
+    #[serde(default = "default_keepalive_enabled")]
+    pub enabled: bool,
+    /// Idle time before the first probe; OS default when unset.
+    #[serde(default, deserialize_with = "duration::optional_millis")]
+    pub idle_ms: Option<u64>,
+    /// Time between probes; OS default when unset.
+    #[serde(default, deserialize_with = "duration::optional_millis")]
+    pub interval_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LineConfig {
+    pub bind: String,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct ControlConfig {
+    #[serde(default = "default_control_bind")]
+    pub bind: String,
+}
+
+/// Periodic snapshot of item values, restored at startup.
+#[derive(Debug, Deserialize, Clone)]
+pub struct PersistenceConfig {
+    pub path: String,
+    #[serde(
+        default = "default_persist_interval_ms",
+        deserialize_with = "duration::millis"
+    )]
+    pub interval_ms: u64,
+}
+
+/// Periodic simulated reboots of every device.
+#[derive(Debug, Deserialize, Clone)]
+pub struct PowerConfig {
+    /// Time the devices stay up between power cycles.
+    #[serde(deserialize_with = "duration::millis")]
+    pub cycle_interval_ms: u64,
+    /// Time the devices stay down in each cycle.
+    #[serde(deserialize_with = "duration::millis")]
+    pub downtime_ms: u64,
+}
+
+/// Upstream Modbus/TCP device whose configured items are polled and served.
+#[derive(Debug, Deserialize, Clone)]
+pub struct MirrorConfig {
+    /// `host:port` of the upstream device.
+    pub upstream: String,
+    /// Unit id to poll; defaults to the device's own.
+    pub unit_id: Option<u8>,
+    #[serde(
+        default = "default_mirror_interval_ms",
+        deserialize_with = "duration::millis"
+    )]
+    pub interval_ms: u64,
+    #[serde(
+        default = "default_mirror_timeout_ms",
+        deserialize_with = "duration::millis"
+    )]
+    pub timeout_ms: u64,
+}
+
+/// One end of the warm standby channel: a primary sets `listen`, a standby
+/// the `primary` it follows.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct StandbyConfig {
+    /// `host:port` the primary serves its changes on.
+    pub listen: Option<String>,
+    /// `host:port` of the primary to follow.
+    pub primary: Option<String>,
+    /// How often the primary sends changes and a standby retries connecting.
+    #[serde(
+        default = "default_standby_interval_ms",
+        deserialize_with = "duration::millis"
+    )]
+    pub interval_ms: u64,
+    /// Silence after which a standby drops the connection and reconnects.
+    #[serde(
+        default = "default_standby_timeout_ms",
+        deserialize_with = "duration::millis"
+    )]
+    pub timeout_ms: u64,
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct RtuConfig {
     pub device: Option<String>,
+    /// Serve on a new pseudo-terminal instead of `device`; its path is
+    /// logged for masters to open.
+    #[serde(default)]
+    pub pty: bool,
     #[serde(default = "default_baud_rate")]
     pub baud_rate: u32,
-    #[serde(default = "default_data_bits")]
+    #[serde(
+        default = "default_data_bits",
+        deserialize_with = "deserialize_data_bits"
+    )]
     pub data_bits: u8,
     #[serde(default = "default_parity")]
     pub parity: Parity,
-    #[serde(default = "default_stop_bits")]
+    #[serde(
+        default = "default_stop_bits",
+        deserialize_with = "deserialize_stop_bits"
+    )]
     pub stop_bits: u8,
+    /// CRC-16 variant frames on the bus carry, for devices that do not use
+    /// the standard Modbus one.
+    #[serde(default)]
+    pub crc: CrcAlgorithm,
+    /// Generator polynomial, in normal notation, replacing the one of `crc`.
+    #[serde(default, deserialize_with = "deserialize_crc_polynomial")]
+    pub crc_polynomial: Option<u16>,
+    /// Hold received bytes back until the configured framing could have
+    /// carried them, for virtual serial ports that deliver them at once.
+    #[serde(default)]
+    pub pace_receive: bool,
+}
+
+impl RtuConfig {
+    /// Valid but unusual framings, which are more likely typos than intent.
+    pub fn framing_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        if self.data_bits != 8 {
+            warnings.push(format!(
+                "rtu.data_bits = {}: Modbus RTU frames use 8 data bits",
+                self.data_bits
+            ));
+        }
+        if self.data_bits == 5 && self.stop_bits == 2 {
+            warnings.push(
+                "rtu.data_bits = 5 with stop_bits = 2: most UARTs send 1.5 stop bits instead"
+                    .to_string(),
+            );
+        }
+        if !matches!(self.parity, Parity::None) && self.stop_bits == 2 {
+            warnings.push(
+                "rtu.parity with stop_bits = 2: Modbus uses 2 stop bits only without parity"
+                    .to_string(),
+            );
+        }
+        warnings
+    }
+}
+
+/// CRC-16 variants seen on RTU buses. All process bits least significant
+/// first like the Modbus CRC; they differ in the initial value, the final
+/// XOR and the order the two CRC bytes are sent in.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum CrcAlgorithm {
+    /// CRC-16/MODBUS, low byte first.
+    #[default]
+    Modbus,
+    /// CRC-16/MODBUS sent high byte first.
+    ModbusSwapped,
+    /// CRC-16/ARC: initial value 0.
+    Arc,
+    /// CRC-16/USB: initial value and final XOR 0xFFFF.
+    Usb,
+    /// CRC-16/MAXIM: initial value 0, final XOR 0xFFFF.
+    Maxim,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -48,10 +649,97 @@ pub enum Parity {
     Odd,
 }
 
+#[derive(Debug, Deserialize, Clone)]
+pub struct FaultConfig {
+    pub latency: Option<LatencyConfig>,
+    /// RTU responses garbled as if another device talked over them.
+    pub collisions: Option<CollisionConfig>,
+    /// Responses cut off partway through the frame.
+    pub truncation: Option<TruncationConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct TruncationConfig {
+    /// Chance of each response being cut off, from 0 to 1.
+    pub probability: f64,
+    /// Bytes of the frame sent before the cut; a random point when unset.
+    pub bytes: Option<usize>,
+    /// Function codes whose responses the fault applies to; all when unset.
+    pub function_codes: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CollisionConfig {
+    /// Chance of each response colliding, from 0 to 1.
+    pub probability: f64,
+    /// Function codes whose responses the fault applies to; all when unset.
+    pub function_codes: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct LatencyConfig {
+    #[serde(default, deserialize_with = "duration::fractional_millis")]
+    pub mean_ms: f64,
+    #[serde(default, deserialize_with = "duration::fractional_millis")]
+    pub stddev_ms: f64,
+    /// File with a cumulative distribution of response times to draw from
+    /// instead of the normal distribution.
+    pub profile: Option<String>,
+    /// Function codes whose responses the fault applies to; all when unset.
+    pub function_codes: Option<Vec<u8>>,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct DeviceConfig {
+    #[serde(default = "default_device_name")]
+    pub name: String,
     #[serde(default = "default_unit_id")]
     pub unit_id: u8,
+    pub tcp: Option<TcpConfig>,
+    pub rtu: Option<RtuConfig>,
+    /// Text line protocol for clients without a Modbus library.
+    pub line: Option<LineConfig>,
+    pub fault: Option<FaultConfig>,
+    pub mirror: Option<MirrorConfig>,
+    /// Capture the registers play back from.
+    pub replay: Option<ReplayConfig>,
+    /// Replication of item values from a primary to warm standbys.
+    pub standby: Option<StandbyConfig>,
+    /// Canned FC43 (Encapsulated Interface Transport) responses keyed by MEI
+    /// type; each value is the response data following the MEI type byte.
+    #[serde(default, deserialize_with = "deserialize_mei")]
+    pub mei: BTreeMap<u8, Vec<u8>>,
+    #[serde(default)]
+    pub address_offsets: AddressOffsets,
+    /// Largest request or response ADU accepted, in bytes, including the
+    /// transport header; unset means the protocol maximum.
+    pub max_adu_bytes: Option<u16>,
+    /// Client writes to unconfigured coils or holding registers create them;
+    /// when false they answer IllegalDataAddress instead.
+    #[serde(default = "default_allow_dynamic_create")]
+    pub allow_dynamic_create: bool,
+    /// Reads must lie within one block of contiguous configured addresses;
+    /// others answer IllegalDataAddress instead of zero-filling the gaps.
+    #[serde(default)]
+    pub block_bounded_reads: bool,
+    /// Reads of an address unconfigured in one register space fall back to
+    /// the other, so holding registers also read as input registers and
+    /// vice versa.
+    #[serde(default)]
+    pub mirror_registers: bool,
+    /// How a client write is answered when another client wrote the same
+    /// address within `busy_window_ms`.
+    #[serde(default)]
+    pub write_conflict: WriteConflictPolicy,
+    #[serde(
+        default = "default_busy_window_ms",
+        deserialize_with = "duration::millis"
+    )]
+    pub busy_window_ms: u64,
+    /// What a reload does to the value of a register whose encoding, word
+    /// order or byte order changed.
+    #[serde(default)]
+    pub encoding_reload: EncodingReload,
     #[serde(default)]
     pub coils: Vec<BoolItemConfig>,
     #[serde(default)]
@@ -60,75 +748,681 @@ pub struct DeviceConfig {
     pub holding_registers: Vec<RegisterItemConfig>,
     #[serde(default)]
     pub input_registers: Vec<RegisterItemConfig>,
+    /// FIFO queues served by Read FIFO Queue (FC24).
+    #[serde(default)]
+    pub fifos: Vec<FifoConfig>,
+    /// Holding register bits that summarize conditions on other registers.
+    #[serde(default)]
+    pub alarms: Vec<AlarmConfig>,
+    /// Holding register blocks capturing the recent history of a register.
+    #[serde(default)]
+    pub ringbuffers: Vec<RingBufferConfig>,
+    /// Holding registers that read and write 16 coils as one word.
+    #[serde(default)]
+    pub coil_register_map: Vec<CoilRegisterMapConfig>,
+    /// Holding register blocks that refuse client writes until unlocked.
+    #[serde(default)]
+    pub write_locks: Vec<WriteLockConfig>,
+    /// Addresses that answer every request touching them with an exception.
+    #[serde(default)]
+    pub faulted: Vec<FaultedAddressConfig>,
+    /// Outage freezing every item of the device.
+    pub outage: Option<OutageConfig>,
+    /// Coupling of each register with dynamics to its neighbors.
+    pub crosstalk: Option<CrosstalkConfig>,
+    /// Function codes the device implements; the others answer
+    /// IllegalFunction.
+    #[serde(default)]
+    pub function_codes: FunctionCodes,
+}
+
+impl DeviceConfig {
+    /// Coils, discrete inputs and registers the device declares.
+    pub fn item_count(&self) -> usize {
+        self.coils.len()
+            + self.discrete_inputs.len()
+            + self.holding_registers.len()
+            + self.input_registers.len()
+    }
+}
+
+/// Value a register takes when a reload changes the layout of its words.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum EncodingReload {
+    /// Reset it to its `initial` in the new encoding.
+    #[default]
+    Reinitialize,
+    /// Keep its logical value, decoded with the old layout and encoded with
+    /// the new one; integer encodings round and saturate.
+    Reinterpret,
+}
+
+/// The function codes a device answers: those in `enabled`, or all when it
+/// is unset, minus those in `disabled`.
+#[derive(Debug, Deserialize, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FunctionCodes {
+    pub enabled: Option<Vec<u8>>,
+    #[serde(default)]
+    pub disabled: Vec<u8>,
+}
+
+impl FunctionCodes {
+    pub fn is_enabled(&self, function: u8) -> bool {
+        self.enabled
+            .as_ref()
+            .is_none_or(|enabled| enabled.contains(&function))
+            && !self.disabled.contains(&function)
+    }
+}
+
+/// `all`, `all but 15, 16` or the enabled codes, such as `1, 3, 4`.
+impl std::fmt::Display for FunctionCodes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (prefix, codes): (&str, Vec<u8>) = match &self.enabled {
+            None if self.disabled.is_empty() => return f.write_str("all"),
+            None => ("all but ", self.disabled.clone()),
+            Some(enabled) => {
+                let codes = enabled.iter().copied();
+                ("", codes.filter(|code| self.is_enabled(*code)).collect())
+            }
+        };
+        let codes: Vec<String> = codes.iter().map(u8::to_string).collect();
+        write!(f, "{prefix}{}", codes.join(", "))
+    }
+}
+
+/// Per-space offsets subtracted from the addresses clients send, e.g.
+/// `holding_registers = 40001` for a master using 40001-style numbering.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct AddressOffsets {
+    #[serde(default)]
+    pub coils: u16,
+    #[serde(default)]
+    pub discrete_inputs: u16,
+    #[serde(default)]
+    pub holding_registers: u16,
+    #[serde(default)]
+    pub input_registers: u16,
+}
+
+/// Sets `bit` of holding register `register` while the value of `source`
+/// is above `above`, below `below`, or makes `expr` true.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AlarmConfig {
+    pub register: u16,
+    pub bit: u8,
+    pub source: u16,
+    #[serde(default)]
+    pub source_space: RegisterSpace,
+    pub above: Option<f64>,
+    pub below: Option<f64>,
+    /// evalexpr expression over `value`, the source's raw value.
+    pub expr: Option<String>,
+}
+
+/// Samples `source` every `interval_ms` into the `length` holding registers
+/// starting at `address`, advancing the index held by `head`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RingBufferConfig {
+    pub source: u16,
+    #[serde(default)]
+    pub source_space: RegisterSpace,
+    pub address: u16,
+    pub length: u16,
+    pub head: u16,
+    /// Defaults to the source's `update_ms`.
+    #[serde(default, deserialize_with = "duration::optional_millis")]
+    pub interval_ms: Option<u64>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RegisterSpace {
+    HoldingRegisters,
+    #[default]
+    InputRegisters,
+}
+
+impl RegisterSpace {
+    pub fn name(self) -> &'static str {
+        match self {
+            RegisterSpace::HoldingRegisters => "holding register",
+            RegisterSpace::InputRegisters => "input register",
+        }
+    }
+}
+
+/// Cross-talk between adjacent registers, like the channels of an ADC
+/// leaking into each other: each register with dynamics in `space` gets
+/// `coupling` times the values of the registers up to `window` addresses
+/// on either side.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CrosstalkConfig {
+    pub coupling: f64,
+    #[serde(default = "default_crosstalk_window")]
+    pub window: u16,
+    #[serde(default)]
+    pub space: RegisterSpace,
+}
+
+/// A holding register whose bits are coils: bit `i` is `coils[i]`, least
+/// significant bit first.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct CoilRegisterMapConfig {
+    pub register: u16,
+    pub coils: Vec<u16>,
+}
+
+/// Holding registers `address..address + count` that refuse client writes
+/// until `unlock_coil` is written on, or `unlock_value` is written to
+/// `unlock_register`. Writes are then accepted for `window_ms`.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct WriteLockConfig {
+    pub address: u16,
+    pub count: u16,
+    pub unlock_coil: Option<u16>,
+    pub unlock_register: Option<u16>,
+    pub unlock_value: Option<u16>,
+    #[serde(
+        default = "default_unlock_window_ms",
+        deserialize_with = "duration::millis"
+    )]
+    pub window_ms: u64,
+    /// Exception answering writes while locked.
+    #[serde(default)]
+    pub exception: ConfiguredException,
+}
+
+/// A Modbus exception configured to answer requests, like those to locked
+/// or faulted addresses.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfiguredException {
+    IllegalFunction,
+    #[default]
+    IllegalDataAddress,
+    IllegalDataValue,
+    ServerDeviceFailure,
+    ServerDeviceBusy,
+    GatewayTargetDeviceFailedToRespond,
+}
+
+/// An address of `space` that answers every request touching it with
+/// `exception`, like a known-bad point in a device map.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FaultedAddressConfig {
+    pub address: u16,
+    pub space: Space,
+    pub exception: ConfiguredException,
 }
 
+/// A FIFO queue addressed by its pointer register.
 #[derive(Debug, Deserialize, Clone)]
+pub struct FifoConfig {
+    pub address: u16,
+    /// Initial queue contents, oldest first.
+    #[serde(default)]
+    pub values: Vec<u16>,
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct BoolItemConfig {
     pub address: u16,
+    /// Optional label used in point lists.
+    pub name: Option<String>,
     #[serde(default)]
     pub initial: bool,
+    #[serde(default, deserialize_with = "duration::optional_millis")]
     pub update_ms: Option<u64>,
     pub dynamics: Option<DynamicsSpec>,
+    pub quality: Option<QualityConfig>,
+    pub stuck: Option<StuckConfig>,
+    pub outage: Option<OutageConfig>,
+    /// Extra time taken to answer any request that touches this item.
+    #[serde(default, deserialize_with = "duration::optional_millis")]
+    pub response_delay_ms: Option<u64>,
+    /// Seeds this item's random stream, independent of other items.
+    pub seed: Option<u64>,
+    /// Evaluate dynamics when the item is read instead of on the tick schedule.
+    #[serde(default)]
+    pub update_on_read: bool,
+    /// Schedule updates on multiples of `update_ms` from the top of the
+    /// wall-clock minute instead of from start.
+    #[serde(default)]
+    pub align_to_clock: bool,
+    /// Blend from `cold_value` into the dynamics output over this window after
+    /// start; overrides `global.warmup_ms`.
+    #[serde(default, deserialize_with = "duration::optional_millis")]
+    pub warmup_ms: Option<u64>,
+    /// Dead time after start during which the item holds its value before
+    /// its dynamics begin; defaults to 0.
+    #[serde(default, deserialize_with = "duration::millis")]
+    pub dynamics_delay_ms: u64,
+    /// Value the item starts warming up from; defaults to 0.
+    pub cold_value: Option<f64>,
+    /// Shifts the time seen by periodic dynamics, e.g. to offset a sine.
+    #[serde(default, deserialize_with = "duration::optional_millis")]
+    pub phase_ms: Option<u64>,
+    /// Include the item in state snapshots; defaults to true only for items
+    /// without dynamics.
+    pub persist: Option<bool>,
+    /// Coils only: after a client writes `true`, reset to `false` on the first
+    /// tick `momentary_ms` later (default: the next tick).
+    #[serde(default)]
+    pub momentary: bool,
+    #[serde(default, deserialize_with = "duration::optional_millis")]
+    pub momentary_ms: Option<u64>,
+    /// Coils only: client writes within `debounce_ms` of the last accepted
+    /// write are ignored. Defaults to 0, no debounce.
+    #[serde(default, deserialize_with = "duration::millis")]
+    pub debounce_ms: u64,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Default)]
 pub struct RegisterItemConfig {
     pub address: u16,
+    /// Optional label used in point lists.
+    pub name: Option<String>,
+    /// Logical value in the item's encoding; the words past the first go to
+    /// the registers that follow.
     #[serde(default)]
-    pub initial: u16,
+    pub initial: Initial,
+    #[serde(default, deserialize_with = "duration::optional_millis")]
     pub update_ms: Option<u64>,
     pub dynamics: Option<DynamicsSpec>,
+    pub quality: Option<QualityConfig>,
+    pub stuck: Option<StuckConfig>,
+    pub outage: Option<OutageConfig>,
+    /// Extra time taken to answer any request that touches this item.
+    #[serde(default, deserialize_with = "duration::optional_millis")]
+    pub response_delay_ms: Option<u64>,
+    /// Seeds this item's random stream, independent of other items.
+    pub seed: Option<u64>,
+    /// Evaluate dynamics when the item is read instead of on the tick schedule.
+    #[serde(default)]
+    pub update_on_read: bool,
+    /// Schedule updates on multiples of `update_ms` from the top of the
+    /// wall-clock minute instead of from start.
+    #[serde(default)]
+    pub align_to_clock: bool,
+    /// Blend from `cold_value` into the dynamics output over this window after
+    /// start; overrides `global.warmup_ms`.
+    #[serde(default, deserialize_with = "duration::optional_millis")]
+    pub warmup_ms: Option<u64>,
+    /// Dead time after start during which the item holds its value before
+    /// its dynamics begin; defaults to 0.
+    #[serde(default, deserialize_with = "duration::millis")]
+    pub dynamics_delay_ms: u64,
+    /// Raw value the item starts warming up from; defaults to 0.
+    pub cold_value: Option<f64>,
+    /// Step, in raw units, the dynamics output is snapped to, like the
+    /// resolution of an ADC; defaults to 1.
+    pub resolution: Option<f64>,
+    /// Function applied to the dynamics output before it is snapped to the
+    /// resolution.
+    pub transform: Option<Transform>,
+    /// Largest change of the value per update, in raw units, whatever
+    /// drives it; unlimited by default.
+    pub max_change_per_tick: Option<f64>,
+    /// Skip the startup warning about a dynamics output range that is
+    /// narrow or clamped.
+    #[serde(default)]
+    pub suppress_range_warning: bool,
+    /// Leave the item out of `global.noise_floor`.
+    #[serde(default)]
+    pub noise_floor_exempt: bool,
+    /// Shifts the time seen by periodic dynamics, e.g. to offset a sine.
+    #[serde(default, deserialize_with = "duration::optional_millis")]
+    pub phase_ms: Option<u64>,
+    /// Include the item in state snapshots; defaults to true only for items
+    /// without dynamics.
+    pub persist: Option<bool>,
+    #[serde(default)]
+    pub encoding: Encoding,
+    #[serde(default)]
+    pub word_order: WordOrder,
+    #[serde(default)]
+    pub byte_order: ByteOrder,
+    /// Engineering value = raw * `scale` + `offset`; defaults to 1.
+    pub scale: Option<f64>,
+    /// Defaults to 0.
+    pub offset: Option<f64>,
+    /// Engineering unit label, such as `degC`, shown in update logs, audit
+    /// records, the control API and the point list.
+    pub unit: Option<String>,
+    /// Track the distribution of the item's raw values for `/metrics`.
+    #[serde(default)]
+    pub histogram: bool,
+    /// Upper bucket bounds; defaults to ten buckets over the dynamics'
+    /// `min`/`max`. Setting them enables the histogram.
+    pub histogram_buckets: Option<Vec<f64>>,
+    /// Coil that pauses the dynamics while off; always enabled when unset.
+    pub dynamics_enable_coil: Option<u16>,
+    /// Rest value a register without dynamics relaxes toward, in logical
+    /// units, at `decay_rate` per second.
+    pub decay_to: Option<f64>,
+    pub decay_rate: Option<f64>,
+    /// Bits of a client write the holding register keeps; the others read
+    /// back as 0. Applied after `write_transform`.
+    pub write_mask: Option<u16>,
+    /// Function a holding register applies to the values clients write.
+    pub write_transform: Option<WriteTransform>,
+    /// Refuse reads covering only some of the registers of the value.
+    #[serde(default)]
+    pub whole_reads: bool,
+}
+
+/// Function a register applies to its dynamics output.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Transform {
+    /// Natural logarithm.
+    Log,
+    Exp,
+    Sqrt,
+    Square,
+    /// Expression of the output `x` and the elapsed seconds `t`.
+    Script(String),
+}
+
+/// Function a holding register applies to a written value before storing it.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WriteTransform {
+    /// Exchanges the high and low byte.
+    SwapBytes,
+    /// Expression of the written value `x`.
+    Script(String),
+}
+
+/// Starting value of a register item. Integers too wide for TOML, such as
+/// a large `u64`, may be given as strings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Initial {
+    Int(i128),
+    Float(f64),
+}
+
+impl Default for Initial {
+    fn default() -> Self {
+        Initial::Int(0)
+    }
+}
+
+impl From<u16> for Initial {
+    fn from(value: u16) -> Self {
+        Initial::Int(value.into())
+    }
+}
+
+impl std::fmt::Display for Initial {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Initial::Int(value) => value.fmt(f),
+            Initial::Float(value) => value.fmt(f),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Initial {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Int(i64),
+            Float(f64),
+            Text(String),
+        }
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Int(value) => Initial::Int(value.into()),
+            Repr::Float(value) => Initial::Float(value),
+            Repr::Text(text) => {
+                let text = text.trim();
+                if let Ok(value) = text.parse() {
+                    Initial::Int(value)
+                } else if let Ok(value) = text.parse::<f64>()
+                    && value.is_finite()
+                {
+                    Initial::Float(value)
+                } else {
+                    return Err(serde::de::Error::custom(format!(
+                        "invalid initial value `{text}`"
+                    )));
+                }
+            }
+        })
+    }
+}
+
+/// Logical type of the value starting at a register.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Encoding {
+    #[default]
+    U16,
+    I16,
+    U32,
+    I32,
+    F32,
+    U64,
+    I64,
+    F64,
+}
+
+/// Order of the registers of a multi-register value.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum WordOrder {
+    #[default]
+    Big,
+    Little,
+}
+
+/// Order of the two bytes within each register.
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ByteOrder {
+    #[default]
+    Big,
+    Little,
+}
+
+/// Schedule during which an item reports bad quality.
+///
+/// The item is bad for the last `bad_ms` of every `period_ms` window. While bad,
+/// reads return `sentinel` (non-zero reads as `true` for bit items) or, when no
+/// sentinel is set, a server device failure exception.
+#[derive(Debug, Deserialize, Clone)]
+pub struct QualityConfig {
+    #[serde(deserialize_with = "duration::millis")]
+    pub period_ms: u64,
+    #[serde(deserialize_with = "duration::millis")]
+    pub bad_ms: u64,
+    pub sentinel: Option<u16>,
+}
+
+/// Schedule during which an item's value freezes, like a stuck sensor.
+///
+/// For the last `stuck_ms` of every `period_ms` window the item skips its
+/// updates and holds its last value; unlike bad quality, nothing marks it.
+#[derive(Debug, Deserialize, Clone)]
+pub struct StuckConfig {
+    #[serde(deserialize_with = "duration::millis")]
+    pub period_ms: u64,
+    #[serde(deserialize_with = "duration::millis")]
+    pub stuck_ms: u64,
 }
 
+/// Schedule of a simulated sensor outage during which values are served from
+/// cache.
+///
+/// For the last `outage_ms` of every `period_ms` window the affected items
+/// skip their updates and hold their last value. Bit `status_bit` of holding
+/// register `status_register`, when set, is on while the outage lasts.
 #[derive(Debug, Deserialize, Clone)]
-#[serde(tag = "kind", rename_all = "kebab-case")]
-pub enum DynamicsSpec {
-    Static,
-    Clamp {
-        min: f64,
-        max: f64,
-    },
-    Sine {
-        amplitude: f64,
-        offset: f64,
-        period_ms: u64,
-    },
-    Ramp {
-        min: f64,
-        max: f64,
-        period_ms: u64,
-    },
-    Step {
-        low: f64,
-        high: f64,
-        period_ms: u64,
-    },
-    RandomWalk {
-        min: f64,
-        max: f64,
-        step: f64,
-    },
-    Noise {
-        min: f64,
-        max: f64,
-    },
-    Script {
-        expr: String,
-        #[serde(default)]
-        min: Option<f64>,
-        #[serde(default)]
-        max: Option<f64>,
-    },
+pub struct OutageConfig {
+    #[serde(deserialize_with = "duration::millis")]
+    pub period_ms: u64,
+    #[serde(deserialize_with = "duration::millis")]
+    pub outage_ms: u64,
+    pub status_register: Option<u16>,
+    #[serde(default)]
+    pub status_bit: u8,
+}
+
+/// An item's `dynamics` table.
+///
+/// `kind` selects the implementation from the dynamics registry; the remaining
+/// keys are passed to it as parameters.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub struct DynamicsSpec {
+    pub kind: String,
+    #[serde(flatten)]
+    pub params: toml::Table,
+}
+
+fn deserialize_mei<'de, D>(deserializer: D) -> Result<BTreeMap<u8, Vec<u8>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    BTreeMap::<String, Vec<u8>>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(key, bytes)| {
+            let mei_type = key
+                .parse::<u8>()
+                .map_err(|_| serde::de::Error::custom(format!("invalid MEI type `{key}`")))?;
+            Ok((mei_type, bytes))
+        })
+        .collect()
+}
+
+fn deserialize_data_bits<'de, D>(deserializer: D) -> Result<u8, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let bits = u8::deserialize(deserializer)?;
+    if !(5..=8).contains(&bits) {
+        return Err(serde::de::Error::custom(format!(
+            "invalid data_bits {bits}, expected 5, 6, 7 or 8"
+        )));
+    }
+    Ok(bits)
+}
+
+fn deserialize_stop_bits<'de, D>(deserializer: D) -> Result<u8, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let bits = u8::deserialize(deserializer)?;
+    if !(1..=2).contains(&bits) {
+        return Err(serde::de::Error::custom(format!(
+            "invalid stop_bits {bits}, expected 1 or 2"
+        )));
+    }
+    Ok(bits)
+}
+
+fn deserialize_crc_polynomial<'de, D>(deserializer: D) -> Result<Option<u16>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let polynomial = u16::deserialize(deserializer)?;
+    // Every CRC generator has the x^0 term; without it the lowest bit of
+    // the message never reaches the CRC.
+    if polynomial & 1 == 0 {
+        return Err(serde::de::Error::custom(format!(
+            "invalid crc_polynomial {polynomial:#06x}, expected an odd polynomial in normal notation such as 0x8005"
+        )));
+    }
+    Ok(Some(polynomial))
 }
 
 fn default_update_ms() -> u64 {
     500
 }
 
+fn default_min_tick_ms() -> u64 {
+    10
+}
+
+fn default_warn_update_ms() -> u64 {
+    50
+}
+
+fn default_max_updates_per_sec() -> u64 {
+    100_000
+}
+
+fn default_max_items() -> usize {
+    100_000
+}
+
+fn default_idle_tick_ms() -> u64 {
+    1000
+}
+
+fn default_persist_interval_ms() -> u64 {
+    5000
+}
+
+fn default_mirror_interval_ms() -> u64 {
+    1000
+}
+
+fn default_mirror_timeout_ms() -> u64 {
+    1000
+}
+
+fn default_standby_interval_ms() -> u64 {
+    100
+}
+
+fn default_standby_timeout_ms() -> u64 {
+    2000
+}
+
+/// CSV capture of a device whose rows drive the registers they list, at the
+/// times they were recorded.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ReplayConfig {
+    pub path: String,
+    /// Start over from the first row after the last.
+    #[serde(default, rename = "loop")]
+    pub looped: bool,
+    /// Playback speed; `2.0` plays the capture twice as fast. Defaults to 1.
+    #[serde(default = "default_time_scale")]
+    pub time_scale: f64,
+    /// Keep the registers' dynamics and switch to them, from the last
+    /// replayed value, once the capture ends.
+    #[serde(default)]
+    pub replay_then_live: bool,
+}
+
+fn default_time_scale() -> f64 {
+    1.0
+}
+
+fn default_keepalive_enabled() -> bool {
+    true
+}
+
 fn default_tcp_bind() -> String {
     "0.0.0.0:5020".to_string()
 }
 
+fn default_control_bind() -> String {
+    "127.0.0.1:8080".to_string()
+}
+
 fn default_baud_rate() -> u32 {
     9600
 }
@@ -145,14 +1439,121 @@ fn default_stop_bits() -> u8 {
     1
 }
 
+fn default_device_name() -> String {
+    "default".to_string()
+}
+
 fn default_unit_id() -> u8 {
     1
 }
 
+fn default_busy_window_ms() -> u64 {
+    100
+}
+
+fn default_crosstalk_window() -> u16 {
+    1
+}
+
+fn default_unlock_window_ms() -> u64 {
+    10_000
+}
+
+fn default_allow_dynamic_create() -> bool {
+    true
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn serial_parameters_are_validated() {
+        let rtu = |text: &str| toml::from_str::<RtuConfig>(text);
+        let err = rtu("data_bits = 9").unwrap_err();
+        assert!(err.to_string().contains("invalid data_bits 9"), "{err}");
+        let err = rtu("stop_bits = 0").unwrap_err();
+        assert!(err.to_string().contains("invalid stop_bits 0"), "{err}");
+        assert!(rtu("parity = \"mark\"").is_err());
+        assert!(rtu("crc = \"crc32\"").is_err());
+        let err = rtu("crc_polynomial = 0x8004").unwrap_err();
+        assert!(
+            err.to_string().contains("invalid crc_polynomial 0x8004"),
+            "{err}"
+        );
+        let config = rtu("crc = \"arc\"\ncrc_polynomial = 0x3d65").unwrap();
+        assert_eq!(config.crc, CrcAlgorithm::Arc);
+        assert_eq!(config.crc_polynomial, Some(0x3d65));
+        assert_eq!(rtu("").unwrap().crc, CrcAlgorithm::Modbus);
+
+        assert!(rtu("").unwrap().framing_warnings().is_empty());
+        let warnings = rtu("data_bits = 5\nstop_bits = 2")
+            .unwrap()
+            .framing_warnings();
+        assert_eq!(warnings.len(), 2);
+    }
+
+    #[test]
+    fn fingerprints_follow_file_contents() {
+        let dir = std::env::temp_dir();
+        let path = |name: &str| {
+            dir.join(format!(
+                "modsim-fingerprint-{}-{name}.toml",
+                std::process::id()
+            ))
+            .to_string_lossy()
+            .into_owned()
+        };
+        let (a, b) = (path("a"), path("b"));
+        std::fs::write(&a, "x = 1\n").unwrap();
+        std::fs::write(&b, "x = 1\n").unwrap();
+        assert_eq!(fingerprint(&[&a]).unwrap(), fingerprint(&[&b]).unwrap());
+        assert_ne!(fingerprint(&[&a]).unwrap(), fingerprint(&[&a, &b]).unwrap());
+        std::fs::write(&b, "x = 2\n").unwrap();
+        assert_ne!(fingerprint(&[&a]).unwrap(), fingerprint(&[&b]).unwrap());
+        assert_eq!(fingerprint(&[&a]).unwrap().len(), 16);
+        for path in [a, b] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn configs_over_max_items_are_refused() {
+        let path =
+            std::env::temp_dir().join(format!("modsim-max-items-{}.toml", std::process::id()));
+        let items: String = (0..5)
+            .map(|address| format!("[[device.coils]]\naddress = {address}\n"))
+            .collect();
+        std::fs::write(&path, format!("[global]\nmax_items = 4\n{items}")).unwrap();
+        let err = load(path.to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("5 items"), "{err}");
+
+        std::fs::write(&path, format!("[global]\nmax_items = 5\n{items}")).unwrap();
+        assert_eq!(load(path.to_str().unwrap()).unwrap().item_count(), 5);
+
+        // Items are counted before they are parsed, so an oversized file
+        // fails on the count even when its items are invalid.
+        let oversized = "[global]\nmax_items = 1\n[[device.coils]]\n[[device.coils]]\n";
+        std::fs::write(&path, oversized).unwrap();
+        let err = load(path.to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("2 items"), "{err}");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn clock_drift_must_leave_the_clock_running() {
+        let path =
+            std::env::temp_dir().join(format!("modsim-clock-drift-{}.toml", std::process::id()));
+        std::fs::write(&path, "[global]\nclock_drift_ppm = -1000000\n").unwrap();
+        let err = load(path.to_str().unwrap()).unwrap_err();
+        assert!(err.to_string().contains("clock_drift_ppm"), "{err}");
+
+        std::fs::write(&path, "[global]\nclock_drift_ppm = 50.0\n").unwrap();
+        let config = load(path.to_str().unwrap()).unwrap();
+        assert_eq!(config.global.unwrap().clock_drift_ppm, 50.0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
     #[test]
     fn parse_example_config() {
         let s = std::fs::read_to_string("config.example.toml").expect("read example config");
@@ -177,4 +1578,168 @@ unit_id = 1
         assert!(cfg.rtu.is_some());
         assert_eq!(cfg.rtu.unwrap().device, Some("/dev/ttyS0".to_string()));
     }
+
+    #[test]
+    fn legacy_device_inherits_top_level_transports() {
+        let s = r#"
+[tcp]
+bind = "127.0.0.1:5020"
+
+[device]
+unit_id = 3
+"#;
+        let cfg: Config = toml::from_str(s).expect("parse toml");
+        let devices = cfg.resolved_devices();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].name, "default");
+        assert_eq!(devices[0].unit_id, 3);
+        assert_eq!(devices[0].tcp.as_ref().unwrap().bind, "127.0.0.1:5020");
+    }
+
+    #[test]
+    fn mirrored_device_drops_item_dynamics() {
+        let s = r#"
+[mirror]
+upstream = "10.0.0.5:502"
+interval_ms = "2s"
+
+[device]
+holding_registers = [{ address = 0, dynamics = { kind = "static" } }]
+"#;
+        let cfg: Config = toml::from_str(s).expect("parse toml");
+        let devices = cfg.resolved_devices();
+        let mirror = devices[0].mirror.as_ref().unwrap();
+        assert_eq!(
+            (mirror.upstream.as_str(), mirror.interval_ms),
+            ("10.0.0.5:502", 2000)
+        );
+        assert!(devices[0].holding_registers[0].dynamics.is_none());
+    }
+
+    #[test]
+    fn parse_multiple_named_devices() {
+        let s = r#"
+[[devices]]
+name = "sensors"
+unit_id = 1
+tcp = { bind = "127.0.0.1:5020" }
+
+[[devices.input_registers]]
+address = 0
+initial = 7
+
+[[devices]]
+name = "actuators"
+unit_id = 2
+tcp = { bind = "127.0.0.1:5021" }
+
+[[devices.coils]]
+address = 4
+"#;
+        let cfg: Config = toml::from_str(s).expect("parse toml");
+        let devices = cfg.resolved_devices();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].name, "sensors");
+        assert_eq!(devices[0].input_registers.len(), 1);
+        assert_eq!(devices[1].name, "actuators");
+        assert_eq!(devices[1].tcp.as_ref().unwrap().bind, "127.0.0.1:5021");
+        assert_eq!(devices[1].coils[0].address, 4);
+    }
+
+    #[test]
+    fn device_layouts_are_validated() {
+        let devices = |extra: &str| {
+            let text = format!("{extra}\n[[devices]]\nname = \"a\"\n\n[[devices]]\nname = \"b\"\n");
+            toml::from_str::<Config>(&text).unwrap().validate()
+        };
+        assert!(devices("").is_ok());
+        let err = devices("[tcp]\nbind = \"127.0.0.1:5020\"").unwrap_err();
+        assert!(err.to_string().contains("top-level [tcp]"), "{err}");
+
+        let text = "[device]\n\n[[devices]]\nname = \"plc\"\n\n[[devices]]\nname = \"plc\"\n";
+        let err = toml::from_str::<Config>(text)
+            .unwrap()
+            .validate()
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("`plc` is used more than once"),
+            "{err}"
+        );
+        let text = "[device]\n\n[[devices]]\nname = \"default\"\n";
+        assert!(toml::from_str::<Config>(text).unwrap().validate().is_err());
+
+        let text = "[device]\n\n[device.rtu]\npty = true\ndevice = \"/dev/ttyS0\"\n";
+        let err = toml::from_str::<Config>(text)
+            .unwrap()
+            .validate()
+            .unwrap_err();
+        assert!(err.to_string().contains("rtu.pty and rtu.device"), "{err}");
+    }
+
+    #[test]
+    fn parse_mei_responses() {
+        let s = r#"
+[device]
+
+[device.mei]
+13 = [0x00, 0x01]
+"#;
+        let cfg: Config = toml::from_str(s).expect("parse toml");
+        let device = cfg.device.unwrap();
+        assert_eq!(device.mei.get(&13), Some(&vec![0x00, 0x01]));
+    }
+
+    #[test]
+    fn reject_non_numeric_mei_type() {
+        let s = r#"
+[device.mei]
+canopen = [0x00]
+"#;
+        assert!(toml::from_str::<Config>(s).is_err());
+    }
+
+    #[test]
+    fn function_codes_default_to_all() {
+        let cfg: Config = toml::from_str("[device]").expect("parse toml");
+        let codes = cfg.device.unwrap().function_codes;
+        assert!(codes.is_enabled(0x0F));
+        assert_eq!(codes.to_string(), "all");
+
+        let s = r#"
+[device.function_codes]
+enabled = [1, 3, 6, 15]
+disabled = [15]
+"#;
+        let cfg: Config = toml::from_str(s).expect("parse toml");
+        let codes = cfg.device.unwrap().function_codes;
+        assert!(codes.is_enabled(3));
+        assert!(!codes.is_enabled(15));
+        assert!(!codes.is_enabled(16));
+        assert_eq!(codes.to_string(), "1, 3, 6");
+        let codes = FunctionCodes {
+            enabled: None,
+            disabled: vec![15, 16],
+        };
+        assert_eq!(codes.to_string(), "all but 15, 16");
+    }
+
+    #[test]
+    fn top_level_fault_applies_to_devices_without_their_own() {
+        let s = r#"
+[fault]
+latency = { mean_ms = 20.0, stddev_ms = 5.0 }
+
+[[devices]]
+name = "a"
+
+[[devices]]
+name = "b"
+fault = {}
+"#;
+        let cfg: Config = toml::from_str(s).expect("parse toml");
+        let devices = cfg.resolved_devices();
+        let latency = devices[0].fault.as_ref().unwrap().latency.as_ref().unwrap();
+        assert_eq!(latency.mean_ms, 20.0);
+        assert!(devices[1].fault.as_ref().unwrap().latency.is_none());
+    }
 }
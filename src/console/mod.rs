@@ -0,0 +1,325 @@
+use std::io::{IsTerminal, Write as _};
+use std::net::SocketAddr;
+
+use anyhow::{Context, Result, bail};
+use rustyline::DefaultEditor;
+use rustyline::error::ReadlineError;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines, Stdin};
+use tokio_modbus::client::{Context as ModbusContext, tcp};
+use tokio_modbus::prelude::{Reader, Slave, Writer};
+
+use crate::config::DeviceConfig;
+use crate::sim::Space;
+
+const HELP: &str = "\
+commands:
+  read <space> <addr> [count]     read values (count defaults to 1)
+  write <space> <addr> <value>... write one or more coils (co) or holding registers (hr)
+  dump <space>                    read every configured address of a space
+  help                            show this help
+  quit                            leave the console
+spaces: co (coils), di (discrete inputs), hr (holding registers), ir (input registers)";
+
+const PROMPT: &str = "modsim> ";
+
+/// One console command line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Read {
+        space: Space,
+        address: u16,
+        count: u16,
+    },
+    Write {
+        space: Space,
+        address: u16,
+        values: Vec<u16>,
+    },
+    Dump(Space),
+    Help,
+    Quit,
+}
+
+pub fn parse(line: &str) -> Result<Command> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    match words.as_slice() {
+        ["read", space, address] => Ok(Command::Read {
+            space: parse_space(space)?,
+            address: parse_number(address)?,
+            count: 1,
+        }),
+        ["read", space, address, count] => Ok(Command::Read {
+            space: parse_space(space)?,
+            address: parse_number(address)?,
+            count: parse_number(count)?,
+        }),
+        ["write", space, address, values @ ..] if !values.is_empty() => {
+            let space = parse_space(space)?;
+            let values = values
+                .iter()
+                .map(|value| match space {
+                    Space::Coils => parse_coil(value),
+                    _ => parse_number(value),
+                })
+                .collect::<Result<_>>()?;
+            Ok(Command::Write {
+                space,
+                address: parse_number(address)?,
+                values,
+            })
+        }
+        ["dump", space] => Ok(Command::Dump(parse_space(space)?)),
+        ["help"] => Ok(Command::Help),
+        ["quit"] | ["exit"] => Ok(Command::Quit),
+        _ => bail!("unrecognised command, try `help`"),
+    }
+}
+
+fn parse_space(word: &str) -> Result<Space> {
+    match word {
+        "co" | "coil" | "coils" => Ok(Space::Coils),
+        "di" | "discrete-inputs" => Ok(Space::DiscreteInputs),
+        "hr" | "holding" => Ok(Space::HoldingRegisters),
+        "ir" | "input" => Ok(Space::InputRegisters),
+        _ => bail!("unknown space `{word}` (co, di, hr, ir)"),
+    }
+}
+
+/// Decimal or `0x`-prefixed hexadecimal.
+fn parse_number(word: &str) -> Result<u16> {
+    let parsed = match word.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => word.parse(),
+    };
+    parsed.with_context(|| format!("invalid number `{word}`"))
+}
+
+fn parse_coil(word: &str) -> Result<u16> {
+    match word {
+        "1" | "on" | "true" => Ok(1),
+        "0" | "off" | "false" => Ok(0),
+        _ => bail!("invalid coil value `{word}` (0/1, on/off, true/false)"),
+    }
+}
+
+fn space_tag(space: Space) -> &'static str {
+    match space {
+        Space::Coils => "co",
+        Space::DiscreteInputs => "di",
+        Space::HoldingRegisters => "hr",
+        Space::InputRegisters => "ir",
+    }
+}
+
+/// Runs a command against the connected device and returns the text to print.
+/// `Quit` prints nothing; leaving the loop is up to the caller.
+pub async fn execute(
+    ctx: &mut ModbusContext,
+    device: &DeviceConfig,
+    command: &Command,
+) -> Result<String> {
+    match command {
+        Command::Read {
+            space,
+            address,
+            count,
+        } => {
+            let values = read(ctx, *space, *address, *count).await?;
+            Ok(values
+                .iter()
+                .zip(*address..)
+                .map(|(value, address)| format!("{} {address} = {value}\n", space_tag(*space)))
+                .collect())
+        }
+        Command::Write {
+            space,
+            address,
+            values,
+        } => {
+            match (space, values.as_slice()) {
+                (Space::Coils, [value]) => ctx.write_single_coil(*address, *value != 0).await?,
+                (Space::Coils, values) => {
+                    let coils: Vec<bool> = values.iter().map(|value| *value != 0).collect();
+                    ctx.write_multiple_coils(*address, &coils).await?
+                }
+                (Space::HoldingRegisters, [value]) => {
+                    ctx.write_single_register(*address, *value).await?
+                }
+                (Space::HoldingRegisters, values) => {
+                    ctx.write_multiple_registers(*address, values).await?
+                }
+                (space, _) => bail!("{} is read-only", space_tag(*space)),
+            }
+            Ok(format!("ok, wrote {} value(s)\n", values.len()))
+        }
+        Command::Dump(space) => {
+            let mut out = String::new();
+            for address in configured_addresses(device, *space) {
+                let value = read(ctx, *space, address, 1).await?;
+                out.push_str(&format!("{} {address} = {}\n", space_tag(*space), value[0]));
+            }
+            Ok(out)
+        }
+        Command::Help => Ok(format!("{HELP}\n")),
+        Command::Quit => Ok(String::new()),
+    }
+}
+
+/// Values of `count` addresses, with bits shown as `0`/`1`.
+async fn read(ctx: &mut ModbusContext, space: Space, address: u16, count: u16) -> Result<Vec<u16>> {
+    let bits = |bits: Vec<bool>| bits.into_iter().map(u16::from).collect();
+    Ok(match space {
+        Space::Coils => bits(ctx.read_coils(address, count).await?),
+        Space::DiscreteInputs => bits(ctx.read_discrete_inputs(address, count).await?),
+        Space::HoldingRegisters => ctx.read_holding_registers(address, count).await?,
+        Space::InputRegisters => ctx.read_input_registers(address, count).await?,
+    })
+}
+
+fn configured_addresses(device: &DeviceConfig, space: Space) -> Vec<u16> {
+    match space {
+        Space::Coils => device.coils.iter().map(|item| item.address).collect(),
+        Space::DiscreteInputs => device.discrete_inputs.iter().map(|i| i.address).collect(),
+        Space::HoldingRegisters => device.holding_registers.iter().map(|i| i.address).collect(),
+        Space::InputRegisters => device.input_registers.iter().map(|i| i.address).collect(),
+    }
+}
+
+/// Where the console reads its command lines.
+enum Input {
+    /// A terminal: lines are edited with rustyline, which keeps a history
+    /// of the session's commands.
+    Editor(Box<DefaultEditor>),
+    /// Piped input, read line by line.
+    Plain(Lines<BufReader<Stdin>>),
+}
+
+impl Input {
+    fn stdin() -> Result<Self> {
+        if std::io::stdin().is_terminal() {
+            Ok(Input::Editor(Box::new(DefaultEditor::new()?)))
+        } else {
+            Ok(Input::Plain(BufReader::new(tokio::io::stdin()).lines()))
+        }
+    }
+
+    /// The next line after prompting for it, `None` at end of input. Ctrl-C
+    /// at the terminal drops the line being edited.
+    async fn next_line(&mut self) -> Result<Option<String>> {
+        match self {
+            Input::Editor(editor) => {
+                match tokio::task::block_in_place(|| editor.readline(PROMPT)) {
+                    Ok(line) => {
+                        if !line.trim().is_empty() {
+                            editor.add_history_entry(line.as_str())?;
+                        }
+                        Ok(Some(line))
+                    }
+                    Err(ReadlineError::Interrupted) => Ok(Some(String::new())),
+                    Err(ReadlineError::Eof) => Ok(None),
+                    Err(err) => Err(err.into()),
+                }
+            }
+            Input::Plain(lines) => {
+                print!("{PROMPT}");
+                std::io::stdout().flush()?;
+                Ok(lines.next_line().await?)
+            }
+        }
+    }
+}
+
+/// Reads commands from stdin and runs them against the simulator at `addr`
+/// until `quit` or end of input. At a terminal lines can be edited and
+/// earlier commands recalled.
+pub async fn run(addr: SocketAddr, device: &DeviceConfig) -> Result<()> {
+    let mut ctx = tcp::connect_slave(addr, Slave(device.unit_id))
+        .await
+        .with_context(|| format!("failed to connect to {addr}"))?;
+    println!(
+        "connected to {} at {addr}; type `help` for commands",
+        device.name
+    );
+    let mut input = Input::stdin()?;
+    loop {
+        let Some(line) = input.next_line().await? else {
+            return Ok(());
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse(&line) {
+            Ok(Command::Quit) => return Ok(()),
+            Ok(command) => match execute(&mut ctx, device, &command).await {
+                Ok(output) => print!("{output}"),
+                Err(err) => println!("error: {err:#}"),
+            },
+            Err(err) => println!("error: {err:#}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::harness::TestHarness;
+    use crate::sim::SimState;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            parse("read hr 0 10").unwrap(),
+            Command::Read {
+                space: Space::HoldingRegisters,
+                address: 0,
+                count: 10,
+            }
+        );
+        assert_eq!(
+            parse("write co 0x10 on off").unwrap(),
+            Command::Write {
+                space: Space::Coils,
+                address: 16,
+                values: vec![1, 0],
+            }
+        );
+        assert_eq!(parse("dump coils").unwrap(), Command::Dump(Space::Coils));
+        assert!(parse("read xx 0").is_err());
+        assert!(parse("write hr 5").is_err());
+    }
+
+    #[tokio::test]
+    async fn executes_against_a_running_simulator() {
+        let device: DeviceConfig = toml::from_str(
+            "[[holding_registers]]\naddress = 5\ninitial = 7\n\n[[holding_registers]]\naddress = 9",
+        )
+        .unwrap();
+        let state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            device.holding_registers.clone(),
+            vec![],
+        )
+        .unwrap();
+        let mut ctx = TestHarness::start(state).await.unwrap().client;
+
+        let write = parse("write hr 9 1234").unwrap();
+        execute(&mut ctx, &device, &write).await.unwrap();
+        let dump = execute(&mut ctx, &device, &Command::Dump(Space::HoldingRegisters))
+            .await
+            .unwrap();
+        assert_eq!(dump, "hr 5 = 7\nhr 9 = 1234\n");
+        let read = parse("read hr 4 2").unwrap();
+        assert_eq!(
+            execute(&mut ctx, &device, &read).await.unwrap(),
+            "hr 4 = 0\nhr 5 = 7\n"
+        );
+        assert!(
+            execute(&mut ctx, &device, &parse("write ir 0 1").unwrap())
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,1427 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Instant, UNIX_EPOCH};
+
+use anyhow::{Context, Result, bail};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+use crate::config::{self, DynamicsSpec, duration};
+
+use crate::sim::encoding::decode_item;
+use crate::sim::{
+    DynamicsState, Event, Histogram, SimBoolItem, SimRegisterItem, SimState, Space, StateSnapshot,
+    UpdateRate,
+};
+
+mod http;
+mod json;
+#[cfg(feature = "sse")]
+mod sse;
+mod state_toml;
+
+pub use http::{HttpRequest, HttpResponse};
+pub use json::Json;
+
+/// A named device state exposed over the control API.
+#[derive(Clone)]
+pub struct DeviceHandle {
+    pub name: String,
+    pub state: Arc<RwLock<SimState>>,
+    /// Listeners serving the device, such as `tcp 0.0.0.0:502`.
+    pub transports: Vec<String>,
+}
+
+/// Routes control requests to the configured devices.
+pub struct ControlApi {
+    devices: Vec<DeviceHandle>,
+    config_paths: Vec<String>,
+    /// Fingerprint of the config files loaded at startup.
+    config_hash: Option<String>,
+    /// Last `POST /checkpoint` of each device, by device name.
+    checkpoints: Mutex<BTreeMap<String, StateSnapshot>>,
+}
+
+impl ControlApi {
+    pub fn new(devices: Vec<DeviceHandle>) -> Self {
+        Self {
+            devices,
+            config_paths: Vec::new(),
+            config_hash: None,
+            checkpoints: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Config file re-read by `POST /reload-dynamics`.
+    pub fn with_config_paths(mut self, paths: Vec<String>) -> Self {
+        self.config_paths = paths;
+        self
+    }
+
+    /// Config fingerprint reported by `GET /info`.
+    pub fn with_config_hash(mut self, hash: String) -> Self {
+        self.config_hash = Some(hash);
+        self
+    }
+
+    /// Dispatches a request.
+    ///
+    /// Paths under `/devices/{name}/` address a named device; other paths
+    /// address the first configured device.
+    pub fn route(&self, request: &HttpRequest) -> HttpResponse {
+        let segments = request.segments();
+        match (request.method.as_str(), segments.as_slice()) {
+            ("POST", ["reload-dynamics"]) => return self.reload_dynamics(&self.devices),
+            ("GET", ["info"]) => return self.info(),
+            ("GET", ["state.toml"]) => return self.state_toml(),
+            ("GET", ["metrics"]) => {
+                let openmetrics = request
+                    .header("accept")
+                    .is_some_and(|accept| accept.contains("application/openmetrics-text"));
+                return metrics(&self.devices, openmetrics);
+            }
+            _ => {}
+        }
+
+        let (device, segments) = match self.device(&segments) {
+            Ok(addressed) => addressed,
+            Err(response) => return response,
+        };
+
+        match (request.method.as_str(), segments) {
+            ("GET", ["dump"]) => dump(device),
+            ("GET", ["stats"]) => stats(device),
+            ("GET", ["type", address]) => address_type(device, address),
+            ("POST", ["events", space, address]) => post_event(device, space, address, request),
+            ("GET", ["dynamics"]) => dynamics_states(device),
+            ("GET", ["dynamics", space, address]) => get_dynamics(device, space, address),
+            ("GET", ["registers", "holding", address]) => get_holding_register(device, address),
+            ("PUT", ["registers", "holding", address]) => {
+                put_holding_register(device, address, request)
+            }
+            ("GET", ["fifos", address]) => get_fifo(device, address),
+            ("POST", ["fifos", address]) => push_fifo(device, address, request),
+            ("DELETE", ["fifos", address]) => pop_fifo(device, address),
+            ("POST", ["checkpoint"]) => self.checkpoint(device),
+            ("POST", ["restore"]) => self.restore(device),
+            ("POST", ["reset"]) => reset(device),
+            ("POST", ["reload-dynamics"]) => self.reload_dynamics(std::slice::from_ref(device)),
+            _ => HttpResponse::not_found(),
+        }
+    }
+
+    /// The device a path addresses and the segments after its prefix.
+    fn device<'a, 'p>(
+        &'a self,
+        segments: &'p [&'p str],
+    ) -> Result<(&'a DeviceHandle, &'p [&'p str]), HttpResponse> {
+        match segments {
+            ["devices", name, rest @ ..] => {
+                match self.devices.iter().find(|device| device.name == *name) {
+                    Some(device) => Ok((device, rest)),
+                    None => Err(HttpResponse::error(404, format!("unknown device `{name}`"))),
+                }
+            }
+            rest => match self.devices.first() {
+                Some(device) => Ok((device, rest)),
+                None => Err(HttpResponse::not_found()),
+            },
+        }
+    }
+
+    /// Saves the live state of `device`, replacing its previous checkpoint.
+    /// Version, config fingerprint and, per device, uptime and transports.
+    fn info(&self) -> HttpResponse {
+        let devices = self.devices.iter().map(|device| {
+            let state = device.state.read().unwrap();
+            let uptime = state.started_at.elapsed().unwrap_or_default();
+            let started_at = state
+                .started_at
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            Json::object([
+                ("name", Json::from(device.name.as_str())),
+                (
+                    "start_time_unix_ms",
+                    Json::from(started_at.as_millis() as u64),
+                ),
+                ("uptime_ms", Json::from(uptime.as_millis() as u64)),
+                ("elapsed_ms", Json::from(state.elapsed_ms())),
+                (
+                    "transports",
+                    Json::Array(
+                        device
+                            .transports
+                            .iter()
+                            .map(|transport| Json::from(transport.as_str()))
+                            .collect(),
+                    ),
+                ),
+            ])
+        });
+        HttpResponse::ok(Json::object([
+            ("version", Json::from(env!("CARGO_PKG_VERSION"))),
+            ("config_hash", Json::from(self.config_hash.as_deref())),
+            ("devices", Json::Array(devices.collect())),
+        ]))
+    }
+
+    fn checkpoint(&self, device: &DeviceHandle) -> HttpResponse {
+        let snapshot = device.state.read().unwrap().capture();
+        let elapsed_ms = snapshot.elapsed_ms;
+        self.checkpoints
+            .lock()
+            .unwrap()
+            .insert(device.name.clone(), snapshot);
+        HttpResponse::ok(Json::object([
+            ("device", Json::from(device.name.as_str())),
+            ("elapsed_ms", Json::from(elapsed_ms)),
+        ]))
+    }
+
+    /// Rewinds `device` to its last checkpoint, which stays available.
+    fn restore(&self, device: &DeviceHandle) -> HttpResponse {
+        let checkpoints = self.checkpoints.lock().unwrap();
+        let Some(snapshot) = checkpoints.get(&device.name) else {
+            return HttpResponse::error(404, format!("no checkpoint for {}", device.name));
+        };
+        device.state.write().unwrap().restore(snapshot);
+        HttpResponse::ok(Json::object([
+            ("device", Json::from(device.name.as_str())),
+            ("elapsed_ms", Json::from(snapshot.elapsed_ms)),
+        ]))
+    }
+
+    /// Re-reads the config file and reloads dynamics of `devices`, matching
+    /// devices by name.
+    fn reload_dynamics(&self, devices: &[DeviceHandle]) -> HttpResponse {
+        if self.config_paths.is_empty() {
+            return HttpResponse::error(404, "no config file to reload");
+        }
+        let config = match config::load_all(&self.config_paths) {
+            Ok(config) => config,
+            Err(err) => return HttpResponse::error(400, format!("{err:#}")),
+        };
+        let configured = config.resolved_devices();
+
+        let mut reloaded = Vec::new();
+        for device in devices {
+            let Some(device_config) = configured.iter().find(|config| config.name == device.name)
+            else {
+                continue;
+            };
+            let changes = match device.state.write().unwrap().reload_dynamics(device_config) {
+                Ok(changes) => changes,
+                Err(err) => {
+                    return HttpResponse::error(400, format!("device {}: {err:#}", device.name));
+                }
+            };
+            for change in &changes {
+                info!(device = %device.name, "{change}");
+            }
+            reloaded.push(Json::object([
+                ("device", Json::from(device.name.as_str())),
+                ("changes", Json::from(changes)),
+            ]));
+        }
+        HttpResponse::ok(Json::object([("devices", Json::Array(reloaded))]))
+    }
+}
+
+/// Where the control API listens: a TCP address, or `unix:<path>` for a
+/// Unix domain socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlBind {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl ControlBind {
+    pub fn parse(bind: &str) -> Result<Self> {
+        match bind.strip_prefix("unix:") {
+            Some("") => bail!("`unix:` needs a socket path"),
+            Some(path) => Ok(Self::Unix(PathBuf::from(path))),
+            None => bind
+                .parse()
+                .map(Self::Tcp)
+                .with_context(|| format!("invalid bind `{bind}`")),
+        }
+    }
+}
+
+pub async fn start_control(bind: &str, api: ControlApi) -> Result<()> {
+    let api = Arc::new(api);
+    match ControlBind::parse(bind)? {
+        ControlBind::Tcp(addr) => {
+            let listener = TcpListener::bind(addr).await?;
+            info!(addr = %addr, "control api listening");
+            tokio::spawn(async move {
+                loop {
+                    match listener.accept().await {
+                        Ok((stream, peer)) => serve(Arc::clone(&api), stream, peer.to_string()),
+                        Err(err) => warn!(error = %err, "control api accept failed"),
+                    }
+                }
+            });
+        }
+        ControlBind::Unix(path) => start_unix(path, api)?,
+    }
+    Ok(())
+}
+
+/// Listens on a Unix socket at `path`. A socket file left behind by an
+/// earlier run is replaced; the file is removed again when the accept loop
+/// is dropped with the runtime at shutdown.
+#[cfg(unix)]
+fn start_unix(path: PathBuf, api: Arc<ControlApi>) -> Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+        if !metadata.file_type().is_socket() {
+            bail!("{} exists and is not a socket", path.display());
+        }
+        if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+            bail!("{} is in use by another process", path.display());
+        }
+        std::fs::remove_file(&path)
+            .with_context(|| format!("failed to remove stale socket {}", path.display()))?;
+    }
+    let listener = tokio::net::UnixListener::bind(&path)
+        .with_context(|| format!("failed to bind {}", path.display()))?;
+    info!(path = %path.display(), "control api listening");
+    let socket = SocketFile(path);
+    tokio::spawn(async move {
+        let peer = socket.0.display().to_string();
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => serve(Arc::clone(&api), stream, peer.clone()),
+                Err(err) => warn!(error = %err, "control api accept failed"),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn start_unix(path: PathBuf, _api: Arc<ControlApi>) -> Result<()> {
+    bail!(
+        "cannot listen on {}: Unix sockets are not supported on this platform",
+        path.display()
+    )
+}
+
+/// Removes the socket file at the path when dropped.
+#[cfg(unix)]
+struct SocketFile(PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(err) = std::fs::remove_file(&self.0) {
+            warn!(path = %self.0.display(), error = %err, "failed to remove control socket");
+        }
+    }
+}
+
+/// Answers the single request of a connection, or streams its events.
+fn serve<S>(api: Arc<ControlApi>, stream: S, peer: String)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    tokio::spawn(async move {
+        let (reader, writer) = tokio::io::split(stream);
+        let response = match http::read_request(reader).await {
+            #[cfg(feature = "sse")]
+            Ok(request) if sse::is_events(&request) => match api.subscribe_changes(&request) {
+                Ok(changes) => {
+                    if let Err(err) = sse::stream(writer, changes).await {
+                        tracing::debug!(
+                            peer = %peer,
+                            error = %err,
+                            "control api event stream closed"
+                        );
+                    }
+                    return;
+                }
+                Err(response) => response,
+            },
+            Ok(request) => api.route(&request),
+            Err(err) => HttpResponse::error(400, err.to_string()),
+        };
+        if let Err(err) = http::write_response(writer, &response).await {
+            warn!(peer = %peer, error = %err, "control api write failed");
+        }
+    });
+}
+
+/// Returns the device to its configured initial state.
+fn reset(device: &DeviceHandle) -> HttpResponse {
+    match device.state.write().unwrap().reset() {
+        Ok(()) => HttpResponse::ok(Json::object([("device", Json::from(device.name.as_str()))])),
+        Err(err) => HttpResponse::error(500, format!("{err:#}")),
+    }
+}
+
+fn get_holding_register(device: &DeviceHandle, address: &str) -> HttpResponse {
+    let Ok(address) = address.parse::<u16>() else {
+        return HttpResponse::error(400, "invalid address");
+    };
+    let state = device.state.read().unwrap();
+    match state.read_holding_registers(address, 1) {
+        Ok(values) => HttpResponse::ok(register_json(&state, address, values[0])),
+        Err(exception) => HttpResponse::error(409, format!("{exception:?}")),
+    }
+}
+
+/// Writes a holding register from the request body, a raw value or, with
+/// `?unit=engineering`, an engineering value. With `?expect=N` the write only
+/// happens if the current raw value is `N`; a mismatch answers 409.
+fn put_holding_register(
+    device: &DeviceHandle,
+    address: &str,
+    request: &HttpRequest,
+) -> HttpResponse {
+    let Ok(address) = address.parse::<u16>() else {
+        return HttpResponse::error(400, "invalid address");
+    };
+    let expected = match request.query("expect").map(str::parse::<u16>) {
+        Some(Ok(expected)) => Some(expected),
+        Some(Err(_)) => return HttpResponse::error(400, "invalid expect value"),
+        None => None,
+    };
+
+    let mut state = device.state.write().unwrap();
+    let value = match request.query("unit") {
+        None | Some("raw") => match request.body.trim().parse::<u16>() {
+            Ok(value) => value,
+            Err(_) => return HttpResponse::error(400, "body must be a register value"),
+        },
+        Some("engineering") => match request.body.trim().parse::<f64>() {
+            Ok(engineering) => match state.holding_registers.get(&address) {
+                Some(item) => item.to_raw(engineering),
+                None => engineering.round().clamp(0.0, u16::MAX as f64) as u16,
+            },
+            Err(_) => return HttpResponse::error(400, "body must be an engineering value"),
+        },
+        Some(_) => return HttpResponse::error(400, "unit must be raw or engineering"),
+    };
+
+    match expected {
+        Some(expected) => {
+            if state.compare_and_swap_register(address, expected, value) {
+                HttpResponse::ok(register_json(&state, address, value))
+            } else {
+                let current = state.holding_register_value(address);
+                HttpResponse::json(
+                    409,
+                    Json::object([
+                        ("error", Json::from("value does not match expect")),
+                        ("address", Json::from(address)),
+                        ("value", Json::from(current)),
+                    ]),
+                )
+            }
+        }
+        None => {
+            state.write_single_register(address, value);
+            HttpResponse::ok(register_json(&state, address, value))
+        }
+    }
+}
+
+fn get_fifo(device: &DeviceHandle, address: &str) -> HttpResponse {
+    let Ok(address) = address.parse::<u16>() else {
+        return HttpResponse::error(400, "invalid address");
+    };
+    match device.state.read().unwrap().fifos.get(&address) {
+        Some(fifo) => HttpResponse::ok(fifo_json(address, fifo)),
+        None => HttpResponse::error(404, format!("no fifo at {address}")),
+    }
+}
+
+/// Appends the value in the request body to a FIFO queue.
+fn push_fifo(device: &DeviceHandle, address: &str, request: &HttpRequest) -> HttpResponse {
+    let Ok(address) = address.parse::<u16>() else {
+        return HttpResponse::error(400, "invalid address");
+    };
+    let Ok(value) = request.body.trim().parse::<u16>() else {
+        return HttpResponse::error(400, "body must be a register value");
+    };
+    match device.state.write().unwrap().fifos.get_mut(&address) {
+        Some(fifo) => {
+            fifo.push_back(value);
+            HttpResponse::ok(fifo_json(address, fifo))
+        }
+        None => HttpResponse::error(404, format!("no fifo at {address}")),
+    }
+}
+
+/// Removes the oldest value of a FIFO queue, as a device consuming it would.
+fn pop_fifo(device: &DeviceHandle, address: &str) -> HttpResponse {
+    let Ok(address) = address.parse::<u16>() else {
+        return HttpResponse::error(400, "invalid address");
+    };
+    match device.state.write().unwrap().fifos.get_mut(&address) {
+        Some(fifo) => {
+            let value = fifo.pop_front();
+            HttpResponse::ok(Json::object([
+                ("address", Json::from(address)),
+                ("value", Json::from(value)),
+            ]))
+        }
+        None => HttpResponse::error(404, format!("no fifo at {address}")),
+    }
+}
+
+fn fifo_json(address: u16, fifo: &VecDeque<u16>) -> Json {
+    Json::object([
+        ("address", Json::from(address)),
+        ("values", Json::from(Vec::from(fifo.clone()))),
+    ])
+}
+
+/// Every configured item; registers show their decoded engineering value next
+/// to the raw words it was decoded from, and their unit.
+fn dump(device: &DeviceHandle) -> HttpResponse {
+    let state = device.state.read().unwrap();
+    HttpResponse::ok(Json::object([
+        ("coils", bool_table_json(&state.coils)),
+        ("discrete_inputs", bool_table_json(&state.discrete_inputs)),
+        (
+            "holding_registers",
+            register_table_json(&state.holding_registers),
+        ),
+        (
+            "input_registers",
+            register_table_json(&state.input_registers),
+        ),
+    ]))
+}
+
+/// The spaces `address` is configured in, with the encoding of registers and
+/// the dynamics kind driving each item.
+fn address_type(device: &DeviceHandle, address: &str) -> HttpResponse {
+    let Ok(address) = address.parse::<u16>() else {
+        return HttpResponse::error(400, "invalid address");
+    };
+    let state = device.state.read().unwrap();
+    let kind =
+        |spec: &Option<DynamicsSpec>| Json::from(spec.as_ref().map(|spec| spec.kind.as_str()));
+    let bits = [
+        ("coils", &state.coils),
+        ("discrete_inputs", &state.discrete_inputs),
+    ]
+    .into_iter()
+    .filter_map(|(space, map)| {
+        let item = map.get(&address)?;
+        Some(Json::object([
+            ("space", Json::from(space)),
+            ("dynamics", kind(&item.dynamics_spec)),
+        ]))
+    });
+    let registers = [
+        ("holding_registers", &state.holding_registers),
+        ("input_registers", &state.input_registers),
+    ]
+    .into_iter()
+    .filter_map(|(space, map)| {
+        let item = map.get(&address)?;
+        Some(Json::object([
+            ("space", Json::from(space)),
+            ("encoding", Json::from(item.encoding.name())),
+            ("dynamics", kind(&item.dynamics_spec)),
+            ("unit", Json::from(item.unit.as_deref())),
+        ]))
+    });
+    let spaces: Vec<Json> = bits.chain(registers).collect();
+    if spaces.is_empty() {
+        return HttpResponse::error(404, format!("address {address} is not configured"));
+    }
+    HttpResponse::ok(Json::object([
+        ("address", Json::from(address)),
+        ("spaces", Json::Array(spaces)),
+    ]))
+}
+
+/// The name of `space` in JSON responses.
+fn space_name(space: Space) -> &'static str {
+    match space {
+        Space::Coils => "coils",
+        Space::DiscreteInputs => "discrete_inputs",
+        Space::HoldingRegisters => "holding_registers",
+        Space::InputRegisters => "input_registers",
+    }
+}
+
+fn parse_space(space: &str) -> Result<Space, HttpResponse> {
+    match space {
+        "coils" => Ok(Space::Coils),
+        "discrete_inputs" => Ok(Space::DiscreteInputs),
+        "holding_registers" => Ok(Space::HoldingRegisters),
+        "input_registers" => Ok(Space::InputRegisters),
+        _ => Err(HttpResponse::error(404, format!("unknown space `{space}`"))),
+    }
+}
+
+/// Whether the dynamics of each item run, or which gate holds them.
+fn dynamics_states(device: &DeviceHandle) -> HttpResponse {
+    let state = device.state.read().unwrap();
+    HttpResponse::ok(Json::Array(
+        state
+            .dynamics_states()
+            .into_iter()
+            .map(|(space, address, dynamics_state)| {
+                Json::object([
+                    ("space", Json::from(space_name(space))),
+                    ("address", Json::from(address)),
+                    ("dynamics_state", Json::from(dynamics_state.name())),
+                ])
+            })
+            .collect(),
+    ))
+}
+
+/// Kind, gating state and internal state of an item's dynamics.
+fn get_dynamics(device: &DeviceHandle, space: &str, address: &str) -> HttpResponse {
+    let space_name = space;
+    let space = match parse_space(space) {
+        Ok(space) => space,
+        Err(response) => return response,
+    };
+    let Ok(address) = address.parse::<u16>() else {
+        return HttpResponse::error(400, "invalid address");
+    };
+    let state = device.state.read().unwrap();
+    let Some((spec, dynamics)) = state.dynamics(space, address) else {
+        return HttpResponse::error(404, format!("no dynamics at {space_name} {address}"));
+    };
+    HttpResponse::ok(Json::object([
+        ("space", Json::from(space_name)),
+        ("address", Json::from(address)),
+        ("kind", Json::from(spec.kind.as_str())),
+        (
+            "dynamics_state",
+            Json::from(
+                state
+                    .dynamics_state(space, address)
+                    .map(DynamicsState::name),
+            ),
+        ),
+        (
+            "state",
+            Json::object(
+                dynamics
+                    .state()
+                    .into_iter()
+                    .map(|(name, value)| (name, Json::from(value))),
+            ),
+        ),
+    ]))
+}
+
+/// Queues an event for the item at `space`/`address`, applied by the next
+/// tick. The event is `?kind=pulse|step|fault-on|fault-off`; pulses take a
+/// raw `value` and an optional `duration_ms`, steps a raw `delta`.
+fn post_event(
+    device: &DeviceHandle,
+    space: &str,
+    address: &str,
+    request: &HttpRequest,
+) -> HttpResponse {
+    let space = match parse_space(space) {
+        Ok(space) => space,
+        Err(response) => return response,
+    };
+    let Ok(address) = address.parse::<u16>() else {
+        return HttpResponse::error(400, "invalid address");
+    };
+    let number = |key: &str| match request.query(key).map(str::parse::<f64>) {
+        Some(Ok(number)) if number.is_finite() => Ok(number),
+        Some(_) => Err(HttpResponse::error(400, format!("invalid {key}"))),
+        None => Err(HttpResponse::error(400, format!("missing {key}"))),
+    };
+    let event = match request.query("kind") {
+        Some("pulse") => {
+            let value = match number("value") {
+                Ok(value) => value,
+                Err(response) => return response,
+            };
+            let duration_ms = match request.query("duration_ms").map(duration::parse_millis) {
+                Some(Ok(ms)) if ms >= 0.0 => ms.round() as u64,
+                Some(_) => return HttpResponse::error(400, "invalid duration_ms"),
+                None => 0,
+            };
+            Event::Pulse { value, duration_ms }
+        }
+        Some("step") => match number("delta") {
+            Ok(delta) => Event::Step { delta },
+            Err(response) => return response,
+        },
+        Some("fault-on") => Event::FaultOn,
+        Some("fault-off") => Event::FaultOff,
+        _ => return HttpResponse::error(400, "kind must be pulse, step, fault-on or fault-off"),
+    };
+    let mut state = device.state.write().unwrap();
+    if let Err(err) = state.queue_event(space, address, event) {
+        let status = if state.is_configured(space, address) {
+            400
+        } else {
+            404
+        };
+        return HttpResponse::error(status, err.to_string());
+    }
+    HttpResponse::ok(Json::object([
+        ("device", Json::from(device.name.as_str())),
+        ("address", Json::from(address)),
+        ("event", Json::from(event.name())),
+    ]))
+}
+
+fn stats(device: &DeviceHandle) -> HttpResponse {
+    let state = device.state.read().unwrap();
+    let exceptions = state
+        .exception_counts
+        .iter()
+        .map(|(exception, count)| (exception.name(), Json::from(*count)));
+    let interval = |space: &str, address: &u16, update_ms: u64, rate: &UpdateRate| {
+        let observed_ms = rate.interval_ms?;
+        Some(Json::object([
+            ("space", Json::from(space)),
+            ("address", Json::from(*address)),
+            ("update_ms", Json::from(update_ms)),
+            (
+                "observed_ms",
+                Json::from((observed_ms * 10.0).round() / 10.0),
+            ),
+        ]))
+    };
+    let bits = [
+        ("coils", &state.coils),
+        ("discrete_inputs", &state.discrete_inputs),
+    ]
+    .into_iter()
+    .flat_map(|(space, map)| {
+        map.iter().filter_map(move |(address, item)| {
+            interval(space, address, item.update_ms, &item.update_rate)
+        })
+    });
+    let registers = [
+        ("holding_registers", &state.holding_registers),
+        ("input_registers", &state.input_registers),
+    ]
+    .into_iter()
+    .flat_map(|(space, map)| {
+        map.iter().filter_map(move |(address, item)| {
+            interval(space, address, item.update_ms, &item.update_rate)
+        })
+    });
+    let now = Instant::now();
+    let last_writes = state
+        .last_writes()
+        .iter()
+        .map(|(&(space, address), write)| {
+            Json::object([
+                ("space", Json::from(space_name(space))),
+                ("address", Json::from(address)),
+                (
+                    "age_ms",
+                    Json::from(now.saturating_duration_since(write.at).as_millis() as u64),
+                ),
+                ("peer", Json::from(write.peer.map(|peer| peer.to_string()))),
+                ("connection_id", Json::from(write.connection_id)),
+            ])
+        })
+        .collect();
+    HttpResponse::ok(Json::object([
+        ("exceptions", Json::object(exceptions)),
+        (
+            "update_intervals",
+            Json::Array(bits.chain(registers).collect()),
+        ),
+        ("last_writes", Json::Array(last_writes)),
+    ]))
+}
+
+/// Prometheus text exposition of every device's counters, or OpenMetrics
+/// with request duration exemplars when `openmetrics` is set.
+fn metrics(devices: &[DeviceHandle], openmetrics: bool) -> HttpResponse {
+    let mut out = String::new();
+    let family = |out: &mut String, name: &str, kind: &str, help: &str| {
+        // OpenMetrics names counter families without their `_total` suffix.
+        let name = match (openmetrics, kind) {
+            (true, "counter") => name.trim_end_matches("_total"),
+            _ => name,
+        };
+        out.push_str(&format!("# HELP {name} {help}\n# TYPE {name} {kind}\n"));
+    };
+    family(
+        &mut out,
+        "modsim_exception_responses_total",
+        "counter",
+        "Modbus exception responses sent.",
+    );
+    for device in devices {
+        let state = device.state.read().unwrap();
+        for (exception, count) in &state.exception_counts {
+            out.push_str(&format!(
+                "modsim_exception_responses_total{{device=\"{}\",code=\"{}\",exception=\"{}\"}} {count}\n",
+                label_value(&device.name),
+                exception.code(),
+                exception.name()
+            ));
+        }
+    }
+    family(
+        &mut out,
+        "modsim_tick_overruns_total",
+        "counter",
+        "Simulator ticks that finished after the next tick was due.",
+    );
+    for device in devices {
+        let state = device.state.read().unwrap();
+        out.push_str(&format!(
+            "modsim_tick_overruns_total{{device=\"{}\"}} {}\n",
+            label_value(&device.name),
+            state.tick_overruns
+        ));
+    }
+    family(
+        &mut out,
+        "modsim_request_duration_seconds",
+        "histogram",
+        "Time taken to answer Modbus requests.",
+    );
+    for device in devices {
+        let state = device.state.read().unwrap();
+        let labels = format!("device=\"{}\"", label_value(&device.name));
+        write_histogram(
+            &mut out,
+            "modsim_request_duration_seconds",
+            &labels,
+            &state.request_duration,
+            openmetrics,
+        );
+    }
+    family(
+        &mut out,
+        "modsim_register_value",
+        "histogram",
+        "Distribution of raw register values, for items with a histogram.",
+    );
+    for device in devices {
+        let state = device.state.read().unwrap();
+        for (space, map) in [
+            ("holding_registers", &state.holding_registers),
+            ("input_registers", &state.input_registers),
+        ] {
+            for (address, item) in map {
+                let Some(histogram) = &item.histogram else {
+                    continue;
+                };
+                let labels = format!(
+                    "device=\"{}\",space=\"{space}\",address=\"{address}\"",
+                    label_value(&device.name)
+                );
+                write_histogram(&mut out, "modsim_register_value", &labels, histogram, false);
+            }
+        }
+    }
+    if openmetrics {
+        out.push_str("# EOF\n");
+        HttpResponse::openmetrics(out)
+    } else {
+        HttpResponse::metrics(out)
+    }
+}
+
+/// Writes the bucket, sum and count samples of `histogram`, with the
+/// exemplar of each bucket appended when `exemplars` is set.
+fn write_histogram(
+    out: &mut String,
+    name: &str,
+    labels: &str,
+    histogram: &Histogram,
+    exemplars: bool,
+) {
+    for ((bound, count), exemplar) in histogram
+        .cumulative()
+        .into_iter()
+        .zip(histogram.exemplars())
+    {
+        let le = if bound.is_infinite() {
+            "+Inf".to_string()
+        } else {
+            bound.to_string()
+        };
+        out.push_str(&format!("{name}_bucket{{{labels},le=\"{le}\"}} {count}"));
+        if let Some(exemplar) = exemplar.as_ref().filter(|_| exemplars) {
+            let labels: Vec<String> = exemplar
+                .labels
+                .iter()
+                .map(|(name, value)| format!("{name}=\"{}\"", label_value(value)))
+                .collect();
+            out.push_str(&format!(
+                " # {{{}}} {} {}",
+                labels.join(","),
+                exemplar.value,
+                exemplar.timestamp
+            ));
+        }
+        out.push('\n');
+    }
+    out.push_str(&format!("{name}_sum{{{labels}}} {}\n", histogram.sum));
+    out.push_str(&format!("{name}_count{{{labels}}} {}\n", histogram.count));
+}
+
+/// Escapes a Prometheus label value.
+fn label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn bool_table_json(map: &BTreeMap<u16, SimBoolItem>) -> Json {
+    Json::Array(
+        map.iter()
+            .map(|(address, item)| {
+                Json::object([
+                    ("address", Json::from(*address)),
+                    ("value", Json::from(item.value)),
+                ])
+            })
+            .collect(),
+    )
+}
+
+fn register_table_json(map: &BTreeMap<u16, SimRegisterItem>) -> Json {
+    Json::Array(
+        map.iter()
+            .map(|(address, item)| {
+                let (raw, value) = decode_item(map, *address).unwrap_or_default();
+                Json::object([
+                    ("address", Json::from(*address)),
+                    ("encoding", Json::from(item.encoding.name())),
+                    ("value", Json::from(value)),
+                    ("raw", Json::from(raw)),
+                    ("unit", Json::from(item.unit.as_deref())),
+                ])
+            })
+            .collect(),
+    )
+}
+
+fn register_json(state: &SimState, address: u16, value: u16) -> Json {
+    let item = state.holding_registers.get(&address);
+    let engineering = item
+        .map(|item| item.to_engineering(value as f64))
+        .unwrap_or(value as f64);
+    Json::object([
+        ("address", Json::from(address)),
+        ("value", Json::from(value)),
+        ("engineering", Json::from(engineering)),
+        (
+            "unit",
+            Json::from(item.and_then(|item| item.unit.as_deref())),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BoolItemConfig, Encoding, Initial, RegisterItemConfig};
+    use crate::sim::{Exception, Exemplar};
+
+    fn api() -> ControlApi {
+        ControlApi::new(devices())
+    }
+
+    fn devices() -> Vec<DeviceHandle> {
+        let reg = RegisterItemConfig {
+            address: 3,
+            initial: 10.into(),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![reg], vec![]).unwrap();
+        vec![DeviceHandle {
+            name: "plc".to_string(),
+            state: Arc::new(RwLock::new(state)),
+            transports: Vec::new(),
+        }]
+    }
+
+    #[test]
+    fn binds_parse_as_tcp_or_unix() {
+        assert_eq!(
+            ControlBind::parse("127.0.0.1:8080").unwrap(),
+            ControlBind::Tcp("127.0.0.1:8080".parse().unwrap())
+        );
+        assert_eq!(
+            ControlBind::parse("unix:/run/modsim.sock").unwrap(),
+            ControlBind::Unix(PathBuf::from("/run/modsim.sock"))
+        );
+        assert!(ControlBind::parse("unix:").is_err());
+        assert!(ControlBind::parse("localhost").is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn serves_over_a_unix_socket() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let path = std::env::temp_dir().join(format!("modsim-control-{}.sock", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let bind = format!("unix:{}", path.display());
+        let err = start_control(&bind, api()).await.unwrap_err();
+        assert!(err.to_string().contains("not a socket"), "{err}");
+        std::fs::remove_file(&path).unwrap();
+
+        start_control(&bind, api()).await.unwrap();
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET /registers/holding/3 HTTP/1.1\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+        assert!(response.contains("\"value\":10"), "{response}");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn put_with_matching_expect_writes() {
+        let api = api();
+        let response = api.route(&HttpRequest::new(
+            "PUT",
+            "/registers/holding/3?expect=10",
+            "42",
+        ));
+        assert_eq!(response.status, 200);
+        let state = api.devices[0].state.read().unwrap();
+        assert_eq!(state.read_holding_registers(3, 1), Ok(vec![42]));
+    }
+
+    #[test]
+    fn put_with_mismatched_expect_conflicts() {
+        let api = api();
+        let response = api.route(&HttpRequest::new(
+            "PUT",
+            "/devices/plc/registers/holding/3?expect=11",
+            "42",
+        ));
+        assert_eq!(response.status, 409);
+        let state = api.devices[0].state.read().unwrap();
+        assert_eq!(state.read_holding_registers(3, 1), Ok(vec![10]));
+    }
+
+    #[test]
+    fn events_are_applied_by_the_next_tick() {
+        let api = api();
+        let post = |target| api.route(&HttpRequest::new("POST", target, ""));
+        let response = post("/events/holding_registers/3?kind=pulse&value=99&duration_ms=1m");
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.body,
+            r#"{"device":"plc","address":3,"event":"pulse"}"#
+        );
+        let state = &api.devices[0].state;
+        assert_eq!(state.read().unwrap().holding_register_value(3), 10);
+        state.write().unwrap().tick().unwrap();
+        assert_eq!(state.read().unwrap().holding_register_value(3), 99);
+
+        for (target, status) in [
+            ("/events/holding_registers/4?kind=fault-on", 404),
+            ("/events/registers/3?kind=fault-on", 404),
+            ("/events/holding_registers/3?kind=spike", 400),
+            ("/events/holding_registers/3?kind=step", 400),
+            (
+                "/events/holding_registers/3?kind=pulse&value=1&duration_ms=soon",
+                400,
+            ),
+        ] {
+            assert_eq!(post(target).status, status, "{target}");
+        }
+    }
+
+    #[test]
+    fn dynamics_show_their_internal_state() {
+        let setpoint = RegisterItemConfig {
+            address: 3,
+            initial: 10.into(),
+            ..Default::default()
+        };
+        let controlled = RegisterItemConfig {
+            address: 4,
+            update_ms: Some(0),
+            dynamics: Some(
+                toml::from_str("kind = \"controlled\"\nsetpoint = 3\nkp = 1.0").unwrap(),
+            ),
+            ..Default::default()
+        };
+        let state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![setpoint, controlled],
+            vec![],
+        )
+        .unwrap();
+        let api = ControlApi::new(vec![DeviceHandle {
+            name: "plc".to_string(),
+            state: Arc::new(RwLock::new(state)),
+            transports: Vec::new(),
+        }]);
+        let get = |target| api.route(&HttpRequest::new("GET", target, ""));
+        let response = get("/dynamics/holding_registers/4");
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.body,
+            r#"{"space":"holding_registers","address":4,"kind":"controlled","dynamics_state":"running","state":{"integral":0}}"#
+        );
+        assert_eq!(
+            get("/dynamics").body,
+            r#"[{"space":"holding_registers","address":4,"dynamics_state":"running"}]"#
+        );
+        api.devices[0].state.write().unwrap().tick().unwrap();
+        let response = get("/dynamics/holding_registers/4");
+        assert!(response.body.contains(r#""value":"#), "{}", response.body);
+        assert!(
+            response.body.contains(r#""updated_at":"#),
+            "{}",
+            response.body
+        );
+
+        for target in [
+            "/dynamics/holding_registers/3",
+            "/dynamics/holding_registers/9",
+            "/dynamics/registers/4",
+        ] {
+            assert_eq!(get(target).status, 404, "{target}");
+        }
+    }
+
+    #[test]
+    fn type_lists_the_spaces_of_an_address() {
+        let coil = BoolItemConfig {
+            address: 3,
+            ..Default::default()
+        };
+        let sine = RegisterItemConfig {
+            address: 3,
+            encoding: Encoding::I16,
+            dynamics: Some(
+                toml::from_str("kind = \"sine\"\namplitude = 5.0\noffset = 5.0\nperiod_ms = 1000")
+                    .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![coil], vec![], vec![], vec![sine]).unwrap();
+        let api = ControlApi::new(vec![DeviceHandle {
+            name: "plc".to_string(),
+            state: Arc::new(RwLock::new(state)),
+            transports: Vec::new(),
+        }]);
+        let response = api.route(&HttpRequest::new("GET", "/type/3", ""));
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.body,
+            r#"{"address":3,"spaces":[{"space":"coils","dynamics":null},{"space":"input_registers","encoding":"i16","dynamics":"sine","unit":null}]}"#
+        );
+        assert_eq!(
+            api.route(&HttpRequest::new("GET", "/type/4", "")).status,
+            404
+        );
+        assert_eq!(
+            api.route(&HttpRequest::new("GET", "/type/x", "")).status,
+            400
+        );
+    }
+
+    #[test]
+    fn dump_decodes_wide_registers() {
+        let bits = 23.4f32.to_bits();
+        // The second word is created from the initial value.
+        let regs = vec![RegisterItemConfig {
+            address: 0,
+            initial: Initial::Float(23.4),
+            encoding: Encoding::F32,
+            unit: Some("degC".to_string()),
+            ..Default::default()
+        }];
+        let state = SimState::new(500, false, vec![], vec![], vec![], regs).unwrap();
+        let api = ControlApi::new(vec![DeviceHandle {
+            name: "plc".to_string(),
+            state: Arc::new(RwLock::new(state)),
+            transports: Vec::new(),
+        }]);
+        let response = api.route(&HttpRequest::new("GET", "/dump", ""));
+        assert_eq!(response.status, 200);
+        assert!(response.body.contains(&format!(
+            r#"{{"address":0,"encoding":"f32","value":23.4,"raw":[{},{}],"unit":"degC"}}"#,
+            bits >> 16,
+            bits & 0xFFFF
+        )));
+        assert!(
+            response
+                .body
+                .contains(r#"{"address":1,"encoding":"u16","value":"#)
+        );
+    }
+
+    #[test]
+    fn put_engineering_value_is_scaled_to_raw() {
+        let reg = RegisterItemConfig {
+            address: 5,
+            scale: Some(0.1),
+            offset: Some(-40.0),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![reg], vec![]).unwrap();
+        let api = ControlApi::new(vec![DeviceHandle {
+            name: "plc".to_string(),
+            state: Arc::new(RwLock::new(state)),
+            transports: Vec::new(),
+        }]);
+        let response = api.route(&HttpRequest::new(
+            "PUT",
+            "/registers/holding/5?unit=engineering",
+            "21.5",
+        ));
+        assert_eq!(response.status, 200);
+        assert_eq!(
+            response.body,
+            r#"{"address":5,"value":615,"engineering":21.5,"unit":null}"#
+        );
+    }
+
+    #[test]
+    fn reload_dynamics_rereads_config() {
+        let path = std::env::temp_dir().join(format!("modsim-reload-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            r#"
+[[devices]]
+name = "plc"
+
+[[devices.holding_registers]]
+address = 3
+update_ms = 100
+dynamics = { kind = "static" }
+"#,
+        )
+        .unwrap();
+        let api = api().with_config_paths(vec![path.to_str().unwrap().to_string()]);
+        let response = api.route(&HttpRequest::new("POST", "/reload-dynamics", ""));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(response.status, 200);
+        assert!(
+            response
+                .body
+                .contains("holding register 3: dynamics none -> static")
+        );
+        let state = api.devices[0].state.read().unwrap();
+        assert_eq!(state.holding_registers[&3].update_ms, 100);
+        assert_eq!(state.holding_registers[&3].value, 10);
+    }
+
+    #[test]
+    fn fifo_push_and_pop() {
+        let api = api();
+        {
+            let mut state = api.devices[0].state.write().unwrap();
+            state.fifos.insert(16, [1].into());
+        }
+        let pushed = api.route(&HttpRequest::new("POST", "/fifos/16", "2"));
+        assert_eq!(pushed.body, r#"{"address":16,"values":[1,2]}"#);
+        let popped = api.route(&HttpRequest::new("DELETE", "/fifos/16", ""));
+        assert_eq!(popped.body, r#"{"address":16,"value":1}"#);
+        let listed = api.route(&HttpRequest::new("GET", "/fifos/16", ""));
+        assert_eq!(listed.body, r#"{"address":16,"values":[2]}"#);
+        assert_eq!(
+            api.route(&HttpRequest::new("POST", "/fifos/17", "2"))
+                .status,
+            404
+        );
+    }
+
+    #[test]
+    fn restore_rewinds_to_checkpoint() {
+        let api = api();
+        assert_eq!(
+            api.route(&HttpRequest::new("POST", "/restore", "")).status,
+            404
+        );
+        api.route(&HttpRequest::new("POST", "/checkpoint", ""));
+        api.route(&HttpRequest::new("PUT", "/registers/holding/3", "77"));
+        let restored = api.route(&HttpRequest::new("POST", "/restore", ""));
+        assert_eq!(restored.status, 200);
+        let state = api.devices[0].state.read().unwrap();
+        assert_eq!(state.holding_register_value(3), 10);
+    }
+
+    #[test]
+    fn reset_returns_to_initial_values() {
+        let api = api();
+        api.route(&HttpRequest::new("PUT", "/registers/holding/3", "77"));
+        let reset = api.route(&HttpRequest::new("POST", "/devices/plc/reset", ""));
+        assert_eq!(reset.status, 200);
+        assert_eq!(reset.body, r#"{"device":"plc"}"#);
+        let state = api.devices[0].state.read().unwrap();
+        assert_eq!(state.holding_register_value(3), 10);
+    }
+
+    #[test]
+    fn unknown_device_is_not_found() {
+        let api = api();
+        let response = api.route(&HttpRequest::new(
+            "GET",
+            "/devices/nope/registers/holding/3",
+            "",
+        ));
+        assert_eq!(response.status, 404);
+    }
+
+    #[test]
+    fn info_reports_version_uptime_and_transports() {
+        let mut devices = devices();
+        devices[0].transports.push("tcp 127.0.0.1:5020".to_string());
+        let api = ControlApi::new(devices).with_config_hash("00ff".to_string());
+        let response = api.route(&HttpRequest::new("GET", "/info", ""));
+        assert_eq!(response.status, 200);
+        let body = &response.body;
+        assert!(
+            body.starts_with(&format!(
+                r#"{{"version":"{}","config_hash":"00ff","devices":[{{"name":"plc","start_time_unix_ms":"#,
+                env!("CARGO_PKG_VERSION")
+            )),
+            "{body}"
+        );
+        assert!(body.contains(r#""uptime_ms":"#), "{body}");
+        assert!(
+            body.ends_with(r#""transports":["tcp 127.0.0.1:5020"]}]}"#),
+            "{body}"
+        );
+    }
+
+    #[test]
+    fn stats_and_metrics_report_exception_counts() {
+        let devices = devices();
+        {
+            let mut state = devices[0].state.write().unwrap();
+            state.record_exception(Exception::IllegalDataAddress);
+            state.record_exception(Exception::IllegalDataAddress);
+        }
+        let api = ControlApi::new(devices);
+
+        let stats = api.route(&HttpRequest::new("GET", "/stats", ""));
+        assert_eq!(
+            stats.body,
+            r#"{"exceptions":{"illegal_data_address":2},"update_intervals":[],"last_writes":[]}"#
+        );
+
+        let metrics = api.route(&HttpRequest::new("GET", "/metrics", ""));
+        assert_eq!(metrics.content_type, "text/plain; version=0.0.4");
+        assert!(metrics.body.contains(
+            "modsim_exception_responses_total{device=\"plc\",code=\"2\",exception=\"illegal_data_address\"} 2\n"
+        ));
+    }
+
+    #[test]
+    fn stats_report_the_last_client_write_of_each_address() {
+        let devices = devices();
+        let peer = "10.0.0.5:50122".parse().unwrap();
+        devices[0]
+            .state
+            .write()
+            .unwrap()
+            .claim_write(Space::HoldingRegisters, 4, 1, Some(peer), Some(3))
+            .unwrap();
+        let api = ControlApi::new(devices);
+
+        let body = api.route(&HttpRequest::new("GET", "/stats", "")).body;
+        assert!(
+            body.contains(r#""last_writes":[{"space":"holding_registers","address":4,"age_ms":"#),
+            "{body}"
+        );
+        assert!(
+            body.ends_with(r#","peer":"10.0.0.5:50122","connection_id":3}]}"#),
+            "{body}"
+        );
+    }
+
+    #[test]
+    fn openmetrics_carries_request_exemplars() {
+        let api = api();
+        api.devices[0]
+            .state
+            .write()
+            .unwrap()
+            .request_duration
+            .observe_with_exemplar(Exemplar {
+                labels: vec![
+                    ("request_id", "7".to_string()),
+                    ("function", "3".to_string()),
+                    ("address", "100".to_string()),
+                ],
+                value: 0.002,
+                timestamp: 1700000000.5,
+            });
+        let request = HttpRequest::new("GET", "/metrics", "");
+        let plain = api.route(&request);
+        assert_eq!(plain.content_type, "text/plain; version=0.0.4");
+        assert!(!plain.body.contains(" # {"));
+
+        let open = api.route(&request.with_header("Accept", "application/openmetrics-text"));
+        assert!(
+            open.content_type
+                .starts_with("application/openmetrics-text")
+        );
+        assert!(open.body.contains(
+            "modsim_request_duration_seconds_bucket{device=\"plc\",le=\"0.005\"} 1 \
+             # {request_id=\"7\",function=\"3\",address=\"100\"} 0.002 1700000000.5\n"
+        ));
+        assert!(
+            open.body
+                .contains("# TYPE modsim_exception_responses counter\n")
+        );
+        assert!(open.body.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn metrics_expose_opted_in_histograms() {
+        let reg = RegisterItemConfig {
+            address: 4,
+            histogram_buckets: Some(vec![10.0, 20.0]),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![], vec![reg]).unwrap();
+        let devices = vec![DeviceHandle {
+            name: "plc".to_string(),
+            state: Arc::new(RwLock::new(state)),
+            transports: Vec::new(),
+        }];
+        for value in [5, 15, 25] {
+            let mut state = devices[0].state.write().unwrap();
+            let item = state.input_registers.get_mut(&4).unwrap();
+            item.histogram.as_mut().unwrap().observe(value as f64);
+        }
+        let metrics = ControlApi::new(devices).route(&HttpRequest::new("GET", "/metrics", ""));
+        let labels = r#"device="plc",space="input_registers",address="4""#;
+        for line in [
+            format!("modsim_register_value_bucket{{{labels},le=\"10\"}} 1\n"),
+            format!("modsim_register_value_bucket{{{labels},le=\"20\"}} 2\n"),
+            format!("modsim_register_value_bucket{{{labels},le=\"+Inf\"}} 3\n"),
+            format!("modsim_register_value_sum{{{labels}}} 45\n"),
+            format!("modsim_register_value_count{{{labels}}} 3\n"),
+        ] {
+            assert!(metrics.body.contains(&line), "missing {line}");
+        }
+    }
+}
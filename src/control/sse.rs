@@ -0,0 +1,131 @@
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use super::json::Json;
+use super::{ControlApi, HttpRequest, HttpResponse, space_name};
+use crate::sim::{Change, Space};
+
+/// Quiet time after which a comment line is sent, so a closed client is
+/// noticed and proxies keep the stream open.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// Whether `request` asks for the change stream of a device.
+pub(super) fn is_events(request: &HttpRequest) -> bool {
+    request.method == "GET" && request.segments().last() == Some(&"events")
+}
+
+impl ControlApi {
+    /// Subscribes to the value changes of the device `GET /events` or
+    /// `GET /devices/{name}/events` addresses.
+    pub(super) fn subscribe_changes(
+        &self,
+        request: &HttpRequest,
+    ) -> Result<broadcast::Receiver<Change>, HttpResponse> {
+        let segments = request.segments();
+        match self.device(&segments)? {
+            (device, ["events"]) => Ok(device.state.write().unwrap().subscribe_changes()),
+            _ => Err(HttpResponse::not_found()),
+        }
+    }
+}
+
+/// Streams `changes` as Server-Sent Events until the client goes away.
+/// Each change is a `change` event whose data is a JSON object; a client
+/// that falls behind gets a comment saying how many it missed.
+pub(super) async fn stream<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut changes: broadcast::Receiver<Change>,
+) -> io::Result<()> {
+    writer
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+        )
+        .await?;
+    writer.flush().await?;
+    loop {
+        let message = match tokio::time::timeout(KEEPALIVE, changes.recv()).await {
+            Ok(Ok(change)) => format!("event: change\ndata: {}\n\n", change_json(&change)),
+            Ok(Err(RecvError::Lagged(missed))) => format!(": missed {missed} changes\n\n"),
+            Ok(Err(RecvError::Closed)) => return Ok(()),
+            Err(_) => ": keepalive\n\n".to_string(),
+        };
+        writer.write_all(message.as_bytes()).await?;
+        writer.flush().await?;
+    }
+}
+
+fn change_json(change: &Change) -> Json {
+    let value = match change.space {
+        Space::Coils | Space::DiscreteInputs => Json::from(change.value != 0.0),
+        Space::HoldingRegisters | Space::InputRegisters => Json::from(change.value),
+    };
+    Json::object([
+        ("space", Json::from(space_name(change.space))),
+        ("address", Json::from(change.address)),
+        ("value", value),
+        ("timestamp_ms", Json::from(change.timestamp_ms)),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use tokio::io::AsyncReadExt;
+
+    use super::*;
+    use crate::config::{BoolItemConfig, RegisterItemConfig};
+    use crate::control::DeviceHandle;
+    use crate::sim::SimState;
+
+    #[tokio::test]
+    async fn changes_stream_as_events() {
+        let coil = BoolItemConfig {
+            address: 1,
+            update_ms: Some(0),
+            dynamics: Some(toml::from_str("kind = \"script\"\nexpr = \"1\"").unwrap()),
+            ..Default::default()
+        };
+        let register = RegisterItemConfig {
+            address: 4,
+            update_ms: Some(0),
+            dynamics: Some(toml::from_str("kind = \"script\"\nexpr = \"42\"").unwrap()),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![coil], vec![], vec![register], vec![]).unwrap();
+        let api = ControlApi::new(vec![DeviceHandle {
+            name: "plc".to_string(),
+            state: Arc::new(RwLock::new(state)),
+            transports: Vec::new(),
+        }]);
+        let request = HttpRequest::new("GET", "/devices/plc/events", "");
+        assert!(is_events(&request));
+        let changes = api.subscribe_changes(&request).unwrap();
+        let unknown = HttpRequest::new("GET", "/devices/rtu/events", "");
+        assert_eq!(api.subscribe_changes(&unknown).unwrap_err().status, 404);
+
+        let (mut client, server) = tokio::io::duplex(4096);
+        tokio::spawn(stream(server, changes));
+        api.devices[0].state.write().unwrap().tick().unwrap();
+
+        let mut received = String::new();
+        let mut buffer = [0; 1024];
+        while received.matches("\n\n").count() < 2 {
+            let len = client.read(&mut buffer).await.unwrap();
+            received.push_str(std::str::from_utf8(&buffer[..len]).unwrap());
+        }
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n"));
+        assert!(
+            received.contains(r#"data: {"space":"coils","address":1,"value":true,"timestamp_ms":"#),
+            "{received}"
+        );
+        assert!(
+            received.contains(r#"data: {"space":"holding_registers","address":4,"value":42,"#),
+            "{received}"
+        );
+        assert_eq!(received.matches("event: change\n").count(), 2, "{received}");
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+
+use toml::{Table, Value};
+
+use super::{ControlApi, DeviceHandle, HttpResponse};
+use crate::config::{self, Initial};
+use crate::sim::encoding::current_initial;
+use crate::sim::{SimBoolItem, SimState};
+
+/// Name of the legacy `[device]`, which has no `name` key.
+const DEFAULT_DEVICE: &str = "default";
+
+impl ControlApi {
+    /// The config files as they are now on disk, with the `initial` of
+    /// every configured item set to its current value, as TOML that starts
+    /// the devices where they are with fresh dynamics.
+    pub(super) fn state_toml(&self) -> HttpResponse {
+        if self.config_paths.is_empty() {
+            return HttpResponse::error(404, "no config file to dump the state into");
+        }
+        let mut config = match config::merged_table(&self.config_paths) {
+            Ok(config) => config,
+            Err(err) => return HttpResponse::error(400, format!("{err:#}")),
+        };
+        set_initials(&mut config, &self.devices);
+        match toml::to_string_pretty(&config) {
+            Ok(body) => HttpResponse::toml(body),
+            Err(err) => HttpResponse::error(500, format!("failed to render the state: {err}")),
+        }
+    }
+}
+
+/// Sets the items of each device table of `config` to the values of the
+/// device of the same name.
+fn set_initials(config: &mut Table, devices: &[DeviceHandle]) {
+    for (key, value) in config.iter_mut() {
+        let tables: Vec<&mut Table> = match (key.as_str(), value) {
+            ("device", Value::Table(table)) => vec![table],
+            ("devices", Value::Array(tables)) => {
+                tables.iter_mut().filter_map(Value::as_table_mut).collect()
+            }
+            _ => continue,
+        };
+        for table in tables {
+            let name = table
+                .get("name")
+                .and_then(Value::as_str)
+                .unwrap_or(DEFAULT_DEVICE)
+                .to_string();
+            if let Some(device) = devices.iter().find(|device| device.name == name) {
+                set_device_initials(table, &device.state.read().unwrap());
+            }
+        }
+    }
+}
+
+/// Sets `initial` of every item listed in `device` to its value in `state`.
+/// Items the device created at runtime, such as by client writes, are not
+/// added.
+fn set_device_initials(device: &mut Table, state: &SimState) {
+    for (space, items) in device.iter_mut() {
+        let Value::Array(items) = items else {
+            continue;
+        };
+        for item in items.iter_mut().filter_map(Value::as_table_mut) {
+            let Some(address) = item
+                .get("address")
+                .and_then(Value::as_integer)
+                .and_then(|address| u16::try_from(address).ok())
+            else {
+                continue;
+            };
+            let current = match space.as_str() {
+                "coils" => bool_initial(&state.coils, address),
+                "discrete_inputs" => bool_initial(&state.discrete_inputs, address),
+                "holding_registers" => {
+                    current_initial(&state.holding_registers, address).map(register_initial)
+                }
+                "input_registers" => {
+                    current_initial(&state.input_registers, address).map(register_initial)
+                }
+                _ => None,
+            };
+            if let Some(current) = current {
+                item.insert("initial".to_string(), current);
+            }
+        }
+    }
+}
+
+fn bool_initial(map: &BTreeMap<u16, SimBoolItem>, address: u16) -> Option<Value> {
+    map.get(&address).map(|item| Value::Boolean(item.value))
+}
+
+/// `initial` as TOML; integers too wide for TOML become strings, which the
+/// config accepts too.
+fn register_initial(initial: Initial) -> Value {
+    match initial {
+        Initial::Int(value) => {
+            i64::try_from(value).map_or_else(|_| Value::String(value.to_string()), Value::Integer)
+        }
+        Initial::Float(value) => Value::Float(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use super::*;
+    use crate::control::HttpRequest;
+
+    const CONFIG: &str = r#"
+[device]
+[[device.coils]]
+address = 1
+
+[[device.holding_registers]]
+address = 0
+initial = 5
+dynamics = { kind = "static" }
+
+[[device.holding_registers]]
+address = 10
+encoding = "u64"
+initial = "18446744073709551615"
+
+[[device.input_registers]]
+address = 4
+encoding = "f32"
+"#;
+
+    #[test]
+    fn state_dumps_as_config_that_loads_back() {
+        let path = std::env::temp_dir().join(format!("modsim-state-{}.toml", std::process::id()));
+        std::fs::write(&path, CONFIG).unwrap();
+        let path = path.to_str().unwrap().to_string();
+        let config = config::load(&path).unwrap();
+        let device = config.resolved_devices().remove(0);
+        let mut state = SimState::new(
+            500,
+            false,
+            device.coils,
+            device.discrete_inputs,
+            device.holding_registers,
+            device.input_registers,
+        )
+        .unwrap();
+        state.write_single_coil(1, true);
+        state.write_single_register(0, 42);
+        state.write_single_register(13, 0xFFFE);
+        state.input_registers.get_mut(&4).unwrap().value = 0x41BB;
+        state.input_registers.get_mut(&5).unwrap().value = 0x3333;
+        let api = ControlApi::new(vec![DeviceHandle {
+            name: DEFAULT_DEVICE.to_string(),
+            state: Arc::new(RwLock::new(state)),
+            transports: Vec::new(),
+        }])
+        .with_config_paths(vec![path.clone()]);
+
+        let response = api.route(&HttpRequest::new("GET", "/state.toml", ""));
+        assert_eq!(response.status, 200, "{}", response.body);
+        std::fs::write(&path, &response.body).unwrap();
+        let config = config::load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let device = config.unwrap().resolved_devices().remove(0);
+        assert!(device.coils[0].initial);
+        assert_eq!(device.holding_registers[0].initial, Initial::Int(42));
+        assert!(device.holding_registers[0].dynamics.is_some());
+        assert_eq!(
+            device.holding_registers[1].initial,
+            Initial::Int(0xFFFF_FFFF_FFFF_FFFE)
+        );
+        assert_eq!(device.input_registers[0].initial, Initial::Float(23.4));
+    }
+
+    #[test]
+    fn state_needs_a_config_file() {
+        let api = ControlApi::new(Vec::new());
+        let response = api.route(&HttpRequest::new("GET", "/state.toml", ""));
+        assert_eq!(response.status, 404);
+    }
+}
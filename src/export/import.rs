@@ -0,0 +1,297 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{Context, Result, bail};
+
+use crate::config::{BoolItemConfig, Config, RegisterItemConfig};
+
+/// Name `points_csv` gives the `[device]` table.
+const DEFAULT_DEVICE: &str = "default";
+
+/// Renders a point list as a modsim config. The header names the columns, in
+/// any order: `space` and `address` are required, `device`, `name`,
+/// `encoding`, `initial`, `dynamics` and `unit` optional, as `points_csv`
+/// writes them. Points of a single `default` device go to `[device]`, others to
+/// `[[devices]]`; each item is checked as config on the way.
+pub fn points_config(csv: &str) -> Result<String> {
+    let mut lines = csv
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty());
+    let Some((_, header)) = lines.next() else {
+        bail!("no header row");
+    };
+    let header = split_row(header).context("header")?;
+    if !(header.iter().any(|column| column == "space")
+        && header.iter().any(|column| column == "address"))
+    {
+        bail!("the header must name the `space` and `address` columns");
+    }
+
+    let mut devices: Vec<(String, toml::Table)> = Vec::new();
+    let mut seen = BTreeSet::new();
+    for (index, line) in lines {
+        let line_number = index + 1;
+        let cells = split_row(line).with_context(|| format!("line {line_number}"))?;
+        if cells.len() != header.len() {
+            bail!(
+                "line {line_number}: {} cells for {} columns",
+                cells.len(),
+                header.len()
+            );
+        }
+        let row: BTreeMap<&str, &str> = header
+            .iter()
+            .map(String::as_str)
+            .zip(cells.iter().map(String::as_str))
+            .collect();
+        let device = match row.get("device") {
+            None | Some(&"") => DEFAULT_DEVICE,
+            Some(device) => device,
+        };
+        let space = row["space"];
+        let item = point_item(&row).with_context(|| format!("line {line_number}"))?;
+        let address = item["address"].as_integer().unwrap_or_default();
+        if !seen.insert((device.to_string(), space.to_string(), address)) {
+            bail!("line {line_number}: {space} {address} of device {device} is listed twice");
+        }
+        let index = match devices.iter().position(|(name, _)| name == device) {
+            Some(index) => index,
+            None => {
+                devices.push((device.to_string(), toml::Table::new()));
+                devices.len() - 1
+            }
+        };
+        let key = format!("{space}s");
+        devices[index]
+            .1
+            .entry(key)
+            .or_insert_with(|| toml::Value::Array(Vec::new()))
+            .as_array_mut()
+            .expect("item lists are arrays")
+            .push(toml::Value::Table(item));
+    }
+
+    let mut config = toml::Table::new();
+    match devices.as_slice() {
+        [(name, device)] if name == DEFAULT_DEVICE => {
+            config.insert("device".into(), device.clone().into());
+        }
+        _ => {
+            let devices = devices
+                .into_iter()
+                .map(|(name, mut device)| {
+                    device.insert("name".into(), name.into());
+                    device.into()
+                })
+                .collect();
+            config.insert("devices".into(), toml::Value::Array(devices));
+        }
+    }
+    let text = toml::to_string(&config).context("failed to render the config")?;
+    toml::from_str::<Config>(&text).context("generated config does not load")?;
+    Ok(text)
+}
+
+/// The item table of one row, checked against the item config of its space.
+fn point_item(row: &BTreeMap<&str, &str>) -> Result<toml::Table> {
+    let cell = |name| row.get(name).copied().unwrap_or_default();
+    let (space, address) = (cell("space"), cell("address"));
+    let mut item = toml::Table::new();
+    let address: u16 = address
+        .parse()
+        .with_context(|| format!("invalid address `{address}`"))?;
+    item.insert("address".into(), i64::from(address).into());
+    if !cell("name").is_empty() {
+        item.insert("name".into(), cell("name").into());
+    }
+    if !cell("initial").is_empty() {
+        item.insert("initial".into(), value(cell("initial")));
+    }
+    if !cell("dynamics").is_empty() {
+        item.insert("dynamics".into(), dynamics(cell("dynamics"))?.into());
+    }
+    let (encoding, unit) = (cell("encoding"), cell("unit"));
+    match space {
+        "coil" | "discrete_input" => {
+            if !matches!(encoding, "" | "bit") {
+                bail!("{space} encoding must be `bit`, got `{encoding}`");
+            }
+            if !unit.is_empty() {
+                bail!("{space} has no unit, got `{unit}`");
+            }
+            item.clone().try_into::<BoolItemConfig>()?;
+        }
+        "holding_register" | "input_register" => {
+            if !encoding.is_empty() {
+                item.insert("encoding".into(), encoding.into());
+            }
+            if !unit.is_empty() {
+                item.insert("unit".into(), unit.into());
+            }
+            item.clone().try_into::<RegisterItemConfig>()?;
+        }
+        _ => bail!(
+            "invalid space `{space}`: expected coil, discrete_input, holding_register or \
+             input_register"
+        ),
+    }
+    Ok(item)
+}
+
+/// Parses `kind key=value ...`, the form `points_csv` writes.
+fn dynamics(summary: &str) -> Result<toml::Table> {
+    let mut words = split_words(summary).into_iter();
+    let kind = words.next().context("dynamics without a kind")?;
+    let mut table = toml::Table::new();
+    table.insert("kind".into(), kind.into());
+    for word in words {
+        let (key, text) = word
+            .split_once('=')
+            .with_context(|| format!("invalid dynamics parameter `{word}`, expected key=value"))?;
+        table.insert(key.to_string(), value(text));
+    }
+    Ok(table)
+}
+
+/// A cell as a TOML value, or as a string when it is not one.
+fn value(text: &str) -> toml::Value {
+    toml::from_str::<toml::Table>(&format!("value = {text}"))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| text.into())
+}
+
+/// Splits on whitespace outside quoted strings, arrays and inline tables.
+fn split_words(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let (mut quote, mut depth, mut escaped) = (None, 0usize, false);
+    for c in text.chars() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(open) if c == open => quote = None,
+            Some(_) => {}
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '[' | '{' => depth += 1,
+                ']' | '}' => depth = depth.saturating_sub(1),
+                _ if c.is_whitespace() && depth == 0 => {
+                    if !word.is_empty() {
+                        words.push(std::mem::take(&mut word));
+                    }
+                    continue;
+                }
+                _ => {}
+            },
+        }
+        word.push(c);
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Splits one CSV row; quoted fields may hold commas and doubled quotes.
+fn split_row(line: &str) -> Result<Vec<String>> {
+    let mut cells = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        let mut cell = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        cell.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => cell.push(c),
+                    None => bail!("unterminated quoted field"),
+                }
+            }
+            if !matches!(chars.peek(), None | Some(',')) {
+                bail!("text after a quoted field");
+            }
+        } else {
+            while let Some(c) = chars.next_if(|&c| c != ',') {
+                cell.push(c);
+            }
+        }
+        cells.push(cell.trim().to_string());
+        if chars.next().is_none() {
+            return Ok(cells);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::points_csv;
+
+    #[test]
+    fn exported_points_import_back() {
+        let config: Config = toml::from_str(
+            r#"
+[device]
+
+[[device.coils]]
+address = 1
+name = "pump, main"
+initial = true
+
+[[device.input_registers]]
+address = 7
+initial = 12
+encoding = "f32"
+unit = "°C"
+dynamics = { kind = "script", expr = "10 + t * 2", min = 0.0 }
+"#,
+        )
+        .unwrap();
+        let csv = points_csv(&config);
+        let imported: Config = toml::from_str(&points_config(&csv).unwrap()).unwrap();
+        assert!(imported.device.is_some());
+        assert_eq!(points_csv(&imported), csv);
+    }
+
+    #[test]
+    fn devices_and_missing_columns() {
+        let text = points_config(
+            "space,address,device\nholding_register,3,pump\ncoil,0,valve\nholding_register,4,pump\n",
+        )
+        .unwrap();
+        let config: Config = toml::from_str(&text).unwrap();
+        let devices = config.resolved_devices();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].name, "pump");
+        assert_eq!(devices[0].holding_registers.len(), 2);
+        assert_eq!(devices[1].coils[0].address, 0);
+    }
+
+    #[test]
+    fn invalid_rows_are_rejected_with_their_line() {
+        for (csv, error) in [
+            ("address\n1\n", "`space` and `address`"),
+            ("space,address\nregister,1\n", "line 2: invalid space"),
+            ("space,address\ncoil,70000\n", "line 2: invalid address"),
+            (
+                "space,address\ncoil,1\ncoil,1\n",
+                "line 3: coil 1 of device default",
+            ),
+            (
+                "space,address,encoding\ncoil,1,f32\n",
+                "encoding must be `bit`",
+            ),
+            ("space,address,encoding\ninput_register,1,f128\n", "line 2"),
+            ("space,address,unit\ncoil,1,bar\n", "coil has no unit"),
+            ("space,address\n\"coil,1\n", "unterminated"),
+        ] {
+            let err = points_config(csv).unwrap_err();
+            assert!(format!("{err:#}").contains(error), "{err:#}");
+        }
+    }
+}
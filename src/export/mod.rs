@@ -0,0 +1,122 @@
+use crate::config::{BoolItemConfig, Config, DynamicsSpec, RegisterItemConfig};
+
+mod import;
+mod wireshark;
+
+pub use import::points_config;
+pub use wireshark::wireshark_lua;
+
+/// Column header of the point list; the order is part of the output format.
+pub const POINT_COLUMNS: &str = "device,space,address,name,encoding,initial,dynamics,unit";
+
+/// Renders every configured point as CSV, one row per item, in config order.
+pub fn points_csv(config: &Config) -> String {
+    let mut out = String::from(POINT_COLUMNS);
+    out.push('\n');
+    for device in config.resolved_devices() {
+        for (space, items) in [
+            ("coil", &device.coils),
+            ("discrete_input", &device.discrete_inputs),
+        ] {
+            for item in items {
+                push_row(&mut out, &device.name, space, &bool_fields(item));
+            }
+        }
+        for (space, items) in [
+            ("holding_register", &device.holding_registers),
+            ("input_register", &device.input_registers),
+        ] {
+            for item in items {
+                push_row(&mut out, &device.name, space, &register_fields(item));
+            }
+        }
+    }
+    out
+}
+
+fn bool_fields(item: &BoolItemConfig) -> [String; 6] {
+    [
+        item.address.to_string(),
+        item.name.clone().unwrap_or_default(),
+        "bit".to_string(),
+        item.initial.to_string(),
+        dynamics_summary(&item.dynamics),
+        String::new(),
+    ]
+}
+
+fn register_fields(item: &RegisterItemConfig) -> [String; 6] {
+    [
+        item.address.to_string(),
+        item.name.clone().unwrap_or_default(),
+        item.encoding.name().to_string(),
+        item.initial.to_string(),
+        dynamics_summary(&item.dynamics),
+        item.unit.clone().unwrap_or_default(),
+    ]
+}
+
+fn push_row(out: &mut String, device: &str, space: &str, fields: &[String; 6]) {
+    out.push_str(&csv_field(device));
+    out.push(',');
+    out.push_str(space);
+    for field in fields {
+        out.push(',');
+        out.push_str(&csv_field(field));
+    }
+    out.push('\n');
+}
+
+/// `kind key=value ...` with parameters in key order; empty without dynamics.
+fn dynamics_summary(spec: &Option<DynamicsSpec>) -> String {
+    let Some(spec) = spec else {
+        return String::new();
+    };
+    let mut keys: Vec<&String> = spec.params.keys().collect();
+    keys.sort();
+    let mut summary = spec.kind.clone();
+    for key in keys {
+        summary.push_str(&format!(" {key}={}", spec.params[key]));
+    }
+    summary
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_every_point() {
+        let config: Config = toml::from_str(
+            r#"
+[device]
+
+[[device.coils]]
+address = 1
+name = "pump, main"
+
+[[device.input_registers]]
+address = 7
+initial = 12
+encoding = "f32"
+unit = "degC"
+dynamics = { kind = "sine", period_ms = 1000, amplitude = 2.0, offset = 10.0 }
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            points_csv(&config),
+            "device,space,address,name,encoding,initial,dynamics,unit\n\
+             default,coil,1,\"pump, main\",bit,false,,\n\
+             default,input_register,7,,f32,12,sine amplitude=2.0 offset=10.0 period_ms=1000,degC\n"
+        );
+    }
+}
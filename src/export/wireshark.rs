@@ -0,0 +1,211 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+
+use crate::config::{Config, DeviceConfig};
+
+/// Modbus tables in the order the script lists them, with the function codes
+/// whose requests and responses address each.
+const SPACES: [(&str, &[u8]); 4] = [
+    ("coils", &[1, 5, 15]),
+    ("discrete_inputs", &[2]),
+    ("holding_registers", &[3, 6, 16, 23]),
+    ("input_registers", &[4]),
+];
+
+const DISSECTOR: &str = r#"
+local unit_id = Field.new("mbtcp.unit_id")
+local func_code = Field.new("modbus.func_code")
+local regnum = Field.new("modbus.regnum16")
+local regval = Field.new("modbus.regval_uint16")
+local bitnum = Field.new("modbus.bitnum")
+local bitval = Field.new("modbus.bitval")
+
+local modsim = Proto("modsim", "modsim register map")
+local point_field = ProtoField.string("modsim.point", "Point")
+modsim.fields = { point_field }
+
+function modsim.dissector(tvb, pinfo, tree)
+    local unit, func = unit_id(), func_code()
+    if not unit or not func or not points[unit.value] then return end
+    local space = spaces[func.value]
+    local names = space and points[unit.value][space]
+    if not names then return end
+    local numbers, values = { regnum() }, { regval() }
+    if space == "coils" or space == "discrete_inputs" then
+        numbers, values = { bitnum() }, { bitval() }
+    end
+    local subtree
+    for i, number in ipairs(numbers) do
+        local name = names[number.value]
+        if name then
+            subtree = subtree or tree:add(modsim)
+            local value = values[i] and tostring(values[i].value) or "?"
+            subtree:add(point_field, string.format("%d %s = %s", number.value, name, value))
+        end
+    end
+end
+
+register_postdissector(modsim)
+"#;
+
+/// Renders a Wireshark Lua post-dissector naming every configured point in
+/// Modbus/TCP traffic. Points are keyed by unit id and by the address
+/// clients send, address offsets included.
+pub fn wireshark_lua(config: &Config) -> String {
+    let mut units: BTreeMap<u8, [BTreeMap<u16, String>; 4]> = BTreeMap::new();
+    for device in config.resolved_devices() {
+        let [coils, discrete_inputs, holding_registers, input_registers] =
+            units.entry(device.unit_id).or_default();
+        add_points(
+            &device,
+            coils,
+            discrete_inputs,
+            holding_registers,
+            input_registers,
+        );
+    }
+
+    let mut out = String::from(
+        "-- modsim register map, generated by `modsim export-wireshark`.\n\
+         -- Place in the Wireshark personal plugins folder; packet details then\n\
+         -- show a \"modsim\" tree naming the configured points of each request\n\
+         -- and response.\n\
+         local points = {\n",
+    );
+    for (unit_id, spaces) in &units {
+        let _ = writeln!(out, "    [{unit_id}] = {{");
+        for ((space, _), names) in SPACES.iter().zip(spaces) {
+            if names.is_empty() {
+                continue;
+            }
+            let _ = writeln!(out, "        {space} = {{");
+            for (address, name) in names {
+                let _ = writeln!(out, "            [{address}] = {},", lua_string(name));
+            }
+            out.push_str("        },\n");
+        }
+        out.push_str("    },\n");
+    }
+    out.push_str("}\n\nlocal spaces = {\n");
+    for (space, codes) in SPACES {
+        for code in codes {
+            let _ = writeln!(out, "    [{code}] = \"{space}\",");
+        }
+    }
+    out.push_str("}\n");
+    out.push_str(DISSECTOR);
+    out
+}
+
+fn add_points(
+    device: &DeviceConfig,
+    coils: &mut BTreeMap<u16, String>,
+    discrete_inputs: &mut BTreeMap<u16, String>,
+    holding_registers: &mut BTreeMap<u16, String>,
+    input_registers: &mut BTreeMap<u16, String>,
+) {
+    let offsets = device.address_offsets;
+    for (names, items, offset) in [
+        (coils, &device.coils, offsets.coils),
+        (
+            discrete_inputs,
+            &device.discrete_inputs,
+            offsets.discrete_inputs,
+        ),
+    ] {
+        for item in items {
+            let name = label(item.name.as_deref(), item.address);
+            names.insert(item.address.wrapping_add(offset), name);
+        }
+    }
+    for (names, items, offset) in [
+        (
+            holding_registers,
+            &device.holding_registers,
+            offsets.holding_registers,
+        ),
+        (
+            input_registers,
+            &device.input_registers,
+            offsets.input_registers,
+        ),
+    ] {
+        for item in items {
+            let name = label(item.name.as_deref(), item.address);
+            let encoding = item.encoding.name();
+            let width = item.encoding.width();
+            let address = item.address.wrapping_add(offset);
+            if width == 1 {
+                names.insert(address, format!("{name} ({encoding})"));
+                continue;
+            }
+            for word in 0..width {
+                names.insert(
+                    address.wrapping_add(word),
+                    format!("{name} ({encoding}, word {} of {width})", word + 1),
+                );
+            }
+        }
+    }
+}
+
+fn label(name: Option<&str>, address: u16) -> String {
+    name.map_or_else(|| format!("address {address}"), str::to_string)
+}
+
+/// A double-quoted Lua string literal.
+fn lua_string(value: &str) -> String {
+    let mut out = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\{}", u32::from(c));
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_points_by_unit_and_client_address() {
+        let config: Config = toml::from_str(
+            r#"
+[[devices]]
+name = "boiler"
+unit_id = 3
+address_offsets = { holding_registers = 40001 }
+
+[[devices.coils]]
+address = 1
+name = "pump \"main\""
+
+[[devices.holding_registers]]
+address = 0
+name = "setpoint"
+
+[[devices.holding_registers]]
+address = 4
+encoding = "f32"
+"#,
+        )
+        .unwrap();
+        let lua = wireshark_lua(&config);
+        assert!(lua.contains(
+            "local points = {\n    [3] = {\n        coils = {\n            [1] = \"pump \\\"main\\\"\",\n        },\n        \
+             holding_registers = {\n            [40001] = \"setpoint (u16)\",\n            \
+             [40005] = \"address 4 (f32, word 1 of 2)\",\n            \
+             [40006] = \"address 4 (f32, word 2 of 2)\",\n        },\n    },\n}\n"
+        ), "{lua}");
+        assert!(lua.ends_with("register_postdissector(modsim)\n"));
+    }
+}
@@ -0,0 +1,520 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::config::{CollisionConfig, LatencyConfig, TruncationConfig, duration};
+
+/// Response delay shared by all connections of a listener, drawn from a
+/// normal distribution or from a measured profile.
+#[derive(Debug, Clone)]
+pub struct Latency {
+    distribution: Distribution,
+    functions: Functions,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+#[derive(Debug, Clone)]
+enum Distribution {
+    Normal { mean_ms: f64, stddev_ms: f64 },
+    Profile(Arc<Profile>),
+}
+
+impl Latency {
+    /// Builds the latency fault, loading its profile file if it has one.
+    pub fn new(config: &LatencyConfig, seed: Option<u64>) -> Result<Self> {
+        let functions = Functions::new(&config.function_codes)?;
+        let distribution = match &config.profile {
+            Some(_) if config.mean_ms != 0.0 || config.stddev_ms != 0.0 => {
+                bail!("set either `profile` or `mean_ms`/`stddev_ms`, not both")
+            }
+            Some(path) => Distribution::Profile(Arc::new(Profile::load(path)?)),
+            None if !(config.mean_ms.is_finite() && config.mean_ms >= 0.0) => {
+                bail!(
+                    "mean_ms must be a non-negative number, got {}",
+                    config.mean_ms
+                )
+            }
+            None if !(config.stddev_ms.is_finite() && config.stddev_ms >= 0.0) => {
+                bail!(
+                    "stddev_ms must be a non-negative number, got {}",
+                    config.stddev_ms
+                )
+            }
+            None => Distribution::Normal {
+                mean_ms: config.mean_ms,
+                stddev_ms: config.stddev_ms,
+            },
+        };
+        Ok(Self {
+            distribution,
+            functions,
+            rng: Arc::new(Mutex::new(seeded_rng(seed))),
+        })
+    }
+
+    /// Whether responses to `function` are delayed.
+    pub fn applies_to(&self, function: u8) -> bool {
+        self.functions.contains(function)
+    }
+
+    /// Draws the next delay; negative draws are clamped to zero.
+    pub fn sample(&self) -> Duration {
+        let mut rng = self.rng.lock().unwrap();
+        let ms = match &self.distribution {
+            Distribution::Normal { mean_ms, stddev_ms } => {
+                sample_normal(&mut *rng, *mean_ms, *stddev_ms).max(0.0)
+            }
+            Distribution::Profile(profile) => profile.sample(rng.r#gen()),
+        };
+        Duration::from_secs_f64(ms / 1000.0)
+    }
+}
+
+/// A cumulative distribution of response times, as (delay in milliseconds,
+/// cumulative weight) points with both columns non-decreasing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    points: Vec<(f64, f64)>,
+}
+
+impl Profile {
+    pub fn load(path: &str) -> Result<Self> {
+        let content =
+            std::fs::read_to_string(path).with_context(|| format!("failed to read {path}"))?;
+        Self::parse(&content).with_context(|| format!("invalid latency profile {path}"))
+    }
+
+    /// Parses one `<delay> <cumulative>` point per line; blank lines and
+    /// `#` comments are skipped. Delays may be duration strings. The
+    /// cumulative column is normalised by its last value, so fractions and
+    /// running counts from a histogram both work.
+    pub fn parse(content: &str) -> Result<Self> {
+        let mut points: Vec<(f64, f64)> = Vec::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let context = || format!("line {}", index + 1);
+            let mut fields = line.split_whitespace();
+            let (Some(delay), Some(weight), None) = (fields.next(), fields.next(), fields.next())
+            else {
+                bail!("{}: expected `<delay> <cumulative>`", context());
+            };
+            let delay = duration::parse_millis(delay)
+                .map_err(anyhow::Error::msg)
+                .with_context(context)?;
+            let weight: f64 = weight
+                .parse()
+                .with_context(|| format!("{}: invalid cumulative value `{weight}`", context()))?;
+            if delay < 0.0 || !weight.is_finite() || weight < 0.0 {
+                bail!(
+                    "{}: delay and cumulative value must not be negative",
+                    context()
+                );
+            }
+            if let Some((last_delay, last_weight)) = points.last()
+                && (delay < *last_delay || weight < *last_weight)
+            {
+                bail!(
+                    "{}: points must be sorted by delay and cumulative value",
+                    context()
+                );
+            }
+            points.push((delay, weight));
+        }
+        let Some(&(_, total)) = points.last() else {
+            bail!("no points");
+        };
+        if total == 0.0 {
+            bail!("the cumulative values are all zero");
+        }
+        for point in &mut points {
+            point.1 /= total;
+        }
+        Ok(Self { points })
+    }
+
+    /// The delay at quantile `u` in `[0, 1)`, interpolated linearly between
+    /// points. Draws below the first point return its delay.
+    pub fn sample(&self, u: f64) -> f64 {
+        let index = self.points.partition_point(|(_, weight)| *weight <= u);
+        let Some(&(upper_delay, upper_weight)) = self.points.get(index) else {
+            return self.points.last().map_or(0.0, |(delay, _)| *delay);
+        };
+        let Some(&(lower_delay, lower_weight)) = index.checked_sub(1).map(|i| &self.points[i])
+        else {
+            return upper_delay;
+        };
+        lower_delay
+            + (upper_delay - lower_delay) * (u - lower_weight) / (upper_weight - lower_weight)
+    }
+}
+
+/// Bus contention on a shared RTU line: with some probability a response
+/// frame is garbled the way it would be if another device had started
+/// talking over it.
+#[derive(Debug, Clone)]
+pub struct Collisions {
+    probability: f64,
+    functions: Functions,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+/// How a collision garbled a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collision {
+    /// The frame was cut off after some bytes, as by a device backing off.
+    Truncated,
+    /// The bytes from some point on were mixed with another device's.
+    Overlaid,
+}
+
+impl Collision {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Collision::Truncated => "truncated",
+            Collision::Overlaid => "overlaid",
+        }
+    }
+}
+
+impl Collisions {
+    pub fn new(config: &CollisionConfig, seed: Option<u64>) -> Result<Self> {
+        if !(0.0..=1.0).contains(&config.probability) {
+            bail!(
+                "probability must be between 0 and 1, got {}",
+                config.probability
+            );
+        }
+        Ok(Self {
+            probability: config.probability,
+            functions: Functions::new(&config.function_codes)?,
+            rng: Arc::new(Mutex::new(seeded_rng(seed))),
+        })
+    }
+
+    /// Whether responses to `function` can collide.
+    pub fn applies_to(&self, function: u8) -> bool {
+        self.functions.contains(function)
+    }
+
+    /// Draws whether `frame` collides and, if it does, returns how along
+    /// with the bytes that reach the bus. Every garbled frame differs from
+    /// the original, so its CRC no longer matches.
+    pub fn garble(&self, frame: &[u8]) -> Option<(Collision, Vec<u8>)> {
+        let mut rng = self.rng.lock().unwrap();
+        if frame.is_empty() || !rng.gen_bool(self.probability) {
+            return None;
+        }
+        if frame.len() > 1 && rng.gen_bool(0.5) {
+            let kept = rng.gen_range(1..frame.len());
+            return Some((Collision::Truncated, frame[..kept].to_vec()));
+        }
+        let start = rng.gen_range(0..frame.len());
+        let mut garbled = frame.to_vec();
+        for byte in &mut garbled[start..] {
+            *byte ^= rng.gen_range(1..=u8::MAX);
+        }
+        Some((Collision::Overlaid, garbled))
+    }
+}
+
+/// Responses cut off partway: with some probability only the first bytes
+/// of a frame are sent.
+#[derive(Debug, Clone)]
+pub struct Truncation {
+    probability: f64,
+    bytes: Option<usize>,
+    functions: Functions,
+    rng: Arc<Mutex<StdRng>>,
+}
+
+impl Truncation {
+    pub fn new(config: &TruncationConfig, seed: Option<u64>) -> Result<Self> {
+        if !(0.0..=1.0).contains(&config.probability) {
+            bail!(
+                "probability must be between 0 and 1, got {}",
+                config.probability
+            );
+        }
+        if config.bytes == Some(0) {
+            bail!("bytes must be at least 1");
+        }
+        Ok(Self {
+            probability: config.probability,
+            bytes: config.bytes,
+            functions: Functions::new(&config.function_codes)?,
+            rng: Arc::new(Mutex::new(seeded_rng(seed))),
+        })
+    }
+
+    /// Whether responses to `function` can be cut off.
+    pub fn applies_to(&self, function: u8) -> bool {
+        self.functions.contains(function)
+    }
+
+    /// Draws whether a frame of `len` bytes is cut off and, if it is, how
+    /// many of its bytes are sent. At least one byte is always cut.
+    pub fn cut(&self, len: usize) -> Option<usize> {
+        let mut rng = self.rng.lock().unwrap();
+        if len < 2 || !rng.gen_bool(self.probability) {
+            return None;
+        }
+        Some(match self.bytes {
+            Some(bytes) => bytes.min(len - 1),
+            None => rng.gen_range(1..len),
+        })
+    }
+}
+
+/// The function codes a fault applies to; all when unset.
+#[derive(Debug, Clone)]
+struct Functions(Option<Vec<u8>>);
+
+impl Functions {
+    fn new(codes: &Option<Vec<u8>>) -> Result<Self> {
+        if let Some(code) = codes
+            .iter()
+            .flatten()
+            .find(|code| !(1..=0x7F).contains(*code))
+        {
+            bail!("function code {code} is not between 1 and 127");
+        }
+        Ok(Self(codes.clone()))
+    }
+
+    /// Whether `function` is one of the codes; an exception response, with
+    /// the high bit set, counts as a response to its request's function.
+    fn contains(&self, function: u8) -> bool {
+        let function = function & 0x7F;
+        self.0
+            .as_ref()
+            .is_none_or(|codes| codes.contains(&function))
+    }
+}
+
+pub fn seeded_rng(seed: Option<u64>) -> StdRng {
+    match seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+fn sample_normal(rng: &mut impl Rng, mean: f64, stddev: f64) -> f64 {
+    // Box-Muller transform; u1 is kept away from zero so ln() stays finite.
+    let u1: f64 = rng.gen_range(f64::EPSILON..1.0);
+    let u2: f64 = rng.r#gen();
+    let z = (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos();
+    mean + stddev * z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_is_reproducible_with_seed() {
+        let cfg = LatencyConfig {
+            mean_ms: 20.0,
+            stddev_ms: 5.0,
+            profile: None,
+            function_codes: None,
+        };
+        let a = Latency::new(&cfg, Some(42)).unwrap();
+        let b = Latency::new(&cfg, Some(42)).unwrap();
+        for _ in 0..10 {
+            assert_eq!(a.sample(), b.sample());
+        }
+    }
+
+    #[test]
+    fn latency_clamps_negative_draws() {
+        let cfg = LatencyConfig {
+            mean_ms: 0.0,
+            stddev_ms: 1000.0,
+            profile: None,
+            function_codes: None,
+        };
+        let latency = Latency::new(&cfg, Some(7)).unwrap();
+        let samples: Vec<Duration> = (0..100).map(|_| latency.sample()).collect();
+        assert!(samples.contains(&Duration::ZERO), "{samples:?}");
+    }
+
+    #[test]
+    fn latency_parameters_must_be_non_negative_numbers() {
+        for (mean_ms, stddev_ms, field) in [
+            (f64::INFINITY, 0.0, "mean_ms"),
+            (-1.0, 0.0, "mean_ms"),
+            (10.0, f64::NAN, "stddev_ms"),
+            (10.0, -1.0, "stddev_ms"),
+        ] {
+            let cfg = LatencyConfig {
+                mean_ms,
+                stddev_ms,
+                profile: None,
+                function_codes: None,
+            };
+            let err = Latency::new(&cfg, None).unwrap_err();
+            assert!(err.to_string().contains(field), "{err}");
+        }
+    }
+
+    #[test]
+    fn collisions_garble_frames_reproducibly() {
+        let frame = [1, 3, 2, 0, 42, 0xB8, 0x44];
+        let never = CollisionConfig {
+            probability: 0.0,
+            function_codes: None,
+        };
+        let never = Collisions::new(&never, Some(1)).unwrap();
+        assert_eq!(never.garble(&frame), None);
+
+        let always = CollisionConfig {
+            probability: 1.0,
+            function_codes: None,
+        };
+        let a = Collisions::new(&always, Some(9)).unwrap();
+        let b = Collisions::new(&always, Some(9)).unwrap();
+        let mut seen = Vec::new();
+        for _ in 0..20 {
+            let (collision, garbled) = a.garble(&frame).unwrap();
+            assert_eq!(b.garble(&frame), Some((collision, garbled.clone())));
+            assert_ne!(garbled, frame);
+            match collision {
+                Collision::Truncated => assert!(frame.starts_with(&garbled)),
+                Collision::Overlaid => assert_eq!(garbled.len(), frame.len()),
+            }
+            seen.push(collision);
+        }
+        assert!(seen.contains(&Collision::Truncated) && seen.contains(&Collision::Overlaid));
+
+        let invalid = CollisionConfig {
+            probability: 1.5,
+            ..always
+        };
+        let err = Collisions::new(&invalid, None).unwrap_err();
+        assert!(err.to_string().contains("between 0 and 1"), "{err}");
+    }
+
+    #[test]
+    fn truncation_keeps_a_fixed_or_random_prefix() {
+        let fixed = TruncationConfig {
+            probability: 1.0,
+            bytes: Some(5),
+            function_codes: None,
+        };
+        let fixed = Truncation::new(&fixed, Some(1)).unwrap();
+        assert_eq!(fixed.cut(9), Some(5));
+        assert_eq!(fixed.cut(4), Some(3));
+        assert_eq!(fixed.cut(1), None);
+
+        let random = TruncationConfig {
+            probability: 1.0,
+            bytes: None,
+            function_codes: None,
+        };
+        let a = Truncation::new(&random, Some(4)).unwrap();
+        let b = Truncation::new(&random, Some(4)).unwrap();
+        for _ in 0..20 {
+            let kept = a.cut(9).unwrap();
+            assert_eq!(b.cut(9), Some(kept));
+            assert!((1..9).contains(&kept), "{kept}");
+        }
+
+        let never = TruncationConfig {
+            probability: 0.0,
+            bytes: None,
+            function_codes: None,
+        };
+        assert_eq!(Truncation::new(&never, None).unwrap().cut(9), None);
+        let zero = TruncationConfig {
+            probability: 0.5,
+            bytes: Some(0),
+            function_codes: None,
+        };
+        let err = Truncation::new(&zero, None).unwrap_err();
+        assert!(err.to_string().contains("at least 1"), "{err}");
+    }
+
+    #[test]
+    fn faults_apply_to_their_function_codes() {
+        let config = TruncationConfig {
+            probability: 1.0,
+            bytes: None,
+            function_codes: Some(vec![4]),
+        };
+        let truncation = Truncation::new(&config, None).unwrap();
+        assert!(truncation.applies_to(4));
+        assert!(truncation.applies_to(0x84));
+        assert!(!truncation.applies_to(3));
+
+        let config = CollisionConfig {
+            probability: 1.0,
+            function_codes: None,
+        };
+        assert!(Collisions::new(&config, None).unwrap().applies_to(3));
+        let config = CollisionConfig {
+            function_codes: Some(vec![3, 0x83]),
+            ..config
+        };
+        let err = Collisions::new(&config, None).unwrap_err();
+        assert!(err.to_string().contains("function code 131"), "{err}");
+    }
+
+    #[test]
+    fn profiles_interpolate_between_points() {
+        let profile = Profile::parse("# capture\n2 0.5\n\n4 0.75 # tail\n1s 1\n").unwrap();
+        assert_eq!(profile.sample(0.1), 2.0);
+        assert_eq!(profile.sample(0.5), 2.0);
+        assert_eq!(profile.sample(0.625), 3.0);
+        assert_eq!(profile.sample(0.875), 502.0);
+        assert_eq!(profile.sample(1.0), 1000.0);
+        // Running counts are normalised.
+        assert_eq!(Profile::parse("2 50\n4 100").unwrap().sample(0.75), 3.0);
+    }
+
+    #[test]
+    fn invalid_profiles_are_rejected() {
+        for (text, message) in [
+            ("", "no points"),
+            ("2", "line 1: expected"),
+            ("2 0.5\n1 1", "line 2: points must be sorted"),
+            ("2 0.5\n3 0.4", "line 2: points must be sorted"),
+            ("-1 1", "must not be negative"),
+            ("2 0", "all zero"),
+        ] {
+            let err = Profile::parse(text).unwrap_err();
+            assert!(format!("{err:#}").contains(message), "{text}: {err:#}");
+        }
+    }
+
+    #[test]
+    fn profiles_are_loaded_and_sampled_reproducibly() {
+        let path = std::env::temp_dir().join(format!("modsim-profile-{}.cdf", std::process::id()));
+        std::fs::write(&path, "10 0\n20 1\n").unwrap();
+        let cfg = LatencyConfig {
+            mean_ms: 0.0,
+            stddev_ms: 0.0,
+            profile: Some(path.to_string_lossy().into_owned()),
+            function_codes: None,
+        };
+        let a = Latency::new(&cfg, Some(42)).unwrap();
+        let b = Latency::new(&cfg, Some(42)).unwrap();
+        for _ in 0..10 {
+            let sample = a.sample();
+            assert_eq!(sample, b.sample());
+            assert!((10..=20).contains(&sample.as_millis()), "{sample:?}");
+        }
+        let both = LatencyConfig {
+            mean_ms: 5.0,
+            ..cfg
+        };
+        let err = Latency::new(&both, None).unwrap_err();
+        assert!(err.to_string().contains("not both"), "{err}");
+        std::fs::remove_file(path).unwrap();
+    }
+}
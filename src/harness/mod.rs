@@ -0,0 +1,158 @@
+use std::net::{SocketAddr, TcpListener};
+use std::sync::{Arc, RwLock, RwLockWriteGuard};
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tokio_modbus::client::{Context as ModbusContext, tcp};
+use tokio_modbus::prelude::Reader;
+
+use crate::config::TcpConfig;
+use crate::sim::SimState;
+use crate::transport::tcp::{ModbusService, UnitRouter, start_tcp};
+
+/// A simulator serving Modbus TCP on a loopback port, with a client
+/// connected to it, for integration tests.
+pub struct TestHarness {
+    pub client: ModbusContext,
+    /// Shared with the server; changes show in the next response.
+    pub state: Arc<RwLock<SimState>>,
+    pub addr: SocketAddr,
+}
+
+impl TestHarness {
+    /// Serves `state` on an ephemeral loopback port and connects a client.
+    pub async fn start(state: SimState) -> Result<Self> {
+        Self::start_with(state, TcpConfig::new(String::new()), UnitRouter::from).await
+    }
+
+    /// Like [`TestHarness::start`], with the settings of `config` but its
+    /// `bind`, serving what `route` makes of the service of `state`.
+    pub async fn start_with(
+        state: SimState,
+        config: TcpConfig,
+        route: impl FnOnce(ModbusService) -> UnitRouter,
+    ) -> Result<Self> {
+        // The port is free once the probe closes; another process could
+        // still take it before the server binds.
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .and_then(|probe| probe.local_addr())
+            .context("failed to reserve a port")?;
+        let state = Arc::new(RwLock::new(state));
+        let config = TcpConfig {
+            bind: addr.to_string(),
+            ..config
+        };
+        let router = route(ModbusService::new(Arc::clone(&state)));
+        start_tcp(&config, router, Duration::ZERO).await?;
+        let client = tcp::connect(addr)
+            .await
+            .with_context(|| format!("failed to connect to {addr}"))?;
+        Ok(Self {
+            client,
+            state,
+            addr,
+        })
+    }
+
+    /// The simulator state, to change it directly.
+    pub fn state(&self) -> RwLockWriteGuard<'_, SimState> {
+        self.state.write().unwrap()
+    }
+
+    /// Moves the simulation clock forward by `by`, then runs one tick, so
+    /// the dynamics show their values at the later time.
+    pub fn advance(&self, by: Duration) -> Result<()> {
+        let mut state = self.state();
+        state.advance_clock(by);
+        state.tick()
+    }
+
+    /// Reads holding registers from `address` on over Modbus and panics
+    /// unless they are `expected`.
+    pub async fn assert_holding_registers(&mut self, address: u16, expected: &[u16]) {
+        let values = self
+            .client
+            .read_holding_registers(address, expected.len() as u16)
+            .await
+            .unwrap_or_else(|err| panic!("reading holding registers {address}: {err}"));
+        assert_eq!(values, expected, "holding registers from {address}");
+    }
+
+    /// Reads input registers from `address` on over Modbus and panics
+    /// unless they are `expected`.
+    pub async fn assert_input_registers(&mut self, address: u16, expected: &[u16]) {
+        let values = self
+            .client
+            .read_input_registers(address, expected.len() as u16)
+            .await
+            .unwrap_or_else(|err| panic!("reading input registers {address}: {err}"));
+        assert_eq!(values, expected, "input registers from {address}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Encoding, Initial, RegisterItemConfig};
+    use crate::sim::spawn_simulator;
+
+    #[tokio::test]
+    async fn advancing_the_clock_moves_the_dynamics() {
+        let ramp = RegisterItemConfig {
+            address: 3,
+            update_ms: Some(60_000),
+            dynamics: Some(toml::from_str("kind = \"script\"\nexpr = \"floor(t)\"").unwrap()),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![], vec![ramp]).unwrap();
+        let mut harness = TestHarness::start(state).await.unwrap();
+        harness.assert_input_registers(3, &[0]).await;
+
+        harness.advance(Duration::from_secs(90)).unwrap();
+        harness.assert_input_registers(3, &[90]).await;
+
+        harness.state().write_single_register(0, 42);
+        harness.assert_holding_registers(0, &[42]).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn wide_values_are_never_read_half_updated() {
+        // Alternates every millisecond between two floats that differ in
+        // both words, so a torn read decodes to neither.
+        let expr = "if(floor(t * 1000) % 2 < 0.5, 0.1, -12345.678)";
+        let dynamics = format!("kind = \"script\"\nexpr = \"{expr}\"");
+        let float = RegisterItemConfig {
+            address: 0,
+            encoding: Encoding::F32,
+            initial: Initial::Float(0.1),
+            update_ms: Some(0),
+            dynamics: Some(toml::from_str(&dynamics).unwrap()),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![], vec![float]).unwrap();
+        let harness = TestHarness::start(state).await.unwrap();
+        let simulator = tokio::spawn(spawn_simulator(Arc::clone(&harness.state)));
+
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let addr = harness.addr;
+                tokio::spawn(async move {
+                    let mut client = tcp::connect(addr).await.unwrap();
+                    for _ in 0..500 {
+                        let words = client.read_input_registers(0, 2).await.unwrap();
+                        let bits = (u32::from(words[0]) << 16) | u32::from(words[1]);
+                        let value = f32::from_bits(bits);
+                        assert!(
+                            value == 0.1 || value == -12345.678,
+                            "read {words:04x?}, which decodes to {value}"
+                        );
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.await.unwrap();
+        }
+        simulator.abort();
+    }
+}
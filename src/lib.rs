@@ -0,0 +1,16 @@
+//! Modbus simulator library; the `modsim` binary wires these modules together.
+
+pub mod config;
+pub mod console;
+pub mod control;
+pub mod export;
+pub mod fault;
+pub mod harness;
+pub mod mirror;
+pub mod persist;
+pub mod power;
+pub mod replay;
+pub mod sim;
+#[cfg(feature = "standby")]
+pub mod standby;
+pub mod transport;
@@ -1,92 +1,695 @@
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::num::NonZeroUsize;
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-use clap::Parser;
-use tracing::{error, info};
+use clap::{Parser, Subcommand};
+use tracing::{error, info, warn};
 
-mod config;
-mod sim;
-mod transport;
-
-use config::Config;
-use sim::{SimState, spawn_simulator};
-use transport::rtu::start_rtu;
-use transport::tcp::start_tcp;
+use modsim::control::{ControlApi, DeviceHandle, start_control};
+use modsim::fault::{Collisions, Latency, Truncation};
+use modsim::mirror::spawn_mirror;
+use modsim::power::{PowerSwitch, spawn_power_cycle};
+use modsim::sim::{
+    BUILTIN_KINDS, Playback, SELF_TEST_PERIODS, SimState, Space, describe_kinds, preview_csv,
+    preview_plot, self_test_tap, spawn_simulator,
+};
+#[cfg(feature = "standby")]
+use modsim::standby;
+use modsim::transport::audit::AuditLog;
+use modsim::transport::line::start_line;
+use modsim::transport::rtu::{BusFaults, start_rtu};
+use modsim::transport::tcp::{ModbusService, UnitRouter, start_tcp};
+use modsim::{config, console, export, persist, replay};
 
 #[derive(Parser, Debug)]
 #[command(name = "modsim", version, about = "Modbus simulator")]
 struct Args {
-    #[arg(short, long, default_value = "config.toml")]
-    config: String,
+    /// Config file; repeat to merge several, later files overriding earlier ones.
+    #[arg(short, long, default_value = "config.toml", global = true)]
+    config: Vec<String>,
+    /// Exit with an error when a register leaves the `min`/`max` of its dynamics.
+    #[arg(long)]
+    assert_bounds: bool,
+    /// Run the periodic dynamics of every device through a few periods on a
+    /// fast clock, print the results in TAP and exit; fails when an item is
+    /// stuck or out of bounds.
+    #[arg(long)]
+    self_test: bool,
+    /// Worker threads of the async runtime; defaults to one per CPU core.
+    #[arg(long, global = true)]
+    threads: Option<NonZeroUsize>,
+    /// Benchmark mode: no logging, no `[fault]` injection, no audit or
+    /// request metrics, and reads served under the shared lock.
+    #[arg(long)]
+    fast: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[tokio::main(flavor = "multi_thread")]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::from_default_env().add_directive("info".parse()?),
-        )
-        .init();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Print every configured point as CSV and exit.
+    ExportPoints,
+    /// Print a Wireshark Lua plugin naming every configured point and exit.
+    ExportWireshark,
+    /// Print a config generated from a CSV point list and exit.
+    ImportPoints {
+        /// Point list, with the columns `export-points` writes.
+        points: String,
+    },
+    /// Interactive console reading and writing a running simulator over Modbus/TCP.
+    Console {
+        /// Device to talk to; defaults to the first configured device.
+        #[arg(long)]
+        device: Option<String>,
+        /// Address to connect to; defaults to the device's `tcp.bind`.
+        #[arg(long)]
+        connect: Option<String>,
+    },
+    /// Print the state of a running simulator as a config and exit.
+    DumpState {
+        /// Control API to ask; defaults to the config's `control.bind`.
+        #[arg(long)]
+        connect: Option<String>,
+    },
+    /// Print the values one item takes on the simulation clock and exit.
+    Preview {
+        /// Device of the item; defaults to the first configured device.
+        #[arg(long)]
+        device: Option<String>,
+        #[arg(long, value_enum)]
+        space: PreviewSpace,
+        #[arg(long)]
+        address: u16,
+        /// How far to run the clock, such as `60s` or `1h`.
+        #[arg(long, default_value = "60s", value_parser = parse_duration)]
+        duration: Duration,
+        /// Time between samples.
+        #[arg(long, default_value = "1s", value_parser = parse_duration)]
+        step: Duration,
+        /// Draw the values as bars instead of printing CSV.
+        #[arg(long)]
+        plot: bool,
+    },
+    /// Print the dynamics kinds and exit.
+    Dynamics {
+        /// Also print each kind's description and parameters.
+        #[arg(long)]
+        list: bool,
+    },
+    /// Replay a recorded request sequence against a device and diff the responses.
+    Replay {
+        /// Recording to replay.
+        recording: String,
+        /// Address of the device under test.
+        #[arg(long)]
+        connect: SocketAddr,
+        /// Unit id to address; defaults to the recording's `unit_id`.
+        #[arg(long)]
+        unit_id: Option<u8>,
+    },
+}
+
+/// Space of the item `preview` samples.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum PreviewSpace {
+    Coil,
+    DiscreteInput,
+    Holding,
+    Input,
+}
+
+impl From<PreviewSpace> for Space {
+    fn from(space: PreviewSpace) -> Self {
+        match space {
+            PreviewSpace::Coil => Space::Coils,
+            PreviewSpace::DiscreteInput => Space::DiscreteInputs,
+            PreviewSpace::Holding => Space::HoldingRegisters,
+            PreviewSpace::Input => Space::InputRegisters,
+        }
+    }
+}
+
+/// A duration argument, such as `500ms` or `1m30s`; bare numbers are
+/// milliseconds, as in the config.
+fn parse_duration(text: &str) -> Result<Duration, String> {
+    let ms = config::duration::parse_millis(text)?;
+    if ms < 0.0 {
+        return Err(format!("duration `{text}` is negative"));
+    }
+    Ok(Duration::from_secs_f64(ms / 1000.0))
+}
 
+fn main() -> Result<()> {
     let args = Args::parse();
-    let config = load_config(&args.config)?;
+    // `--fast` silences every log line, whatever RUST_LOG says.
+    let filter = if args.fast {
+        tracing_subscriber::EnvFilter::new("off")
+    } else {
+        tracing_subscriber::EnvFilter::from_default_env().add_directive("info".parse()?)
+    };
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(threads) = args.threads {
+        runtime.worker_threads(threads.get());
+    }
+    runtime
+        .build()
+        .context("failed to start the async runtime")?
+        .block_on(run(args))
+}
 
+/// A TCP listener with the truncation fault and the services of the devices
+/// sharing it by unit id; the first device's settings apply.
+type Listener = (
+    config::TcpConfig,
+    Option<Truncation>,
+    BTreeMap<u8, ModbusService>,
+);
+
+async fn run(args: Args) -> Result<()> {
+    if let Some(Command::Replay {
+        recording,
+        connect,
+        unit_id,
+    }) = &args.command
+    {
+        return run_replay(recording, *connect, *unit_id).await;
+    }
+    if let Some(Command::Dynamics { list }) = args.command {
+        print_dynamics(list);
+        return Ok(());
+    }
+    if let Some(Command::ImportPoints { points }) = &args.command {
+        let csv =
+            std::fs::read_to_string(points).with_context(|| format!("failed to read {points}"))?;
+        print!(
+            "{}",
+            export::points_config(&csv).with_context(|| format!("invalid point list {points}"))?
+        );
+        return Ok(());
+    }
+    let loading = Instant::now();
+    let config = config::load_all(&args.config)?;
+    let config_hash = config::fingerprint(&args.config)?;
+    let items = config.item_count();
+    let large = items >= config::LARGE_CONFIG_ITEMS;
+    if large {
+        info!(
+            items,
+            elapsed_ms = loading.elapsed().as_millis() as u64,
+            "large config parsed"
+        );
+    }
+
+    match args.command {
+        Some(Command::ExportPoints) => {
+            print!("{}", export::points_csv(&config));
+            return Ok(());
+        }
+        Some(Command::ExportWireshark) => {
+            print!("{}", export::wireshark_lua(&config));
+            return Ok(());
+        }
+        Some(Command::Console { device, connect }) => {
+            return run_console(&config, device.as_deref(), connect.as_deref()).await;
+        }
+        Some(Command::DumpState { connect }) => {
+            return run_dump_state(&config, connect.as_deref()).await;
+        }
+        Some(Command::Preview {
+            device,
+            space,
+            address,
+            duration,
+            step,
+            plot,
+        }) => {
+            let device = device.as_deref();
+            return run_preview(&config, device, space.into(), address, duration, step, plot);
+        }
+        Some(Command::Replay { .. } | Command::Dynamics { .. } | Command::ImportPoints { .. })
+        | None => {}
+    }
+
+    let fast = args.fast;
     let log_value_updates = config
         .logging
         .as_ref()
         .map(|logging| logging.log_value_updates)
-        .unwrap_or(false);
-    let global_update_ms = config
-        .global
+        .unwrap_or(false)
+        && !fast;
+    let log_transactions = config
+        .logging
         .as_ref()
-        .map(|global| global.update_ms)
-        .unwrap_or(500);
-
-    let unit_id = config.device.unit_id;
-    let state = Arc::new(RwLock::new(SimState::new(
-        global_update_ms,
-        log_value_updates,
-        config.device.coils,
-        config.device.discrete_inputs,
-        config.device.holding_registers,
-        config.device.input_registers,
-    )));
-
-    let simulator_state = Arc::clone(&state);
-    let simulator_handle = tokio::spawn(async move { spawn_simulator(simulator_state).await });
+        .is_some_and(|logging| logging.log_transactions)
+        && !fast;
+    let audit = match &config.logging {
+        Some(logging) if logging.audit_writes && !fast => {
+            Some(AuditLog::open(logging.audit_file.as_deref())?)
+        }
+        _ => None,
+    };
+    let global = config.global.clone().unwrap_or_default();
+    let seed = global.seed;
+    let startup_delay = Duration::from_millis(global.startup_delay_ms);
 
+    let snapshot = match &config.persistence {
+        Some(persistence) => persist::load(&persistence.path)?,
+        None => persist::Snapshot::default(),
+    };
+
+    let mut devices = config.resolved_devices();
+    if fast {
+        for device in &mut devices {
+            device.fault = None;
+        }
+    }
+    if devices.is_empty() {
+        error!("no devices configured: add a [device] or [[devices]] section");
+        return Ok(());
+    }
+
+    let mut simulators = Vec::new();
+    let mut mirrors = Vec::new();
     let mut tasks = Vec::new();
-    if let Some(tcp) = config.tcp {
-        let state = Arc::clone(&state);
-        tasks.push(tokio::spawn(
-            async move { start_tcp(&tcp.bind, state).await },
-        ));
+    let mut handles = Vec::new();
+    let mut self_tests = Vec::new();
+    let power = PowerSwitch::default();
+    // Devices sharing a TCP bind are served by one listener, routed by unit id.
+    let mut listeners: BTreeMap<String, Listener> = BTreeMap::new();
+    for device in devices {
+        let name = device.name.clone();
+        let unit_id = device.unit_id;
+        if large {
+            info!(device = %name, items = device.item_count(), "building device");
+        }
+        let mut state = SimState::new(
+            global.update_ms,
+            log_value_updates,
+            device.coils,
+            device.discrete_inputs,
+            device.holding_registers,
+            device.input_registers,
+        )
+        .with_context(|| format!("device {name}"))?
+        .with_tick_floor(global.min_tick_ms)
+        .with_warmup(global.warmup_ms)
+        .with_clock_drift(global.clock_drift_ppm)
+        .with_assert_bounds(args.assert_bounds)
+        .with_seed(seed)
+        .with_noise_floor(global.noise_floor, seed)
+        .with_random_phase(global.randomize_phase)
+        .with_tick_overrun_policy(global.tick_overrun_policy)
+        .with_tick_order(global.tick_order, global.tick_evaluation)
+        // A serial line has no connections to tell an idle device by.
+        .with_idle(global.idle.filter(|_| device.rtu.is_none()))
+        .with_dynamic_create(device.allow_dynamic_create)
+        .with_mirror_registers(device.mirror_registers)
+        .with_block_bounded_reads(device.block_bounded_reads)
+        .with_write_conflict(device.write_conflict, device.busy_window_ms)
+        .with_fifos(&device.fifos)
+        .with_alarms(&device.alarms)
+        .and_then(|state| state.with_ringbuffers(&device.ringbuffers))
+        .and_then(|state| state.with_coil_register_map(&device.coil_register_map))
+        .and_then(|state| state.with_write_locks(&device.write_locks))
+        .and_then(|state| state.with_outage(device.outage.as_ref()))
+        .and_then(|state| state.with_crosstalk(device.crosstalk.as_ref()))
+        .with_context(|| format!("device {name}"))?;
+        if args.self_test {
+            let results = state
+                .self_test(SELF_TEST_PERIODS)
+                .with_context(|| format!("device {name}"))?;
+            self_tests.push((name, results));
+            continue;
+        }
+        if let Some(replay) = &device.replay {
+            let playback = Playback::load(replay).with_context(|| format!("device {name}"))?;
+            state = state.with_playback(playback);
+        }
+        if let Some(saved) = snapshot.device(&name) {
+            let restored = persist::restore(&mut state, saved);
+            info!(device = %name, restored, "restored persisted values");
+        }
+        for warning in state.tick_rate_warnings(global.warn_update_ms, global.max_updates_per_sec) {
+            warn!(device = %name, "{warning}");
+        }
+        for warning in state.range_warnings() {
+            warn!(device = %name, "{warning}");
+        }
+        let state = Arc::new(RwLock::new(state));
+
+        let simulator_state = Arc::clone(&state);
+        let simulator_name = name.clone();
+        simulators.push(tokio::spawn(async move {
+            if let Err(err) = spawn_simulator(simulator_state).await {
+                error!(device = %simulator_name, "{err:#}");
+                std::process::exit(1);
+            }
+        }));
+
+        if let Some(mirror) = device.mirror {
+            let unit_id = mirror.unit_id.unwrap_or(unit_id);
+            mirrors.push(tokio::spawn(spawn_mirror(
+                name.clone(),
+                mirror,
+                unit_id,
+                Arc::clone(&state),
+            )));
+        }
+
+        if let Some(standby) = device.standby {
+            #[cfg(feature = "standby")]
+            {
+                standby::Role::new(&standby).with_context(|| format!("device {name}"))?;
+                tasks.push(tokio::spawn(standby::spawn_standby(
+                    name.clone(),
+                    standby,
+                    Arc::clone(&state),
+                )));
+            }
+            #[cfg(not(feature = "standby"))]
+            {
+                let _ = standby;
+                anyhow::bail!(
+                    "device {name}: standby needs modsim built with the `standby` feature"
+                );
+            }
+        }
+
+        let service = ModbusService::new(Arc::clone(&state))
+            .with_mei(device.mei)
+            .with_address_offsets(device.address_offsets)
+            .with_max_adu_bytes(device.max_adu_bytes)
+            .with_faulted(&device.faulted)
+            .with_function_codes(device.function_codes.clone())
+            .with_transaction_log(log_transactions)
+            .with_fast_path(fast)
+            .with_audit(audit.clone());
+        let mut transports = Vec::new();
+        let truncation = device
+            .fault
+            .as_ref()
+            .and_then(|fault| fault.truncation.as_ref())
+            .map(|truncation| Truncation::new(truncation, seed))
+            .transpose()
+            .with_context(|| format!("device {name}: fault.truncation"))?;
+        if let Some(tcp) = device.tcp {
+            transports.push(format!("tcp {}", tcp.bind));
+            let latency = device
+                .fault
+                .as_ref()
+                .and_then(|fault| fault.latency.as_ref())
+                .map(|latency| Latency::new(latency, seed))
+                .transpose()
+                .with_context(|| format!("device {name}: fault.latency"))?;
+            let service = service.clone().with_latency(latency);
+            let (_, _, units) = listeners
+                .entry(tcp.bind.clone())
+                .or_insert_with(|| (tcp.clone(), truncation.clone(), BTreeMap::new()));
+            if units.insert(unit_id, service).is_some() {
+                anyhow::bail!(
+                    "device {name}: unit id {unit_id} is already served on tcp {}",
+                    tcp.bind
+                );
+            }
+        }
+
+        if let Some(rtu) = device.rtu {
+            transports.push(format!("rtu {}", rtu.device.as_deref().unwrap_or("pty")));
+            for warning in rtu.framing_warnings() {
+                warn!(device = %name, "{warning}");
+            }
+            let collisions = device
+                .fault
+                .as_ref()
+                .and_then(|fault| fault.collisions.as_ref())
+                .map(|collisions| Collisions::new(collisions, seed))
+                .transpose()
+                .with_context(|| format!("device {name}: fault.collisions"))?;
+            let faults = BusFaults {
+                collisions,
+                truncation,
+            };
+            let service = service.clone();
+            let power = power.clone();
+            tasks.push(tokio::spawn(async move {
+                start_rtu(&rtu, service, power, faults, startup_delay).await
+            }));
+        }
+
+        if let Some(line) = device.line {
+            transports.push(format!("line {}", line.bind));
+            let state = Arc::clone(&state);
+            tasks.push(tokio::spawn(async move {
+                start_line(&line, state, startup_delay).await
+            }));
+        }
+
+        handles.push(DeviceHandle {
+            name: name.clone(),
+            state,
+            transports,
+        });
+        info!(
+            device = %name,
+            unit_id,
+            function_codes = %device.function_codes,
+            "device configured"
+        );
     }
 
-    if let Some(rtu) = config.rtu {
-        let state = Arc::clone(&state);
-        tasks.push(tokio::spawn(async move { start_rtu(&rtu, state).await }));
+    if args.self_test {
+        print!("{}", self_test_tap(&self_tests));
+        let results = self_tests.iter().flat_map(|(_, results)| results);
+        let failed = results
+            .clone()
+            .filter(|result| result.failure.is_some())
+            .count();
+        if failed > 0 {
+            anyhow::bail!("{failed} of {} items failed the self-test", results.count());
+        }
+        return Ok(());
+    }
+
+    for (tcp, truncation, units) in listeners.into_values() {
+        let router = UnitRouter::new(units)
+            .with_power(power.clone())
+            .with_truncation(truncation);
+        tasks.push(tokio::spawn(async move {
+            start_tcp(&tcp, router, startup_delay).await
+        }));
+    }
+
+    if large {
+        info!(
+            items,
+            elapsed_ms = loading.elapsed().as_millis() as u64,
+            "large config loaded"
+        );
     }
 
     if tasks.is_empty() {
-        error!("no transports configured: enable tcp or rtu");
+        error!("no transports configured: enable tcp, rtu or line");
         return Ok(());
     }
 
-    info!(unit_id, "modsim started");
+    if let Some(persistence) = &config.persistence {
+        let path = persistence.path.clone();
+        let interval = Duration::from_millis(persistence.interval_ms);
+        let devices = handles.clone();
+        tasks.push(tokio::spawn(async move {
+            persist::spawn_persistence(path, interval, devices).await;
+            Ok(())
+        }));
+    }
+
+    if let Some(cycle) = config.power.clone() {
+        let devices = handles.clone();
+        let power = power.clone();
+        tasks.push(tokio::spawn(async move {
+            spawn_power_cycle(cycle, power, devices).await;
+            Ok(())
+        }));
+    }
+
+    if let Some(control) = config.control {
+        let handles = handles.clone();
+        tasks.push(tokio::spawn(async move {
+            let api = ControlApi::new(handles)
+                .with_config_paths(args.config)
+                .with_config_hash(config_hash);
+            start_control(&control.bind, api).await
+        }));
+    }
+
+    info!(devices = simulators.len(), "modsim started");
     tokio::signal::ctrl_c().await?;
     info!("shutdown requested");
-    simulator_handle.abort();
+    for simulator in simulators {
+        simulator.abort();
+    }
+    for mirror in mirrors {
+        mirror.abort();
+    }
     for task in tasks {
         task.abort();
     }
+    if let Some(persistence) = &config.persistence {
+        persist::save(&persistence.path, &handles)?;
+        info!(path = %persistence.path, "state snapshot saved");
+    }
 
     Ok(())
 }
 
-fn load_config(path: &str) -> Result<Config> {
-    let content =
-        std::fs::read_to_string(path).with_context(|| format!("failed to read {path}"))?;
-    let config: Config = toml::from_str(&content).context("failed to parse TOML")?;
-    Ok(config)
+async fn run_console(
+    config: &config::Config,
+    device: Option<&str>,
+    connect: Option<&str>,
+) -> Result<()> {
+    let devices = config.resolved_devices();
+    let device = match device {
+        Some(name) => devices
+            .iter()
+            .find(|device| device.name == name)
+            .with_context(|| format!("no device named `{name}`"))?,
+        None => devices.first().context("no devices configured")?,
+    };
+    let addr: SocketAddr = match connect {
+        Some(connect) => connect.parse()?,
+        None => {
+            let bind = &device
+                .tcp
+                .as_ref()
+                .with_context(|| {
+                    format!("device {} has no tcp listener; pass --connect", device.name)
+                })?
+                .bind;
+            let mut addr: SocketAddr = bind.parse()?;
+            if addr.ip().is_unspecified() {
+                addr.set_ip(Ipv4Addr::LOCALHOST.into());
+            }
+            addr
+        }
+    };
+    console::run(addr, device).await
+}
+
+/// Prints the config the control API at `connect`, or at the configured
+/// `control.bind`, dumps the running state as.
+async fn run_dump_state(config: &config::Config, connect: Option<&str>) -> Result<()> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let addr: SocketAddr = match connect {
+        Some(connect) => connect.parse()?,
+        None => {
+            let bind = &config
+                .control
+                .as_ref()
+                .context("no [control] configured; pass --connect")?
+                .bind;
+            let mut addr: SocketAddr = bind.parse()?;
+            if addr.ip().is_unspecified() {
+                addr.set_ip(Ipv4Addr::LOCALHOST.into());
+            }
+            addr
+        }
+    };
+    let mut stream = tokio::net::TcpStream::connect(addr)
+        .await
+        .with_context(|| format!("failed to connect to {addr}"))?;
+    stream
+        .write_all(b"GET /state.toml HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .await?;
+    // The control API closes the connection after the response.
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .with_context(|| format!("malformed response from {addr}"))?;
+    let status = head.lines().next().unwrap_or_default();
+    if !status.starts_with("HTTP/1.1 200") {
+        anyhow::bail!("{addr} answered `{status}`: {}", body.trim());
+    }
+    print!("{body}");
+    Ok(())
+}
+
+/// Builds `device`, or the first device, and prints the values its item at
+/// `space`/`address` takes through `duration` every `step`, as CSV or, with
+/// `plot`, as bars.
+fn run_preview(
+    config: &config::Config,
+    device: Option<&str>,
+    space: Space,
+    address: u16,
+    duration: Duration,
+    step: Duration,
+    plot: bool,
+) -> Result<()> {
+    let mut devices = config.resolved_devices();
+    let index = match device {
+        Some(name) => devices
+            .iter()
+            .position(|device| device.name == name)
+            .with_context(|| format!("no device named `{name}`"))?,
+        None if devices.is_empty() => anyhow::bail!("no devices configured"),
+        None => 0,
+    };
+    let device = devices.swap_remove(index);
+    let global = config.global.clone().unwrap_or_default();
+    let name = device.name;
+    let mut state = SimState::new(
+        global.update_ms,
+        false,
+        device.coils,
+        device.discrete_inputs,
+        device.holding_registers,
+        device.input_registers,
+    )
+    .with_context(|| format!("device {name}"))?
+    .with_warmup(global.warmup_ms)
+    .with_seed(global.seed)
+    .with_noise_floor(global.noise_floor, global.seed)
+    .with_random_phase(global.randomize_phase);
+    let samples = state
+        .preview(space, address, duration, step)
+        .with_context(|| format!("device {name}"))?;
+    if plot {
+        print!("{}", preview_plot(&samples));
+    } else {
+        print!("{}", preview_csv(&samples));
+    }
+    Ok(())
+}
+
+/// Prints the built-in dynamics kinds, with their parameters when `list`.
+fn print_dynamics(list: bool) {
+    if list {
+        print!("{}", describe_kinds());
+        return;
+    }
+    for doc in BUILTIN_KINDS {
+        println!("{}", doc.kind);
+    }
+}
+
+async fn run_replay(path: &str, addr: SocketAddr, unit_id: Option<u8>) -> Result<()> {
+    let recording = replay::load(path)?;
+    let report = replay::replay(addr, unit_id.unwrap_or(recording.unit_id), &recording).await?;
+    print!("{report}");
+    if report.failed() > 0 {
+        anyhow::bail!(
+            "{} of {} requests did not match the recording",
+            report.failed(),
+            report.results.len()
+        );
+    }
+    Ok(())
 }
@@ -0,0 +1,275 @@
+use std::future::Future;
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+use tokio::time::MissedTickBehavior;
+use tokio_modbus::client::{Context as ModbusContext, tcp};
+use tokio_modbus::prelude::{Reader, Slave};
+use tracing::{info, warn};
+
+use crate::config::MirrorConfig;
+use crate::sim::{SimState, Space};
+
+const MAX_BITS: u16 = 2000;
+const MAX_REGISTERS: u16 = 125;
+
+/// A run of consecutive configured addresses polled with one request.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Block {
+    pub space: Space,
+    pub address: u16,
+    pub count: u16,
+}
+
+enum Values {
+    Bits(Vec<bool>),
+    Words(Vec<u16>),
+}
+
+/// Groups the configured items of `state` into as few reads as the protocol
+/// limits allow. Gaps between items start a new block.
+pub fn blocks(state: &SimState) -> Vec<Block> {
+    let mut blocks: Vec<Block> = Vec::new();
+    for (space, addresses, max) in [
+        (
+            Space::Coils,
+            state.coils.keys().collect::<Vec<_>>(),
+            MAX_BITS,
+        ),
+        (
+            Space::DiscreteInputs,
+            state.discrete_inputs.keys().collect(),
+            MAX_BITS,
+        ),
+        (
+            Space::HoldingRegisters,
+            state.holding_registers.keys().collect(),
+            MAX_REGISTERS,
+        ),
+        (
+            Space::InputRegisters,
+            state.input_registers.keys().collect(),
+            MAX_REGISTERS,
+        ),
+    ] {
+        for &address in addresses {
+            match blocks.last_mut() {
+                Some(block)
+                    if block.space == space
+                        && block.count < max
+                        && u32::from(block.address) + u32::from(block.count)
+                            == u32::from(address) =>
+                {
+                    block.count += 1
+                }
+                _ => blocks.push(Block {
+                    space,
+                    address,
+                    count: 1,
+                }),
+            }
+        }
+    }
+    blocks
+}
+
+/// Polls the upstream device every `interval_ms` and copies the values of
+/// every configured item into `state`. Connection failures are retried on
+/// the next poll; items keep their last mirrored value meanwhile.
+pub async fn spawn_mirror(
+    device: String,
+    config: MirrorConfig,
+    unit_id: u8,
+    state: Arc<RwLock<SimState>>,
+) {
+    let blocks = blocks(&state.read().unwrap());
+    let timeout = Duration::from_millis(config.timeout_ms.max(1));
+    let mut interval = tokio::time::interval(Duration::from_millis(config.interval_ms.max(1)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    info!(device = %device, upstream = %config.upstream, blocks = blocks.len(), "mirroring upstream device");
+
+    let mut upstream: Option<ModbusContext> = None;
+    let mut reported_down = false;
+    loop {
+        interval.tick().await;
+        if upstream.is_none() {
+            match connect(&config.upstream, unit_id, timeout).await {
+                Ok(ctx) => {
+                    info!(device = %device, upstream = %config.upstream, "connected to upstream");
+                    upstream = Some(ctx);
+                    reported_down = false;
+                }
+                Err(err) => {
+                    if !reported_down {
+                        warn!(device = %device, upstream = %config.upstream, "{err:#}; retrying");
+                        reported_down = true;
+                    }
+                    continue;
+                }
+            }
+        }
+        if let Some(ctx) = upstream.as_mut()
+            && let Err(err) = poll(ctx, &blocks, timeout, &state).await
+        {
+            warn!(device = %device, upstream = %config.upstream, "{err:#}; reconnecting");
+            upstream = None;
+        }
+    }
+}
+
+async fn connect(upstream: &str, unit_id: u8, timeout: Duration) -> Result<ModbusContext> {
+    let addr = tokio::net::lookup_host(upstream)
+        .await
+        .with_context(|| format!("failed to resolve {upstream}"))?
+        .next()
+        .with_context(|| format!("no address for {upstream}"))?;
+    within(timeout, tcp::connect_slave(addr, Slave(unit_id)))
+        .await
+        .with_context(|| format!("failed to connect to {upstream}"))
+}
+
+/// Reads every block, then applies them under a single lock so clients never
+/// see a partially updated poll.
+async fn poll(
+    ctx: &mut ModbusContext,
+    blocks: &[Block],
+    timeout: Duration,
+    state: &RwLock<SimState>,
+) -> Result<()> {
+    let mut polled = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let (address, count) = (block.address, block.count);
+        let values = match block.space {
+            Space::Coils => Values::Bits(within(timeout, ctx.read_coils(address, count)).await?),
+            Space::DiscreteInputs => {
+                Values::Bits(within(timeout, ctx.read_discrete_inputs(address, count)).await?)
+            }
+            Space::HoldingRegisters => {
+                Values::Words(within(timeout, ctx.read_holding_registers(address, count)).await?)
+            }
+            Space::InputRegisters => {
+                Values::Words(within(timeout, ctx.read_input_registers(address, count)).await?)
+            }
+        };
+        polled.push((block, values));
+    }
+
+    let mut state = state.write().unwrap();
+    for (block, values) in polled {
+        match (block.space, values) {
+            (Space::Coils, Values::Bits(bits)) => state
+                .write_multiple_coils(block.address, &bits)
+                .map_err(|exception| anyhow!("coils {}: {}", block.address, exception.name()))?,
+            (Space::DiscreteInputs, Values::Bits(bits)) => {
+                state.write_discrete_inputs(block.address, &bits)
+            }
+            (Space::HoldingRegisters, Values::Words(words)) => state
+                .write_multiple_registers(block.address, &words)
+                .map_err(|exception| {
+                    anyhow!("holding registers {}: {}", block.address, exception.name())
+                })?,
+            (Space::InputRegisters, Values::Words(words)) => {
+                state.write_input_registers(block.address, &words)
+            }
+            _ => unreachable!("values match the space they were read from"),
+        }
+    }
+    Ok(())
+}
+
+async fn within<T>(timeout: Duration, request: impl Future<Output = io::Result<T>>) -> Result<T> {
+    let response = tokio::time::timeout(timeout, request)
+        .await
+        .context("upstream timed out")?;
+    Ok(response?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DeviceConfig, TcpConfig};
+    use crate::transport::tcp::{ModbusService, start_tcp};
+
+    fn state(device: &DeviceConfig) -> SimState {
+        SimState::new(
+            500,
+            false,
+            device.coils.clone(),
+            device.discrete_inputs.clone(),
+            device.holding_registers.clone(),
+            device.input_registers.clone(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn groups_consecutive_addresses() {
+        let device: DeviceConfig = toml::from_str(
+            "holding_registers = [{ address = 0 }, { address = 1 }, { address = 5 }]\n\
+             input_registers = [{ address = 2 }]",
+        )
+        .unwrap();
+        let block = |space, address, count| Block {
+            space,
+            address,
+            count,
+        };
+        assert_eq!(
+            blocks(&state(&device)),
+            vec![
+                block(Space::HoldingRegisters, 0, 2),
+                block(Space::HoldingRegisters, 5, 1),
+                block(Space::InputRegisters, 2, 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn mirrors_upstream_once_it_comes_up() {
+        let upstream: DeviceConfig = toml::from_str(
+            "coils = [{ address = 3, initial = true }]\n\
+             holding_registers = [{ address = 0, initial = 11 }, { address = 1, initial = 12 }]\n\
+             input_registers = [{ address = 7, initial = 77 }]",
+        )
+        .unwrap();
+        let mirror: DeviceConfig = toml::from_str(
+            "coils = [{ address = 3 }]\n\
+             holding_registers = [{ address = 0 }, { address = 1 }]\n\
+             input_registers = [{ address = 7 }]",
+        )
+        .unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let mirrored = Arc::new(RwLock::new(state(&mirror)));
+        let config = MirrorConfig {
+            upstream: addr.to_string(),
+            unit_id: None,
+            interval_ms: 20,
+            timeout_ms: 200,
+        };
+        let task = tokio::spawn(spawn_mirror(
+            "mirror".to_string(),
+            config,
+            1,
+            Arc::clone(&mirrored),
+        ));
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(mirrored.read().unwrap().holding_register_value(0), 0);
+
+        let service = ModbusService::new(Arc::new(RwLock::new(state(&upstream))));
+        start_tcp(&TcpConfig::new(addr.to_string()), service, Duration::ZERO)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        task.abort();
+
+        let mirrored = mirrored.read().unwrap();
+        assert_eq!(mirrored.read_coils(3, 1).unwrap(), vec![true]);
+        assert_eq!(mirrored.read_holding_registers(0, 2).unwrap(), vec![11, 12]);
+        assert_eq!(mirrored.read_input_registers(7, 1).unwrap(), vec![77]);
+    }
+}
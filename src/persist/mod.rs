@@ -0,0 +1,240 @@
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::control::DeviceHandle;
+use crate::sim::SimState;
+
+/// Persisted item values of every device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    #[serde(default)]
+    pub devices: Vec<DeviceSnapshot>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeviceSnapshot {
+    pub name: String,
+    #[serde(default)]
+    pub coils: Vec<Entry<bool>>,
+    #[serde(default)]
+    pub discrete_inputs: Vec<Entry<bool>>,
+    #[serde(default)]
+    pub holding_registers: Vec<Entry<u16>>,
+    #[serde(default)]
+    pub input_registers: Vec<Entry<u16>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Entry<T> {
+    pub address: u16,
+    pub value: T,
+}
+
+impl Snapshot {
+    pub fn device(&self, name: &str) -> Option<&DeviceSnapshot> {
+        self.devices.iter().find(|device| device.name == name)
+    }
+}
+
+/// Values of the items of `state` marked `persist`.
+pub fn capture(name: &str, state: &SimState) -> DeviceSnapshot {
+    fn entries<T: Copy, I>(items: I) -> Vec<Entry<T>>
+    where
+        I: Iterator<Item = (u16, bool, T)>,
+    {
+        items
+            .filter(|(_, persist, _)| *persist)
+            .map(|(address, _, value)| Entry { address, value })
+            .collect()
+    }
+
+    DeviceSnapshot {
+        name: name.to_string(),
+        coils: entries(state.coils.iter().map(|(a, i)| (*a, i.persist, i.value))),
+        discrete_inputs: entries(
+            state
+                .discrete_inputs
+                .iter()
+                .map(|(a, i)| (*a, i.persist, i.value)),
+        ),
+        holding_registers: entries(
+            state
+                .holding_registers
+                .iter()
+                .map(|(a, i)| (*a, i.persist, i.value)),
+        ),
+        input_registers: entries(
+            state
+                .input_registers
+                .iter()
+                .map(|(a, i)| (*a, i.persist, i.value)),
+        ),
+    }
+}
+
+/// Overlays snapshot values onto configured items marked `persist`. Coils and
+/// holding registers missing from the config were created by client writes
+/// and are recreated; anything else is skipped. Returns the number of values
+/// restored.
+pub fn restore(state: &mut SimState, snapshot: &DeviceSnapshot) -> usize {
+    let mut restored = 0;
+    for entry in &snapshot.coils {
+        match state.coils.get_mut(&entry.address) {
+            Some(item) if !item.persist => continue,
+            Some(item) => item.value = entry.value,
+            None => state.write_single_coil(entry.address, entry.value),
+        }
+        restored += 1;
+    }
+    for entry in &snapshot.holding_registers {
+        match state.holding_registers.get_mut(&entry.address) {
+            Some(item) if !item.persist => continue,
+            Some(item) => item.value = entry.value,
+            None => state.write_single_register(entry.address, entry.value),
+        }
+        restored += 1;
+    }
+    for entry in &snapshot.discrete_inputs {
+        if let Some(item) = state.discrete_inputs.get_mut(&entry.address)
+            && item.persist
+        {
+            item.value = entry.value;
+            restored += 1;
+        }
+    }
+    for entry in &snapshot.input_registers {
+        if let Some(item) = state.input_registers.get_mut(&entry.address)
+            && item.persist
+        {
+            item.value = entry.value;
+            restored += 1;
+        }
+    }
+    restored
+}
+
+/// Reads a snapshot file; a missing file is an empty snapshot.
+pub fn load(path: &str) -> Result<Snapshot> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => {
+            toml::from_str(&content).with_context(|| format!("failed to parse snapshot {path}"))
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Snapshot::default()),
+        Err(err) => Err(err).with_context(|| format!("failed to read snapshot {path}")),
+    }
+}
+
+/// Writes a snapshot of every device, replacing `path` atomically.
+pub fn save(path: &str, devices: &[DeviceHandle]) -> Result<()> {
+    let snapshot = Snapshot {
+        devices: devices
+            .iter()
+            .map(|device| capture(&device.name, &device.state.read().unwrap()))
+            .collect(),
+    };
+    let content = toml::to_string(&snapshot).context("failed to encode snapshot")?;
+    let tmp = format!("{path}.tmp");
+    std::fs::write(&tmp, content).with_context(|| format!("failed to write {tmp}"))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("failed to replace {path}"))?;
+    Ok(())
+}
+
+/// Saves a snapshot every `interval` until aborted.
+pub async fn spawn_persistence(path: String, interval: Duration, devices: Vec<DeviceHandle>) {
+    info!(path = %path, interval_ms = interval.as_millis() as u64, "state persistence enabled");
+    let mut interval = tokio::time::interval(interval.max(Duration::from_millis(1)));
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        if let Err(err) = save(&path, &devices) {
+            warn!(error = %err, "failed to save state snapshot");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RegisterItemConfig;
+    use std::sync::{Arc, RwLock};
+
+    fn state() -> SimState {
+        let setpoint = RegisterItemConfig {
+            address: 0,
+            initial: 5.into(),
+            ..Default::default()
+        };
+        let noisy = RegisterItemConfig {
+            address: 1,
+            dynamics: Some(toml::from_str("kind = \"noise\"\nmin = 0.0\nmax = 9.0").unwrap()),
+            ..Default::default()
+        };
+        let counter = RegisterItemConfig {
+            address: 2,
+            persist: Some(true),
+            dynamics: Some(toml::from_str("kind = \"static\"").unwrap()),
+            ..Default::default()
+        };
+        SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![setpoint, noisy, counter],
+            vec![],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn capture_skips_items_with_dynamics_unless_persisted() {
+        let mut state = state();
+        state.write_single_register(7, 70);
+        let addresses: Vec<u16> = capture("plc", &state)
+            .holding_registers
+            .iter()
+            .map(|entry| entry.address)
+            .collect();
+        assert_eq!(addresses, vec![0, 2, 7]);
+    }
+
+    #[test]
+    fn restore_overlays_only_persisted_items() {
+        let snapshot = DeviceSnapshot {
+            name: "plc".to_string(),
+            holding_registers: [(0, 50), (1, 60), (7, 70)]
+                .into_iter()
+                .map(|(address, value)| Entry { address, value })
+                .collect(),
+            ..Default::default()
+        };
+        let mut state = state();
+        assert_eq!(restore(&mut state, &snapshot), 2);
+        assert_eq!(state.holding_register_value(0), 50);
+        assert_eq!(state.holding_register_value(1), 0);
+        assert_eq!(state.holding_register_value(7), 70);
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("modsim-snapshot-{}.toml", std::process::id()));
+        let path = path.to_str().unwrap();
+        let devices = vec![DeviceHandle {
+            name: "plc".to_string(),
+            state: Arc::new(RwLock::new(state())),
+            transports: Vec::new(),
+        }];
+        save(path, &devices).unwrap();
+        let snapshot = load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(
+            snapshot.device("plc"),
+            Some(&capture("plc", &devices[0].state.read().unwrap()))
+        );
+        assert_eq!(load(path).unwrap(), Snapshot::default());
+    }
+}
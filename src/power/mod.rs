@@ -0,0 +1,118 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+use tracing::{error, info, warn};
+
+use crate::config::PowerConfig;
+use crate::control::DeviceHandle;
+
+/// Whether the simulated devices are powered. Transports check it on every
+/// connection and request; clones share the switch.
+#[derive(Debug, Clone, Default)]
+pub struct PowerSwitch {
+    off: Arc<AtomicBool>,
+    boots: Arc<AtomicU64>,
+}
+
+impl PowerSwitch {
+    pub fn is_on(&self) -> bool {
+        !self.off.load(Ordering::SeqCst)
+    }
+
+    /// How often the power came back on. A connection accepted before the
+    /// latest boot belongs to a previous power cycle.
+    pub fn boots(&self) -> u64 {
+        self.boots.load(Ordering::SeqCst)
+    }
+
+    pub fn power_off(&self) {
+        self.off.store(true, Ordering::SeqCst);
+    }
+
+    pub fn power_on(&self) {
+        self.boots.fetch_add(1, Ordering::SeqCst);
+        self.off.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Power cycles the devices forever: every `cycle_interval_ms` the power goes
+/// off for `downtime_ms`, then every device is reset to its initial state
+/// and the power comes back on.
+pub async fn spawn_power_cycle(
+    config: PowerConfig,
+    power: PowerSwitch,
+    devices: Vec<DeviceHandle>,
+) {
+    let interval = Duration::from_millis(config.cycle_interval_ms.max(1));
+    let downtime = Duration::from_millis(config.downtime_ms);
+    info!(
+        cycle_interval_ms = config.cycle_interval_ms,
+        downtime_ms = config.downtime_ms,
+        "power cycling enabled"
+    );
+    loop {
+        tokio::time::sleep(interval).await;
+        power.power_off();
+        warn!(downtime_ms = config.downtime_ms, "power off: devices down");
+        tokio::time::sleep(downtime).await;
+        for device in &devices {
+            if let Err(err) = device.state.write().unwrap().reset() {
+                error!(device = %device.name, "{err:#}");
+            }
+        }
+        power.power_on();
+        warn!(
+            boot = power.boots(),
+            "power on: devices reset to initial state"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+    use std::time::Duration;
+
+    use super::{PowerSwitch, spawn_power_cycle};
+    use crate::config::{PowerConfig, RegisterItemConfig};
+    use crate::control::DeviceHandle;
+    use crate::sim::SimState;
+
+    #[tokio::test]
+    async fn power_cycles_reset_the_devices() {
+        let register = RegisterItemConfig {
+            address: 0,
+            initial: 7.into(),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![register], vec![]).unwrap();
+        let state = Arc::new(RwLock::new(state));
+        state.write().unwrap().write_single_register(0, 99);
+        let device = DeviceHandle {
+            name: "plc".to_string(),
+            state: Arc::clone(&state),
+            transports: vec![],
+        };
+        let power = PowerSwitch::default();
+        let config = PowerConfig {
+            cycle_interval_ms: 50,
+            downtime_ms: 100,
+        };
+        tokio::spawn(spawn_power_cycle(config, power.clone(), vec![device]));
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(!power.is_on());
+        assert_eq!(
+            state.read().unwrap().read_holding_registers(0, 1),
+            Ok(vec![99])
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(power.is_on());
+        assert_eq!(power.boots(), 1);
+        assert_eq!(
+            state.read().unwrap().read_holding_registers(0, 1),
+            Ok(vec![7])
+        );
+    }
+}
@@ -0,0 +1,356 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use tokio_modbus::client::{Context as ModbusContext, tcp};
+use tokio_modbus::prelude::{Reader, Slave, Writer};
+
+use crate::sim::Exception;
+
+/// A recorded session: the requests a client sent and what it got back.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Recording {
+    #[serde(default = "default_unit_id")]
+    pub unit_id: u8,
+    #[serde(default)]
+    pub requests: Vec<RecordedRequest>,
+}
+
+/// One request of a recording. Reads carry `count` and the `response`
+/// values, writes carry `values`; `exception` names the exception the
+/// request was answered with instead.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RecordedRequest {
+    pub function: u8,
+    pub address: u16,
+    pub count: Option<u16>,
+    pub values: Option<Values>,
+    pub response: Option<Values>,
+    pub exception: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Values {
+    Bits(Vec<bool>),
+    Words(Vec<u16>),
+}
+
+/// What a request was answered with.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Values(Values),
+    Written,
+    Exception(String),
+}
+
+/// The result of replaying one request.
+#[derive(Debug, Clone)]
+pub struct RequestResult {
+    pub index: usize,
+    pub request: String,
+    /// Differences from the recording; empty when the request matched.
+    pub diffs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub results: Vec<RequestResult>,
+}
+
+impl Report {
+    pub fn failed(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|result| !result.diffs.is_empty())
+            .count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            let status = if result.diffs.is_empty() {
+                "PASS"
+            } else {
+                "FAIL"
+            };
+            writeln!(f, "{status} #{} {}", result.index + 1, result.request)?;
+            for diff in &result.diffs {
+                writeln!(f, "    {diff}")?;
+            }
+        }
+        let failed = self.failed();
+        writeln!(
+            f,
+            "{}: {} requests, {} passed, {failed} failed",
+            if failed == 0 { "PASS" } else { "FAIL" },
+            self.results.len(),
+            self.results.len() - failed,
+        )
+    }
+}
+
+pub fn load(path: &str) -> Result<Recording> {
+    let content =
+        std::fs::read_to_string(path).with_context(|| format!("failed to read {path}"))?;
+    let recording: Recording =
+        toml::from_str(&content).with_context(|| format!("failed to parse {path}"))?;
+    for (index, request) in recording.requests.iter().enumerate() {
+        request
+            .check()
+            .with_context(|| format!("request #{}", index + 1))?;
+    }
+    Ok(recording)
+}
+
+impl RecordedRequest {
+    fn check(&self) -> Result<()> {
+        match (self.function, &self.count, &self.values) {
+            (0x01..=0x04, Some(_), None) => {}
+            (0x01..=0x04, _, _) => bail!("reads need `count` and no `values`"),
+            (0x05 | 0x0F, None, Some(Values::Bits(_))) => {}
+            (0x06 | 0x10, None, Some(Values::Words(_))) => {}
+            (0x05 | 0x06 | 0x0F | 0x10, _, _) => {
+                bail!("writes need `values` of the written type and no `count`")
+            }
+            (function, _, _) => bail!("unsupported function code {function}"),
+        }
+        if let Some(name) = &self.exception
+            && !EXCEPTIONS.iter().any(|(known, _)| known.name() == name)
+        {
+            bail!("unknown exception `{name}`");
+        }
+        Ok(())
+    }
+
+    /// The outcome the recording expects.
+    fn expected(&self) -> Outcome {
+        match (&self.exception, &self.response) {
+            (Some(name), _) => Outcome::Exception(name.clone()),
+            (None, Some(values)) => Outcome::Values(values.clone()),
+            (None, None) => Outcome::Written,
+        }
+    }
+
+    fn describe(&self) -> String {
+        let name = match self.function {
+            0x01 => "read coils",
+            0x02 => "read discrete inputs",
+            0x03 => "read holding registers",
+            0x04 => "read input registers",
+            0x05 => "write single coil",
+            0x06 => "write single register",
+            0x0F => "write multiple coils",
+            _ => "write multiple registers",
+        };
+        match (&self.count, &self.values) {
+            (Some(count), _) => format!("{name} {} x{count}", self.address),
+            (None, Some(values)) => format!("{name} {} {}", self.address, values),
+            (None, None) => format!("{name} {}", self.address),
+        }
+    }
+}
+
+impl fmt::Display for Values {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Values::Bits(bits) => write!(f, "{bits:?}"),
+            Values::Words(words) => write!(f, "{words:?}"),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Values(values) => write!(f, "{values}"),
+            Outcome::Written => write!(f, "write accepted"),
+            Outcome::Exception(name) => write!(f, "exception {name}"),
+        }
+    }
+}
+
+/// tokio-modbus reports exceptions as `io::Error`s without exposing the
+/// exception type, so they are recognised by their message.
+const EXCEPTIONS: [(Exception, &str); 6] = [
+    (Exception::IllegalFunction, "Illegal function"),
+    (Exception::IllegalDataAddress, "Illegal data address"),
+    (Exception::IllegalDataValue, "Illegal data value"),
+    (Exception::ServerDeviceFailure, "Server device failure"),
+    (Exception::ServerDeviceBusy, "Server device busy"),
+    (
+        Exception::GatewayTargetDeviceFailedToRespond,
+        "Gateway target device failed to respond",
+    ),
+];
+
+fn outcome<T>(result: io::Result<T>, values: impl FnOnce(T) -> Outcome) -> io::Result<Outcome> {
+    match result {
+        Ok(response) => Ok(values(response)),
+        Err(err) if err.kind() == io::ErrorKind::Other => {
+            let message = err.to_string();
+            match EXCEPTIONS
+                .iter()
+                .find(|(_, description)| message.ends_with(description))
+            {
+                Some((exception, _)) => Ok(Outcome::Exception(exception.name().to_string())),
+                None => Err(err),
+            }
+        }
+        Err(err) => Err(err),
+    }
+}
+
+async fn issue(ctx: &mut ModbusContext, request: &RecordedRequest) -> io::Result<Outcome> {
+    let address = request.address;
+    let count = request.count.unwrap_or_default();
+    let bits = |bits| Outcome::Values(Values::Bits(bits));
+    let words = |words| Outcome::Values(Values::Words(words));
+    let written = |()| Outcome::Written;
+    match (request.function, &request.values) {
+        (0x01, _) => outcome(ctx.read_coils(address, count).await, bits),
+        (0x02, _) => outcome(ctx.read_discrete_inputs(address, count).await, bits),
+        (0x03, _) => outcome(ctx.read_holding_registers(address, count).await, words),
+        (0x04, _) => outcome(ctx.read_input_registers(address, count).await, words),
+        (0x05, Some(Values::Bits(bits))) if bits.len() == 1 => {
+            outcome(ctx.write_single_coil(address, bits[0]).await, written)
+        }
+        (0x06, Some(Values::Words(words))) if words.len() == 1 => {
+            outcome(ctx.write_single_register(address, words[0]).await, written)
+        }
+        (0x0F, Some(Values::Bits(bits))) => {
+            outcome(ctx.write_multiple_coils(address, bits).await, written)
+        }
+        (0x10, Some(Values::Words(words))) => {
+            outcome(ctx.write_multiple_registers(address, words).await, written)
+        }
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "single writes need exactly one value",
+        )),
+    }
+}
+
+/// Differences between the recorded and the actual outcome; values of equal
+/// length are compared address by address.
+pub fn diff(address: u16, expected: &Outcome, actual: &Outcome) -> Vec<String> {
+    if expected == actual {
+        return Vec::new();
+    }
+    let per_address = |expected: Vec<String>, actual: Vec<String>| {
+        (address..=u16::MAX)
+            .zip(expected.into_iter().zip(actual))
+            .filter(|(_, (expected, actual))| expected != actual)
+            .map(|(address, (expected, actual))| {
+                format!("address {address}: expected {expected}, got {actual}")
+            })
+            .collect()
+    };
+    match (expected, actual) {
+        (Outcome::Values(Values::Bits(expected)), Outcome::Values(Values::Bits(actual)))
+            if expected.len() == actual.len() =>
+        {
+            per_address(
+                expected.iter().map(bool::to_string).collect(),
+                actual.iter().map(bool::to_string).collect(),
+            )
+        }
+        (Outcome::Values(Values::Words(expected)), Outcome::Values(Values::Words(actual)))
+            if expected.len() == actual.len() =>
+        {
+            per_address(
+                expected.iter().map(u16::to_string).collect(),
+                actual.iter().map(u16::to_string).collect(),
+            )
+        }
+        _ => vec![format!("expected {expected}, got {actual}")],
+    }
+}
+
+/// Issues every request of `recording`, in order, against the device at
+/// `addr` and compares the responses with the recorded ones.
+pub async fn replay(addr: SocketAddr, unit_id: u8, recording: &Recording) -> Result<Report> {
+    let mut ctx = tcp::connect_slave(addr, Slave(unit_id))
+        .await
+        .with_context(|| format!("failed to connect to {addr}"))?;
+    let mut report = Report::default();
+    for (index, request) in recording.requests.iter().enumerate() {
+        let actual = issue(&mut ctx, request)
+            .await
+            .with_context(|| format!("request #{} ({})", index + 1, request.describe()))?;
+        report.results.push(RequestResult {
+            index,
+            request: request.describe(),
+            diffs: diff(request.address, &request.expected(), &actual),
+        });
+    }
+    Ok(report)
+}
+
+fn default_unit_id() -> u8 {
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DeviceConfig;
+    use crate::harness::TestHarness;
+    use crate::sim::SimState;
+
+    #[test]
+    fn diffs_values_per_address() {
+        let words = |words: &[u16]| Outcome::Values(Values::Words(words.to_vec()));
+        assert!(diff(10, &words(&[1, 2]), &words(&[1, 2])).is_empty());
+        assert_eq!(
+            diff(10, &words(&[1, 2, 3]), &words(&[1, 5, 3])),
+            vec!["address 11: expected 2, got 5"]
+        );
+        assert_eq!(
+            diff(
+                0,
+                &words(&[1]),
+                &Outcome::Exception("illegal_data_address".into())
+            ),
+            vec!["expected [1], got exception illegal_data_address"]
+        );
+    }
+
+    #[tokio::test]
+    async fn replays_against_a_device() {
+        let device: DeviceConfig =
+            toml::from_str("holding_registers = [{ address = 0, initial = 11 }]").unwrap();
+        let state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            device.holding_registers.clone(),
+            vec![],
+        )
+        .unwrap();
+        let harness = TestHarness::start(state).await.unwrap();
+
+        let recording: Recording = toml::from_str(
+            "[[requests]]\nfunction = 6\naddress = 1\nvalues = [5]\n\n\
+             [[requests]]\nfunction = 3\naddress = 0\ncount = 2\nresponse = [11, 6]\n\n\
+             [[requests]]\nfunction = 3\naddress = 65535\ncount = 2\n\
+             exception = \"illegal_data_address\"",
+        )
+        .unwrap();
+        let report = replay(harness.addr, 1, &recording).await.unwrap();
+        assert_eq!(report.failed(), 1, "{report}");
+        assert_eq!(
+            report.results[1].diffs,
+            vec!["address 1: expected 6, got 5"]
+        );
+        assert!(
+            report
+                .to_string()
+                .ends_with("FAIL: 3 requests, 2 passed, 1 failed\n")
+        );
+    }
+}
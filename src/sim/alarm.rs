@@ -0,0 +1,224 @@
+use anyhow::{Context, Result, bail};
+use evalexpr::{ContextWithMutableVariables, HashMapContext, Node, Value};
+use tracing::info;
+
+use super::SimState;
+use crate::config::{AlarmConfig, RegisterSpace};
+
+/// A holding register bit that reflects a condition on another register.
+#[derive(Debug, Clone)]
+pub struct Alarm {
+    pub register: u16,
+    pub bit: u8,
+    pub source_space: RegisterSpace,
+    pub source: u16,
+    condition: Condition,
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+    /// Set while the value is above `above` or below `below`.
+    Threshold {
+        above: Option<f64>,
+        below: Option<f64>,
+    },
+    /// Set while the expression, seeing the value as `value`, is true or
+    /// non-zero.
+    Expr(Node),
+}
+
+impl Alarm {
+    fn new(config: &AlarmConfig) -> Result<Self> {
+        if config.bit > 15 {
+            bail!("bit {} is not a register bit (0-15)", config.bit);
+        }
+        let condition = match (&config.expr, config.above, config.below) {
+            (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+                bail!("set either `expr` or `above`/`below`, not both")
+            }
+            (Some(expr), None, None) => Condition::Expr(
+                evalexpr::build_operator_tree(expr)
+                    .with_context(|| format!("invalid expr `{expr}`"))?,
+            ),
+            (None, None, None) => bail!("needs a condition: `above`, `below` or `expr`"),
+            (None, above, below) => Condition::Threshold { above, below },
+        };
+        Ok(Self {
+            register: config.register,
+            bit: config.bit,
+            source_space: config.source_space,
+            source: config.source,
+            condition,
+        })
+    }
+
+    fn active(&self, value: f64) -> bool {
+        match &self.condition {
+            Condition::Threshold { above, below } => {
+                above.is_some_and(|above| value > above) || below.is_some_and(|below| value < below)
+            }
+            Condition::Expr(node) => {
+                let mut context = HashMapContext::new();
+                if context
+                    .set_value("value".to_string(), Value::Float(value))
+                    .is_err()
+                {
+                    return false;
+                }
+                match node.eval_with_context(&context) {
+                    Ok(Value::Boolean(active)) => active,
+                    Ok(Value::Int(value)) => value != 0,
+                    Ok(Value::Float(value)) => value != 0.0,
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+impl SimState {
+    /// Adds alarm bits. Each must name an existing source register and a
+    /// target holding register that no dynamics drive; the target is created
+    /// if it is not configured.
+    pub fn with_alarms(mut self, alarms: &[AlarmConfig]) -> Result<Self> {
+        for (index, config) in alarms.iter().enumerate() {
+            let context = format!(
+                "alarm {index} (holding register {} bit {})",
+                config.register, config.bit
+            );
+            let alarm = Alarm::new(config).with_context(|| context.clone())?;
+            let sources = match alarm.source_space {
+                RegisterSpace::HoldingRegisters => &self.holding_registers,
+                RegisterSpace::InputRegisters => &self.input_registers,
+            };
+            if !sources.contains_key(&alarm.source) {
+                bail!(
+                    "{context}: source {} {} is not configured",
+                    alarm.source_space.name(),
+                    alarm.source
+                );
+            }
+            if self
+                .holding_registers
+                .get(&alarm.register)
+                .is_some_and(|item| item.dynamics.is_some())
+            {
+                bail!("{context}: the register has dynamics, which would overwrite the alarm bit");
+            }
+            if self
+                .alarms
+                .iter()
+                .any(|other| other.register == alarm.register && other.bit == alarm.bit)
+            {
+                bail!("{context}: the bit is already used by another alarm");
+            }
+            if !self.holding_registers.contains_key(&alarm.register) {
+                self.write_single_register(alarm.register, 0);
+            }
+            self.alarms.push(alarm);
+        }
+        Ok(self)
+    }
+
+    /// Sets or clears every alarm bit from the current source values. All
+    /// sources are read before any bit changes, so alarms never see each
+    /// other's updates within a tick.
+    pub(super) fn evaluate_alarms(&mut self) {
+        let states: Vec<bool> = self
+            .alarms
+            .iter()
+            .map(|alarm| {
+                let sources = match alarm.source_space {
+                    RegisterSpace::HoldingRegisters => &self.holding_registers,
+                    RegisterSpace::InputRegisters => &self.input_registers,
+                };
+                let value = sources.get(&alarm.source).map_or(0, |item| item.value);
+                alarm.active(f64::from(value))
+            })
+            .collect();
+        for (alarm, active) in self.alarms.iter().zip(states) {
+            let Some(item) = self.holding_registers.get_mut(&alarm.register) else {
+                continue;
+            };
+            let mask = 1u16 << alarm.bit;
+            let value = if active {
+                item.value | mask
+            } else {
+                item.value & !mask
+            };
+            if value != item.value {
+                item.last_value = item.value;
+                item.value = value;
+                if self.log_value_updates {
+                    info!(
+                        address = alarm.register,
+                        bit = alarm.bit,
+                        active,
+                        "alarm bit updated"
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{AlarmConfig, RegisterItemConfig};
+    use crate::sim::SimState;
+
+    fn alarm(text: &str) -> AlarmConfig {
+        toml::from_str(text).unwrap()
+    }
+
+    fn state() -> SimState {
+        let level = RegisterItemConfig {
+            address: 100,
+            initial: 85.into(),
+            ..Default::default()
+        };
+        SimState::new(500, false, vec![], vec![], vec![], vec![level]).unwrap()
+    }
+
+    #[test]
+    fn thresholds_set_and_clear_bits() {
+        let mut state = state()
+            .with_alarms(&[
+                alarm("register = 200\nbit = 0\nsource = 100\nabove = 80.0"),
+                alarm("register = 200\nbit = 3\nsource = 100\nbelow = 10.0"),
+            ])
+            .unwrap();
+        state.write_single_register(200, 0x0100);
+        state.tick().unwrap();
+        assert_eq!(state.holding_register_value(200), 0x0101);
+
+        state.write_input_registers(100, &[5]);
+        state.tick().unwrap();
+        assert_eq!(state.holding_register_value(200), 0x0108);
+    }
+
+    #[test]
+    fn insane_alarms_are_rejected() {
+        for (text, message) in [
+            (
+                "register = 200\nbit = 16\nsource = 100\nabove = 1.0",
+                "bit 16",
+            ),
+            ("register = 200\nbit = 0\nsource = 100", "needs a condition"),
+            (
+                "register = 200\nbit = 0\nsource = 7\nabove = 1.0",
+                "input register 7",
+            ),
+            (
+                "register = 200\nbit = 0\nsource = 100\nabove = 1.0\nexpr = \"value > 1\"",
+                "not both",
+            ),
+        ] {
+            let err = state().with_alarms(&[alarm(text)]).unwrap_err();
+            assert!(format!("{err:#}").contains(message), "{err:#}");
+        }
+        let twice = alarm("register = 200\nbit = 0\nsource = 100\nabove = 1.0");
+        let err = state().with_alarms(&[twice.clone(), twice]).unwrap_err();
+        assert!(err.to_string().contains("already used"), "{err}");
+    }
+}
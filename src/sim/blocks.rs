@@ -0,0 +1,159 @@
+use std::collections::BTreeMap;
+
+use super::{Exception, SimRegisterItem, SimState, Space};
+
+impl SimState {
+    /// Limits reads to ranges within one block of contiguous configured
+    /// addresses, computed from the items configured now. Registers of
+    /// 32-bit encodings count with both their addresses; with mirrored
+    /// registers, both register spaces share the blocks of the two.
+    /// Call after `with_mirror_registers`.
+    pub fn with_block_bounded_reads(mut self, enabled: bool) -> Self {
+        if !enabled {
+            self.read_blocks = None;
+            return self;
+        }
+        let mut blocks = BTreeMap::new();
+        for (space, map) in [
+            (Space::Coils, &self.coils),
+            (Space::DiscreteInputs, &self.discrete_inputs),
+        ] {
+            blocks.insert(space, merge(map.keys().map(|&address| (address, address))));
+        }
+        let ranges = |map: &BTreeMap<u16, SimRegisterItem>| {
+            map.iter()
+                .map(|(&address, item)| {
+                    (address, address.saturating_add(item.encoding.width() - 1))
+                })
+                .collect::<Vec<_>>()
+        };
+        let (mut holding, mut input) = (
+            ranges(&self.holding_registers),
+            ranges(&self.input_registers),
+        );
+        if self.mirror_registers {
+            holding.extend(input.iter().copied());
+            holding.sort_unstable();
+            input = holding.clone();
+        }
+        blocks.insert(Space::HoldingRegisters, merge(holding.into_iter()));
+        blocks.insert(Space::InputRegisters, merge(input.into_iter()));
+        self.read_blocks = Some(blocks);
+        self
+    }
+
+    /// Refuses a client read that covers only part of a value whose register
+    /// has `whole_reads`, or that does not lie within a single block when
+    /// reads are block-bounded.
+    pub fn check_readable(&self, space: Space, address: u16, count: u16) -> Result<(), Exception> {
+        self.check_whole_values(space, address, count)?;
+        let Some(blocks) = &self.read_blocks else {
+            return Ok(());
+        };
+        let last = u32::from(address) + u32::from(count.max(1)) - 1;
+        let blocks = &blocks[&space];
+        // The only candidate is the last block starting at or before `address`.
+        let index = blocks.partition_point(|&(start, _)| start <= address);
+        match index.checked_sub(1).map(|index| blocks[index]) {
+            Some((_, end)) if last <= u32::from(end) => Ok(()),
+            _ => Err(Exception::IllegalDataAddress),
+        }
+    }
+
+    fn check_whole_values(&self, space: Space, address: u16, count: u16) -> Result<(), Exception> {
+        let map = match space {
+            Space::HoldingRegisters => &self.holding_registers,
+            Space::InputRegisters => &self.input_registers,
+            Space::Coils | Space::DiscreteInputs => return Ok(()),
+        };
+        let first = u32::from(address);
+        let last = first + u32::from(count.max(1)) - 1;
+        // The widest values span four registers, so only those starting up
+        // to three before the read can reach into it.
+        let candidates = address.saturating_sub(3)..=last.min(u16::MAX.into()) as u16;
+        for (&start, item) in map.range(candidates) {
+            let end = u32::from(start) + u32::from(item.encoding.width()) - 1;
+            let overlaps = u32::from(start) <= last && end >= first;
+            let covered = first <= u32::from(start) && end <= last;
+            if item.whole_reads && overlaps && !covered {
+                return Err(Exception::IllegalDataAddress);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Merges inclusive ranges, sorted by start, into disjoint blocks; ranges
+/// that overlap or touch join.
+fn merge(ranges: impl Iterator<Item = (u16, u16)>) -> Vec<(u16, u16)> {
+    let mut blocks: Vec<(u16, u16)> = Vec::new();
+    for (start, end) in ranges {
+        match blocks.last_mut() {
+            Some(block) if u32::from(start) <= u32::from(block.1) + 1 => {
+                block.1 = block.1.max(end);
+            }
+            _ => blocks.push((start, end)),
+        }
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Encoding, RegisterItemConfig};
+    use crate::sim::{Exception, SimState, Space};
+
+    #[test]
+    fn reads_must_stay_within_one_block() {
+        let register = |address, encoding| RegisterItemConfig {
+            address,
+            encoding,
+            ..Default::default()
+        };
+        let holding = vec![
+            register(100, Encoding::U16),
+            register(101, Encoding::F32),
+            register(105, Encoding::U16),
+        ];
+        let state = SimState::new(500, false, vec![], vec![], holding, vec![]).unwrap();
+        assert_eq!(state.check_readable(Space::HoldingRegisters, 98, 8), Ok(()));
+
+        let state = state.with_block_bounded_reads(true);
+        let check = |address, count| state.check_readable(Space::HoldingRegisters, address, count);
+        assert_eq!(check(100, 3), Ok(()));
+        assert_eq!(check(102, 1), Ok(()));
+        assert_eq!(check(105, 1), Ok(()));
+        assert_eq!(check(98, 8), Err(Exception::IllegalDataAddress));
+        assert_eq!(check(100, 4), Err(Exception::IllegalDataAddress));
+        assert_eq!(check(104, 2), Err(Exception::IllegalDataAddress));
+        assert_eq!(
+            state.check_readable(Space::Coils, 0, 1),
+            Err(Exception::IllegalDataAddress)
+        );
+    }
+
+    #[test]
+    fn wide_values_can_require_whole_reads() {
+        let register = |address, encoding, whole_reads| RegisterItemConfig {
+            address,
+            encoding,
+            whole_reads,
+            ..Default::default()
+        };
+        let input = vec![
+            register(10, Encoding::F32, true),
+            register(12, Encoding::U64, true),
+            register(20, Encoding::I32, false),
+        ];
+        let state = SimState::new(500, false, vec![], vec![], vec![], input).unwrap();
+        let check = |address, count| state.check_readable(Space::InputRegisters, address, count);
+        assert_eq!(check(10, 2), Ok(()));
+        assert_eq!(check(8, 10), Ok(()));
+        assert_eq!(check(12, 4), Ok(()));
+        assert_eq!(check(21, 1), Ok(()));
+        assert_eq!(check(11, 1), Err(Exception::IllegalDataAddress));
+        assert_eq!(check(9, 2), Err(Exception::IllegalDataAddress));
+        assert_eq!(check(10, 3), Err(Exception::IllegalDataAddress));
+        assert_eq!(check(15, 1), Err(Exception::IllegalDataAddress));
+    }
+}
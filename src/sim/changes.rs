@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::sync::broadcast;
+
+use super::{SimBoolItem, SimRegisterItem, SimState, Space, encoding};
+
+/// Changes a subscriber may fall behind by before it misses some.
+const FEED_CAPACITY: usize = 1024;
+
+/// Addresses in a space.
+const SPACE_SIZE: usize = 1 << 16;
+
+/// A value a tick or a write changed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Change {
+    pub space: Space,
+    pub address: u16,
+    /// The new value: `0` or `1` for bits, the logical value for registers.
+    pub value: f64,
+    /// Wall-clock time of the change, in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+}
+
+/// The values of some items before a write, to publish those it changes.
+pub(super) struct Watched {
+    space: Space,
+    address: u16,
+    count: usize,
+    values: BTreeMap<u16, f64>,
+}
+
+impl SimState {
+    /// Receives every value change ticks, writes, restores and resets make
+    /// from now on. The feed is created by the first subscriber; until then
+    /// nothing is sent.
+    pub fn subscribe_changes(&mut self) -> broadcast::Receiver<Change> {
+        self.change_feed
+            .get_or_insert_with(|| broadcast::channel(FEED_CAPACITY).0)
+            .subscribe()
+    }
+
+    /// The values of the items a write of `count` items from `address` may
+    /// change, for [`SimState::publish_watched`] once it is done; `None`
+    /// while nobody subscribes.
+    pub(super) fn watch(&self, space: Space, address: u16, count: usize) -> Option<Watched> {
+        self.change_feed
+            .as_ref()
+            .is_some_and(|feed| feed.receiver_count() > 0)
+            .then(|| Watched {
+                space,
+                address,
+                count,
+                values: self.feed_values(space, address, count),
+            })
+    }
+
+    /// [`SimState::watch`] over every item of the device.
+    pub(super) fn watch_all(&self) -> Vec<Watched> {
+        [
+            Space::Coils,
+            Space::DiscreteInputs,
+            Space::HoldingRegisters,
+            Space::InputRegisters,
+        ]
+        .into_iter()
+        .filter_map(|space| self.watch(space, 0, SPACE_SIZE))
+        .collect()
+    }
+
+    /// Publishes the items whose value differs from the one `watched` holds,
+    /// including items created since.
+    pub(super) fn publish_watched(&self, watched: Option<Watched>) {
+        let Some(watched) = watched else {
+            return;
+        };
+        let timestamp_ms = timestamp_ms();
+        let values = self.feed_values(watched.space, watched.address, watched.count);
+        for (address, value) in values {
+            let before = watched.values.get(&address).map(|value| value.to_bits());
+            if before != Some(value.to_bits()) {
+                publish(
+                    &self.change_feed,
+                    watched.space,
+                    address,
+                    value,
+                    timestamp_ms,
+                );
+            }
+        }
+    }
+
+    /// The values the feed reports for the items `count` items from
+    /// `address` touch: registers as the logical value of the item whose
+    /// words they hold.
+    fn feed_values(&self, space: Space, address: u16, count: usize) -> BTreeMap<u16, f64> {
+        let Some(rest) = count.checked_sub(1) else {
+            return BTreeMap::new();
+        };
+        let last = usize::from(address)
+            .saturating_add(rest)
+            .min(usize::from(u16::MAX)) as u16;
+        let bits = |map: &BTreeMap<u16, SimBoolItem>| -> BTreeMap<u16, f64> {
+            map.range(address..=last)
+                .map(|(address, item)| (*address, f64::from(u8::from(item.value))))
+                .collect()
+        };
+        match space {
+            Space::Coils => bits(&self.coils),
+            Space::DiscreteInputs => bits(&self.discrete_inputs),
+            Space::HoldingRegisters => register_values(&self.holding_registers, address, last),
+            Space::InputRegisters => register_values(&self.input_registers, address, last),
+        }
+    }
+}
+
+/// The logical values of the items of `map` holding a word in
+/// `first..=last`; the words past the first of a wider value are part of it.
+fn register_values(
+    map: &BTreeMap<u16, SimRegisterItem>,
+    first: u16,
+    last: u16,
+) -> BTreeMap<u16, f64> {
+    // The widest encoding has four words.
+    let start = first.saturating_sub(3);
+    let mut values = BTreeMap::new();
+    let mut covered_until = 0;
+    for (&address, item) in map.range(start..=last) {
+        if u32::from(address) < covered_until {
+            continue;
+        }
+        covered_until = u32::from(address) + u32::from(item.encoding.width());
+        if covered_until > u32::from(first)
+            && let Some((_, value)) = encoding::decode_raw(map, address, item)
+        {
+            values.insert(address, value);
+        }
+    }
+    values
+}
+
+/// Wall-clock milliseconds since the Unix epoch.
+pub(super) fn timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64)
+}
+
+/// Sends a change to the subscribers of `feed`, if there are any.
+pub(super) fn publish(
+    feed: &Option<broadcast::Sender<Change>>,
+    space: Space,
+    address: u16,
+    value: f64,
+    timestamp_ms: u64,
+) {
+    if let Some(feed) = feed {
+        // Fails only without receivers, which then miss nothing.
+        let _ = feed.send(Change {
+            space,
+            address,
+            value,
+            timestamp_ms,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RegisterItemConfig;
+
+    #[test]
+    fn subscribers_see_ticked_changes() {
+        let item = RegisterItemConfig {
+            address: 4,
+            update_ms: Some(0),
+            dynamics: Some(toml::from_str("kind = \"script\"\nexpr = \"42\"").unwrap()),
+            ..Default::default()
+        };
+        let mut state = SimState::new(500, false, vec![], vec![], vec![item], vec![]).unwrap();
+        let mut changes = state.subscribe_changes();
+        state.tick().unwrap();
+        let change = changes.try_recv().unwrap();
+        assert_eq!(
+            (change.space, change.address, change.value),
+            (Space::HoldingRegisters, 4, 42.0)
+        );
+        assert!(change.timestamp_ms > 0);
+        // Unchanged values send nothing.
+        state.tick().unwrap();
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[test]
+    fn subscribers_see_restored_values() {
+        let item = RegisterItemConfig {
+            address: 4,
+            ..Default::default()
+        };
+        let mut state = SimState::new(500, false, vec![], vec![], vec![], vec![item]).unwrap();
+        let snapshot = state.capture();
+        state.write_input_registers(4, &[9]);
+        let mut changes = state.subscribe_changes();
+        state.restore(&snapshot);
+        let change = changes.try_recv().unwrap();
+        assert_eq!(
+            (change.space, change.address, change.value),
+            (Space::InputRegisters, 4, 0.0)
+        );
+        assert!(changes.try_recv().is_err());
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+use super::{Exception, SimState, Space};
+use crate::config::WriteConflictPolicy;
+
+/// Who last wrote an address over Modbus, and when.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LastWrite {
+    pub peer: Option<SocketAddr>,
+    pub connection_id: Option<u64>,
+    pub at: Instant,
+}
+
+impl SimState {
+    pub fn with_write_conflict(mut self, policy: WriteConflictPolicy, busy_window_ms: u64) -> Self {
+        self.write_conflict = policy;
+        self.busy_window = Duration::from_millis(busy_window_ms);
+        self
+    }
+
+    /// Claims `count` addresses from `address` for a write by `writer` and
+    /// records it as their last write. Under reject-if-busy, an address
+    /// another writer wrote within the busy window answers ServerDeviceBusy
+    /// and nothing is claimed. Writers without an address, such as the RTU
+    /// master, count as one writer.
+    pub fn claim_write(
+        &mut self,
+        space: Space,
+        address: u16,
+        count: usize,
+        writer: Option<SocketAddr>,
+        connection_id: Option<u64>,
+    ) -> Result<(), Exception> {
+        let now = Instant::now();
+        let addresses = (address..=u16::MAX).take(count);
+        let busy = self.write_conflict == WriteConflictPolicy::RejectIfBusy
+            && addresses.clone().any(|address| {
+                self.last_writers
+                    .get(&(space, address))
+                    .is_some_and(|last| {
+                        last.peer != writer && now.duration_since(last.at) < self.busy_window
+                    })
+            });
+        if busy {
+            return Err(Exception::ServerDeviceBusy);
+        }
+        let write = LastWrite {
+            peer: writer,
+            connection_id,
+            at: now,
+        };
+        for address in addresses {
+            self.last_writers.insert((space, address), write);
+        }
+        Ok(())
+    }
+
+    /// Last client write to each address written since start or reset.
+    pub fn last_writes(&self) -> &BTreeMap<(Space, u16), LastWrite> {
+        &self.last_writers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::WriteConflictPolicy;
+    use crate::sim::{Exception, SimState, Space};
+
+    #[test]
+    fn reject_if_busy_refuses_other_writers_within_the_window() {
+        let first = Some("127.0.0.1:40000".parse().unwrap());
+        let second = Some("127.0.0.1:40001".parse().unwrap());
+        let mut state = SimState::new(500, false, vec![], vec![], vec![], vec![])
+            .unwrap()
+            .with_write_conflict(WriteConflictPolicy::RejectIfBusy, 60_000);
+        let holding = Space::HoldingRegisters;
+        assert_eq!(state.claim_write(holding, 4, 2, first, None), Ok(()));
+        assert_eq!(state.claim_write(holding, 4, 1, first, None), Ok(()));
+        assert_eq!(
+            state.claim_write(holding, 3, 2, second, None),
+            Err(Exception::ServerDeviceBusy)
+        );
+        assert_eq!(state.claim_write(holding, 6, 1, second, None), Ok(()));
+        assert_eq!(state.claim_write(Space::Coils, 4, 1, second, None), Ok(()));
+
+        let mut state = state.with_write_conflict(WriteConflictPolicy::RejectIfBusy, 0);
+        assert_eq!(state.claim_write(holding, 4, 1, second, None), Ok(()));
+        let mut state = state.with_write_conflict(WriteConflictPolicy::LastWriterWins, 60_000);
+        assert_eq!(state.claim_write(holding, 4, 1, first, None), Ok(()));
+    }
+
+    #[test]
+    fn client_writes_are_recorded_under_either_policy() {
+        let client = Some("127.0.0.1:40000".parse().unwrap());
+        let mut state = SimState::new(500, false, vec![], vec![], vec![], vec![]).unwrap();
+        assert_eq!(
+            state.claim_write(Space::Coils, 2, 2, client, Some(7)),
+            Ok(())
+        );
+        let writes = state.last_writes();
+        assert_eq!(
+            writes.keys().copied().collect::<Vec<_>>(),
+            [(Space::Coils, 2), (Space::Coils, 3)]
+        );
+        assert_eq!(writes[&(Space::Coils, 3)].peer, client);
+        assert_eq!(writes[&(Space::Coils, 3)].connection_id, Some(7));
+
+        state.reset().unwrap();
+        assert!(state.last_writes().is_empty());
+    }
+}
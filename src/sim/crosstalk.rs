@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+
+use super::{SimState, Space};
+use crate::config::{CrosstalkConfig, Encoding};
+
+impl SimState {
+    /// Couples the registers with dynamics in `crosstalk.space` to their
+    /// neighbors; the coupling must be finite and the window at least 1.
+    pub fn with_crosstalk(mut self, crosstalk: Option<&CrosstalkConfig>) -> Result<Self> {
+        if let Some(crosstalk) = crosstalk {
+            if !crosstalk.coupling.is_finite() {
+                bail!(
+                    "crosstalk coupling must be finite, got {}",
+                    crosstalk.coupling
+                );
+            }
+            if crosstalk.window == 0 {
+                bail!("crosstalk window must be at least 1");
+            }
+        }
+        self.crosstalk = crosstalk.cloned();
+        Ok(self)
+    }
+
+    /// Cross-talk to add to the next update of each `u16` register with
+    /// dynamics in `space`, by address. It is taken from a snapshot of the
+    /// values the neighbors held before the update, without the cross-talk
+    /// and noise they got themselves, so it depends neither on the order
+    /// registers update in nor on earlier cross-talk.
+    pub(super) fn crosstalk(&self, space: Space) -> BTreeMap<u16, f64> {
+        let Some(crosstalk) = &self.crosstalk else {
+            return BTreeMap::new();
+        };
+        if Space::from(crosstalk.space) != space {
+            return BTreeMap::new();
+        }
+        let map = if space == Space::HoldingRegisters {
+            &self.holding_registers
+        } else {
+            &self.input_registers
+        };
+        let clean: BTreeMap<u16, f64> = map
+            .iter()
+            .filter(|(_, item)| item.encoding == Encoding::U16)
+            .map(|(address, item)| (*address, item.value as f64 - item.noise))
+            .collect();
+        map.iter()
+            .filter(|(_, item)| item.dynamics.is_some() && item.encoding == Encoding::U16)
+            .map(|(&address, _)| {
+                let first = address.saturating_sub(crosstalk.window);
+                let last = address.saturating_add(crosstalk.window);
+                let neighbors: f64 = clean
+                    .range(first..=last)
+                    .filter(|(neighbor, _)| **neighbor != address)
+                    .map(|(_, value)| value)
+                    .sum();
+                (address, crosstalk.coupling * neighbors)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RegisterItemConfig;
+
+    fn channel(address: u16, expr: &str) -> RegisterItemConfig {
+        let dynamics = format!("kind = \"script\"\nexpr = \"{expr}\"");
+        RegisterItemConfig {
+            address,
+            update_ms: Some(0),
+            dynamics: Some(toml::from_str(&dynamics).unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn neighbors_couple_in_without_feedback() {
+        let setpoint = RegisterItemConfig {
+            address: 3,
+            initial: 2000.into(),
+            ..Default::default()
+        };
+        let items = vec![
+            channel(0, "1000"),
+            channel(1, "0"),
+            channel(2, "3000"),
+            setpoint,
+        ];
+        let crosstalk: CrosstalkConfig =
+            toml::from_str("coupling = 0.01\nspace = \"input_registers\"").unwrap();
+        let mut state = SimState::new(500, false, vec![], vec![], vec![], items)
+            .unwrap()
+            .with_crosstalk(Some(&crosstalk))
+            .unwrap();
+        let values =
+            |state: &SimState| [0, 1, 2, 3].map(|address| state.input_registers[&address].value);
+        // The first tick couples in the values from before it.
+        state.tick().unwrap();
+        assert_eq!(values(&state), [1000, 0, 3020, 2000]);
+        // Later ticks see the clean neighbors, however often they run.
+        for _ in 0..3 {
+            state.tick().unwrap();
+            assert_eq!(values(&state), [1000, 40, 3020, 2000]);
+        }
+    }
+
+    #[test]
+    fn coupling_must_be_finite() {
+        let crosstalk: CrosstalkConfig = toml::from_str("coupling = nan").unwrap();
+        let state = SimState::new(500, false, vec![], vec![], vec![], vec![]).unwrap();
+        let err = state.with_crosstalk(Some(&crosstalk)).unwrap_err();
+        assert!(err.to_string().contains("coupling"), "{err}");
+    }
+}
@@ -0,0 +1,131 @@
+use anyhow::{Result, bail};
+
+use crate::config::RegisterItemConfig;
+
+/// Relaxation of a register without dynamics toward a rest value: every
+/// update covers `1 - e^(-rate * dt)` of the distance left, `dt` being the
+/// seconds since the previous update.
+#[derive(Debug, Clone)]
+pub struct Decay {
+    pub to: f64,
+    pub rate: f64,
+    /// Time of the last update, the unrounded value it reached and the words
+    /// that showed it.
+    last: Option<(f64, f64, Vec<u16>)>,
+}
+
+impl Decay {
+    /// The decay `item` asks for, if any. `decay_to` and `decay_rate` come
+    /// together, the rate must be positive and the item must not have
+    /// dynamics.
+    pub(super) fn new(item: &RegisterItemConfig) -> Result<Option<Self>> {
+        match (item.decay_to, item.decay_rate) {
+            (None, None) => Ok(None),
+            (Some(to), Some(rate)) => {
+                if item.dynamics.is_some() {
+                    bail!("decay cannot be combined with dynamics, which drive the value");
+                }
+                if !to.is_finite() {
+                    bail!("decay_to must be finite, got {to}");
+                }
+                if !(rate > 0.0 && rate.is_finite()) {
+                    bail!("decay_rate must be positive, got {rate}");
+                }
+                Ok(Some(Self {
+                    to,
+                    rate,
+                    last: None,
+                }))
+            }
+            _ => bail!("decay_to and decay_rate must be set together"),
+        }
+    }
+
+    /// Relaxes `current`, shown as `words`, up to `elapsed` seconds, and
+    /// returns the value `encode` shows it as. The unrounded value carries
+    /// over between updates unless something else changed the words, such
+    /// as a client write, so slow decays still move.
+    pub(super) fn relax(
+        &mut self,
+        current: f64,
+        words: &[u16],
+        elapsed: f64,
+        encode: impl Fn(f64) -> (f64, Vec<u16>),
+    ) -> (f64, Vec<u16>) {
+        let (from, since) = match &self.last {
+            Some((at, exact, shown)) if shown == words => (*exact, *at),
+            Some((at, ..)) => (current, *at),
+            None => (current, elapsed),
+        };
+        let dt = (elapsed - since).max(0.0);
+        let value = self.to + (from - self.to) * (-self.rate * dt).exp();
+        let (shown, words) = encode(value);
+        self.last = Some((elapsed, value, words.clone()));
+        (shown, words)
+    }
+
+    /// Forgets the last update, as at startup.
+    pub(super) fn restart(&mut self) {
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::RegisterItemConfig;
+    use crate::sim::SimState;
+    use std::time::Duration;
+
+    fn item(toml: &str) -> RegisterItemConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn written_values_relax_toward_the_rest_value() {
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![item(
+                "address = 0\ninitial = 20\nupdate_ms = 1\ndecay_to = 20.0\ndecay_rate = 50.0",
+            )],
+            vec![],
+        )
+        .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        state.tick().unwrap();
+        state.write_single_register(0, 1000);
+        let mut previous = 1000;
+        for _ in 0..4 {
+            std::thread::sleep(Duration::from_millis(5));
+            state.tick().unwrap();
+            let value = state.holding_register_value(0);
+            assert!(value < previous && value >= 20, "{value} after {previous}");
+            previous = value;
+        }
+        std::thread::sleep(Duration::from_millis(200));
+        state.tick().unwrap();
+        assert_eq!(state.holding_register_value(0), 20);
+    }
+
+    #[test]
+    fn invalid_decays_are_rejected() {
+        for (config, error) in [
+            ("address = 0\ndecay_to = 1.0", "set together"),
+            (
+                "address = 0\ndecay_to = 1.0\ndecay_rate = 0.0",
+                "must be positive",
+            ),
+            (
+                "address = 0\ndecay_to = 1.0\ndecay_rate = 1.0\n\
+                 dynamics = { kind = \"static\" }",
+                "combined with dynamics",
+            ),
+        ] {
+            let err =
+                SimState::new(500, false, vec![], vec![], vec![], vec![item(config)]).unwrap_err();
+            assert!(format!("{err:#}").contains(error), "{err:#}");
+        }
+    }
+}
@@ -0,0 +1,1107 @@
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+
+use anyhow::{Context, Result, anyhow};
+use evalexpr::{ContextWithMutableVariables, HashMapContext, Value};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+use crate::config::{DynamicsSpec, RegisterSpace, duration};
+
+/// Inputs available to a dynamic when it computes the next value of an item.
+#[derive(Debug, Clone, Copy)]
+pub struct EvalContext {
+    /// Current value of the item; bit items use `0.0`/`1.0`.
+    pub current: f64,
+    /// Seconds since the simulator started.
+    pub elapsed: f64,
+    /// Value of the register named by [`Dynamic::source`] at the start of
+    /// the tick; `None` for dynamics without a source.
+    pub source: Option<f64>,
+}
+
+/// A value generator driving one simulated item.
+///
+/// Implementations are created from an item's `dynamics` table by a
+/// [`DynamicsRegistry`] and may keep state between evaluations.
+pub trait Dynamic: CloneDynamic + Debug + Send + Sync {
+    fn eval(&mut self, ctx: &EvalContext) -> f64;
+
+    /// Hands the item's random stream to the dynamic. Stochastic dynamics keep
+    /// it and draw from it; the default ignores it.
+    fn reseed(&mut self, _rng: StdRng) {}
+
+    /// Register whose value the dynamic reads as [`EvalContext::source`]. The
+    /// default reads none.
+    fn source(&self) -> Option<(RegisterSpace, u16)> {
+        None
+    }
+
+    /// Lowest and highest value the dynamic can produce, when known from its
+    /// parameters alone. The default claims no range.
+    fn output_range(&self) -> Option<(f64, f64)> {
+        None
+    }
+
+    /// Internal state kept between evaluations, by name, for debugging. The
+    /// default keeps none.
+    fn state(&self) -> Vec<(&'static str, f64)> {
+        Vec::new()
+    }
+}
+
+/// Object-safe cloning for boxed dynamics; implemented for every `Clone` dynamic.
+pub trait CloneDynamic {
+    fn clone_dynamic(&self) -> Box<dyn Dynamic>;
+}
+
+impl<T: Dynamic + Clone + 'static> CloneDynamic for T {
+    fn clone_dynamic(&self) -> Box<dyn Dynamic> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Dynamic> {
+    fn clone(&self) -> Self {
+        self.clone_dynamic()
+    }
+}
+
+/// Builds a dynamic from the parameters of a `dynamics` table (without `kind`).
+pub type DynamicFactory = fn(toml::Table) -> Result<Box<dyn Dynamic>>;
+
+/// Maps `kind` tags to the factories that build them.
+#[derive(Debug, Clone, Default)]
+pub struct DynamicsRegistry {
+    factories: BTreeMap<String, DynamicFactory>,
+}
+
+impl DynamicsRegistry {
+    /// A registry with every built-in dynamics kind.
+    pub fn builtin() -> Self {
+        let mut registry = Self::default();
+        registry.register("static", from_params::<Static>);
+        registry.register("clamp", from_params::<Clamp>);
+        registry.register("sine", sine_from_params);
+        registry.register("ramp", from_params::<Ramp>);
+        registry.register("step", from_params::<Step>);
+        registry.register("random-walk", from_params::<RandomWalk>);
+        registry.register("noise", from_params::<Noise>);
+        registry.register("script", script_from_params);
+        registry.register("waveform", waveform_from_params);
+        registry.register("system", system_from_params);
+        registry.register("follow", from_params::<Follow>);
+        registry.register("controlled", from_params::<Controlled>);
+        registry
+    }
+
+    /// Registers `factory` for `kind`, replacing any existing entry.
+    pub fn register(&mut self, kind: &str, factory: DynamicFactory) {
+        self.factories.insert(kind.to_string(), factory);
+    }
+
+    pub fn build(&self, spec: &DynamicsSpec) -> Result<Box<dyn Dynamic>> {
+        let factory = self
+            .factories
+            .get(&spec.kind)
+            .with_context(|| format!("unknown dynamics kind `{}`", spec.kind))?;
+        factory(spec.params.clone()).with_context(|| format!("invalid `{}` dynamics", spec.kind))
+    }
+}
+
+/// A built-in dynamics kind as listed by `modsim dynamics --list`.
+pub struct KindDoc {
+    pub kind: &'static str,
+    pub summary: &'static str,
+    /// Name, type and default of each parameter.
+    pub params: &'static [(&'static str, &'static str, &'static str)],
+}
+
+const DURATION: &str = "duration";
+const REQUIRED: &str = "required";
+const OPTIONAL: &str = "optional";
+
+/// Every kind of [`DynamicsRegistry::builtin`], kept in its order.
+pub const BUILTIN_KINDS: &[KindDoc] = &[
+    KindDoc {
+        kind: "static",
+        summary: "Holds the current value.",
+        params: &[],
+    },
+    KindDoc {
+        kind: "clamp",
+        summary: "Holds the current value within min..max.",
+        params: &[("min", "float", REQUIRED), ("max", "float", REQUIRED)],
+    },
+    KindDoc {
+        kind: "sine",
+        summary: "A sine wave around offset, whose amplitude and offset may vary through nested dynamics.",
+        params: &[
+            (
+                "amplitude",
+                "float",
+                "required without amplitude_dynamics, else 0",
+            ),
+            (
+                "offset",
+                "float",
+                "required without offset_dynamics, else 0",
+            ),
+            ("period_ms", DURATION, REQUIRED),
+            ("amplitude_dynamics", "dynamics table", OPTIONAL),
+            ("offset_dynamics", "dynamics table", OPTIONAL),
+        ],
+    },
+    KindDoc {
+        kind: "ramp",
+        summary: "Rises linearly from min to max every period, then starts over.",
+        params: &[
+            ("min", "float", REQUIRED),
+            ("max", "float", REQUIRED),
+            ("period_ms", DURATION, REQUIRED),
+        ],
+    },
+    KindDoc {
+        kind: "step",
+        summary: "Alternates: low for the first half of every period, high for the second.",
+        params: &[
+            ("low", "float", REQUIRED),
+            ("high", "float", REQUIRED),
+            ("period_ms", DURATION, REQUIRED),
+        ],
+    },
+    KindDoc {
+        kind: "random-walk",
+        summary: "Moves by up to step either way every update, within min..max.",
+        params: &[
+            ("min", "float", REQUIRED),
+            ("max", "float", REQUIRED),
+            ("step", "float", REQUIRED),
+        ],
+    },
+    KindDoc {
+        kind: "noise",
+        summary: "A uniformly random value in min..max every update.",
+        params: &[("min", "float", REQUIRED), ("max", "float", REQUIRED)],
+    },
+    KindDoc {
+        kind: "script",
+        summary: "An evalexpr expression of t, the seconds since start.",
+        params: &[
+            ("expr", "string", "required without script_file"),
+            ("script_file", "path", "required without expr"),
+            ("min", "float", OPTIONAL),
+            ("max", "float", OPTIONAL),
+        ],
+    },
+    KindDoc {
+        kind: "waveform",
+        summary: "A periodic evalexpr expression of t, sampled over one period at load and replayed.",
+        params: &[
+            ("expr", "string", REQUIRED),
+            ("period_ms", DURATION, REQUIRED),
+            ("sample_ms", DURATION, REQUIRED),
+            ("min", "float", OPTIONAL),
+            ("max", "float", OPTIONAL),
+        ],
+    },
+    KindDoc {
+        kind: "system",
+        summary: "A metric of the host read from /proc, times scale.",
+        params: &[
+            ("metric", "cpu-percent | mem-bytes | load-avg", REQUIRED),
+            ("scale", "float", "1"),
+        ],
+    },
+    KindDoc {
+        kind: "follow",
+        summary: "Tracks a source register with a first-order lag.",
+        params: &[
+            ("source", "address", REQUIRED),
+            (
+                "source_space",
+                "holding_registers | input_registers",
+                "input_registers",
+            ),
+            ("tau_ms", DURATION, REQUIRED),
+        ],
+    },
+    KindDoc {
+        kind: "controlled",
+        summary: "Moves toward the setpoint in a holding register under PI control.",
+        params: &[
+            ("setpoint", "address", REQUIRED),
+            ("kp", "float", REQUIRED),
+            ("ki", "float", "0"),
+            ("min", "float", OPTIONAL),
+            ("max", "float", OPTIONAL),
+        ],
+    },
+];
+
+/// [`BUILTIN_KINDS`] as text, one paragraph per kind.
+pub fn describe_kinds() -> String {
+    let mut text = String::new();
+    for doc in BUILTIN_KINDS {
+        text.push_str(&format!("{}\n    {}\n", doc.kind, doc.summary));
+        for (name, ty, default) in doc.params {
+            text.push_str(&format!("    {name}: {ty} ({default})\n"));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// Factory for dynamics whose parameters deserialize straight from the table.
+pub fn from_params<T>(params: toml::Table) -> Result<Box<dyn Dynamic>>
+where
+    T: Dynamic + DeserializeOwned + 'static,
+{
+    Ok(Box::new(toml::Value::Table(params).try_into::<T>()?))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Static {}
+
+impl Dynamic for Static {
+    fn eval(&mut self, ctx: &EvalContext) -> f64 {
+        ctx.current
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Clamp {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Dynamic for Clamp {
+    fn eval(&mut self, ctx: &EvalContext) -> f64 {
+        ctx.current.clamp(self.min, self.max)
+    }
+}
+
+/// A sine wave. `amplitude_dynamics`/`offset_dynamics` make the amplitude
+/// or offset vary: each evaluation first advances them, starting from
+/// `amplitude`/`offset`.
+#[derive(Debug, Clone)]
+pub struct Sine {
+    pub amplitude: f64,
+    pub offset: f64,
+    pub period_ms: u64,
+    pub amplitude_dynamics: Option<Box<dyn Dynamic>>,
+    pub offset_dynamics: Option<Box<dyn Dynamic>>,
+}
+
+#[derive(Deserialize)]
+struct SineParams {
+    amplitude: Option<f64>,
+    offset: Option<f64>,
+    #[serde(deserialize_with = "duration::millis")]
+    period_ms: u64,
+    amplitude_dynamics: Option<DynamicsSpec>,
+    offset_dynamics: Option<DynamicsSpec>,
+}
+
+fn sine_from_params(params: toml::Table) -> Result<Box<dyn Dynamic>> {
+    let params: SineParams = toml::Value::Table(params).try_into()?;
+    let (amplitude, amplitude_dynamics) =
+        nested_param("amplitude", params.amplitude, params.amplitude_dynamics)?;
+    let (offset, offset_dynamics) = nested_param("offset", params.offset, params.offset_dynamics)?;
+    Ok(Box::new(Sine {
+        amplitude,
+        offset,
+        period_ms: params.period_ms,
+        amplitude_dynamics,
+        offset_dynamics,
+    }))
+}
+
+/// A parameter that may vary through nested dynamics, which then start from
+/// its value (default 0). Nested dynamics resolve through the built-in kinds
+/// and cannot nest further or read a source register.
+fn nested_param(
+    name: &str,
+    value: Option<f64>,
+    spec: Option<DynamicsSpec>,
+) -> Result<(f64, Option<Box<dyn Dynamic>>)> {
+    let Some(spec) = spec else {
+        let value = value.with_context(|| format!("missing `{name}`"))?;
+        return Ok((value, None));
+    };
+    let context = || format!("invalid `{name}_dynamics`");
+    if spec.params.keys().any(|key| key.ends_with("_dynamics")) {
+        return Err(anyhow!("nested dynamics cannot nest further")).with_context(context);
+    }
+    let dynamic = DynamicsRegistry::builtin()
+        .build(&spec)
+        .with_context(context)?;
+    if dynamic.source().is_some() {
+        return Err(anyhow!("nested dynamics cannot read a source register")).with_context(context);
+    }
+    Ok((value.unwrap_or(0.0), Some(dynamic)))
+}
+
+impl Dynamic for Sine {
+    fn eval(&mut self, ctx: &EvalContext) -> f64 {
+        let nested = |dynamic: &mut Box<dyn Dynamic>, current| {
+            dynamic.eval(&EvalContext {
+                current,
+                elapsed: ctx.elapsed,
+                source: None,
+            })
+        };
+        if let Some(dynamic) = &mut self.amplitude_dynamics {
+            self.amplitude = nested(dynamic, self.amplitude);
+        }
+        if let Some(dynamic) = &mut self.offset_dynamics {
+            self.offset = nested(dynamic, self.offset);
+        }
+        let period = (self.period_ms as f64) / 1000.0;
+        if period <= 0.0 {
+            return self.offset;
+        }
+        self.offset + self.amplitude * (ctx.elapsed * std::f64::consts::TAU / period).sin()
+    }
+
+    fn output_range(&self) -> Option<(f64, f64)> {
+        if self.amplitude_dynamics.is_some() || self.offset_dynamics.is_some() {
+            return None;
+        }
+        let amplitude = self.amplitude.abs();
+        Some((self.offset - amplitude, self.offset + amplitude))
+    }
+
+    fn reseed(&mut self, mut rng: StdRng) {
+        // Each nested dynamic gets its own stream drawn from the item's.
+        for dynamic in [&mut self.amplitude_dynamics, &mut self.offset_dynamics]
+            .into_iter()
+            .flatten()
+        {
+            dynamic.reseed(StdRng::seed_from_u64(rng.r#gen()));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Ramp {
+    pub min: f64,
+    pub max: f64,
+    #[serde(deserialize_with = "duration::millis")]
+    pub period_ms: u64,
+}
+
+impl Dynamic for Ramp {
+    fn eval(&mut self, ctx: &EvalContext) -> f64 {
+        let period = (self.period_ms as f64) / 1000.0;
+        if period <= 0.0 {
+            return self.min;
+        }
+        let phase = (ctx.elapsed % period) / period;
+        self.min + (self.max - self.min) * phase
+    }
+
+    fn output_range(&self) -> Option<(f64, f64)> {
+        Some((self.min.min(self.max), self.min.max(self.max)))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Step {
+    pub low: f64,
+    pub high: f64,
+    #[serde(deserialize_with = "duration::millis")]
+    pub period_ms: u64,
+}
+
+impl Dynamic for Step {
+    fn eval(&mut self, ctx: &EvalContext) -> f64 {
+        let period = (self.period_ms as f64) / 1000.0;
+        if period <= 0.0 {
+            return self.low;
+        }
+        let phase = (ctx.elapsed % period) / period;
+        if phase < 0.5 { self.low } else { self.high }
+    }
+
+    fn output_range(&self) -> Option<(f64, f64)> {
+        Some((self.low.min(self.high), self.low.max(self.high)))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RandomWalk {
+    pub min: f64,
+    pub max: f64,
+    pub step: f64,
+    #[serde(skip, default = "entropy_rng")]
+    rng: StdRng,
+}
+
+impl Dynamic for RandomWalk {
+    fn eval(&mut self, ctx: &EvalContext) -> f64 {
+        let delta = self.rng.gen_range(-self.step..=self.step);
+        (ctx.current + delta).clamp(self.min, self.max)
+    }
+
+    fn reseed(&mut self, rng: StdRng) {
+        self.rng = rng;
+    }
+
+    fn output_range(&self) -> Option<(f64, f64)> {
+        Some((self.min, self.max))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Noise {
+    pub min: f64,
+    pub max: f64,
+    #[serde(skip, default = "entropy_rng")]
+    rng: StdRng,
+}
+
+impl Dynamic for Noise {
+    fn eval(&mut self, _ctx: &EvalContext) -> f64 {
+        self.rng.gen_range(self.min..=self.max)
+    }
+
+    fn reseed(&mut self, rng: StdRng) {
+        self.rng = rng;
+    }
+
+    fn output_range(&self) -> Option<(f64, f64)> {
+        Some((self.min, self.max))
+    }
+}
+
+/// Tracks a source register with a first-order lag of time constant
+/// `tau_ms`: after `tau_ms` the item has covered 63% of a step in the source.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Follow {
+    pub source: u16,
+    #[serde(default)]
+    pub source_space: RegisterSpace,
+    #[serde(deserialize_with = "duration::millis")]
+    pub tau_ms: u64,
+    /// Unrounded output and when it was computed, so slow approaches are not
+    /// lost to rounding between evaluations.
+    #[serde(skip)]
+    last: Option<(f64, f64)>,
+}
+
+impl Dynamic for Follow {
+    fn eval(&mut self, ctx: &EvalContext) -> f64 {
+        let Some(target) = ctx.source else {
+            return ctx.current;
+        };
+        let (value, since) = match self.last {
+            // A client write replaces the value being smoothed.
+            Some((value, since)) if value.round() == ctx.current.round() => (value, since),
+            Some((_, since)) => (ctx.current, since),
+            None => (ctx.current, 0.0),
+        };
+        let dt_ms = (ctx.elapsed - since).max(0.0) * 1000.0;
+        let value = if self.tau_ms == 0 {
+            target
+        } else {
+            value + (target - value) * (1.0 - (-dt_ms / self.tau_ms as f64).exp())
+        };
+        self.last = Some((value, ctx.elapsed));
+        value
+    }
+
+    fn source(&self) -> Option<(RegisterSpace, u16)> {
+        Some((self.source_space, self.source))
+    }
+
+    fn state(&self) -> Vec<(&'static str, f64)> {
+        self.last
+            .map(|(value, since)| vec![("value", value), ("updated_at", since)])
+            .unwrap_or_default()
+    }
+}
+
+/// Drives the item toward the holding register `setpoint` like a PI
+/// controller: the value moves by `kp` times the error plus `ki` times the
+/// error integrated over time, per second, and stays within `min`/`max`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Controlled {
+    pub setpoint: u16,
+    pub kp: f64,
+    #[serde(default)]
+    pub ki: f64,
+    #[serde(default)]
+    pub min: Option<f64>,
+    #[serde(default)]
+    pub max: Option<f64>,
+    /// Error integrated over time, in value-seconds.
+    #[serde(skip)]
+    integral: f64,
+    /// Unrounded output and when it was computed, as for [`Follow`].
+    #[serde(skip)]
+    last: Option<(f64, f64)>,
+}
+
+impl Dynamic for Controlled {
+    fn eval(&mut self, ctx: &EvalContext) -> f64 {
+        let Some(setpoint) = ctx.source else {
+            return ctx.current;
+        };
+        let (value, since) = match self.last {
+            Some((value, since)) if value.round() == ctx.current.round() => (value, since),
+            Some((_, since)) => (ctx.current, since),
+            None => (ctx.current, 0.0),
+        };
+        let dt = (ctx.elapsed - since).max(0.0);
+        let error = setpoint - value;
+        let integral = self.integral + error * dt;
+        let unclamped = value + (self.kp * error + self.ki * integral) * dt;
+        let output = clamp_optional(unclamped, self.min, self.max).clamp(0.0, f64::from(u16::MAX));
+        // No integration while the output is held at a limit, so the
+        // integral does not wind up.
+        if output == unclamped {
+            self.integral = integral;
+        }
+        self.last = Some((output, ctx.elapsed));
+        output
+    }
+
+    fn source(&self) -> Option<(RegisterSpace, u16)> {
+        Some((RegisterSpace::HoldingRegisters, self.setpoint))
+    }
+
+    fn state(&self) -> Vec<(&'static str, f64)> {
+        let mut state = vec![("integral", self.integral)];
+        if let Some((value, since)) = self.last {
+            state.extend([("value", value), ("updated_at", since)]);
+        }
+        state
+    }
+}
+
+fn entropy_rng() -> StdRng {
+    StdRng::from_entropy()
+}
+
+#[derive(Debug, Clone)]
+pub struct Script {
+    pub expr: String,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct ScriptParams {
+    expr: Option<String>,
+    /// File holding the expression, read once at load.
+    script_file: Option<String>,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+fn script_from_params(params: toml::Table) -> Result<Box<dyn Dynamic>> {
+    let params: ScriptParams = toml::Value::Table(params).try_into()?;
+    let expr = match (params.expr, params.script_file) {
+        (Some(expr), None) => expr,
+        (None, Some(path)) => {
+            let expr = std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read script file {path}"))?;
+            evalexpr::build_operator_tree(&expr)
+                .with_context(|| format!("failed to parse script file {path}"))?;
+            expr
+        }
+        (Some(_), Some(_)) => return Err(anyhow!("set either `expr` or `script_file`, not both")),
+        (None, None) => return Err(anyhow!("missing `expr` or `script_file`")),
+    };
+    Ok(Box::new(Script {
+        expr,
+        min: params.min,
+        max: params.max,
+    }))
+}
+
+impl Dynamic for Script {
+    fn eval(&mut self, ctx: &EvalContext) -> f64 {
+        let value = eval_expr(&self.expr, &[("t", ctx.elapsed)]).unwrap_or(ctx.current);
+        clamp_optional(value, self.min, self.max)
+    }
+
+    fn output_range(&self) -> Option<(f64, f64)> {
+        Some((self.min?, self.max?))
+    }
+}
+
+/// Most samples a waveform may hold.
+const MAX_WAVEFORM_SAMPLES: u64 = 1_000_000;
+
+/// A `script` expression sampled every `sample_ms` over its first period at
+/// load; each evaluation looks up the sample at or before the time into
+/// the period instead of evaluating the expression.
+#[derive(Debug, Clone)]
+pub struct Waveform {
+    pub samples: Vec<f64>,
+    pub sample_ms: u64,
+    pub period_ms: u64,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct WaveformParams {
+    expr: String,
+    #[serde(deserialize_with = "duration::millis")]
+    period_ms: u64,
+    #[serde(deserialize_with = "duration::millis")]
+    sample_ms: u64,
+    min: Option<f64>,
+    max: Option<f64>,
+}
+
+fn waveform_from_params(params: toml::Table) -> Result<Box<dyn Dynamic>> {
+    let params: WaveformParams = toml::Value::Table(params).try_into()?;
+    if params.period_ms == 0 || params.sample_ms == 0 {
+        return Err(anyhow!("period_ms and sample_ms must be positive"));
+    }
+    if params.sample_ms > params.period_ms {
+        return Err(anyhow!(
+            "sample_ms ({}) must not exceed period_ms ({})",
+            params.sample_ms,
+            params.period_ms
+        ));
+    }
+    let count = params.period_ms.div_ceil(params.sample_ms);
+    if count > MAX_WAVEFORM_SAMPLES {
+        return Err(anyhow!(
+            "{count} samples per period, more than {MAX_WAVEFORM_SAMPLES}; raise sample_ms"
+        ));
+    }
+    evalexpr::build_operator_tree(&params.expr).context("failed to parse expr")?;
+    let samples = (0..count)
+        .map(|index| {
+            let t = (index * params.sample_ms) as f64 / 1000.0;
+            eval_expr(&params.expr, &[("t", t)])
+                .filter(|value| value.is_finite())
+                .with_context(|| format!("expr does not yield a finite number at t = {t}"))
+        })
+        .collect::<Result<_>>()?;
+    Ok(Box::new(Waveform {
+        samples,
+        sample_ms: params.sample_ms,
+        period_ms: params.period_ms,
+        min: params.min,
+        max: params.max,
+    }))
+}
+
+impl Dynamic for Waveform {
+    fn eval(&mut self, ctx: &EvalContext) -> f64 {
+        let into_period = (ctx.elapsed * 1000.0).rem_euclid(self.period_ms as f64);
+        let index = (into_period / self.sample_ms as f64) as usize;
+        let value = self.samples[index.min(self.samples.len() - 1)];
+        clamp_optional(value, self.min, self.max)
+    }
+
+    fn output_range(&self) -> Option<(f64, f64)> {
+        Some((self.min?, self.max?))
+    }
+}
+
+fn clamp_optional(value: f64, min: Option<f64>, max: Option<f64>) -> f64 {
+    match (min, max) {
+        (Some(min), Some(max)) => value.clamp(min, max),
+        (Some(min), None) => value.max(min),
+        (None, Some(max)) => value.min(max),
+        _ => value,
+    }
+}
+
+/// Evaluates `expr` with `variables` bound, if it yields a number.
+pub(super) fn eval_expr(expr: &str, variables: &[(&str, f64)]) -> Option<f64> {
+    let expr = expr.trim();
+    let mut context = HashMapContext::new();
+    for (name, value) in variables {
+        context
+            .set_value(name.to_string(), Value::Float(*value))
+            .ok()?;
+    }
+    let value = evalexpr::eval_with_context_mut(expr, &mut context).ok()?;
+    match value {
+        Value::Int(value) => Some(value as f64),
+        Value::Float(value) => Some(value),
+        _ => None,
+    }
+}
+
+/// Host metric reported by a `system` dynamic.
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SystemMetric {
+    /// Host CPU busy time since the previous sample, 0-100.
+    CpuPercent,
+    /// Resident memory of this process.
+    MemBytes,
+    /// One-minute load average.
+    LoadAvg,
+}
+
+/// Reports a metric of the host, read from `/proc` at the item's cadence and
+/// multiplied by `scale`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct System {
+    pub metric: SystemMetric,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    /// Busy and total CPU time at the previous sample.
+    #[serde(skip)]
+    cpu_times: Option<(u64, u64)>,
+}
+
+impl System {
+    fn sample(&mut self) -> Result<f64> {
+        match self.metric {
+            SystemMetric::CpuPercent => {
+                let (busy, total) = parse_cpu_times(&read_proc("/proc/stat")?)?;
+                let (last_busy, last_total) =
+                    self.cpu_times.replace((busy, total)).unwrap_or((0, 0));
+                let total = total.saturating_sub(last_total);
+                if total == 0 {
+                    return Ok(0.0);
+                }
+                Ok(100.0 * busy.saturating_sub(last_busy) as f64 / total as f64)
+            }
+            SystemMetric::MemBytes => parse_rss_bytes(&read_proc("/proc/self/status")?),
+            SystemMetric::LoadAvg => parse_load_avg(&read_proc("/proc/loadavg")?),
+        }
+    }
+}
+
+impl Dynamic for System {
+    fn eval(&mut self, ctx: &EvalContext) -> f64 {
+        // A metric that stops being readable holds the value.
+        self.sample()
+            .map_or(ctx.current, |value| value * self.scale)
+    }
+
+    fn state(&self) -> Vec<(&'static str, f64)> {
+        self.cpu_times
+            .map(|(busy, total)| vec![("cpu_busy", busy as f64), ("cpu_total", total as f64)])
+            .unwrap_or_default()
+    }
+}
+
+/// Builds a `system` dynamic, sampling once so a host without the metric
+/// fails at load and the first CPU reading covers a real interval.
+fn system_from_params(params: toml::Table) -> Result<Box<dyn Dynamic>> {
+    let mut system: System = toml::Value::Table(params).try_into()?;
+    system.sample()?;
+    Ok(Box::new(system))
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn read_proc(path: &str) -> Result<String> {
+    std::fs::read_to_string(path).with_context(|| format!("failed to read {path}"))
+}
+
+/// Busy and total time from the aggregate `cpu` line of `/proc/stat`; idle
+/// and iowait count as not busy.
+fn parse_cpu_times(stat: &str) -> Result<(u64, u64)> {
+    let times: Vec<u64> = stat
+        .lines()
+        .find_map(|line| line.strip_prefix("cpu "))
+        .context("no cpu line in /proc/stat")?
+        .split_whitespace()
+        .take(8)
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .context("invalid cpu line in /proc/stat")?;
+    if times.len() < 5 {
+        anyhow::bail!("short cpu line in /proc/stat");
+    }
+    let total = times.iter().sum::<u64>();
+    Ok((total - times[3] - times[4], total))
+}
+
+fn parse_rss_bytes(status: &str) -> Result<f64> {
+    let kib: f64 = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|rest| rest.trim().trim_end_matches("kB").trim().parse().ok())
+        .context("no VmRSS in /proc/self/status")?;
+    Ok(kib * 1024.0)
+}
+
+fn parse_load_avg(loadavg: &str) -> Result<f64> {
+    loadavg
+        .split_whitespace()
+        .next()
+        .and_then(|load| load.parse().ok())
+        .context("invalid /proc/loadavg")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(s: &str) -> DynamicsSpec {
+        toml::from_str(s).expect("parse dynamics")
+    }
+
+    #[test]
+    fn kind_docs_cover_the_builtin_registry() {
+        let mut documented: Vec<&str> = BUILTIN_KINDS.iter().map(|doc| doc.kind).collect();
+        documented.sort_unstable();
+        let registry = DynamicsRegistry::builtin();
+        let registered: Vec<&str> = registry.factories.keys().map(String::as_str).collect();
+        assert_eq!(documented, registered);
+        assert!(describe_kinds().contains("follow\n    Tracks a source register"));
+    }
+
+    #[test]
+    fn builtin_registry_builds_known_kinds() {
+        let registry = DynamicsRegistry::builtin();
+        let mut step = registry
+            .build(&spec(
+                "kind = \"step\"\nlow = 1.0\nhigh = 9.0\nperiod_ms = 1000",
+            ))
+            .unwrap();
+        let ctx = EvalContext {
+            current: 0.0,
+            elapsed: 0.75,
+            source: None,
+        };
+        assert_eq!(step.eval(&ctx), 9.0);
+    }
+
+    #[test]
+    fn sine_parameters_follow_nested_dynamics() {
+        let registry = DynamicsRegistry::builtin();
+        let mut sine = registry
+            .build(&spec(
+                "kind = \"sine\"\nperiod_ms = 3000\noffset = 100.0\n\
+                 amplitude_dynamics = { kind = \"step\", low = 0.0, high = 10.0, period_ms = 1000 }",
+            ))
+            .unwrap();
+        let at = |elapsed| EvalContext {
+            current: 0.0,
+            elapsed,
+            source: None,
+        };
+        // Step low for the first half second: no swing at all.
+        assert_eq!(sine.eval(&at(0.25)), 100.0);
+        assert!((sine.eval(&at(0.75)) - 110.0).abs() < 1e-9);
+
+        let err = registry
+            .build(&spec(
+                "kind = \"sine\"\nperiod_ms = 4000\noffset = 0.0\n\
+                 amplitude_dynamics = { kind = \"sine\", offset = 1.0, period_ms = 10, \
+                 amplitude_dynamics = { kind = \"step\", low = 0.0, high = 1.0, period_ms = 1 } }",
+            ))
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("cannot nest further"),
+            "{err:#}"
+        );
+        let err = registry
+            .build(&spec("kind = \"sine\"\nperiod_ms = 4000\noffset = 0.0"))
+            .unwrap_err();
+        assert!(
+            format!("{err:#}").contains("missing `amplitude`"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn controlled_approaches_the_setpoint() {
+        let registry = DynamicsRegistry::builtin();
+        let mut controlled = registry
+            .build(&spec(
+                "kind = \"controlled\"\nsetpoint = 3\nkp = 1.0\nki = 0.5\nmax = 90.0",
+            ))
+            .unwrap();
+        assert_eq!(
+            controlled.source(),
+            Some((RegisterSpace::HoldingRegisters, 3))
+        );
+        let mut current = 0.0;
+        let mut at = |elapsed| {
+            current = controlled.eval(&EvalContext {
+                current,
+                elapsed,
+                source: Some(100.0),
+            });
+            current
+        };
+        assert_eq!(at(0.0), 0.0);
+        // P term 100 and integral 50 over half a second.
+        assert_eq!(at(0.5), 62.5);
+        // The limit holds the output and stops the integral.
+        assert_eq!(at(1.0), 90.0);
+        assert_eq!(at(1.5), 90.0);
+    }
+
+    #[test]
+    fn script_expressions_load_from_files() {
+        let path = std::env::temp_dir().join(format!("modsim-script-{}.expr", std::process::id()));
+        std::fs::write(&path, "42\n").unwrap();
+        let registry = DynamicsRegistry::builtin();
+        let build = |params: &str| {
+            registry.build(&spec(&format!(
+                "kind = \"script\"\n{params}\nscript_file = {:?}",
+                path.to_str().unwrap()
+            )))
+        };
+        let mut script = build("").unwrap();
+        let ctx = EvalContext {
+            current: 0.0,
+            elapsed: 1.0,
+            source: None,
+        };
+        assert_eq!(script.eval(&ctx), 42.0);
+        let err = build("expr = \"1\"").unwrap_err();
+        assert!(format!("{err:#}").contains("not both"), "{err:#}");
+        std::fs::remove_file(&path).unwrap();
+        let err = build("").unwrap_err();
+        assert!(
+            format!("{err:#}").contains("failed to read script file"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn waveforms_replay_samples_of_one_period() {
+        let registry = DynamicsRegistry::builtin();
+        let build = |params: &str| registry.build(&spec(&format!("kind = \"waveform\"\n{params}")));
+        let mut waveform =
+            build("expr = \"t\"\nperiod_ms = 1000\nsample_ms = \"250ms\"\nmax = 0.6").unwrap();
+        let mut at = |elapsed| {
+            waveform.eval(&EvalContext {
+                current: 0.0,
+                elapsed,
+                source: None,
+            })
+        };
+        assert_eq!(at(0.0), 0.0);
+        assert_eq!(at(0.3), 0.25);
+        assert_eq!(at(0.6), 0.5);
+        assert_eq!(at(0.9), 0.6);
+        // The next period replays the first.
+        assert_eq!(at(1.3), 0.25);
+
+        for (params, error) in [
+            (
+                "expr = \"t\"\nperiod_ms = 0\nsample_ms = 1",
+                "must be positive",
+            ),
+            (
+                "expr = \"t\"\nperiod_ms = 10\nsample_ms = 20",
+                "must not exceed",
+            ),
+            (
+                "expr = \"t\"\nperiod_ms = \"1h\"\nsample_ms = 1",
+                "raise sample_ms",
+            ),
+        ] {
+            let err = build(params).unwrap_err();
+            assert!(format!("{err:#}").contains(error), "{err:#}");
+        }
+    }
+
+    #[test]
+    fn system_metrics_parse_proc_files() {
+        let stat = "cpu  100 0 50 800 50 0 0 0 0 0\ncpu0 50 0 25 400 25 0 0 0 0 0\n";
+        assert_eq!(parse_cpu_times(stat).unwrap(), (150, 1000));
+        let status = "Name:\tmodsim\nVmRSS:\t    2048 kB\n";
+        assert_eq!(parse_rss_bytes(status).unwrap(), 2_097_152.0);
+        assert_eq!(parse_load_avg("0.52 0.48 0.40 1/123 4567\n").unwrap(), 0.52);
+        assert!(parse_cpu_times("intr 1 2 3").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn system_dynamics_sample_the_host() {
+        let registry = DynamicsRegistry::builtin();
+        let mut memory = registry
+            .build(&spec(
+                "kind = \"system\"\nmetric = \"mem-bytes\"\nscale = 0.5",
+            ))
+            .unwrap();
+        let ctx = EvalContext {
+            current: 0.0,
+            elapsed: 0.0,
+            source: None,
+        };
+        assert!(memory.eval(&ctx) > 0.0);
+        assert!(
+            registry
+                .build(&spec("kind = \"system\"\nmetric = \"disk\""))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn follow_lags_behind_its_source() {
+        let registry = DynamicsRegistry::builtin();
+        let mut follow = registry
+            .build(&spec("kind = \"follow\"\nsource = 3\ntau_ms = 1000"))
+            .unwrap();
+        assert_eq!(follow.source(), Some((RegisterSpace::InputRegisters, 3)));
+        let at = |current, elapsed| EvalContext {
+            current,
+            elapsed,
+            source: Some(100.0),
+        };
+        let value = follow.eval(&at(0.0, 1.0));
+        assert!((value - 63.2).abs() < 0.1, "{value}");
+        // Rounded to 63 in the register, the next step continues from 63.2.
+        let value = follow.eval(&at(63.0, 2.0));
+        assert!((value - 86.5).abs() < 0.1, "{value}");
+    }
+
+    #[test]
+    fn unknown_kind_is_rejected() {
+        let registry = DynamicsRegistry::builtin();
+        assert!(registry.build(&spec("kind = \"bogus\"")).is_err());
+    }
+
+    #[test]
+    fn missing_parameters_are_rejected() {
+        let registry = DynamicsRegistry::builtin();
+        assert!(
+            registry
+                .build(&spec("kind = \"sine\"\namplitude = 1.0"))
+                .is_err()
+        );
+    }
+
+    #[derive(Debug, Clone, Deserialize)]
+    struct Constant {
+        value: f64,
+    }
+
+    impl Dynamic for Constant {
+        fn eval(&mut self, _ctx: &EvalContext) -> f64 {
+            self.value
+        }
+    }
+
+    #[test]
+    fn custom_kinds_can_be_registered() {
+        let mut registry = DynamicsRegistry::builtin();
+        registry.register("constant", from_params::<Constant>);
+        let mut dynamic = registry
+            .build(&spec("kind = \"constant\"\nvalue = 42.0"))
+            .unwrap();
+        let ctx = EvalContext {
+            current: 0.0,
+            elapsed: 0.0,
+            source: None,
+        };
+        assert_eq!(dynamic.eval(&ctx), 42.0);
+    }
+}
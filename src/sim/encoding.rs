@@ -0,0 +1,340 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Result, anyhow, bail};
+
+use super::SimRegisterItem;
+use crate::config::{ByteOrder, Encoding, Initial, RegisterItemConfig, WordOrder};
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::U16 => "u16",
+            Encoding::I16 => "i16",
+            Encoding::U32 => "u32",
+            Encoding::I32 => "i32",
+            Encoding::F32 => "f32",
+            Encoding::U64 => "u64",
+            Encoding::I64 => "i64",
+            Encoding::F64 => "f64",
+        }
+    }
+
+    /// Whether `value` is representable in this encoding without saturating:
+    /// rounded into the range of integer encodings, finite for float ones.
+    pub fn fits(self, value: f64) -> bool {
+        let (low, high) = match self {
+            Encoding::U16 => (0.0, u16::MAX as f64),
+            Encoding::I16 => (i16::MIN as f64, i16::MAX as f64),
+            Encoding::U32 => (0.0, u32::MAX as f64),
+            Encoding::I32 => (i32::MIN as f64, i32::MAX as f64),
+            Encoding::U64 => (0.0, u64::MAX as f64),
+            Encoding::I64 => (i64::MIN as f64, i64::MAX as f64),
+            Encoding::F32 => return (value as f32).is_finite(),
+            Encoding::F64 => return value.is_finite(),
+        };
+        (low..=high).contains(&value.round())
+    }
+
+    /// Number of registers a value of this encoding spans.
+    pub fn width(self) -> u16 {
+        match self {
+            Encoding::U16 | Encoding::I16 => 1,
+            Encoding::U32 | Encoding::I32 | Encoding::F32 => 2,
+            Encoding::U64 | Encoding::I64 | Encoding::F64 => 4,
+        }
+    }
+
+    /// Bits of `value` in this encoding, rounded and saturated to its range
+    /// for integer encodings.
+    fn bits(self, value: f64) -> u64 {
+        match self {
+            Encoding::U16 => u64::from(value.round() as u16),
+            Encoding::I16 => u64::from(value.round() as i16 as u16),
+            Encoding::U32 => u64::from(value.round() as u32),
+            Encoding::I32 => u64::from(value.round() as i32 as u32),
+            Encoding::F32 => u64::from((value as f32).to_bits()),
+            Encoding::U64 => value.round() as u64,
+            Encoding::I64 => value.round() as i64 as u64,
+            Encoding::F64 => value.to_bits(),
+        }
+    }
+
+    /// Bits of the exact integer `value` in this encoding, or `None` if it
+    /// is out of range. Float encodings take the nearest float.
+    pub fn int_bits(self, value: i128) -> Option<u64> {
+        Some(match self {
+            Encoding::U16 => u64::from(u16::try_from(value).ok()?),
+            Encoding::I16 => u64::from(i16::try_from(value).ok()? as u16),
+            Encoding::U32 => u64::from(u32::try_from(value).ok()?),
+            Encoding::I32 => u64::from(i32::try_from(value).ok()? as u32),
+            Encoding::U64 => u64::try_from(value).ok()?,
+            Encoding::I64 => i64::try_from(value).ok()? as u64,
+            Encoding::F32 | Encoding::F64 => self.bits(value as f64),
+        })
+    }
+}
+
+/// Decodes the logical value stored in `words`, most significant word first
+/// unless `word_order` says otherwise. Returns `None` if too few words are given.
+pub fn decode(
+    encoding: Encoding,
+    word_order: WordOrder,
+    byte_order: ByteOrder,
+    words: &[u16],
+) -> Option<f64> {
+    let width = encoding.width() as usize;
+    if words.len() < width {
+        return None;
+    }
+    let bits = join(word_order, byte_order, &words[..width]);
+    Some(match encoding {
+        Encoding::U16 => bits as u16 as f64,
+        Encoding::I16 => bits as u16 as i16 as f64,
+        Encoding::U32 => bits as u32 as f64,
+        Encoding::I32 => bits as u32 as i32 as f64,
+        // Go through the shortest f32 representation so 23.4 is not shown as
+        // 23.399999618530273.
+        Encoding::F32 => f32::from_bits(bits as u32)
+            .to_string()
+            .parse()
+            .unwrap_or(f64::NAN),
+        Encoding::U64 => bits as f64,
+        Encoding::I64 => bits as i64 as f64,
+        Encoding::F64 => f64::from_bits(bits),
+    })
+}
+
+/// The bits of the value stored in `words`, the inverse of [`split`].
+fn join(word_order: WordOrder, byte_order: ByteOrder, words: &[u16]) -> u64 {
+    let mut words = words.to_vec();
+    if word_order == WordOrder::Little {
+        words.reverse();
+    }
+    if byte_order == ByteOrder::Little {
+        for word in &mut words {
+            *word = word.swap_bytes();
+        }
+    }
+    words
+        .iter()
+        .fold(0u64, |acc, word| (acc << 16) | *word as u64)
+}
+
+/// Encodes `value` into the words of `encoding`, the inverse of [`decode`].
+/// Integer encodings round and saturate.
+pub fn encode(
+    encoding: Encoding,
+    word_order: WordOrder,
+    byte_order: ByteOrder,
+    value: f64,
+) -> Vec<u16> {
+    split(encoding, word_order, byte_order, encoding.bits(value))
+}
+
+/// Splits the `bits` of a value in `encoding` into its words.
+pub fn split(
+    encoding: Encoding,
+    word_order: WordOrder,
+    byte_order: ByteOrder,
+    bits: u64,
+) -> Vec<u16> {
+    let width = encoding.width();
+    let mut words: Vec<u16> = (0..width)
+        .rev()
+        .map(|word| (bits >> (16 * word)) as u16)
+        .collect();
+    if byte_order == ByteOrder::Little {
+        for word in &mut words {
+            *word = word.swap_bytes();
+        }
+    }
+    if word_order == WordOrder::Little {
+        words.reverse();
+    }
+    words
+}
+
+/// The raw words of the value the item at `address` of `map` starts, zero
+/// for registers that are not configured, and its engineering value.
+pub fn decode_item(map: &BTreeMap<u16, SimRegisterItem>, address: u16) -> Option<(Vec<u16>, f64)> {
+    let item = map.get(&address)?;
+    let (raw, value) = decode_raw(map, address, item)?;
+    Some((raw, item.to_engineering(value)))
+}
+
+/// The value the item at `address` of `map` holds, as the `initial` that
+/// starts it there again; exact for integer encodings.
+pub fn current_initial(map: &BTreeMap<u16, SimRegisterItem>, address: u16) -> Option<Initial> {
+    let item = map.get(&address)?;
+    let (raw, value) = decode_raw(map, address, item)?;
+    let bits = join(item.word_order, item.byte_order, &raw);
+    Some(match item.encoding {
+        // The shortest decimal that is the same `f32`, so `23.4` does not
+        // come back as `23.399999618530273`.
+        Encoding::F32 => Initial::Float((value as f32).to_string().parse().unwrap_or(value)),
+        Encoding::F64 => Initial::Float(value),
+        Encoding::U16 => Initial::Int((bits as u16).into()),
+        Encoding::I16 => Initial::Int((bits as u16 as i16).into()),
+        Encoding::U32 => Initial::Int((bits as u32).into()),
+        Encoding::I32 => Initial::Int((bits as u32 as i32).into()),
+        Encoding::U64 => Initial::Int(bits.into()),
+        Encoding::I64 => Initial::Int((bits as i64).into()),
+    })
+}
+
+/// The raw words of the value `item` at `address` of `map` starts, zero for
+/// registers that are not configured, and that value decoded.
+pub(super) fn decode_raw(
+    map: &BTreeMap<u16, SimRegisterItem>,
+    address: u16,
+    item: &SimRegisterItem,
+) -> Option<(Vec<u16>, f64)> {
+    let raw: Vec<u16> = (0..item.encoding.width())
+        .map(|offset| {
+            address
+                .checked_add(offset)
+                .and_then(|address| map.get(&address))
+                .map(|item| item.value)
+                .unwrap_or(0)
+        })
+        .collect();
+    let value = decode(item.encoding, item.word_order, item.byte_order, &raw)?;
+    Some((raw, value))
+}
+
+/// The words and decoded value of the items of `map` with an encoding
+/// other than `u16`, whose dynamics run on that value.
+pub fn logical_values(map: &BTreeMap<u16, SimRegisterItem>) -> BTreeMap<u16, (f64, Vec<u16>)> {
+    map.iter()
+        .filter(|(_, item)| item.encoding != Encoding::U16)
+        .filter_map(|(&address, item)| {
+            let (raw, value) = decode_raw(map, address, item)?;
+            Some((address, (value, raw)))
+        })
+        .collect()
+}
+
+/// The words of the initial value of `item`, first register first.
+pub fn initial_words(item: &RegisterItemConfig) -> Result<Vec<u16>> {
+    let encoding = item.encoding;
+    let out_of_range = || anyhow!("initial {} does not fit {}", item.initial, encoding.name());
+    let bits = match item.initial {
+        Initial::Float(value) if matches!(encoding, Encoding::F32 | Encoding::F64) => {
+            encoding.bits(value)
+        }
+        Initial::Float(value) if value.fract() == 0.0 => {
+            encoding.int_bits(value as i128).ok_or_else(out_of_range)?
+        }
+        Initial::Float(value) => {
+            bail!(
+                "initial {value} is not a whole number, as {} needs",
+                encoding.name()
+            )
+        }
+        Initial::Int(value) => encoding.int_bits(value).ok_or_else(out_of_range)?,
+    };
+    Ok(split(encoding, item.word_order, item.byte_order, bits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_f32_big_endian() {
+        let bits = 23.4f32.to_bits();
+        let words = [(bits >> 16) as u16, bits as u16];
+        let value = decode(Encoding::F32, WordOrder::Big, ByteOrder::Big, &words);
+        assert_eq!(value, Some(23.4));
+    }
+
+    #[test]
+    fn decodes_i32_with_swapped_words() {
+        let bits = (-2i32) as u32;
+        let words = [bits as u16, (bits >> 16) as u16];
+        let value = decode(Encoding::I32, WordOrder::Little, ByteOrder::Big, &words);
+        assert_eq!(value, Some(-2.0));
+    }
+
+    #[test]
+    fn decodes_i16_with_swapped_bytes() {
+        let value = decode(Encoding::I16, WordOrder::Big, ByteOrder::Little, &[0xFFFE]);
+        assert_eq!(value, Some(-257.0));
+    }
+
+    #[test]
+    fn encodings_round_trip_in_every_order() {
+        let cases = [
+            (Encoding::U16, 65_000.0),
+            (Encoding::I16, -300.0),
+            (Encoding::U32, 4_000_000_000.0),
+            (Encoding::I32, -2_000_000_000.0),
+            (Encoding::F32, 23.4),
+            (Encoding::U64, 18_000_000_000_000_000_000.0),
+            (Encoding::I64, -9_000_000_000_000_000_000.0),
+            (Encoding::F64, -1234.5678),
+        ];
+        for (encoding, value) in cases {
+            for word_order in [WordOrder::Big, WordOrder::Little] {
+                for byte_order in [ByteOrder::Big, ByteOrder::Little] {
+                    let words = encode(encoding, word_order, byte_order, value);
+                    assert_eq!(words.len(), usize::from(encoding.width()));
+                    assert_eq!(
+                        decode(encoding, word_order, byte_order, &words),
+                        Some(value),
+                        "{encoding:?} {word_order:?} {byte_order:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn u64_words_are_most_significant_first() {
+        let bits = Encoding::U64.int_bits(0x0001_0002_0003_0004).unwrap();
+        let words = split(Encoding::U64, WordOrder::Big, ByteOrder::Big, bits);
+        assert_eq!(words, vec![1, 2, 3, 4]);
+        let words = split(Encoding::U64, WordOrder::Little, ByteOrder::Big, bits);
+        assert_eq!(words, vec![4, 3, 2, 1]);
+        // Exact beyond the 53 bits of an f64 mantissa.
+        let bits = Encoding::U64.int_bits(u64::MAX.into()).unwrap();
+        assert_eq!(bits, u64::MAX);
+        assert_eq!(Encoding::U16.int_bits(70_000), None);
+        assert_eq!(Encoding::I64.int_bits(-1), Some(u64::MAX));
+    }
+
+    #[test]
+    fn initial_values_are_split_into_words() {
+        let item = |encoding, initial| RegisterItemConfig {
+            encoding,
+            initial,
+            ..Default::default()
+        };
+        let words = initial_words(&item(Encoding::U64, Initial::Int(u64::MAX.into()))).unwrap();
+        assert_eq!(words, vec![u16::MAX; 4]);
+        let words = initial_words(&item(Encoding::I16, Initial::Int(-2))).unwrap();
+        assert_eq!(words, vec![0xfffe]);
+        let words = initial_words(&item(Encoding::F64, Initial::Int(1))).unwrap();
+        assert_eq!(words, vec![0x3ff0, 0, 0, 0]);
+        let words = initial_words(&item(Encoding::U32, Initial::Float(65536.0))).unwrap();
+        assert_eq!(words, vec![1, 0]);
+        assert!(initial_words(&item(Encoding::U16, Initial::Int(-1))).is_err());
+        assert!(initial_words(&item(Encoding::I64, Initial::Float(1.5))).is_err());
+    }
+
+    #[test]
+    fn integer_encodings_saturate() {
+        let words = encode(Encoding::I16, WordOrder::Big, ByteOrder::Big, -40_000.0);
+        assert_eq!(words, vec![0x8000]);
+        let words = encode(Encoding::U32, WordOrder::Big, ByteOrder::Big, -5.0);
+        assert_eq!(words, vec![0, 0]);
+    }
+
+    #[test]
+    fn too_few_words_is_none() {
+        assert_eq!(
+            decode(Encoding::U32, WordOrder::Big, ByteOrder::Big, &[1]),
+            None
+        );
+    }
+}
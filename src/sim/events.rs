@@ -0,0 +1,288 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+use tracing::info;
+
+use super::{SimState, Space};
+
+/// A one-off change fired through the control API. Events are queued and
+/// applied at the start of the next tick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    /// Holds the item at `value` for `duration_ms`, a single tick when zero,
+    /// then restores the value it had and resumes its dynamics.
+    Pulse {
+        value: f64,
+        duration_ms: u64,
+    },
+    /// Shifts a register by `delta` now and every later dynamics value with
+    /// it. Steps add up.
+    Step {
+        delta: f64,
+    },
+    /// Makes reads of the item fail like bad quality until `FaultOff`.
+    FaultOn,
+    FaultOff,
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Pulse { .. } => "pulse",
+            Event::Step { .. } => "step",
+            Event::FaultOn => "fault-on",
+            Event::FaultOff => "fault-off",
+        }
+    }
+}
+
+/// What the events fired at an item left behind.
+#[derive(Debug, Clone, Default)]
+pub struct ItemEvents {
+    pulse: Option<Pulse>,
+    /// Sum of the steps applied so far.
+    pub step: f64,
+    /// Whether a fault is on.
+    pub fault: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Pulse {
+    value: f64,
+    until: Instant,
+    /// Value the item had before the pulse.
+    restore: f64,
+}
+
+/// Where a pulse stands at a tick.
+pub(super) enum PulseState {
+    /// The item is held at this value.
+    Active(f64),
+    /// The pulse is over; the item returns to this value.
+    Ended(f64),
+}
+
+impl ItemEvents {
+    /// Whether a pulse holds the item.
+    pub fn pulsing(&self) -> bool {
+        self.pulse.is_some()
+    }
+
+    /// Advances the pulse, if there is one, to `now`.
+    pub(super) fn pulse(&mut self, now: Instant) -> Option<PulseState> {
+        let pulse = self.pulse?;
+        if now <= pulse.until {
+            return Some(PulseState::Active(pulse.value));
+        }
+        self.pulse = None;
+        Some(PulseState::Ended(pulse.restore))
+    }
+
+    /// Applies `event` to an item showing `current`, returning the value the
+    /// item takes at once, if it changes.
+    fn apply(&mut self, event: Event, now: Instant, current: f64) -> Option<f64> {
+        match event {
+            Event::Pulse { value, duration_ms } => {
+                // A pulse during a pulse extends it; the original value
+                // still comes back at the end.
+                let restore = self.pulse.map_or(current, |pulse| pulse.restore);
+                self.pulse = Some(Pulse {
+                    value,
+                    until: now + Duration::from_millis(duration_ms),
+                    restore,
+                });
+                Some(value)
+            }
+            Event::Step { delta } => {
+                self.step += delta;
+                if let Some(pulse) = &mut self.pulse {
+                    pulse.restore += delta;
+                    return None;
+                }
+                Some(current + delta)
+            }
+            Event::FaultOn => {
+                self.fault = true;
+                None
+            }
+            Event::FaultOff => {
+                self.fault = false;
+                None
+            }
+        }
+    }
+}
+
+impl SimState {
+    /// Queues `event` for the item at `address`; the next tick applies it.
+    pub fn queue_event(&mut self, space: Space, address: u16, event: Event) -> Result<()> {
+        if !self.is_configured(space, address) {
+            bail!("{} {address} is not configured", space.item_name());
+        }
+        if matches!(event, Event::Step { .. })
+            && matches!(space, Space::Coils | Space::DiscreteInputs)
+        {
+            bail!("step events need a register, not a {}", space.item_name());
+        }
+        self.pending_events.push((space, address, event));
+        Ok(())
+    }
+
+    /// Applies the queued events in the order they were fired.
+    pub(super) fn apply_events(&mut self, now: Instant) {
+        for (space, address, event) in std::mem::take(&mut self.pending_events) {
+            match space {
+                Space::Coils | Space::DiscreteInputs => {
+                    let map = if space == Space::Coils {
+                        &mut self.coils
+                    } else {
+                        &mut self.discrete_inputs
+                    };
+                    let Some(item) = map.get_mut(&address) else {
+                        continue;
+                    };
+                    let current = if item.value { 1.0 } else { 0.0 };
+                    if let Some(value) = item.events.apply(event, now, current) {
+                        item.last_value = item.value;
+                        item.value = value != 0.0;
+                    }
+                }
+                Space::HoldingRegisters | Space::InputRegisters => {
+                    let map = if space == Space::HoldingRegisters {
+                        &mut self.holding_registers
+                    } else {
+                        &mut self.input_registers
+                    };
+                    let Some(item) = map.get_mut(&address) else {
+                        continue;
+                    };
+                    if let Some(value) = item.events.apply(event, now, f64::from(item.value)) {
+                        item.last_value = item.value;
+                        item.value = raw(value);
+                    }
+                }
+            }
+            if self.log_value_updates {
+                info!(
+                    address,
+                    event = event.name(),
+                    "{} event applied",
+                    space.item_name()
+                );
+            }
+        }
+    }
+}
+
+/// A register value from an event value.
+pub(super) fn raw(value: f64) -> u16 {
+    value.round().clamp(0.0, f64::from(u16::MAX)) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Event;
+    use crate::config::{BoolItemConfig, RegisterItemConfig};
+    use crate::sim::{Exception, SimState, Space};
+
+    fn state() -> SimState {
+        let level = RegisterItemConfig {
+            address: 0,
+            initial: 50.into(),
+            seed: Some(3),
+            update_ms: Some(1),
+            dynamics: Some(
+                toml::from_str("kind = \"random-walk\"\nmin = 0.0\nmax = 1000.0\nstep = 1.0")
+                    .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let constant = RegisterItemConfig {
+            address: 1,
+            initial: 10.into(),
+            ..Default::default()
+        };
+        let coil = BoolItemConfig {
+            address: 0,
+            ..Default::default()
+        };
+        SimState::new(
+            500,
+            false,
+            vec![coil],
+            vec![],
+            vec![],
+            vec![level, constant],
+        )
+        .unwrap()
+    }
+
+    fn tick(state: &mut SimState) {
+        std::thread::sleep(Duration::from_millis(2));
+        state.tick().unwrap();
+    }
+
+    #[test]
+    fn pulses_hold_the_value_then_restore_it() {
+        let mut state = state();
+        let pulse = Event::Pulse {
+            value: 900.0,
+            duration_ms: 0,
+        };
+        state.queue_event(Space::InputRegisters, 1, pulse).unwrap();
+        state.queue_event(Space::InputRegisters, 0, pulse).unwrap();
+        assert_eq!(state.read_input_registers(0, 2), Ok(vec![50, 10]));
+        tick(&mut state);
+        assert_eq!(state.read_input_registers(0, 2), Ok(vec![900, 900]));
+        tick(&mut state);
+        let values = state.read_input_registers(0, 2).unwrap();
+        assert!(values[0].abs_diff(50) <= 1, "{values:?}");
+        assert_eq!(values[1], 10);
+    }
+
+    #[test]
+    fn steps_shift_the_dynamics() {
+        let mut state = state();
+        state
+            .queue_event(Space::InputRegisters, 0, Event::Step { delta: 500.0 })
+            .unwrap();
+        state
+            .queue_event(Space::InputRegisters, 1, Event::Step { delta: -4.0 })
+            .unwrap();
+        for _ in 0..5 {
+            tick(&mut state);
+            let values = state.read_input_registers(0, 2).unwrap();
+            assert!(values[0].abs_diff(550) <= 5, "{values:?}");
+            assert_eq!(values[1], 6);
+        }
+    }
+
+    #[test]
+    fn faults_fail_reads_until_cleared() {
+        let mut state = state();
+        state.queue_event(Space::Coils, 0, Event::FaultOn).unwrap();
+        tick(&mut state);
+        assert_eq!(state.read_coils(0, 1), Err(Exception::ServerDeviceFailure));
+        state.queue_event(Space::Coils, 0, Event::FaultOff).unwrap();
+        tick(&mut state);
+        assert_eq!(state.read_coils(0, 1), Ok(vec![false]));
+    }
+
+    #[test]
+    fn events_need_a_fitting_item() {
+        let mut state = state();
+        let err = state
+            .queue_event(Space::HoldingRegisters, 0, Event::FaultOn)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("holding register 0 is not configured")
+        );
+        let err = state
+            .queue_event(Space::Coils, 0, Event::Step { delta: 1.0 })
+            .unwrap_err();
+        assert!(err.to_string().contains("need a register"));
+    }
+}
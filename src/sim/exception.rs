@@ -0,0 +1,54 @@
+use crate::config::ConfiguredException;
+
+/// Modbus exception codes the simulator can answer with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Exception {
+    IllegalFunction,
+    IllegalDataAddress,
+    IllegalDataValue,
+    ServerDeviceFailure,
+    ServerDeviceBusy,
+    GatewayTargetDeviceFailedToRespond,
+}
+
+impl Exception {
+    pub fn code(self) -> u8 {
+        match self {
+            Exception::IllegalFunction => 0x01,
+            Exception::IllegalDataAddress => 0x02,
+            Exception::IllegalDataValue => 0x03,
+            Exception::ServerDeviceFailure => 0x04,
+            Exception::ServerDeviceBusy => 0x06,
+            Exception::GatewayTargetDeviceFailedToRespond => 0x0B,
+        }
+    }
+
+    /// Snake-case name used in stats and metrics.
+    pub fn name(self) -> &'static str {
+        match self {
+            Exception::IllegalFunction => "illegal_function",
+            Exception::IllegalDataAddress => "illegal_data_address",
+            Exception::IllegalDataValue => "illegal_data_value",
+            Exception::ServerDeviceFailure => "server_device_failure",
+            Exception::ServerDeviceBusy => "server_device_busy",
+            Exception::GatewayTargetDeviceFailedToRespond => {
+                "gateway_target_device_failed_to_respond"
+            }
+        }
+    }
+}
+
+impl From<ConfiguredException> for Exception {
+    fn from(exception: ConfiguredException) -> Self {
+        match exception {
+            ConfiguredException::IllegalFunction => Exception::IllegalFunction,
+            ConfiguredException::IllegalDataAddress => Exception::IllegalDataAddress,
+            ConfiguredException::IllegalDataValue => Exception::IllegalDataValue,
+            ConfiguredException::ServerDeviceFailure => Exception::ServerDeviceFailure,
+            ConfiguredException::ServerDeviceBusy => Exception::ServerDeviceBusy,
+            ConfiguredException::GatewayTargetDeviceFailedToRespond => {
+                Exception::GatewayTargetDeviceFailedToRespond
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+
+use super::{
+    ItemEvents, SimBoolItem, SimState, Space, bad_quality_value, dynamics_enabled, frozen,
+    in_outage,
+};
+use crate::config::{OutageConfig, QualityConfig, StuckConfig};
+
+/// Whether an item's dynamics move its value right now, and if not, which
+/// gate holds them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DynamicsState {
+    Running,
+    /// Inside the item's `dynamics_delay_ms`, or while a
+    /// `replay_then_live` capture plays into it.
+    Delayed,
+    /// The item's `dynamics_enable_coil` is off.
+    Paused,
+    /// Held by a device or item outage, a stuck window or a pulse.
+    Frozen,
+    /// Reads fail or report the sentinel, from bad quality or a fault.
+    Errored,
+}
+
+impl DynamicsState {
+    pub fn name(self) -> &'static str {
+        match self {
+            DynamicsState::Running => "running",
+            DynamicsState::Delayed => "delayed",
+            DynamicsState::Paused => "paused",
+            DynamicsState::Frozen => "frozen",
+            DynamicsState::Errored => "errored",
+        }
+    }
+}
+
+/// The settings of one item that gate its dynamics in `tick()`.
+struct Gates<'a> {
+    quality: &'a Option<QualityConfig>,
+    events: &'a ItemEvents,
+    stuck: &'a Option<StuckConfig>,
+    outage: &'a Option<OutageConfig>,
+    enable_coil: Option<u16>,
+    delay_ms: u64,
+    /// Whether a `replay_then_live` capture is still playing into the item.
+    replaying: bool,
+}
+
+impl Gates<'_> {
+    /// The first gate holding the dynamics, checked in the order a client
+    /// would notice it.
+    fn state(
+        &self,
+        coils: &BTreeMap<u16, SimBoolItem>,
+        device_outage: bool,
+        elapsed_ms: u64,
+    ) -> DynamicsState {
+        if bad_quality_value(self.quality, self.events.fault, elapsed_ms).is_some() {
+            DynamicsState::Errored
+        } else if self.events.pulsing()
+            || frozen(device_outage, self.stuck, self.outage, elapsed_ms)
+        {
+            DynamicsState::Frozen
+        } else if !dynamics_enabled(coils, self.enable_coil) {
+            DynamicsState::Paused
+        } else if self.replaying || elapsed_ms < self.delay_ms {
+            DynamicsState::Delayed
+        } else {
+            DynamicsState::Running
+        }
+    }
+}
+
+impl SimState {
+    /// Whether the dynamics of the item at `address` currently run, or
+    /// `None` if it has none.
+    pub fn dynamics_state(&self, space: Space, address: u16) -> Option<DynamicsState> {
+        let elapsed_ms = self.elapsed_ms();
+        let device_outage = in_outage(&self.outage, elapsed_ms);
+        let replaying = self.replay_held().contains(&(space, address));
+        let gates = match space {
+            Space::Coils | Space::DiscreteInputs => {
+                let map = if space == Space::Coils {
+                    &self.coils
+                } else {
+                    &self.discrete_inputs
+                };
+                let item = map.get(&address).filter(|item| item.dynamics.is_some())?;
+                Gates {
+                    quality: &item.quality,
+                    events: &item.events,
+                    stuck: &item.stuck,
+                    outage: &item.outage,
+                    enable_coil: None,
+                    delay_ms: item.dynamics_delay_ms,
+                    replaying,
+                }
+            }
+            Space::HoldingRegisters | Space::InputRegisters => {
+                let map = if space == Space::HoldingRegisters {
+                    &self.holding_registers
+                } else {
+                    &self.input_registers
+                };
+                let item = map.get(&address).filter(|item| item.dynamics.is_some())?;
+                Gates {
+                    quality: &item.quality,
+                    events: &item.events,
+                    stuck: &item.stuck,
+                    outage: &item.outage,
+                    enable_coil: item.dynamics_enable_coil,
+                    delay_ms: item.dynamics_delay_ms,
+                    replaying,
+                }
+            }
+        };
+        Some(gates.state(&self.coils, device_outage, elapsed_ms))
+    }
+
+    /// [`SimState::dynamics_state`] of every item with dynamics, by space
+    /// then address.
+    pub fn dynamics_states(&self) -> Vec<(Space, u16, DynamicsState)> {
+        let bits = [
+            (Space::Coils, &self.coils),
+            (Space::DiscreteInputs, &self.discrete_inputs),
+        ]
+        .into_iter()
+        .flat_map(|(space, map)| map.keys().map(move |address| (space, *address)));
+        let registers = [
+            (Space::HoldingRegisters, &self.holding_registers),
+            (Space::InputRegisters, &self.input_registers),
+        ]
+        .into_iter()
+        .flat_map(|(space, map)| map.keys().map(move |address| (space, *address)));
+        bits.chain(registers)
+            .filter_map(|(space, address)| {
+                Some((space, address, self.dynamics_state(space, address)?))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BoolItemConfig, DynamicsSpec, RegisterItemConfig};
+
+    fn sine() -> Option<DynamicsSpec> {
+        Some(
+            toml::from_str("kind = \"sine\"\namplitude = 5.0\noffset = 5.0\nperiod_ms = 1000")
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn gates_are_reported_per_item() {
+        let enable = BoolItemConfig {
+            address: 7,
+            ..Default::default()
+        };
+        let running = RegisterItemConfig {
+            address: 0,
+            dynamics: sine(),
+            ..Default::default()
+        };
+        let delayed = RegisterItemConfig {
+            address: 1,
+            dynamics: sine(),
+            dynamics_delay_ms: 60_000,
+            ..Default::default()
+        };
+        let paused = RegisterItemConfig {
+            address: 2,
+            dynamics: sine(),
+            dynamics_enable_coil: Some(7),
+            ..Default::default()
+        };
+        let plain = RegisterItemConfig {
+            address: 3,
+            ..Default::default()
+        };
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![enable],
+            vec![],
+            vec![running, delayed, paused, plain],
+            vec![],
+        )
+        .unwrap();
+        let states = |state: &SimState| {
+            (0..4)
+                .map(|address| state.dynamics_state(Space::HoldingRegisters, address))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            states(&state),
+            [
+                Some(DynamicsState::Running),
+                Some(DynamicsState::Delayed),
+                Some(DynamicsState::Paused),
+                None,
+            ]
+        );
+
+        state.write_single_coil(7, true);
+        state.holding_registers.get_mut(&0).unwrap().events.fault = true;
+        assert_eq!(
+            states(&state)[..3],
+            [
+                Some(DynamicsState::Errored),
+                Some(DynamicsState::Delayed),
+                Some(DynamicsState::Running),
+            ]
+        );
+        assert_eq!(state.dynamics_states().len(), 3);
+    }
+}
@@ -0,0 +1,139 @@
+/// Number of buckets used when an item does not list its own.
+const DEFAULT_BUCKETS: usize = 10;
+
+/// Cumulative distribution of observed values, in the shape of a Prometheus
+/// histogram.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    /// Upper bounds of the buckets, ascending; `+Inf` is implicit.
+    pub bounds: Vec<f64>,
+    /// Observations per bucket, not cumulative; the last entry is `+Inf`.
+    counts: Vec<u64>,
+    /// Latest exemplar per bucket, aligned with `counts`.
+    exemplars: Vec<Option<Exemplar>>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+/// One observation with labels identifying it, attached to the bucket it
+/// fell in as an OpenMetrics exemplar.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exemplar {
+    pub labels: Vec<(&'static str, String)>,
+    pub value: f64,
+    /// Seconds since the Unix epoch.
+    pub timestamp: f64,
+}
+
+impl Histogram {
+    pub fn new(mut bounds: Vec<f64>) -> Self {
+        bounds.retain(|bound| bound.is_finite());
+        bounds.sort_by(f64::total_cmp);
+        bounds.dedup();
+        let counts = vec![0; bounds.len() + 1];
+        Self {
+            exemplars: vec![None; counts.len()],
+            bounds,
+            counts,
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    /// Evenly spaced buckets over `min..=max`, or over the whole register
+    /// range when either is unknown.
+    pub fn for_range(min: Option<f64>, max: Option<f64>) -> Self {
+        let (min, max) = match (min, max) {
+            (Some(min), Some(max)) if max > min => (min, max),
+            _ => (0.0, u16::MAX as f64),
+        };
+        let step = (max - min) / DEFAULT_BUCKETS as f64;
+        Self::new(
+            (1..=DEFAULT_BUCKETS)
+                .map(|bucket| min + step * bucket as f64)
+                .collect(),
+        )
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        self.bucket_observe(value);
+    }
+
+    /// Observes `exemplar.value` and keeps `exemplar` for its bucket.
+    pub fn observe_with_exemplar(&mut self, exemplar: Exemplar) {
+        let bucket = self.bucket_observe(exemplar.value);
+        self.exemplars[bucket] = Some(exemplar);
+    }
+
+    fn bucket_observe(&mut self, value: f64) -> usize {
+        let bucket = self.bounds.partition_point(|bound| *bound < value);
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+        bucket
+    }
+
+    /// Latest exemplar per bucket, in the order of [`Histogram::cumulative`].
+    pub fn exemplars(&self) -> &[Option<Exemplar>] {
+        &self.exemplars
+    }
+
+    /// `(upper bound, cumulative count)` per bucket, ending with `+Inf`.
+    pub fn cumulative(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        self.bounds
+            .iter()
+            .copied()
+            .chain([f64::INFINITY])
+            .zip(&self.counts)
+            .map(|(bound, count)| {
+                total += count;
+                (bound, total)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_are_cumulative_and_inclusive() {
+        let mut histogram = Histogram::new(vec![20.0, 10.0]);
+        for value in [5.0, 10.0, 15.0, 30.0] {
+            histogram.observe(value);
+        }
+        assert_eq!(
+            histogram.cumulative(),
+            vec![(10.0, 2), (20.0, 3), (f64::INFINITY, 4)]
+        );
+        assert_eq!((histogram.sum, histogram.count), (60.0, 4));
+    }
+
+    #[test]
+    fn exemplars_are_kept_per_bucket() {
+        let mut histogram = Histogram::new(vec![1.0]);
+        let exemplar = |id: &str, value| Exemplar {
+            labels: vec![("request_id", id.to_string())],
+            value,
+            timestamp: 0.0,
+        };
+        histogram.observe_with_exemplar(exemplar("1", 0.5));
+        histogram.observe_with_exemplar(exemplar("2", 0.7));
+        histogram.observe(3.0);
+        assert_eq!(histogram.exemplars(), &[Some(exemplar("2", 0.7)), None]);
+        assert_eq!(histogram.count, 3);
+    }
+
+    #[test]
+    fn default_buckets_span_the_range() {
+        let histogram = Histogram::for_range(Some(100.0), Some(200.0));
+        assert_eq!(histogram.bounds.first(), Some(&110.0));
+        assert_eq!(histogram.bounds.last(), Some(&200.0));
+        assert_eq!(
+            Histogram::for_range(None, Some(5.0)).bounds.last(),
+            Some(&65535.0)
+        );
+    }
+}
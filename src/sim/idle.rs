@@ -0,0 +1,149 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+use tracing::info;
+
+use super::SimState;
+use crate::config::{IdleConfig, IdleMode};
+
+/// The clients connected to a device, counted by its transports.
+#[derive(Debug, Default)]
+pub(super) struct Clients {
+    count: AtomicUsize,
+    /// Wakes the tick loop of an idle device when a client connects.
+    pub(super) connected: Notify,
+}
+
+/// One connected client, counted until it is dropped.
+#[derive(Debug)]
+pub struct ClientGuard {
+    clients: Arc<Clients>,
+}
+
+impl Drop for ClientGuard {
+    fn drop(&mut self) {
+        self.clients.count.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// How the tick loop of a device without clients runs, and since when.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Sleep {
+    since: Instant,
+    config: IdleConfig,
+}
+
+impl Sleep {
+    /// Whether ticks are skipped, rather than only spaced out.
+    pub(super) fn paused(&self) -> bool {
+        self.config.mode == IdleMode::Pause
+    }
+
+    pub(super) fn tick_ms(&self) -> u64 {
+        self.config.tick_ms
+    }
+}
+
+impl SimState {
+    /// Slows or pauses the tick loop as `idle` says while no client is
+    /// connected; without it the device always ticks at full rate.
+    pub fn with_idle(mut self, idle: Option<IdleConfig>) -> Self {
+        self.idle = idle;
+        self
+    }
+
+    /// Counts a client as connected until the guard is dropped, waking the
+    /// tick loop if the device is idle.
+    pub fn connect_client(&self) -> ClientGuard {
+        self.clients.count.fetch_add(1, Ordering::Relaxed);
+        self.clients.connected.notify_one();
+        ClientGuard {
+            clients: Arc::clone(&self.clients),
+        }
+    }
+
+    pub fn connected_clients(&self) -> usize {
+        self.clients.count.load(Ordering::Relaxed)
+    }
+
+    /// Puts the tick loop to sleep when the last client left and wakes it
+    /// when one connects, logging both. Waking from a pause moves the
+    /// simulation clock back by its length, so the dynamics continue where
+    /// they stopped instead of jumping ahead.
+    pub(super) fn update_sleep(&mut self, sleep: &mut Option<Sleep>) {
+        let idle = self.idle.filter(|_| self.connected_clients() == 0);
+        match (idle, *sleep) {
+            (Some(config), None) => {
+                info!(
+                    mode = ?config.mode,
+                    tick_ms = config.tick_ms,
+                    "no clients connected; device idle"
+                );
+                *sleep = Some(Sleep {
+                    since: Instant::now(),
+                    config,
+                });
+            }
+            (None, Some(current)) => {
+                let slept = current.since.elapsed();
+                if current.paused() {
+                    self.resume_clock(slept);
+                }
+                info!(
+                    idle_ms = slept.as_millis() as u64,
+                    "client connected; device back to full rate"
+                );
+                *sleep = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Moves the simulation clock back by `paused`, as if it had stood still.
+    fn resume_clock(&mut self, paused: Duration) {
+        self.shift_clock(|instant| instant.checked_add(paused).unwrap_or(instant));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::RwLock;
+
+    use super::*;
+    use crate::config::RegisterItemConfig;
+    use crate::sim::spawn_simulator;
+
+    #[tokio::test]
+    async fn paused_devices_resume_where_they_stopped() {
+        let clock = RegisterItemConfig {
+            address: 0,
+            update_ms: Some(0),
+            dynamics: Some(toml::from_str("kind = \"script\"\nexpr = \"t * 1000\"").unwrap()),
+            ..Default::default()
+        };
+        let idle: IdleConfig = toml::from_str("mode = \"pause\"").unwrap();
+        let state = SimState::new(500, false, vec![], vec![], vec![], vec![clock])
+            .unwrap()
+            .with_idle(Some(idle));
+        let state = Arc::new(RwLock::new(state));
+        let simulator = tokio::spawn(spawn_simulator(Arc::clone(&state)));
+        let value = || state.read().unwrap().input_registers[&0].value;
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(value(), 0);
+
+        // The clock starts once a client connects, not at startup.
+        let client = state.read().unwrap().connect_client();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!((1..250).contains(&value()), "{}", value());
+
+        drop(client);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let paused = value();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(value(), paused);
+        simulator.abort();
+    }
+}
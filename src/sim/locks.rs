@@ -0,0 +1,190 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+
+use super::{Exception, SimState, Space};
+use crate::config::WriteLockConfig;
+
+/// A write lock and, while it is open, when it closes again.
+#[derive(Debug, Clone)]
+pub(super) struct WriteLock {
+    config: WriteLockConfig,
+    unlocked_until: Option<Instant>,
+}
+
+impl WriteLock {
+    fn protects(&self, address: u16, count: usize) -> bool {
+        let start = usize::from(self.config.address);
+        let address = usize::from(address);
+        address < start + usize::from(self.config.count) && start < address + count
+    }
+
+    fn is_open(&self, now: Instant) -> bool {
+        self.unlocked_until.is_some_and(|until| now < until)
+    }
+}
+
+impl SimState {
+    /// Guards holding register blocks behind an unlock coil or register. The
+    /// unlock items are created if not configured. Every lock starts closed.
+    pub fn with_write_locks(mut self, locks: &[WriteLockConfig]) -> Result<Self> {
+        for lock in locks {
+            let end = u32::from(lock.address) + u32::from(lock.count);
+            let context = format!("write lock (holding registers {}..{end})", lock.address);
+            if lock.count == 0 || end > 0x1_0000 {
+                bail!("{context}: `count` must be at least 1 and the block end by address 65535");
+            }
+            match (lock.unlock_coil, lock.unlock_register, lock.unlock_value) {
+                (Some(coil), None, None) => {
+                    if !self.coils.contains_key(&coil) {
+                        self.write_single_coil(coil, false);
+                    }
+                }
+                (None, Some(register), Some(_)) => {
+                    if register >= lock.address && u32::from(register) < end {
+                        bail!("{context}: the unlock register is inside the block it unlocks");
+                    }
+                    if !self.holding_registers.contains_key(&register) {
+                        self.write_single_register(register, 0);
+                    }
+                }
+                (None, Some(_), None) => {
+                    bail!("{context}: `unlock_register` needs an `unlock_value`")
+                }
+                _ => bail!(
+                    "{context}: set either `unlock_coil`, or `unlock_register` with `unlock_value`"
+                ),
+            }
+            self.write_locks.push(WriteLock {
+                config: lock.clone(),
+                unlocked_until: None,
+            });
+        }
+        Ok(self)
+    }
+
+    /// Refuses a client write touching a locked block with the lock's
+    /// exception.
+    pub fn check_unlocked(
+        &self,
+        space: Space,
+        address: u16,
+        count: usize,
+    ) -> Result<(), Exception> {
+        if space != Space::HoldingRegisters {
+            return Ok(());
+        }
+        let now = Instant::now();
+        match self
+            .write_locks
+            .iter()
+            .find(|lock| lock.protects(address, count) && !lock.is_open(now))
+        {
+            Some(lock) => Err(lock.config.exception.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Opens the locks whose unlock item a client write of `count` items
+    /// from `address` just set to its unlock value, for their window from
+    /// now, and closes those whose unlock item it set to anything else.
+    pub fn update_write_locks(&mut self, space: Space, address: u16, count: usize) {
+        let now = Instant::now();
+        let written = |item: u16| (address..=u16::MAX).take(count).any(|a| a == item);
+        for lock in &mut self.write_locks {
+            let unlocked = match (space, lock.config.unlock_coil, lock.config.unlock_register) {
+                (Space::Coils, Some(coil), _) if written(coil) => {
+                    self.coils.get(&coil).is_some_and(|item| item.value)
+                }
+                (Space::HoldingRegisters, _, Some(register)) if written(register) => self
+                    .holding_registers
+                    .get(&register)
+                    .is_some_and(|item| Some(item.value) == lock.config.unlock_value),
+                _ => continue,
+            };
+            lock.unlocked_until =
+                unlocked.then(|| now + Duration::from_millis(lock.config.window_ms));
+        }
+    }
+
+    /// Closes every write lock.
+    pub(super) fn relock_all(&mut self) {
+        for lock in &mut self.write_locks {
+            lock.unlocked_until = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::WriteLockConfig;
+    use crate::sim::{Exception, SimState, Space};
+
+    fn lock(toml: &str) -> WriteLockConfig {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn writes_pass_only_while_unlocked() {
+        let mut state = SimState::new(500, false, vec![], vec![], vec![], vec![])
+            .unwrap()
+            .with_write_locks(&[
+                lock("address = 100\ncount = 4\nunlock_coil = 5"),
+                lock("address = 200\ncount = 1\nunlock_register = 9\nunlock_value = 0xA55A\nwindow_ms = 0\nexception = \"server_device_busy\""),
+            ])
+            .unwrap();
+        let holding = Space::HoldingRegisters;
+        assert_eq!(
+            state.check_unlocked(holding, 98, 3),
+            Err(Exception::IllegalDataAddress)
+        );
+        assert_eq!(state.check_unlocked(holding, 96, 4), Ok(()));
+        assert_eq!(state.check_unlocked(Space::Coils, 100, 1), Ok(()));
+
+        state.write_single_coil(5, true);
+        state.update_write_locks(Space::Coils, 5, 1);
+        assert_eq!(state.check_unlocked(holding, 98, 3), Ok(()));
+        // Writing the unlock coil off relocks.
+        state.write_single_coil(5, false);
+        state.update_write_locks(Space::Coils, 4, 2);
+        assert!(state.check_unlocked(holding, 103, 1).is_err());
+
+        state.write_single_coil(5, true);
+        state.update_write_locks(Space::Coils, 5, 1);
+        state.reset().unwrap();
+        assert!(state.check_unlocked(holding, 100, 1).is_err());
+
+        // The magic value opens a window that is already over.
+        state.write_single_register(9, 0xA55A);
+        state.update_write_locks(holding, 9, 1);
+        assert_eq!(
+            state.check_unlocked(holding, 200, 1),
+            Err(Exception::ServerDeviceBusy)
+        );
+    }
+
+    #[test]
+    fn invalid_locks_are_rejected() {
+        for (config, error) in [
+            ("address = 1\ncount = 0\nunlock_coil = 1", "at least 1"),
+            (
+                "address = 1\ncount = 2\nunlock_register = 2\nunlock_value = 1",
+                "inside",
+            ),
+            (
+                "address = 1\ncount = 2\nunlock_register = 7",
+                "needs an `unlock_value`",
+            ),
+            (
+                "address = 1\ncount = 2\nunlock_coil = 1\nunlock_register = 7",
+                "set either",
+            ),
+        ] {
+            let err = SimState::new(500, false, vec![], vec![], vec![], vec![])
+                .unwrap()
+                .with_write_locks(&[lock(config)])
+                .unwrap_err();
+            assert!(err.to_string().contains(error), "{err}");
+        }
+    }
+}
@@ -1,11 +1,130 @@
-use std::collections::BTreeMap;
-use std::time::{Duration, Instant};
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::{Bound, RangeBounds};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use evalexpr::{ContextWithMutableVariables, HashMapContext, Value};
-use rand::Rng;
-use tracing::info;
+use anyhow::{Context, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::Deserialize;
+use tokio::sync::broadcast;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, warn};
 
-use crate::config::{BoolItemConfig, DynamicsSpec, RegisterItemConfig};
+use crate::config::{
+    BoolItemConfig, ByteOrder, CrosstalkConfig, DeviceConfig, DynamicsSpec, Encoding,
+    EncodingReload, FifoConfig, IdleConfig, OutageConfig, QualityConfig, RegisterItemConfig,
+    RegisterSpace, StuckConfig, TickEvaluation, TickOrder, TickOverrunPolicy, WordOrder,
+    WriteConflictPolicy, duration,
+};
+
+mod alarm;
+mod blocks;
+mod changes;
+mod conflict;
+mod crosstalk;
+mod decay;
+pub mod dynamics;
+pub mod encoding;
+mod events;
+mod exception;
+mod gating;
+pub mod histogram;
+mod idle;
+mod locks;
+mod noise;
+mod order;
+mod outage;
+mod packed;
+mod playback;
+mod preview;
+mod rate_limit;
+mod reset;
+mod ringbuffer;
+mod selftest;
+mod shaping;
+mod snapshot;
+mod write_quirk;
+
+pub use alarm::Alarm;
+pub use changes::Change;
+pub use conflict::LastWrite;
+pub use decay::Decay;
+pub use dynamics::{BUILTIN_KINDS, Dynamic, DynamicsRegistry, EvalContext, describe_kinds};
+pub use events::{Event, ItemEvents};
+pub use exception::Exception;
+pub use gating::DynamicsState;
+pub use histogram::{Exemplar, Histogram};
+pub use idle::ClientGuard;
+pub use preview::{preview_csv, preview_plot};
+pub use ringbuffer::RingBuffer;
+pub use selftest::{SELF_TEST_PERIODS, SelfTestResult, self_test_tap};
+
+use events::{PulseState, raw};
+use idle::{Clients, Sleep};
+use locks::WriteLock;
+use noise::NoiseFloor;
+use order::{RegisterPass, TickPass};
+use outage::in_outage;
+pub use playback::Playback;
+pub use rate_limit::RateLimit;
+pub use shaping::Shaping;
+pub use snapshot::StateSnapshot;
+pub use write_quirk::WriteQuirk;
+
+const DEFAULT_TICK_FLOOR_MS: u64 = 10;
+
+const MINUTE_MS: u64 = 60_000;
+
+/// Most values a Read FIFO Queue response may carry.
+const MAX_FIFO_COUNT: usize = 31;
+
+/// Least time between two "falling behind" warnings.
+const OVERRUN_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Share of the raw range below which a dynamics output range is reported
+/// as suspiciously narrow.
+const NARROW_RANGE_FRACTION: f64 = 0.001;
+
+/// Upper bounds of the request duration buckets, in seconds.
+const REQUEST_DURATION_BUCKETS: [f64; 12] = [
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Keeps random phases independent of the items' dynamics streams.
+const PHASE_SEED_SALT: u64 = 0x50_4841_5345;
+
+/// Weight of the newest interval in an item's observed update interval.
+const UPDATE_INTERVAL_ALPHA: f64 = 0.2;
+
+/// One of the four Modbus data tables.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Space {
+    Coils,
+    DiscreteInputs,
+    HoldingRegisters,
+    InputRegisters,
+}
+
+impl Space {
+    fn item_name(self) -> &'static str {
+        match self {
+            Space::Coils => "coil",
+            Space::DiscreteInputs => "discrete input",
+            Space::HoldingRegisters => "holding register",
+            Space::InputRegisters => "input register",
+        }
+    }
+}
+
+impl From<RegisterSpace> for Space {
+    fn from(space: RegisterSpace) -> Self {
+        match space {
+            RegisterSpace::HoldingRegisters => Space::HoldingRegisters,
+            RegisterSpace::InputRegisters => Space::InputRegisters,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct SimState {
@@ -15,27 +134,245 @@ pub struct SimState {
     pub input_registers: BTreeMap<u16, SimRegisterItem>,
     pub global_update_ms: u64,
     pub log_value_updates: bool,
+    pub min_tick_floor_ms: u64,
+    /// Global seed item streams are derived from, if any.
+    pub seed: Option<u64>,
+    /// Warmup window for items without their own `warmup_ms`.
+    pub warmup_ms: u64,
+    /// Fail the tick when a register leaves the range its dynamics declare.
+    pub assert_bounds: bool,
+    /// Exception responses sent since start, by exception.
+    pub exception_counts: BTreeMap<Exception, u64>,
+    /// Time taken to answer Modbus requests, in seconds.
+    pub request_duration: Histogram,
+    /// FIFO queues by pointer address, oldest value first.
+    pub fifos: BTreeMap<u16, VecDeque<u16>>,
+    /// Alarm bits, evaluated at the end of every tick.
+    pub alarms: Vec<Alarm>,
+    /// Ring buffers, sampled at the end of every tick after the alarms.
+    pub ringbuffers: Vec<RingBuffer>,
+    /// Coils aliased by holding registers, bit 0 first.
+    packed_registers: BTreeMap<u16, Vec<u16>>,
+    /// Holding register blocks refusing client writes until unlocked.
+    write_locks: Vec<WriteLock>,
+    /// Outage freezing every item; items may also have their own.
+    pub outage: Option<OutageConfig>,
+    /// Noise added to every register with dynamics, when enabled.
+    noise_floor: Option<NoiseFloor>,
+    /// Coupling of registers to their neighbors, added like noise.
+    crosstalk: Option<CrosstalkConfig>,
+    /// Capture driving some registers instead of their dynamics.
+    playback: Option<Playback>,
+    pub tick_overrun_policy: TickOverrunPolicy,
+    pub tick_order: TickOrder,
+    pub tick_evaluation: TickEvaluation,
+    /// Whether client writes may create items at unconfigured addresses.
+    pub allow_dynamic_create: bool,
+    pub write_conflict: WriteConflictPolicy,
+    /// How long a client's write keeps an address busy for other clients.
+    pub busy_window: Duration,
+    /// Last client write to each address, since start or reset.
+    last_writers: BTreeMap<(Space, u16), LastWrite>,
+    /// Contiguous configured address ranges per space, inclusive, when reads
+    /// must stay within one.
+    read_blocks: Option<BTreeMap<Space, Vec<(u16, u16)>>>,
+    /// Whether register reads fall back to the other register space at
+    /// addresses unconfigured in their own.
+    pub mirror_registers: bool,
+    /// Ticks that finished after the next one was due, since start.
+    pub tick_overruns: u64,
+    /// Whether a standby takes its values from its primary, so the tick
+    /// loop leaves them alone.
+    pub following: bool,
+    /// How the tick loop slows down while no client is connected.
+    idle: Option<IdleConfig>,
+    clients: std::sync::Arc<Clients>,
+    /// Control API events waiting for the next tick.
+    pending_events: Vec<(Space, u16, Event)>,
+    registry: DynamicsRegistry,
     start_time: Instant,
+    /// Simulation seconds per wall-clock second.
+    clock_rate: f64,
+    /// Wall-clock time the state was created; unlike the simulation clock,
+    /// restores leave it alone.
+    pub started_at: SystemTime,
+    /// Value changes made by ticks and writes, once something subscribed to
+    /// them.
+    change_feed: Option<broadcast::Sender<Change>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct SimBoolItem {
     pub value: bool,
     pub last_value: bool,
-    pub dynamics: Option<DynamicsSpec>,
+    /// Value a reset returns to: the configured one, `false` for items
+    /// created by writes.
+    pub initial: bool,
+    pub dynamics: Option<Box<dyn Dynamic>>,
+    pub dynamics_spec: Option<DynamicsSpec>,
+    pub seed: Option<u64>,
+    pub update_on_read: bool,
+    pub quality: Option<QualityConfig>,
+    /// Windows during which updates are skipped and the value is held.
+    pub stuck: Option<StuckConfig>,
+    /// Outage windows of the item alone; see [`SimState::outage`].
+    pub outage: Option<OutageConfig>,
+    /// State left by control API events.
+    pub events: ItemEvents,
+    /// Update interval the item achieved in ticks.
+    pub update_rate: UpdateRate,
+    /// Added to the response time of requests touching the item.
+    pub response_delay_ms: u64,
+    pub warmup_ms: Option<u64>,
+    pub dynamics_delay_ms: u64,
+    pub cold_value: f64,
+    /// Offset added to the time seen by the item's dynamics.
+    pub phase_ms: Option<u64>,
+    /// Whether state snapshots include this item.
+    pub persist: bool,
     pub update_ms: u64,
+    /// Updates fall on wall-clock multiples of `update_ms`.
+    pub align_to_clock: bool,
     pub next_due: Instant,
+    /// Delay after which a written `true` resets, for momentary coils.
+    pub momentary_ms: Option<u64>,
+    /// When a momentary coil written `true` resets to `false`.
+    pub reset_at: Option<Instant>,
+    /// Writes within this long of the last accepted one are ignored.
+    pub debounce_ms: u64,
+    /// When the last write was accepted.
+    pub last_write: Option<Instant>,
 }
 
 #[derive(Debug, Clone)]
 pub struct SimRegisterItem {
     pub value: u16,
     pub last_value: u16,
-    pub dynamics: Option<DynamicsSpec>,
+    /// Value a reset returns to: the configured one, `0` for items created
+    /// by writes.
+    pub initial: u16,
+    pub dynamics: Option<Box<dyn Dynamic>>,
+    pub dynamics_spec: Option<DynamicsSpec>,
+    pub seed: Option<u64>,
+    pub update_on_read: bool,
+    pub quality: Option<QualityConfig>,
+    /// Windows during which updates are skipped and the value is held.
+    pub stuck: Option<StuckConfig>,
+    /// Outage windows of the item alone; see [`SimState::outage`].
+    pub outage: Option<OutageConfig>,
+    /// State left by control API events.
+    pub events: ItemEvents,
+    /// Update interval the item achieved in ticks.
+    pub update_rate: UpdateRate,
+    /// Added to the response time of requests touching the item.
+    pub response_delay_ms: u64,
+    pub warmup_ms: Option<u64>,
+    pub dynamics_delay_ms: u64,
+    pub cold_value: f64,
+    /// Transform and resolution applied to the dynamics output.
+    pub shaping: Shaping,
+    pub suppress_range_warning: bool,
+    /// Left out of the device's noise floor.
+    pub noise_floor_exempt: bool,
+    /// Noise floor added to the value by the last update, which the
+    /// dynamics do not see.
+    pub noise: f64,
+    /// Value of the last update before the encoding rounded and saturated
+    /// it into the raw words.
+    pub unclamped: f64,
+    /// Offset added to the time seen by the item's dynamics.
+    pub phase_ms: Option<u64>,
+    /// Whether state snapshots include this item.
+    pub persist: bool,
+    pub encoding: Encoding,
+    pub word_order: WordOrder,
+    pub byte_order: ByteOrder,
+    pub scale: f64,
+    pub offset: f64,
+    /// Engineering unit label.
+    pub unit: Option<String>,
+    /// Distribution of the values the item took, when opted in.
+    pub histogram: Option<Histogram>,
     pub update_ms: u64,
+    /// Updates fall on wall-clock multiples of `update_ms`.
+    pub align_to_clock: bool,
+    /// Coil that must be on for the dynamics to run; the value is held
+    /// while it is off.
+    pub dynamics_enable_coil: Option<u16>,
+    /// Relaxation toward a rest value, for items without dynamics.
+    pub decay: Option<Decay>,
+    /// Bound on the change of each update.
+    pub rate_limit: Option<RateLimit>,
+    /// How client writes are mangled before they are stored.
+    pub write_quirk: Option<WriteQuirk>,
+    /// Reads must cover all the registers of the value or none.
+    pub whole_reads: bool,
     pub next_due: Instant,
 }
 
+impl SimRegisterItem {
+    /// A register holding `value` with nothing configured, as writes create.
+    fn plain(value: u16, update_ms: u64) -> Self {
+        Self {
+            value,
+            last_value: value,
+            initial: 0,
+            dynamics: None,
+            dynamics_spec: None,
+            seed: None,
+            update_on_read: false,
+            quality: None,
+            stuck: None,
+            outage: None,
+            events: ItemEvents::default(),
+            update_rate: UpdateRate::default(),
+            response_delay_ms: 0,
+            warmup_ms: None,
+            dynamics_delay_ms: 0,
+            phase_ms: None,
+            cold_value: 0.0,
+            shaping: Shaping::default(),
+            suppress_range_warning: false,
+            noise_floor_exempt: false,
+            noise: 0.0,
+            unclamped: 0.0,
+            persist: true,
+            encoding: Encoding::default(),
+            word_order: WordOrder::default(),
+            byte_order: ByteOrder::default(),
+            scale: 1.0,
+            offset: 0.0,
+            unit: None,
+            histogram: None,
+            update_ms,
+            align_to_clock: false,
+            dynamics_enable_coil: None,
+            decay: None,
+            rate_limit: None,
+            write_quirk: None,
+            whole_reads: false,
+            next_due: Instant::now() + Duration::from_millis(update_ms),
+        }
+    }
+
+    /// Engineering value of a raw (decoded) register value. Every surface that
+    /// shows engineering units goes through this.
+    pub fn to_engineering(&self, raw: f64) -> f64 {
+        raw * self.scale + self.offset
+    }
+
+    /// Raw register value for an engineering value, rounded and clamped to the
+    /// register range.
+    pub fn to_raw(&self, engineering: f64) -> u16 {
+        let raw = if self.scale == 0.0 {
+            0.0
+        } else {
+            (engineering - self.offset) / self.scale
+        };
+        raw.round().clamp(0.0, u16::MAX as f64) as u16
+    }
+}
+
 impl SimState {
     pub fn new(
         global_update_ms: u64,
@@ -44,356 +381,3245 @@ impl SimState {
         discrete_inputs: Vec<BoolItemConfig>,
         holding_registers: Vec<RegisterItemConfig>,
         input_registers: Vec<RegisterItemConfig>,
-    ) -> Self {
+    ) -> Result<Self> {
+        Self::with_registry(
+            &DynamicsRegistry::builtin(),
+            global_update_ms,
+            log_value_updates,
+            coils,
+            discrete_inputs,
+            holding_registers,
+            input_registers,
+        )
+    }
+
+    /// Like [`SimState::new`], resolving dynamics kinds through `registry`.
+    pub fn with_registry(
+        registry: &DynamicsRegistry,
+        global_update_ms: u64,
+        log_value_updates: bool,
+        coils: Vec<BoolItemConfig>,
+        discrete_inputs: Vec<BoolItemConfig>,
+        holding_registers: Vec<RegisterItemConfig>,
+        input_registers: Vec<RegisterItemConfig>,
+    ) -> Result<Self> {
         let start_time = Instant::now();
         let coils = coils
             .into_iter()
             .map(|item| {
                 let update_ms = item.update_ms.unwrap_or(global_update_ms);
-                let next_due = start_time + Duration::from_millis(update_ms);
-                (
+                let next_due = next_due(start_time, update_ms, item.align_to_clock);
+                let persist = item.persist.unwrap_or(item.dynamics.is_none());
+                let dynamics = build_dynamics(registry, &item.dynamics, item.seed)
+                    .with_context(|| format!("coil {}", item.address))?;
+                Ok((
                     item.address,
                     SimBoolItem {
                         value: item.initial,
                         last_value: item.initial,
-                        dynamics: item.dynamics,
+                        initial: item.initial,
+                        dynamics,
+                        dynamics_spec: item.dynamics,
+                        seed: item.seed,
+                        update_on_read: item.update_on_read,
+                        quality: item.quality,
+                        stuck: item.stuck,
+                        outage: item.outage,
+                        events: ItemEvents::default(),
+                        update_rate: UpdateRate::default(),
+                        response_delay_ms: item.response_delay_ms.unwrap_or(0),
+                        warmup_ms: item.warmup_ms,
+                        dynamics_delay_ms: item.dynamics_delay_ms,
+                        phase_ms: item.phase_ms,
+                        cold_value: item.cold_value.unwrap_or(0.0),
+                        persist,
                         update_ms,
+                        align_to_clock: item.align_to_clock,
                         next_due,
+                        momentary_ms: item.momentary.then(|| item.momentary_ms.unwrap_or(0)),
+                        reset_at: None,
+                        debounce_ms: item.debounce_ms,
+                        last_write: None,
                     },
-                )
+                ))
             })
-            .collect();
+            .collect::<Result<_>>()?;
 
         let discrete_inputs = discrete_inputs
             .into_iter()
             .map(|item| {
                 let update_ms = item.update_ms.unwrap_or(global_update_ms);
-                let next_due = start_time + Duration::from_millis(update_ms);
-                (
+                let next_due = next_due(start_time, update_ms, item.align_to_clock);
+                let persist = item.persist.unwrap_or(item.dynamics.is_none());
+                let dynamics = build_dynamics(registry, &item.dynamics, item.seed)
+                    .with_context(|| format!("discrete input {}", item.address))?;
+                Ok((
                     item.address,
                     SimBoolItem {
                         value: item.initial,
                         last_value: item.initial,
-                        dynamics: item.dynamics,
+                        initial: item.initial,
+                        dynamics,
+                        dynamics_spec: item.dynamics,
+                        seed: item.seed,
+                        update_on_read: item.update_on_read,
+                        quality: item.quality,
+                        stuck: item.stuck,
+                        outage: item.outage,
+                        events: ItemEvents::default(),
+                        update_rate: UpdateRate::default(),
+                        response_delay_ms: item.response_delay_ms.unwrap_or(0),
+                        warmup_ms: item.warmup_ms,
+                        dynamics_delay_ms: item.dynamics_delay_ms,
+                        phase_ms: item.phase_ms,
+                        cold_value: item.cold_value.unwrap_or(0.0),
+                        persist,
                         update_ms,
+                        align_to_clock: item.align_to_clock,
                         next_due,
+                        momentary_ms: None,
+                        reset_at: None,
+                        debounce_ms: 0,
+                        last_write: None,
                     },
-                )
+                ))
             })
-            .collect();
+            .collect::<Result<_>>()?;
 
-        let holding_registers = holding_registers
+        let mut holding_words = Vec::new();
+        let mut holding_registers = holding_registers
             .into_iter()
             .map(|item| {
                 let update_ms = item.update_ms.unwrap_or(global_update_ms);
-                let next_due = start_time + Duration::from_millis(update_ms);
-                (
+                let next_due = next_due(start_time, update_ms, item.align_to_clock);
+                let persist = item.persist.unwrap_or(item.dynamics.is_none());
+                let histogram = item_histogram(&item);
+                let shaping = Shaping::new(&item)
+                    .with_context(|| format!("holding register {}", item.address))?;
+                let decay = Decay::new(&item)
+                    .with_context(|| format!("holding register {}", item.address))?;
+                let rate_limit = RateLimit::new(&item)
+                    .with_context(|| format!("holding register {}", item.address))?;
+                let write_quirk = WriteQuirk::new(&item)
+                    .with_context(|| format!("holding register {}", item.address))?;
+                let words = encoding::initial_words(&item)
+                    .with_context(|| format!("holding register {}", item.address))?;
+                let initial = words[0];
+                if words.len() > 1 {
+                    holding_words.push((item.address, words));
+                }
+                let dynamics = build_dynamics(registry, &item.dynamics, item.seed)
+                    .with_context(|| format!("holding register {}", item.address))?;
+                Ok((
                     item.address,
                     SimRegisterItem {
-                        value: item.initial,
-                        last_value: item.initial,
-                        dynamics: item.dynamics,
+                        value: initial,
+                        last_value: initial,
+                        initial,
+                        dynamics,
+                        dynamics_spec: item.dynamics,
+                        seed: item.seed,
+                        update_on_read: item.update_on_read,
+                        quality: item.quality,
+                        stuck: item.stuck,
+                        outage: item.outage,
+                        events: ItemEvents::default(),
+                        update_rate: UpdateRate::default(),
+                        response_delay_ms: item.response_delay_ms.unwrap_or(0),
+                        warmup_ms: item.warmup_ms,
+                        dynamics_delay_ms: item.dynamics_delay_ms,
+                        phase_ms: item.phase_ms,
+                        cold_value: item.cold_value.unwrap_or(0.0),
+                        shaping,
+                        suppress_range_warning: item.suppress_range_warning,
+                        noise_floor_exempt: item.noise_floor_exempt,
+                        noise: 0.0,
+                        unclamped: 0.0,
+                        persist,
+                        encoding: item.encoding,
+                        word_order: item.word_order,
+                        byte_order: item.byte_order,
+                        scale: item.scale.unwrap_or(1.0),
+                        offset: item.offset.unwrap_or(0.0),
+                        unit: item.unit,
+                        histogram,
                         update_ms,
+                        align_to_clock: item.align_to_clock,
+                        dynamics_enable_coil: item.dynamics_enable_coil,
+                        decay,
+                        rate_limit,
+                        write_quirk,
+                        whole_reads: item.whole_reads,
                         next_due,
                     },
-                )
+                ))
             })
-            .collect();
+            .collect::<Result<_>>()?;
 
-        let input_registers = input_registers
+        let mut input_words = Vec::new();
+        let mut input_registers = input_registers
             .into_iter()
             .map(|item| {
                 let update_ms = item.update_ms.unwrap_or(global_update_ms);
-                let next_due = start_time + Duration::from_millis(update_ms);
-                (
+                let next_due = next_due(start_time, update_ms, item.align_to_clock);
+                let persist = item.persist.unwrap_or(item.dynamics.is_none());
+                let histogram = item_histogram(&item);
+                let shaping = Shaping::new(&item)
+                    .with_context(|| format!("input register {}", item.address))?;
+                let decay = Decay::new(&item)
+                    .with_context(|| format!("input register {}", item.address))?;
+                let rate_limit = RateLimit::new(&item)
+                    .with_context(|| format!("input register {}", item.address))?;
+                if item.write_mask.is_some() || item.write_transform.is_some() {
+                    anyhow::bail!(
+                        "input register {}: write_mask and write_transform only apply to \
+                         holding registers",
+                        item.address
+                    );
+                }
+                let words = encoding::initial_words(&item)
+                    .with_context(|| format!("input register {}", item.address))?;
+                let initial = words[0];
+                if words.len() > 1 {
+                    input_words.push((item.address, words));
+                }
+                let dynamics = build_dynamics(registry, &item.dynamics, item.seed)
+                    .with_context(|| format!("input register {}", item.address))?;
+                Ok((
                     item.address,
                     SimRegisterItem {
-                        value: item.initial,
-                        last_value: item.initial,
-                        dynamics: item.dynamics,
+                        value: initial,
+                        last_value: initial,
+                        initial,
+                        dynamics,
+                        dynamics_spec: item.dynamics,
+                        seed: item.seed,
+                        update_on_read: item.update_on_read,
+                        quality: item.quality,
+                        stuck: item.stuck,
+                        outage: item.outage,
+                        events: ItemEvents::default(),
+                        update_rate: UpdateRate::default(),
+                        response_delay_ms: item.response_delay_ms.unwrap_or(0),
+                        warmup_ms: item.warmup_ms,
+                        dynamics_delay_ms: item.dynamics_delay_ms,
+                        phase_ms: item.phase_ms,
+                        cold_value: item.cold_value.unwrap_or(0.0),
+                        shaping,
+                        suppress_range_warning: item.suppress_range_warning,
+                        noise_floor_exempt: item.noise_floor_exempt,
+                        noise: 0.0,
+                        unclamped: 0.0,
+                        persist,
+                        encoding: item.encoding,
+                        word_order: item.word_order,
+                        byte_order: item.byte_order,
+                        scale: item.scale.unwrap_or(1.0),
+                        offset: item.offset.unwrap_or(0.0),
+                        unit: item.unit,
+                        histogram,
                         update_ms,
+                        align_to_clock: item.align_to_clock,
+                        dynamics_enable_coil: item.dynamics_enable_coil,
+                        decay,
+                        rate_limit,
+                        write_quirk: None,
+                        whole_reads: item.whole_reads,
                         next_due,
                     },
-                )
+                ))
             })
-            .collect();
+            .collect::<Result<_>>()?;
+        spread_words(&mut holding_registers, holding_words, global_update_ms)
+            .context("holding registers")?;
+        spread_words(&mut input_registers, input_words, global_update_ms)
+            .context("input registers")?;
 
-        Self {
+        let state = Self {
             coils,
             discrete_inputs,
             holding_registers,
             input_registers,
             global_update_ms,
             log_value_updates,
+            min_tick_floor_ms: DEFAULT_TICK_FLOOR_MS,
+            seed: None,
+            warmup_ms: 0,
+            assert_bounds: false,
+            exception_counts: BTreeMap::new(),
+            request_duration: Histogram::new(REQUEST_DURATION_BUCKETS.to_vec()),
+            fifos: BTreeMap::new(),
+            alarms: Vec::new(),
+            ringbuffers: Vec::new(),
+            packed_registers: BTreeMap::new(),
+            write_locks: Vec::new(),
+            outage: None,
+            noise_floor: None,
+            crosstalk: None,
+            playback: None,
+            tick_overrun_policy: TickOverrunPolicy::default(),
+            tick_order: TickOrder::default(),
+            tick_evaluation: TickEvaluation::default(),
+            tick_overruns: 0,
+            following: false,
+            idle: None,
+            clients: std::sync::Arc::default(),
+            allow_dynamic_create: true,
+            write_conflict: WriteConflictPolicy::default(),
+            busy_window: Duration::ZERO,
+            last_writers: BTreeMap::new(),
+            read_blocks: None,
+            mirror_registers: false,
+            pending_events: Vec::new(),
+            registry: registry.clone(),
             start_time,
+            clock_rate: 1.0,
+            started_at: SystemTime::now(),
+            change_feed: None,
+        };
+        check_follows(&state.follows(), |space, address| {
+            state.is_configured(space, address)
+        })?;
+        Ok(state)
+    }
+
+    /// The register each item with a following dynamic follows, by item.
+    fn follows(&self) -> BTreeMap<(Space, u16), (Space, u16)> {
+        let bools = [
+            (Space::Coils, &self.coils),
+            (Space::DiscreteInputs, &self.discrete_inputs),
+        ]
+        .into_iter()
+        .flat_map(|(space, map)| {
+            map.iter()
+                .map(move |(address, item)| ((space, *address), &item.dynamics))
+        });
+        let registers = [
+            (Space::HoldingRegisters, &self.holding_registers),
+            (Space::InputRegisters, &self.input_registers),
+        ]
+        .into_iter()
+        .flat_map(|(space, map)| {
+            map.iter()
+                .map(move |(address, item)| ((space, *address), &item.dynamics))
+        });
+        bools
+            .chain(registers)
+            .filter_map(|(item, dynamics)| {
+                let (space, address) = dynamics.as_ref()?.source()?;
+                Some((item, (space.into(), address)))
+            })
+            .collect()
+    }
+
+    /// The dynamics of the item at `address` and the spec they were built
+    /// from, if it has any.
+    pub fn dynamics(&self, space: Space, address: u16) -> Option<(&DynamicsSpec, &dyn Dynamic)> {
+        let (spec, dynamics) = match space {
+            Space::Coils | Space::DiscreteInputs => {
+                let map = if space == Space::Coils {
+                    &self.coils
+                } else {
+                    &self.discrete_inputs
+                };
+                let item = map.get(&address)?;
+                (&item.dynamics_spec, &item.dynamics)
+            }
+            Space::HoldingRegisters | Space::InputRegisters => {
+                let map = if space == Space::HoldingRegisters {
+                    &self.holding_registers
+                } else {
+                    &self.input_registers
+                };
+                let item = map.get(&address)?;
+                (&item.dynamics_spec, &item.dynamics)
+            }
+        };
+        Some((spec.as_ref()?, dynamics.as_deref()?))
+    }
+
+    pub fn is_configured(&self, space: Space, address: u16) -> bool {
+        match space {
+            Space::Coils => self.coils.contains_key(&address),
+            Space::DiscreteInputs => self.discrete_inputs.contains_key(&address),
+            Space::HoldingRegisters => self.holding_registers.contains_key(&address),
+            Space::InputRegisters => self.input_registers.contains_key(&address),
         }
     }
 
-    pub fn min_tick_ms(&self) -> u64 {
-        let mut min_ms = self.global_update_ms.max(10);
-        for item in self.coils.values().chain(self.discrete_inputs.values()) {
-            min_ms = min_ms.min(item.update_ms.max(10));
+    /// Current source values of the following items of `space` in `range`,
+    /// by item address.
+    fn follow_snapshot(&self, space: Space, range: impl RangeBounds<u16>) -> BTreeMap<u16, f64> {
+        let sources: Vec<(u16, (RegisterSpace, u16))> = match space {
+            Space::Coils | Space::DiscreteInputs => {
+                let map = if space == Space::Coils {
+                    &self.coils
+                } else {
+                    &self.discrete_inputs
+                };
+                map.range(range)
+                    .filter_map(|(address, item)| {
+                        Some((*address, item.dynamics.as_ref()?.source()?))
+                    })
+                    .collect()
+            }
+            Space::HoldingRegisters | Space::InputRegisters => {
+                let map = if space == Space::HoldingRegisters {
+                    &self.holding_registers
+                } else {
+                    &self.input_registers
+                };
+                map.range(range)
+                    .filter_map(|(address, item)| {
+                        Some((*address, item.dynamics.as_ref()?.source()?))
+                    })
+                    .collect()
+            }
+        };
+        sources
+            .into_iter()
+            .filter_map(|(address, (space, source))| {
+                let map = match space {
+                    RegisterSpace::HoldingRegisters => &self.holding_registers,
+                    RegisterSpace::InputRegisters => &self.input_registers,
+                };
+                Some((address, f64::from(map.get(&source)?.value)))
+            })
+            .collect()
+    }
+
+    /// Sets the absolute floor for the tick interval; `0` is treated as `1`.
+    pub fn with_tick_floor(mut self, floor_ms: u64) -> Self {
+        self.min_tick_floor_ms = floor_ms.max(1);
+        self
+    }
+
+    pub fn with_tick_overrun_policy(mut self, policy: TickOverrunPolicy) -> Self {
+        self.tick_overrun_policy = policy;
+        self
+    }
+
+    pub fn with_dynamic_create(mut self, allow: bool) -> Self {
+        self.allow_dynamic_create = allow;
+        self
+    }
+
+    /// Lets register reads fall back to the other register space.
+    pub fn with_mirror_registers(mut self, enabled: bool) -> Self {
+        self.mirror_registers = enabled;
+        self
+    }
+
+    /// Adds the FIFO queues served by Read FIFO Queue (FC24).
+    pub fn with_fifos(mut self, fifos: &[FifoConfig]) -> Self {
+        self.fifos.extend(
+            fifos
+                .iter()
+                .map(|fifo| (fifo.address, fifo.values.iter().copied().collect())),
+        );
+        self
+    }
+
+    /// Makes the simulation clock drift from the wall clock by `ppm` parts per
+    /// million, like a device with a cheap oscillator.
+    pub fn with_clock_drift(mut self, ppm: f64) -> Self {
+        self.clock_rate = 1.0 + ppm / 1_000_000.0;
+        self
+    }
+
+    /// Sets the warmup window used by items without their own `warmup_ms`.
+    pub fn with_warmup(mut self, warmup_ms: u64) -> Self {
+        self.warmup_ms = warmup_ms;
+        self
+    }
+
+    /// Makes [`SimState::tick`] fail when a register it updates falls outside
+    /// the `min`/`max` of its dynamics.
+    pub fn with_assert_bounds(mut self, assert_bounds: bool) -> Self {
+        self.assert_bounds = assert_bounds;
+        self
+    }
+
+    /// Gives every periodic item (dynamics with a `period_ms`) without its own
+    /// `phase_ms` a random phase within its period, so banks of identical
+    /// items do not move in lockstep. Phases derive from the global seed when
+    /// one is set.
+    pub fn with_random_phase(mut self, enabled: bool) -> Self {
+        if !enabled {
+            return self;
         }
-        for item in self
-            .holding_registers
-            .values()
-            .chain(self.input_registers.values())
+        let seed = self.seed;
+        let phase = |table: u64, address: u16, spec: &Option<DynamicsSpec>| {
+            let period = period_ms(spec)?;
+            let mut rng = match seed {
+                Some(global) => {
+                    StdRng::seed_from_u64(derived_seed(global ^ PHASE_SEED_SALT, table, address))
+                }
+                None => StdRng::from_entropy(),
+            };
+            Some(rng.gen_range(0..period))
+        };
+        for (table, map) in [(0, &mut self.coils), (1, &mut self.discrete_inputs)] {
+            for (address, item) in map.iter_mut() {
+                if item.phase_ms.is_none() {
+                    item.phase_ms = phase(table, *address, &item.dynamics_spec);
+                }
+            }
+        }
+        for (table, map) in [
+            (2, &mut self.holding_registers),
+            (3, &mut self.input_registers),
+        ] {
+            for (address, item) in map.iter_mut() {
+                if item.phase_ms.is_none() {
+                    item.phase_ms = phase(table, *address, &item.dynamics_spec);
+                }
+            }
+        }
+        self
+    }
+
+    /// Seeds the random stream of every item without its own `seed` from
+    /// `global`, the table and the address, so each stream is stable no matter
+    /// which other items are configured. Without a global seed those items keep
+    /// entropy-seeded streams.
+    pub fn with_seed(mut self, global: Option<u64>) -> Self {
+        let Some(global) = global else {
+            return self;
+        };
+        self.seed = Some(global);
+        for (table, map) in [(0, &mut self.coils), (1, &mut self.discrete_inputs)] {
+            for (address, item) in map.iter_mut() {
+                if let (None, Some(dynamic)) = (item.seed, item.dynamics.as_mut()) {
+                    dynamic.reseed(StdRng::seed_from_u64(derived_seed(global, table, *address)));
+                }
+            }
+        }
+        for (table, map) in [
+            (2, &mut self.holding_registers),
+            (3, &mut self.input_registers),
+        ] {
+            for (address, item) in map.iter_mut() {
+                if let (None, Some(dynamic)) = (item.seed, item.dynamics.as_mut()) {
+                    dynamic.reseed(StdRng::seed_from_u64(derived_seed(global, table, *address)));
+                }
+            }
+        }
+        self
+    }
+
+    /// Replaces the dynamics and `update_ms` of items present both here and in
+    /// `device`, keeping their live values. Items that only exist on one side
+    /// are left alone. Registers whose encoding, word order or byte order
+    /// changed take a new value as `device.encoding_reload` says, spread over
+    /// the registers of the new width. Every dynamics table and layout is
+    /// checked before anything is applied, so an invalid one leaves the state
+    /// untouched. Returns a description of each change.
+    pub fn reload_dynamics(&mut self, device: &DeviceConfig) -> Result<Vec<String>> {
+        let mut changes = Vec::new();
+
+        let mut bool_updates = Vec::new();
+        for (table, name, map, items) in [
+            (0, "coil", &self.coils, &device.coils),
+            (
+                1,
+                "discrete input",
+                &self.discrete_inputs,
+                &device.discrete_inputs,
+            ),
+        ] {
+            for item in items {
+                let Some(current) = map.get(&item.address) else {
+                    continue;
+                };
+                let update_ms = item.update_ms.unwrap_or(self.global_update_ms);
+                if current.dynamics_spec == item.dynamics && current.update_ms == update_ms {
+                    continue;
+                }
+                let seed = stream_seed(self.seed, current.seed, table, item.address);
+                let dynamics = build_dynamics(&self.registry, &item.dynamics, seed)
+                    .with_context(|| format!("{name} {}", item.address))?;
+                changes.push(describe_reload(
+                    name,
+                    item.address,
+                    (&current.dynamics_spec, current.update_ms),
+                    (&item.dynamics, update_ms),
+                ));
+                bool_updates.push((
+                    table,
+                    item.address,
+                    dynamics,
+                    item.dynamics.clone(),
+                    update_ms,
+                ));
+            }
+        }
+
+        let mut register_updates = Vec::new();
+        let mut layout_updates = Vec::new();
+        for (table, name, map, items) in [
+            (
+                2,
+                "holding register",
+                &self.holding_registers,
+                &device.holding_registers,
+            ),
+            (
+                3,
+                "input register",
+                &self.input_registers,
+                &device.input_registers,
+            ),
+        ] {
+            let space = if table == 2 {
+                Space::HoldingRegisters
+            } else {
+                Space::InputRegisters
+            };
+            for item in items {
+                let Some(current) = map.get(&item.address) else {
+                    continue;
+                };
+                // Playback drives these instead of dynamics.
+                if self.replayed(space, item.address) {
+                    continue;
+                }
+                let layout = (item.encoding, item.word_order, item.byte_order);
+                if layout != (current.encoding, current.word_order, current.byte_order) {
+                    let words = relayout_words(map, item, device.encoding_reload)
+                        .with_context(|| format!("{name} {}", item.address))?;
+                    changes.push(format!(
+                        "{name} {}: encoding {} -> {}, {} to {words:?}",
+                        item.address,
+                        current.encoding.name(),
+                        item.encoding.name(),
+                        match device.encoding_reload {
+                            EncodingReload::Reinitialize => "re-initialized",
+                            EncodingReload::Reinterpret => "reinterpreted",
+                        }
+                    ));
+                    layout_updates.push((table, item.address, layout, words));
+                }
+                let update_ms = item.update_ms.unwrap_or(self.global_update_ms);
+                if current.dynamics_spec == item.dynamics && current.update_ms == update_ms {
+                    continue;
+                }
+                let seed = stream_seed(self.seed, current.seed, table, item.address);
+                let dynamics = build_dynamics(&self.registry, &item.dynamics, seed)
+                    .with_context(|| format!("{name} {}", item.address))?;
+                changes.push(describe_reload(
+                    name,
+                    item.address,
+                    (&current.dynamics_spec, current.update_ms),
+                    (&item.dynamics, update_ms),
+                ));
+                register_updates.push((
+                    table,
+                    item.address,
+                    dynamics,
+                    item.dynamics.clone(),
+                    update_ms,
+                ));
+            }
+        }
+
+        let spaces = [
+            Space::Coils,
+            Space::DiscreteInputs,
+            Space::HoldingRegisters,
+            Space::InputRegisters,
+        ];
+        let mut follows = self.follows();
+        for (table, address, dynamics) in bool_updates
+            .iter()
+            .map(|(table, address, dynamics, ..)| (table, address, dynamics))
+            .chain(
+                register_updates
+                    .iter()
+                    .map(|(table, address, dynamics, ..)| (table, address, dynamics)),
+            )
         {
-            min_ms = min_ms.min(item.update_ms.max(10));
+            let item = (spaces[*table as usize], *address);
+            match dynamics.as_ref().and_then(|dynamics| dynamics.source()) {
+                Some((space, source)) => follows.insert(item, (space.into(), source)),
+                None => follows.remove(&item),
+            };
+        }
+        check_follows(&follows, |space, address| {
+            self.is_configured(space, address)
+        })?;
+
+        let now = Instant::now();
+        for (table, address, dynamics, spec, update_ms) in bool_updates {
+            let map = if table == 0 {
+                &mut self.coils
+            } else {
+                &mut self.discrete_inputs
+            };
+            if let Some(item) = map.get_mut(&address) {
+                item.dynamics = dynamics;
+                item.dynamics_spec = spec;
+                item.update_ms = update_ms;
+                item.next_due = next_due(now, update_ms, item.align_to_clock);
+            }
+        }
+        for (table, address, dynamics, spec, update_ms) in register_updates {
+            let map = if table == 2 {
+                &mut self.holding_registers
+            } else {
+                &mut self.input_registers
+            };
+            if let Some(item) = map.get_mut(&address) {
+                item.dynamics = dynamics;
+                item.dynamics_spec = spec;
+                item.update_ms = update_ms;
+                item.next_due = next_due(now, update_ms, item.align_to_clock);
+            }
+        }
+        let update_ms = self.global_update_ms;
+        for (table, address, (encoding, word_order, byte_order), words) in layout_updates {
+            let map = if table == 2 {
+                &mut self.holding_registers
+            } else {
+                &mut self.input_registers
+            };
+            if let Some(item) = map.get_mut(&address) {
+                item.encoding = encoding;
+                item.word_order = word_order;
+                item.byte_order = byte_order;
+            }
+            for (address, word) in (address..=u16::MAX).zip(words) {
+                let item = map
+                    .entry(address)
+                    .or_insert_with(|| SimRegisterItem::plain(word, update_ms));
+                item.value = word;
+                item.last_value = word;
+            }
+        }
+
+        Ok(changes)
+    }
+
+    pub fn min_tick_ms(&self) -> u64 {
+        let floor = self.min_tick_floor_ms;
+        let mut min_ms = self.global_update_ms.max(floor);
+        for update_ms in self.item_update_intervals() {
+            min_ms = min_ms.min(update_ms.max(floor));
         }
         min_ms
     }
 
-    pub fn tick(&mut self) {
-        let now = Instant::now();
-        let elapsed = now.saturating_duration_since(self.start_time).as_secs_f64();
+    /// Warnings for update rates likely to burn CPU: items updating faster than
+    /// `warn_update_ms`, and an estimated update rate above `max_updates_per_sec`.
+    pub fn tick_rate_warnings(&self, warn_update_ms: u64, max_updates_per_sec: u64) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let fast: Vec<u64> = self
+            .item_update_intervals()
+            .filter(|update_ms| *update_ms < warn_update_ms)
+            .collect();
+        if let Some(fastest) = fast.iter().min() {
+            warnings.push(format!(
+                "{} item(s) have update_ms below {warn_update_ms} (fastest {fastest}ms, floored to {}ms)",
+                fast.len(),
+                fastest.max(&self.min_tick_floor_ms),
+            ));
+        }
+
+        let floor = self.min_tick_floor_ms;
+        let updates_per_sec: f64 = self
+            .item_update_intervals()
+            .map(|update_ms| 1000.0 / update_ms.max(floor) as f64)
+            .sum::<f64>()
+            + 1000.0 / self.min_tick_ms() as f64;
+        if updates_per_sec > max_updates_per_sec as f64 {
+            warnings.push(format!(
+                "estimated {updates_per_sec:.0} item updates/s exceeds {max_updates_per_sec}; raise update_ms or global.min_tick_ms"
+            ));
+        }
+        warnings
+    }
+
+    /// Warnings for registers whose dynamics can only produce a sliver of the
+    /// raw range, or values outside it that get clamped: both usually mean a
+    /// forgotten scale. Dynamics without a known output range are skipped.
+    pub fn range_warnings(&self) -> Vec<String> {
+        let raw_max = f64::from(u16::MAX);
+        let mut warnings = Vec::new();
+        for (name, map) in [
+            ("holding register", &self.holding_registers),
+            ("input register", &self.input_registers),
+        ] {
+            for (address, item) in map {
+                // A transform reshapes the range, and encoded items are not
+                // clamped to a register, so neither says anything here.
+                if item.suppress_range_warning
+                    || item.shaping.transform.is_some()
+                    || item.encoding != Encoding::U16
+                {
+                    continue;
+                }
+                let (Some(dynamics), Some(spec)) = (&item.dynamics, &item.dynamics_spec) else {
+                    continue;
+                };
+                let Some((low, high)) = dynamics.output_range() else {
+                    continue;
+                };
+                let kind = &spec.kind;
+                if low < 0.0 || high > raw_max {
+                    let always = if high < 0.0 || low > raw_max {
+                        "always "
+                    } else {
+                        ""
+                    };
+                    warnings.push(format!(
+                        "{name} {address}: `{kind}` dynamics produce {low}..={high} and are {always}clamped to 0..=65535"
+                    ));
+                } else if high - low < raw_max * NARROW_RANGE_FRACTION {
+                    warnings.push(format!(
+                        "{name} {address}: `{kind}` dynamics only produce {low}..={high}; a missing scale? Set suppress_range_warning to silence this"
+                    ));
+                }
+            }
+        }
+        warnings
+    }
 
-        for (address, item) in self.coils.iter_mut() {
-            if now < item.next_due {
-                continue;
+    /// Update intervals of tick-scheduled items.
+    fn item_update_intervals(&self) -> impl Iterator<Item = u64> + '_ {
+        self.coils
+            .values()
+            .chain(self.discrete_inputs.values())
+            .filter(|item| !item.update_on_read)
+            .map(|item| item.update_ms)
+            .chain(
+                self.holding_registers
+                    .values()
+                    .chain(self.input_registers.values())
+                    .filter(|item| !item.update_on_read)
+                    .map(|item| item.update_ms),
+            )
+    }
+
+    /// Evaluates every tick-scheduled item that is due, in the device's
+    /// [`TickOrder`]: by default coils, discrete inputs, holding registers,
+    /// then input registers, each by ascending address. Items reading other
+    /// items (`follow` sources, `controlled` setpoints and enable coils) see
+    /// them as of the start of the tick, unless the evaluation is
+    /// [`TickEvaluation::Sequential`], where they see updates made earlier in
+    /// the same tick and so depend on the order. Alarm bits and ring buffers
+    /// follow, from the values just computed. With bounds assertion enabled,
+    /// returns an error at the first register out of bounds.
+    ///
+    /// A value spanning several registers gets all its words in the same
+    /// tick, so a reader holding the state lock, which the tick loop keeps
+    /// for the whole tick, never sees a half-updated value.
+    pub fn tick(&mut self) -> Result<()> {
+        let now = Instant::now();
+        let elapsed_ms = self.sim_elapsed(now).as_millis() as u64;
+        self.apply_events(now);
+        let pass = TickPass {
+            now,
+            elapsed: self.sim_elapsed(now).as_secs_f64(),
+            elapsed_ms,
+            outage: in_outage(&self.outage, elapsed_ms),
+            timestamp_ms: changes::timestamp_ms(),
+            snapshot: (self.tick_evaluation == TickEvaluation::TwoPass)
+                .then(|| self.tick_snapshot()),
+            replay_held: self.replay_held(),
+            crosstalk: [Space::HoldingRegisters, Space::InputRegisters]
+                .into_iter()
+                .flat_map(|space| {
+                    self.crosstalk(space)
+                        .into_iter()
+                        .map(move |(address, crosstalk)| ((space, address), crosstalk))
+                })
+                .collect(),
+        };
+        let mut holding = RegisterPass::new(&self.holding_registers);
+        let mut input = RegisterPass::new(&self.input_registers);
+        for (space, address) in self.tick_items() {
+            match space {
+                Space::Coils | Space::DiscreteInputs => self.tick_bool(space, address, &pass),
+                Space::HoldingRegisters => {
+                    self.tick_register(space, address, &pass, &mut holding)?
+                }
+                Space::InputRegisters => self.tick_register(space, address, &pass, &mut input)?,
             }
-            let value = eval_bool(item.value, &item.dynamics, elapsed);
-            let changed = value != item.value;
+        }
+        apply_spill(&mut self.holding_registers, holding.spill);
+        apply_spill(&mut self.input_registers, input.spill);
+        self.apply_playback(elapsed_ms, pass.outage);
+
+        self.update_outage_status(elapsed_ms);
+        self.evaluate_alarms();
+        self.sample_ringbuffers(now);
+        Ok(())
+    }
+
+    /// Updates the coil or discrete input at `address` if it is due.
+    fn tick_bool(&mut self, space: Space, address: u16, pass: &TickPass) {
+        let source = self.tick_source(pass, space, address);
+        let now = pass.now;
+        let (map, name) = if space == Space::Coils {
+            (&mut self.coils, "coil")
+        } else {
+            (&mut self.discrete_inputs, "discrete input")
+        };
+        let Some(item) = map.get_mut(&address) else {
+            return;
+        };
+        if let Some(reset_at) = item.reset_at
+            && now >= reset_at
+        {
+            item.reset_at = None;
             item.last_value = item.value;
-            item.value = value;
-            item.next_due = now + Duration::from_millis(item.update_ms);
-            if self.log_value_updates && changed {
-                info!(address = *address, value = item.value, "coil updated");
+            item.value = false;
+            if self.log_value_updates {
+                info!(address, "momentary coil reset");
+            }
+        }
+        let before = item.value;
+        let pulse = item.events.pulse(now);
+        match pulse {
+            Some(PulseState::Active(value)) => {
+                item.last_value = item.value;
+                item.value = value != 0.0;
+            }
+            Some(PulseState::Ended(value)) => {
+                item.value = value != 0.0;
+                item.next_due = now;
             }
+            None => {}
+        }
+        if item.value != before {
+            let value = f64::from(u8::from(item.value));
+            changes::publish(&self.change_feed, space, address, value, pass.timestamp_ms);
+        }
+        if matches!(pulse, Some(PulseState::Active(_))) {
+            return;
         }
+        if item.update_on_read || now < item.next_due {
+            return;
+        }
+        if frozen(pass.outage, &item.stuck, &item.outage, pass.elapsed_ms) {
+            item.next_due = next_due(now, item.update_ms, item.align_to_clock);
+            return;
+        }
+        let startup = item.startup(self.warmup_ms);
+        let value = eval_bool(
+            item.value,
+            &mut item.dynamics,
+            startup,
+            pass.elapsed,
+            source,
+        );
+        let changed = value != item.value;
+        item.last_value = item.value;
+        item.value = value;
+        item.next_due = next_due(now, item.update_ms, item.align_to_clock);
+        item.update_rate.observe(now);
+        if changed {
+            let value = f64::from(u8::from(value));
+            changes::publish(&self.change_feed, space, address, value, pass.timestamp_ms);
+        }
+        if self.log_value_updates && changed {
+            info!(address, value = item.value, "{name} updated");
+        }
+    }
 
-        for (address, item) in self.discrete_inputs.iter_mut() {
-            if now < item.next_due {
-                continue;
+    /// Updates the holding or input register at `address` if it is due,
+    /// leaving the words past the first in `registers.spill`.
+    fn tick_register(
+        &mut self,
+        space: Space,
+        address: u16,
+        pass: &TickPass,
+        registers: &mut RegisterPass,
+    ) -> Result<()> {
+        let source = self.tick_source(pass, space, address);
+        let now = pass.now;
+        let (map, name) = if space == Space::HoldingRegisters {
+            (&mut self.holding_registers, "holding register")
+        } else {
+            (&mut self.input_registers, "input register")
+        };
+        let Some(item) = map.get_mut(&address) else {
+            return Ok(());
+        };
+        let before = item.value;
+        let pulse = item.events.pulse(now);
+        match pulse {
+            Some(PulseState::Active(value)) => {
+                item.last_value = item.value;
+                item.value = raw(value);
             }
-            let value = eval_bool(item.value, &item.dynamics, elapsed);
-            let changed = value != item.value;
-            item.last_value = item.value;
-            item.value = value;
-            item.next_due = now + Duration::from_millis(item.update_ms);
-            if self.log_value_updates && changed {
-                info!(
-                    address = *address,
-                    value = item.value,
-                    "discrete input updated"
-                );
+            Some(PulseState::Ended(value)) => {
+                item.value = raw(value);
+                item.next_due = now;
             }
+            None => {}
+        }
+        if item.value != before {
+            let value = f64::from(item.value);
+            changes::publish(&self.change_feed, space, address, value, pass.timestamp_ms);
+        }
+        if matches!(pulse, Some(PulseState::Active(_))) {
+            return Ok(());
+        }
+        if item.update_on_read || now < item.next_due {
+            return Ok(());
+        }
+        let enabled = match &pass.snapshot {
+            Some(snapshot) => item
+                .dynamics_enable_coil
+                .is_none_or(|coil| snapshot.coils.get(&coil) == Some(&true)),
+            None => dynamics_enabled(&self.coils, item.dynamics_enable_coil),
+        };
+        if !enabled
+            || frozen(pass.outage, &item.stuck, &item.outage, pass.elapsed_ms)
+            || pass.replay_held.contains(&(space, address))
+        {
+            item.next_due = next_due(now, item.update_ms, item.align_to_clock);
+            return Ok(());
+        }
+        let startup = item.startup(self.warmup_ms);
+        let logical = registers.logical.remove(&address);
+        let current = logical
+            .as_ref()
+            .map_or(item.value as f64, |(value, _)| *value);
+        let crosstalk = pass.crosstalk.get(&(space, address)).copied();
+        let noise = noise::noise(&mut self.noise_floor, item) + crosstalk.unwrap_or(0.0);
+        let (value, words) = item.eval_words(logical, noise, startup, pass.elapsed, source);
+        let (value, words) = item.limit_change(name, address, current, (value, words));
+        if self.assert_bounds {
+            check_bounds(item, startup, value, pass.elapsed)
+                .with_context(|| format!("{name} {address}"))?;
+        }
+        if let Some(histogram) = &mut item.histogram {
+            histogram.observe(value);
         }
+        let changed = value != current;
+        item.last_value = item.value;
+        item.value = words[0];
+        registers
+            .spill
+            .extend((address.saturating_add(1)..=u16::MAX).zip(words.into_iter().skip(1)));
+        item.next_due = next_due(now, item.update_ms, item.align_to_clock);
+        item.update_rate.observe(now);
+        if changed {
+            changes::publish(&self.change_feed, space, address, value, pass.timestamp_ms);
+        }
+        if self.log_value_updates && changed {
+            info!(
+                address,
+                value = item.value,
+                engineering = item.to_engineering(value),
+                unit = item.unit.as_deref(),
+                "{name} updated"
+            );
+        }
+        Ok(())
+    }
+
+    /// Whether a read of the range evaluates an `update_on_read` item, and so
+    /// has to go through [`SimState::sample_on_read`] under the write lock.
+    pub fn samples_on_read(&self, space: Space, address: u16, count: u16) -> bool {
+        let range = read_range(address, count);
+        match space {
+            Space::Coils => self.coils.range(range).any(|(_, item)| item.update_on_read),
+            Space::DiscreteInputs => self
+                .discrete_inputs
+                .range(range)
+                .any(|(_, item)| item.update_on_read),
+            Space::HoldingRegisters => self
+                .holding_registers
+                .range(range)
+                .any(|(_, item)| item.update_on_read),
+            Space::InputRegisters => self
+                .input_registers
+                .range(range)
+                .any(|(_, item)| item.update_on_read),
+        }
+    }
 
-        for (address, item) in self.holding_registers.iter_mut() {
-            if now < item.next_due {
-                continue;
+    /// Evaluates the dynamics of `update_on_read` items in the range so a
+    /// following read sees their value at this moment. Callers reading on
+    /// behalf of a client call this under the write lock before reading.
+    pub fn sample_on_read(&mut self, space: Space, address: u16, count: u16) {
+        let elapsed = self.sim_elapsed(Instant::now()).as_secs_f64();
+        let elapsed_ms = self.elapsed_ms();
+        let outage = in_outage(&self.outage, elapsed_ms);
+        let log = self.log_value_updates;
+        let warmup_ms = self.warmup_ms;
+        let range = read_range(address, count);
+        let sources = self.follow_snapshot(space, range.clone());
+        match space {
+            Space::Coils | Space::DiscreteInputs => {
+                let (map, name) = if space == Space::Coils {
+                    (&mut self.coils, "coil")
+                } else {
+                    (&mut self.discrete_inputs, "discrete input")
+                };
+                for (address, item) in map.range_mut(range) {
+                    if !item.update_on_read
+                        || item.events.pulsing()
+                        || frozen(outage, &item.stuck, &item.outage, elapsed_ms)
+                    {
+                        continue;
+                    }
+                    let startup = item.startup(warmup_ms);
+                    let value = eval_bool(
+                        item.value,
+                        &mut item.dynamics,
+                        startup,
+                        elapsed,
+                        sources.get(address).copied(),
+                    );
+                    item.last_value = item.value;
+                    item.value = value;
+                    if log && item.last_value != value {
+                        info!(address = *address, value, "{name} sampled on read");
+                    }
+                }
             }
-            let value = eval_register(item.value, &item.dynamics, elapsed);
-            let changed = value != item.value;
-            item.last_value = item.value;
-            item.value = value;
-            item.next_due = now + Duration::from_millis(item.update_ms);
-            if self.log_value_updates && changed {
-                info!(
-                    address = *address,
-                    value = item.value,
-                    "holding register updated"
-                );
+            Space::HoldingRegisters | Space::InputRegisters => {
+                let crosstalk = self.crosstalk(space);
+                let (map, name) = if space == Space::HoldingRegisters {
+                    (&mut self.holding_registers, "holding register")
+                } else {
+                    (&mut self.input_registers, "input register")
+                };
+                let mut logical = encoding::logical_values(map);
+                let mut spill = Vec::new();
+                for (address, item) in map.range_mut(range) {
+                    if !item.update_on_read
+                        || item.events.pulsing()
+                        || !dynamics_enabled(&self.coils, item.dynamics_enable_coil)
+                        || frozen(outage, &item.stuck, &item.outage, elapsed_ms)
+                    {
+                        continue;
+                    }
+                    let startup = item.startup(warmup_ms);
+                    let logical = logical.remove(address);
+                    let current = logical
+                        .as_ref()
+                        .map_or(item.value as f64, |(value, _)| *value);
+                    let noise = noise::noise(&mut self.noise_floor, item)
+                        + crosstalk.get(address).copied().unwrap_or(0.0);
+                    let (value, words) = item.eval_words(
+                        logical,
+                        noise,
+                        startup,
+                        elapsed,
+                        sources.get(address).copied(),
+                    );
+                    let (value, words) = item.limit_change(name, *address, current, (value, words));
+                    if let Some(histogram) = &mut item.histogram {
+                        histogram.observe(value);
+                    }
+                    item.last_value = item.value;
+                    item.value = words[0];
+                    spill.extend(
+                        (address.saturating_add(1)..=u16::MAX).zip(words.into_iter().skip(1)),
+                    );
+                    if log && value != current {
+                        info!(
+                            address = *address,
+                            value = item.value,
+                            engineering = item.to_engineering(value),
+                            "{name} sampled on read"
+                        );
+                    }
+                }
+                apply_spill(map, spill);
             }
         }
+    }
 
-        for (address, item) in self.input_registers.iter_mut() {
-            if now < item.next_due {
-                continue;
+    /// Longest `response_delay_ms` of the items in `count` addresses from
+    /// `address`.
+    pub fn response_delay(&self, space: Space, address: u16, count: u16) -> Duration {
+        let end = u32::from(address) + u32::from(count);
+        let in_range = |item_address: &u16| u32::from(*item_address) < end;
+        let delay_ms = match space {
+            Space::Coils | Space::DiscreteInputs => {
+                let map = if space == Space::Coils {
+                    &self.coils
+                } else {
+                    &self.discrete_inputs
+                };
+                map.range(address..)
+                    .take_while(|(item_address, _)| in_range(item_address))
+                    .map(|(_, item)| item.response_delay_ms)
+                    .max()
             }
-            let value = eval_register(item.value, &item.dynamics, elapsed);
-            let changed = value != item.value;
-            item.last_value = item.value;
-            item.value = value;
-            item.next_due = now + Duration::from_millis(item.update_ms);
-            if self.log_value_updates && changed {
-                info!(
-                    address = *address,
-                    value = item.value,
-                    "input register updated"
-                );
+            Space::HoldingRegisters | Space::InputRegisters => {
+                let map = if space == Space::HoldingRegisters {
+                    &self.holding_registers
+                } else {
+                    &self.input_registers
+                };
+                map.range(address..)
+                    .take_while(|(item_address, _)| in_range(item_address))
+                    .map(|(_, item)| item.response_delay_ms)
+                    .max()
             }
+        };
+        Duration::from_millis(delay_ms.unwrap_or(0))
+    }
+
+    /// Counts an exception response sent to a client.
+    pub fn record_exception(&mut self, exception: Exception) {
+        *self.exception_counts.entry(exception).or_default() += 1;
+    }
+
+    /// Contents of the FIFO queue at `pointer`, oldest first. Reading leaves
+    /// the queue unchanged. A queue longer than 31 values cannot be read.
+    pub fn read_fifo_queue(&self, pointer: u16) -> Result<Vec<u16>, Exception> {
+        if self.fifos.is_empty() {
+            return Err(Exception::IllegalFunction);
+        }
+        let fifo = self
+            .fifos
+            .get(&pointer)
+            .ok_or(Exception::IllegalDataAddress)?;
+        if fifo.len() > MAX_FIFO_COUNT {
+            return Err(Exception::IllegalDataValue);
         }
+        Ok(fifo.iter().copied().collect())
+    }
+
+    pub fn read_coils(&self, address: u16, count: u16) -> Result<Vec<bool>, Exception> {
+        read_range_bool(&self.coils, address, count, self.elapsed_ms())
+    }
+
+    pub fn read_discrete_inputs(&self, address: u16, count: u16) -> Result<Vec<bool>, Exception> {
+        read_range_bool(&self.discrete_inputs, address, count, self.elapsed_ms())
     }
 
-    pub fn read_coils(&self, address: u16, count: u16) -> Vec<bool> {
-        read_range_bool(&self.coils, address, count)
+    pub fn read_holding_registers(&self, address: u16, count: u16) -> Result<Vec<u16>, Exception> {
+        let mirror = self.mirror_registers.then_some(&self.input_registers);
+        read_range_register(
+            &self.holding_registers,
+            mirror,
+            address,
+            count,
+            self.elapsed_ms(),
+        )
     }
 
-    pub fn read_discrete_inputs(&self, address: u16, count: u16) -> Vec<bool> {
-        read_range_bool(&self.discrete_inputs, address, count)
+    pub fn read_input_registers(&self, address: u16, count: u16) -> Result<Vec<u16>, Exception> {
+        let mirror = self.mirror_registers.then_some(&self.holding_registers);
+        read_range_register(
+            &self.input_registers,
+            mirror,
+            address,
+            count,
+            self.elapsed_ms(),
+        )
     }
 
-    pub fn read_holding_registers(&self, address: u16, count: u16) -> Vec<u16> {
-        read_range_register(&self.holding_registers, address, count)
+    /// Time on the simulation clock, which a restore rewinds.
+    pub fn elapsed_ms(&self) -> u64 {
+        self.sim_elapsed(Instant::now()).as_millis() as u64
+    }
+
+    /// Moves the simulation clock forward by `by` at once, for tests that
+    /// would otherwise sleep. Items whose next update falls within `by`
+    /// become due, so the next tick updates them as of the later time.
+    pub fn advance_clock(&mut self, by: Duration) {
+        let by = by.div_f64(self.clock_rate);
+        self.shift_clock(|instant| instant.checked_sub(by).unwrap_or(instant));
+    }
+
+    /// Moves the start of the simulation clock and every item's next update
+    /// by `shift`.
+    fn shift_clock(&mut self, shift: impl Fn(Instant) -> Instant) {
+        self.start_time = shift(self.start_time);
+        for item in self
+            .coils
+            .values_mut()
+            .chain(self.discrete_inputs.values_mut())
+        {
+            item.next_due = shift(item.next_due);
+        }
+        for item in self
+            .holding_registers
+            .values_mut()
+            .chain(self.input_registers.values_mut())
+        {
+            item.next_due = shift(item.next_due);
+        }
     }
 
-    pub fn read_input_registers(&self, address: u16, count: u16) -> Vec<u16> {
-        read_range_register(&self.input_registers, address, count)
+    /// Time on the simulation clock at `now`, drift included.
+    fn sim_elapsed(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.start_time)
+            .mul_f64(self.clock_rate)
     }
 
+    /// Writes a coil, creating it if it is not configured. Writes within the
+    /// coil's `debounce_ms` of the last accepted write are ignored.
     pub fn write_single_coil(&mut self, address: u16, value: bool) {
+        let watched = self.watch(Space::Coils, address, 1);
         if let Some(item) = self.coils.get_mut(&address) {
+            let now = Instant::now();
+            if let Some(last_write) = item.last_write
+                && now < last_write + Duration::from_millis(item.debounce_ms)
+            {
+                debug!(address, value, "coil write ignored by debounce");
+                return;
+            }
+            item.last_write = Some(now);
             item.value = value;
+            item.reset_at = item
+                .momentary_ms
+                .filter(|_| value)
+                .map(|delay_ms| now + Duration::from_millis(delay_ms));
         } else {
             self.coils.insert(
                 address,
                 SimBoolItem {
                     value,
                     last_value: value,
+                    initial: false,
                     dynamics: None,
+                    dynamics_spec: None,
+                    seed: None,
+                    update_on_read: false,
+                    quality: None,
+                    stuck: None,
+                    outage: None,
+                    events: ItemEvents::default(),
+                    update_rate: UpdateRate::default(),
+                    response_delay_ms: 0,
+                    warmup_ms: None,
+                    dynamics_delay_ms: 0,
+                    phase_ms: None,
+                    cold_value: 0.0,
+                    persist: true,
                     update_ms: self.global_update_ms,
+                    align_to_clock: false,
                     next_due: Instant::now() + Duration::from_millis(self.global_update_ms),
+                    momentary_ms: None,
+                    reset_at: None,
+                    debounce_ms: 0,
+                    last_write: None,
                 },
             );
         }
+        self.publish_watched(watched);
+    }
+
+    /// Refuses a client write touching an unconfigured address when writes
+    /// may not create items.
+    pub fn check_writable(
+        &self,
+        space: Space,
+        address: u16,
+        count: usize,
+    ) -> Result<(), Exception> {
+        if self.allow_dynamic_create
+            || (address..=u16::MAX)
+                .take(count)
+                .all(|address| self.is_configured(space, address))
+        {
+            return Ok(());
+        }
+        Err(Exception::IllegalDataAddress)
     }
 
-    pub fn write_multiple_coils(&mut self, address: u16, values: &[bool]) {
-        for (offset, value) in values.iter().copied().enumerate() {
-            let addr = address.saturating_add(offset as u16);
+    /// Writes consecutive coils. The whole range is validated first, so a
+    /// rejected write leaves every coil unchanged.
+    pub fn write_multiple_coils(&mut self, address: u16, values: &[bool]) -> Result<(), Exception> {
+        check_write_range(address, values.len())?;
+        for (addr, value) in (address..=u16::MAX).zip(values.iter().copied()) {
             self.write_single_coil(addr, value);
         }
+        Ok(())
     }
 
     pub fn write_single_register(&mut self, address: u16, value: u16) {
+        let watched = self.watch(Space::HoldingRegisters, address, 1);
         if let Some(item) = self.holding_registers.get_mut(&address) {
             item.value = value;
         } else {
             self.holding_registers.insert(
                 address,
-                SimRegisterItem {
-                    value,
-                    last_value: value,
-                    dynamics: None,
-                    update_ms: self.global_update_ms,
-                    next_due: Instant::now() + Duration::from_millis(self.global_update_ms),
-                },
+                SimRegisterItem::plain(value, self.global_update_ms),
             );
         }
+        self.publish_watched(watched);
+    }
+
+    /// Writes `new` to a holding register only if it currently holds `expected`.
+    /// Unconfigured registers hold `0`.
+    pub fn compare_and_swap_register(&mut self, address: u16, expected: u16, new: u16) -> bool {
+        if self.holding_register_value(address) != expected {
+            return false;
+        }
+        self.write_single_register(address, new);
+        true
+    }
+
+    /// Raw value of a holding register, ignoring quality; `0` if unconfigured.
+    pub fn holding_register_value(&self, address: u16) -> u16 {
+        self.holding_registers
+            .get(&address)
+            .map(|item| item.value)
+            .unwrap_or(0)
     }
 
-    pub fn write_multiple_registers(&mut self, address: u16, values: &[u16]) {
-        for (offset, value) in values.iter().copied().enumerate() {
-            let addr = address.saturating_add(offset as u16);
+    /// Writes consecutive holding registers. The whole range is validated
+    /// first, so a rejected write leaves every register unchanged.
+    pub fn write_multiple_registers(
+        &mut self,
+        address: u16,
+        values: &[u16],
+    ) -> Result<(), Exception> {
+        check_write_range(address, values.len())?;
+        for (addr, value) in (address..=u16::MAX).zip(values.iter().copied()) {
             self.write_single_register(addr, value);
         }
+        Ok(())
     }
-}
 
-pub async fn spawn_simulator(state: std::sync::Arc<std::sync::RwLock<SimState>>) {
-    let tick_ms = state.read().unwrap().min_tick_ms();
-    let mut interval = tokio::time::interval(Duration::from_millis(tick_ms));
+    /// Sets configured discrete inputs from `address` on; addresses without
+    /// an item are skipped.
+    pub fn write_discrete_inputs(&mut self, address: u16, values: &[bool]) {
+        let watched = self.watch(Space::DiscreteInputs, address, values.len());
+        for (addr, value) in (address..=u16::MAX).zip(values.iter().copied()) {
+            if let Some(item) = self.discrete_inputs.get_mut(&addr) {
+                item.value = value;
+            }
+        }
+        self.publish_watched(watched);
+    }
 
-    loop {
-        interval.tick().await;
-        let mut guard = state.write().unwrap();
-        guard.tick();
+    /// Sets configured input registers from `address` on; addresses without
+    /// an item are skipped.
+    pub fn write_input_registers(&mut self, address: u16, values: &[u16]) {
+        let watched = self.watch(Space::InputRegisters, address, values.len());
+        for (addr, value) in (address..=u16::MAX).zip(values.iter().copied()) {
+            if let Some(item) = self.input_registers.get_mut(&addr) {
+                item.value = value;
+            }
+        }
+        self.publish_watched(watched);
     }
 }
 
-fn read_range_bool(map: &BTreeMap<u16, SimBoolItem>, address: u16, count: u16) -> Vec<bool> {
-    (0..count)
-        .map(|offset| {
-            map.get(&(address + offset))
-                .map(|item| item.value)
-                .unwrap_or(false)
-        })
-        .collect()
+/// The addresses a read of `count` items from `address` covers; the last
+/// address of a space is included, and no address when `count` is 0.
+fn read_range(address: u16, count: u16) -> (Bound<u16>, Bound<u16>) {
+    let end = match count.checked_sub(1) {
+        Some(rest) => Bound::Included(address.saturating_add(rest)),
+        None => Bound::Excluded(address),
+    };
+    (Bound::Included(address), end)
 }
 
-fn read_range_register(map: &BTreeMap<u16, SimRegisterItem>, address: u16, count: u16) -> Vec<u16> {
-    (0..count)
-        .map(|offset| {
-            map.get(&(address + offset))
-                .map(|item| item.value)
-                .unwrap_or(0)
-        })
-        .collect()
+/// Whether the dynamics gated by `coil` may run; unconfigured coils read as
+/// off.
+fn dynamics_enabled(coils: &BTreeMap<u16, SimBoolItem>, coil: Option<u16>) -> bool {
+    coil.is_none_or(|coil| coils.get(&coil).is_some_and(|item| item.value))
 }
 
-fn eval_bool(current: bool, dynamics: &Option<DynamicsSpec>, elapsed: f64) -> bool {
-    let numeric = eval_numeric(if current { 1.0 } else { 0.0 }, dynamics, elapsed);
-    numeric > 0.5
+/// When an item updated at `now` is next due.
+fn next_due(now: Instant, update_ms: u64, align_to_clock: bool) -> Instant {
+    if !align_to_clock {
+        return now + Duration::from_millis(update_ms);
+    }
+    let wall_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64);
+    now + Duration::from_millis(clock_aligned_delay(wall_ms, update_ms))
+}
+
+/// Milliseconds from `wall_ms` to the next multiple of `update_ms` counted
+/// from the top of the minute. Periods of a minute or more count from the
+/// Unix epoch instead.
+fn clock_aligned_delay(wall_ms: u64, update_ms: u64) -> u64 {
+    let update_ms = update_ms.max(1);
+    if update_ms >= MINUTE_MS {
+        return update_ms - wall_ms % update_ms;
+    }
+    let into_minute = wall_ms % MINUTE_MS;
+    let next = (into_minute / update_ms + 1) * update_ms;
+    next.min(MINUTE_MS) - into_minute
 }
 
-fn eval_register(current: u16, dynamics: &Option<DynamicsSpec>, elapsed: f64) -> u16 {
-    let numeric = eval_numeric(current as f64, dynamics, elapsed);
-    numeric.round().clamp(0.0, u16::MAX as f64) as u16
-}
-
-fn eval_numeric(current: f64, dynamics: &Option<DynamicsSpec>, elapsed: f64) -> f64 {
-    let mut rng = rand::thread_rng();
-    match dynamics {
-        None | Some(DynamicsSpec::Static) => current,
-        Some(DynamicsSpec::Clamp { min, max }) => current.clamp(*min, *max),
-        Some(DynamicsSpec::Sine {
-            amplitude,
-            offset,
-            period_ms,
-        }) => {
-            let period = (*period_ms as f64) / 1000.0;
-            if period <= 0.0 {
-                return *offset;
-            }
-            offset + amplitude * (elapsed * std::f64::consts::TAU / period).sin()
-        }
-        Some(DynamicsSpec::Ramp {
-            min,
-            max,
-            period_ms,
-        }) => {
-            let period = (*period_ms as f64) / 1000.0;
-            if period <= 0.0 {
-                return *min;
-            }
-            let phase = (elapsed % period) / period;
-            min + (max - min) * phase
-        }
-        Some(DynamicsSpec::Step {
-            low,
-            high,
-            period_ms,
-        }) => {
-            let period = (*period_ms as f64) / 1000.0;
-            if period <= 0.0 {
-                return *low;
-            }
-            let phase = (elapsed % period) / period;
-            if phase < 0.5 { *low } else { *high }
-        }
-        Some(DynamicsSpec::RandomWalk { min, max, step }) => {
-            let delta = rng.gen_range(-step..=*step);
-            (current + delta).clamp(*min, *max)
-        }
-        Some(DynamicsSpec::Noise { min, max }) => rng.gen_range(*min..=*max),
-        Some(DynamicsSpec::Script { expr, min, max }) => {
-            let value = eval_script(expr, elapsed).unwrap_or(current);
-            clamp_optional(value, *min, *max)
-        }
-    }
-}
-
-fn clamp_optional(value: f64, min: Option<f64>, max: Option<f64>) -> f64 {
-    match (min, max) {
-        (Some(min), Some(max)) => value.clamp(min, max),
-        (Some(min), None) => value.max(min),
-        (None, Some(max)) => value.min(max),
-        _ => value,
-    }
-}
-
-fn eval_script(expr: &str, elapsed: f64) -> Option<f64> {
-    let expr = expr.trim();
-    let mut context = HashMapContext::new();
-    context
-        .set_value("t".to_string(), Value::Float(elapsed))
-        .ok()?;
-    let value = evalexpr::eval_with_context(expr, &context).ok()?;
-    match value {
-        Value::Int(value) => Some(value as f64),
-        Value::Float(value) => Some(value),
-        _ => None,
+/// Fails with IllegalDataAddress unless all `count` addresses from `address`
+/// exist.
+fn check_write_range(address: u16, count: usize) -> Result<(), Exception> {
+    if usize::from(address) + count > usize::from(u16::MAX) + 1 {
+        return Err(Exception::IllegalDataAddress);
+    }
+    Ok(())
+}
+
+/// Drives the tick loop; only returns when a tick fails.
+pub async fn spawn_simulator(state: std::sync::Arc<std::sync::RwLock<SimState>>) -> Result<()> {
+    let (mut tick_ms, policy, clients) = {
+        let state = state.read().unwrap();
+        let clients = std::sync::Arc::clone(&state.clients);
+        (state.min_tick_ms(), state.tick_overrun_policy, clients)
+    };
+    let mut interval = tick_interval(tick_ms, policy);
+    let mut widened_ms = 0;
+    let mut last_warning: Option<Instant> = None;
+    let mut sleep: Option<Sleep> = None;
+
+    loop {
+        let due = if sleep.is_some() {
+            // A client connecting wakes an idle device at once.
+            tokio::select! {
+                due = interval.tick() => due,
+                () = clients.connected.notified() => tokio::time::Instant::now(),
+            }
+        } else {
+            interval.tick().await
+        };
+        let (next_tick_ms, behind, overruns) = {
+            // One guard for the whole tick keeps multi-register values whole
+            // for concurrent reads.
+            let mut guard = state.write().unwrap();
+            guard.update_sleep(&mut sleep);
+            if !sleep.is_some_and(|sleep| sleep.paused()) && !guard.following {
+                guard.tick()?;
+            }
+            // A tick that ends after the next deadline, whether because it
+            // started late or ran long, leaves the loop behind.
+            let behind = due.elapsed();
+            if behind > Duration::from_millis(tick_ms) {
+                guard.tick_overruns += 1;
+            }
+            let next_tick_ms = match sleep {
+                Some(sleep) => sleep.tick_ms(),
+                None => guard.min_tick_ms(),
+            };
+            (next_tick_ms, behind, guard.tick_overruns)
+        };
+        if behind > Duration::from_millis(tick_ms) {
+            let behind_ms = behind.as_millis() as u64;
+            if policy == TickOverrunPolicy::Widen {
+                widened_ms = (behind_ms * 3 / 2).max(tick_ms + 1);
+                warn!(
+                    behind_ms,
+                    interval_ms = widened_ms,
+                    overruns,
+                    "tick loop fell behind; widening the tick interval"
+                );
+            } else if last_warning.is_none_or(|at| at.elapsed() >= OVERRUN_WARNING_INTERVAL) {
+                warn!(
+                    behind_ms,
+                    interval_ms = tick_ms,
+                    overruns,
+                    "tick loop is falling behind; raise update_ms or global.min_tick_ms"
+                );
+                last_warning = Some(Instant::now());
+            }
+        }
+        // Reloaded dynamics may change the fastest item.
+        let next_tick_ms = next_tick_ms.max(widened_ms);
+        if next_tick_ms != tick_ms {
+            tick_ms = next_tick_ms;
+            interval = tick_interval(tick_ms, policy);
+            interval.tick().await;
+        }
+    }
+}
+
+fn tick_interval(tick_ms: u64, policy: TickOverrunPolicy) -> tokio::time::Interval {
+    let mut interval = tokio::time::interval(Duration::from_millis(tick_ms));
+    if policy == TickOverrunPolicy::Skip {
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    }
+    interval
+}
+
+/// Gives the registers after each encoded item the remaining `words` of its
+/// initial value, creating those not configured. They belong to the item, so
+/// may not have dynamics or an encoding of their own.
+fn spread_words(
+    map: &mut BTreeMap<u16, SimRegisterItem>,
+    words: Vec<(u16, Vec<u16>)>,
+    update_ms: u64,
+) -> Result<()> {
+    for (owner, words) in words {
+        let encoding = map[&owner].encoding.name();
+        for (offset, word) in (0u16..).zip(words).skip(1) {
+            let Some(address) = owner.checked_add(offset) else {
+                anyhow::bail!("the {encoding} at {owner} runs past the last register");
+            };
+            match map.get_mut(&address) {
+                None => {
+                    let mut item = SimRegisterItem::plain(word, update_ms);
+                    item.initial = word;
+                    map.insert(address, item);
+                }
+                Some(item) if item.dynamics.is_some() || item.encoding != Encoding::U16 => {
+                    anyhow::bail!(
+                        "register {address} is part of the {encoding} at {owner} and cannot have \
+                         dynamics or an encoding of its own"
+                    );
+                }
+                Some(item) if item.initial != 0 && item.initial != word => {
+                    anyhow::bail!(
+                        "register {address} is part of the {encoding} at {owner}, whose initial \
+                         value conflicts with its initial {}",
+                        item.initial
+                    );
+                }
+                Some(item) => {
+                    item.value = word;
+                    item.last_value = word;
+                    item.initial = word;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes the words past the first of encoded items, `(address, word)`, to
+/// the registers that follow them.
+fn apply_spill(map: &mut BTreeMap<u16, SimRegisterItem>, spill: Vec<(u16, u16)>) {
+    for (address, word) in spill {
+        if let Some(item) = map.get_mut(&address) {
+            item.last_value = item.value;
+            item.value = word;
+        }
+    }
+}
+
+/// Fails when the last update of `item` did not fit its encoding and was
+/// saturated, or `value` lies outside the `min`/`max` parameters of its
+/// dynamics, rounded like register values. Items still warming up are not
+/// checked.
+fn check_bounds(item: &SimRegisterItem, startup: Startup, value: f64, elapsed: f64) -> Result<()> {
+    let Some(spec) = &item.dynamics_spec else {
+        return Ok(());
+    };
+    if startup.holding(elapsed) || (elapsed - startup.delay) * 1000.0 < startup.warmup_ms as f64 {
+        return Ok(());
+    }
+    if !item.encoding.fits(item.unclamped) {
+        anyhow::bail!(
+            "value {} of its `{}` dynamics does not fit its {} encoding",
+            item.unclamped,
+            spec.kind,
+            item.encoding.name()
+        );
+    }
+    let min = numeric_param(spec, "min").map_or(f64::NEG_INFINITY, f64::round);
+    let max = numeric_param(spec, "max").map_or(f64::INFINITY, f64::round);
+    if value < min || value > max {
+        anyhow::bail!(
+            "value {value} outside the bounds {min}..={max} of its `{}` dynamics",
+            spec.kind
+        );
+    }
+    Ok(())
+}
+
+/// The `period_ms` of periodic dynamics; `None` for other dynamics and a
+/// zero period.
+fn period_ms(spec: &Option<DynamicsSpec>) -> Option<u64> {
+    spec.as_ref()?
+        .params
+        .get("period_ms")
+        .and_then(duration::value_millis)
+        .filter(|period| *period > 0)
+}
+
+fn numeric_param(spec: &DynamicsSpec, key: &str) -> Option<f64> {
+    spec.params.get(key).and_then(|param| {
+        param
+            .as_float()
+            .or(param.as_integer().map(|int| int as f64))
+    })
+}
+
+/// The histogram an item opted into, if any.
+fn item_histogram(item: &RegisterItemConfig) -> Option<Histogram> {
+    if let Some(buckets) = &item.histogram_buckets {
+        return Some(Histogram::new(buckets.clone()));
+    }
+    if !item.histogram {
+        return None;
+    }
+    let param = |key| {
+        item.dynamics
+            .as_ref()
+            .and_then(|spec| numeric_param(spec, key))
+    };
+    Some(Histogram::for_range(param("min"), param("max")))
+}
+
+fn read_range_bool(
+    map: &BTreeMap<u16, SimBoolItem>,
+    address: u16,
+    count: u16,
+    elapsed_ms: u64,
+) -> Result<Vec<bool>, Exception> {
+    (0..count)
+        .map(|offset| {
+            let address = address
+                .checked_add(offset)
+                .ok_or(Exception::IllegalDataAddress)?;
+            let Some(item) = map.get(&address) else {
+                return Ok(false);
+            };
+            match bad_quality_value(&item.quality, item.events.fault, elapsed_ms) {
+                Some(bad) => bad.map(|value| value != 0),
+                None => Ok(item.value),
+            }
+        })
+        .collect()
+}
+
+/// Reads `count` registers from `map`, taking addresses it lacks from
+/// `mirror` when set.
+fn read_range_register(
+    map: &BTreeMap<u16, SimRegisterItem>,
+    mirror: Option<&BTreeMap<u16, SimRegisterItem>>,
+    address: u16,
+    count: u16,
+    elapsed_ms: u64,
+) -> Result<Vec<u16>, Exception> {
+    (0..count)
+        .map(|offset| {
+            let address = address
+                .checked_add(offset)
+                .ok_or(Exception::IllegalDataAddress)?;
+            let item = map
+                .get(&address)
+                .or_else(|| mirror.and_then(|mirror| mirror.get(&address)));
+            let Some(item) = item else {
+                return Ok(0);
+            };
+            bad_quality_value(&item.quality, item.events.fault, elapsed_ms)
+                .unwrap_or(Ok(item.value))
+        })
+        .collect()
+}
+
+/// Returns what a bad-quality item reports, or `None` while its quality is
+/// good. A fault fired through the control API makes the quality bad.
+fn bad_quality_value(
+    quality: &Option<QualityConfig>,
+    fault: bool,
+    elapsed_ms: u64,
+) -> Option<Result<u16, Exception>> {
+    if fault {
+        let sentinel = quality.as_ref().and_then(|quality| quality.sentinel);
+        return Some(sentinel.ok_or(Exception::ServerDeviceFailure));
+    }
+    let quality = quality.as_ref()?;
+    if quality.period_ms == 0 {
+        return None;
+    }
+    let phase = elapsed_ms % quality.period_ms;
+    if phase + quality.bad_ms < quality.period_ms {
+        return None;
+    }
+    Some(quality.sentinel.ok_or(Exception::ServerDeviceFailure))
+}
+
+/// Whether an item is inside a stuck window and must hold its value.
+fn is_stuck(stuck: &Option<StuckConfig>, elapsed_ms: u64) -> bool {
+    stuck
+        .as_ref()
+        .is_some_and(|stuck| in_window(stuck.period_ms, stuck.stuck_ms, elapsed_ms))
+}
+
+/// Whether `elapsed_ms` falls in the last `window_ms` of a `period_ms` cycle.
+fn in_window(period_ms: u64, window_ms: u64, elapsed_ms: u64) -> bool {
+    period_ms != 0 && elapsed_ms % period_ms + window_ms >= period_ms
+}
+
+/// Whether an item holds its value instead of updating: during a device
+/// outage, or inside its own stuck or outage window.
+fn frozen(
+    device_outage: bool,
+    stuck: &Option<StuckConfig>,
+    outage: &Option<OutageConfig>,
+    elapsed_ms: u64,
+) -> bool {
+    device_outage || is_stuck(stuck, elapsed_ms) || in_outage(outage, elapsed_ms)
+}
+
+/// Checks that every followed register is configured and that no item
+/// follows itself through a chain of others.
+fn check_follows(
+    follows: &BTreeMap<(Space, u16), (Space, u16)>,
+    is_configured: impl Fn(Space, u16) -> bool,
+) -> Result<()> {
+    for (&(space, address), &(source_space, source)) in follows {
+        let item = format!("{} {address}", space.item_name());
+        if !is_configured(source_space, source) {
+            anyhow::bail!(
+                "{item}: follows {} {source}, which is not configured",
+                source_space.item_name()
+            );
+        }
+        // Each item follows at most one other, so a cycle through `item`
+        // comes back to it within `follows.len()` steps.
+        let mut next = (source_space, source);
+        let mut path = vec![item.clone()];
+        for _ in 0..follows.len() {
+            path.push(format!("{} {}", next.0.item_name(), next.1));
+            if next == (space, address) {
+                anyhow::bail!(
+                    "{item}: follows itself through a cycle, {}",
+                    path.join(" -> ")
+                );
+            }
+            match follows.get(&next) {
+                Some(&source) => next = source,
+                None => break,
+            }
+        }
+    }
+    Ok(())
+}
+
+fn build_dynamics(
+    registry: &DynamicsRegistry,
+    spec: &Option<DynamicsSpec>,
+    seed: Option<u64>,
+) -> Result<Option<Box<dyn Dynamic>>> {
+    let Some(spec) = spec else {
+        return Ok(None);
+    };
+    let mut dynamic = registry.build(spec)?;
+    if let Some(seed) = seed {
+        dynamic.reseed(StdRng::seed_from_u64(seed));
+    }
+    Ok(Some(dynamic))
+}
+
+/// Seed for an item's random stream: its own seed, else one derived from the
+/// global seed.
+fn stream_seed(global: Option<u64>, item: Option<u64>, table: u64, address: u16) -> Option<u64> {
+    item.or_else(|| global.map(|global| derived_seed(global, table, address)))
+}
+
+/// The words a register takes when a reload gives it the layout of `item`,
+/// first register first. The registers past the first must be free to take
+/// them: unconfigured, or without dynamics and an encoding of their own.
+fn relayout_words(
+    map: &BTreeMap<u16, SimRegisterItem>,
+    item: &RegisterItemConfig,
+    policy: EncodingReload,
+) -> Result<Vec<u16>> {
+    let words = match policy {
+        EncodingReload::Reinitialize => encoding::initial_words(item)?,
+        EncodingReload::Reinterpret => {
+            let current = &map[&item.address];
+            let (_, value) = encoding::decode_raw(map, item.address, current)
+                .context("register holds too few words to reinterpret")?;
+            encoding::encode(item.encoding, item.word_order, item.byte_order, value)
+        }
+    };
+    let encoding = item.encoding.name();
+    for offset in 1..item.encoding.width() {
+        let Some(address) = item.address.checked_add(offset) else {
+            anyhow::bail!("the {encoding} runs past the last register");
+        };
+        if let Some(next) = map.get(&address)
+            && (next.dynamics.is_some() || next.encoding != Encoding::U16)
+        {
+            anyhow::bail!(
+                "register {address} would be part of the {encoding} and cannot have dynamics \
+                 or an encoding of its own"
+            );
+        }
+    }
+    Ok(words)
+}
+
+fn describe_reload(
+    name: &str,
+    address: u16,
+    (old_spec, old_update_ms): (&Option<DynamicsSpec>, u64),
+    (new_spec, new_update_ms): (&Option<DynamicsSpec>, u64),
+) -> String {
+    let kind = |spec: &Option<DynamicsSpec>| {
+        spec.as_ref()
+            .map(|spec| spec.kind.clone())
+            .unwrap_or_else(|| "none".to_string())
+    };
+    format!(
+        "{name} {address}: dynamics {} -> {}, update_ms {old_update_ms} -> {new_update_ms}",
+        kind(old_spec),
+        kind(new_spec)
+    )
+}
+
+/// Mixes the global seed with an item's table and address (splitmix64).
+fn derived_seed(global: u64, table: u64, address: u16) -> u64 {
+    let mut z = global ^ ((table << 16) | address as u64);
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Time between an item's tick updates as achieved, which tick granularity
+/// and overruns can stretch beyond its `update_ms`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UpdateRate {
+    last: Option<Instant>,
+    /// Exponentially weighted moving average of the intervals, from the
+    /// second update on.
+    pub interval_ms: Option<f64>,
+}
+
+impl UpdateRate {
+    fn observe(&mut self, now: Instant) {
+        if let Some(last) = self.last.replace(now) {
+            let interval_ms = now.saturating_duration_since(last).as_secs_f64() * 1000.0;
+            self.interval_ms = Some(self.interval_ms.map_or(interval_ms, |average| {
+                average + UPDATE_INTERVAL_ALPHA * (interval_ms - average)
+            }));
+        }
+    }
+}
+
+/// How an item starts: it holds its value for `delay` seconds, then its
+/// value moves linearly from `cold_value` to the dynamics output over
+/// `warmup_ms`, and its dynamics see time shifted by `phase` seconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Startup {
+    warmup_ms: u64,
+    cold_value: f64,
+    phase: f64,
+    /// Seconds the item holds its value before its dynamics start.
+    delay: f64,
+}
+
+impl Startup {
+    /// Whether the item is still in its dynamics delay at `elapsed`.
+    fn holding(&self, elapsed: f64) -> bool {
+        elapsed < self.delay
+    }
+}
+
+impl SimBoolItem {
+    fn startup(&self, global_warmup_ms: u64) -> Startup {
+        Startup {
+            warmup_ms: self.warmup_ms.unwrap_or(global_warmup_ms),
+            cold_value: self.cold_value,
+            phase: self.phase_ms.unwrap_or(0) as f64 / 1000.0,
+            delay: self.dynamics_delay_ms as f64 / 1000.0,
+        }
+    }
+}
+
+impl SimRegisterItem {
+    /// Evaluates the dynamics into the value and the words the item starts,
+    /// adding `noise` from the noise floor. Items with an encoding other than
+    /// `u16` pass `logical`, their value and words, and their dynamics run on
+    /// the decoded value.
+    fn eval_words(
+        &mut self,
+        logical: Option<(f64, Vec<u16>)>,
+        noise: f64,
+        startup: Startup,
+        elapsed: f64,
+        source: Option<f64>,
+    ) -> (f64, Vec<u16>) {
+        let (current, words) = logical.unwrap_or_else(|| (self.value as f64, vec![self.value]));
+        self.unclamped = current;
+        if self.dynamics.is_none()
+            && let Some(decay) = &mut self.decay
+        {
+            let (encoding, word_order, byte_order) =
+                (self.encoding, self.word_order, self.byte_order);
+            return decay.relax(current, &words, elapsed, |value| {
+                if encoding == Encoding::U16 {
+                    let raw = value.round().clamp(0.0, u16::MAX as f64) as u16;
+                    (raw as f64, vec![raw])
+                } else {
+                    (
+                        value,
+                        encoding::encode(encoding, word_order, byte_order, value),
+                    )
+                }
+            });
+        }
+        if self.dynamics.is_none() || startup.holding(elapsed) {
+            return (current, words);
+        }
+        // The dynamics see the value without the noise they were given last.
+        let clean = current - self.noise;
+        if self.encoding == Encoding::U16 {
+            let clean = clean.round().clamp(0.0, u16::MAX as f64) as u16;
+            let logical = eval_logical(
+                clean as f64,
+                self.events.step,
+                &self.shaping,
+                &mut self.dynamics,
+                startup,
+                elapsed,
+                source,
+            )
+            .unwrap_or(clean as f64);
+            let value = logical.round().clamp(0.0, u16::MAX as f64) as u16;
+            let noisy = (value as f64 + noise).round().clamp(0.0, u16::MAX as f64) as u16;
+            self.noise = noisy as f64 - value as f64;
+            self.unclamped = logical + noise;
+            return (noisy as f64, vec![noisy]);
+        }
+        match eval_logical(
+            clean,
+            self.events.step,
+            &self.shaping,
+            &mut self.dynamics,
+            startup,
+            elapsed,
+            source,
+        ) {
+            Some(value) => {
+                self.noise = noise;
+                let value = value + noise;
+                self.unclamped = value;
+                (
+                    value,
+                    encoding::encode(self.encoding, self.word_order, self.byte_order, value),
+                )
+            }
+            None => (current, words),
+        }
+    }
+
+    fn startup(&self, global_warmup_ms: u64) -> Startup {
+        Startup {
+            warmup_ms: self.warmup_ms.unwrap_or(global_warmup_ms),
+            cold_value: self.cold_value,
+            phase: self.phase_ms.unwrap_or(0) as f64 / 1000.0,
+            delay: self.dynamics_delay_ms as f64 / 1000.0,
+        }
+    }
+}
+
+fn eval_bool(
+    current: bool,
+    dynamics: &mut Option<Box<dyn Dynamic>>,
+    startup: Startup,
+    elapsed: f64,
+    source: Option<f64>,
+) -> bool {
+    let current = if current { 1.0 } else { 0.0 };
+    let numeric = eval_numeric(current, dynamics, startup, elapsed, source);
+    numeric > 0.5
+}
+
+/// Evaluates a register whose dynamics run `step` below the value it shows,
+/// shaping the result; `None` when the value holds, and a result that is not
+/// a number holds `current`.
+fn eval_logical(
+    current: f64,
+    step: f64,
+    shaping: &Shaping,
+    dynamics: &mut Option<Box<dyn Dynamic>>,
+    startup: Startup,
+    elapsed: f64,
+    source: Option<f64>,
+) -> Option<f64> {
+    if dynamics.is_none() || startup.holding(elapsed) {
+        return None;
+    }
+    let numeric = eval_numeric(current - step, dynamics, startup, elapsed, source) + step;
+    shaping.apply(numeric, elapsed)
+}
+
+fn eval_numeric(
+    current: f64,
+    dynamics: &mut Option<Box<dyn Dynamic>>,
+    startup: Startup,
+    elapsed: f64,
+    source: Option<f64>,
+) -> f64 {
+    let Some(dynamic) = dynamics else {
+        return current;
+    };
+    if startup.holding(elapsed) {
+        return current;
+    }
+    // Dynamics, and the warmup, start when the delay ends.
+    let elapsed = elapsed - startup.delay;
+    let normal = dynamic.eval(&EvalContext {
+        current,
+        elapsed: elapsed + startup.phase,
+        source,
+    });
+    let elapsed_ms = elapsed * 1000.0;
+    if startup.warmup_ms == 0 || elapsed_ms >= startup.warmup_ms as f64 {
+        return normal;
+    }
+    let progress = elapsed_ms / startup.warmup_ms as f64;
+    startup.cold_value + (normal - startup.cold_value) * progress
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Initial;
+
+    fn register(address: u16, initial: u16, quality: Option<QualityConfig>) -> RegisterItemConfig {
+        RegisterItemConfig {
+            address,
+            initial: initial.into(),
+            quality,
+            ..Default::default()
+        }
+    }
+
+    /// [`eval_logical`] on a `u16` register, clamped to its raw range.
+    fn eval_register(
+        current: u16,
+        step: f64,
+        shaping: &Shaping,
+        dynamics: &mut Option<Box<dyn Dynamic>>,
+        startup: Startup,
+        elapsed: f64,
+        source: Option<f64>,
+    ) -> u16 {
+        eval_logical(
+            current as f64,
+            step,
+            shaping,
+            dynamics,
+            startup,
+            elapsed,
+            source,
+        )
+        .map_or(current, |value| {
+            value.round().clamp(0.0, u16::MAX as f64) as u16
+        })
+    }
+
+    #[test]
+    fn clock_alignment_targets_minute_multiples() {
+        // 12:00:01.250 with a 1 s period: due at 12:00:02.
+        assert_eq!(clock_aligned_delay(60_000 * 720 + 1_250, 1_000), 750);
+        // 7 s does not divide a minute; the last slot runs to the next minute.
+        assert_eq!(clock_aligned_delay(57_000, 7_000), 3_000);
+        assert_eq!(clock_aligned_delay(56_000, 7_000), 4_000);
+        // On a boundary the next one is a full period away.
+        assert_eq!(clock_aligned_delay(120_000, 500), 500);
+        assert_eq!(clock_aligned_delay(90_000, 300_000), 210_000);
+    }
+
+    #[test]
+    fn momentary_coil_resets_on_next_tick() {
+        let button = BoolItemConfig {
+            address: 2,
+            momentary: true,
+            ..Default::default()
+        };
+        let delayed = BoolItemConfig {
+            address: 3,
+            momentary: true,
+            momentary_ms: Some(60_000),
+            ..Default::default()
+        };
+        let mut state =
+            SimState::new(500, false, vec![button, delayed], vec![], vec![], vec![]).unwrap();
+        state.write_multiple_coils(2, &[true, true]).unwrap();
+        assert_eq!(state.read_coils(2, 2), Ok(vec![true, true]));
+        state.tick().unwrap();
+        assert_eq!(state.read_coils(2, 2), Ok(vec![false, true]));
+
+        state.write_single_coil(3, false);
+        assert_eq!(state.coils[&3].reset_at, None);
+    }
+
+    #[test]
+    fn debounced_coils_ignore_rapid_writes() {
+        let coil = BoolItemConfig {
+            address: 2,
+            debounce_ms: 60_000,
+            ..Default::default()
+        };
+        let mut state = SimState::new(500, false, vec![coil], vec![], vec![], vec![]).unwrap();
+        state.write_single_coil(2, true);
+        state.write_single_coil(2, false);
+        assert_eq!(state.read_coils(2, 1), Ok(vec![true]));
+
+        state.coils.get_mut(&2).unwrap().debounce_ms = 0;
+        state.write_single_coil(2, false);
+        assert_eq!(state.read_coils(2, 1), Ok(vec![false]));
+    }
+
+    #[test]
+    fn enable_coil_pauses_and_resumes_dynamics() {
+        let gate = BoolItemConfig {
+            address: 7,
+            ..Default::default()
+        };
+        let walk = RegisterItemConfig {
+            address: 0,
+            initial: 50.into(),
+            seed: Some(3),
+            update_ms: Some(1),
+            dynamics_enable_coil: Some(7),
+            dynamics: Some(
+                toml::from_str("kind = \"random-walk\"\nmin = 0.0\nmax = 100.0\nstep = 5.0")
+                    .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let mut state = SimState::new(500, false, vec![gate], vec![], vec![], vec![walk]).unwrap();
+        let mut values = Vec::new();
+        for _ in 0..5 {
+            std::thread::sleep(Duration::from_millis(2));
+            state.tick().unwrap();
+            values.push(state.input_registers[&0].value);
+        }
+        assert_eq!(values, vec![50; 5]);
+
+        state.write_single_coil(7, true);
+        for _ in 0..5 {
+            std::thread::sleep(Duration::from_millis(2));
+            state.tick().unwrap();
+            values.push(state.input_registers[&0].value);
+        }
+        assert!(values[5..].iter().any(|value| *value != 50), "{values:?}");
+    }
+
+    #[test]
+    fn stuck_items_hold_their_value() {
+        let window = |period_ms, stuck_ms| {
+            Some(StuckConfig {
+                period_ms,
+                stuck_ms,
+            })
+        };
+        assert!(!is_stuck(&window(120_000, 10_000), 109_999));
+        assert!(is_stuck(&window(120_000, 10_000), 110_000));
+        assert!(!is_stuck(&window(120_000, 10_000), 120_000));
+        assert!(!is_stuck(&window(0, 10_000), 5));
+        assert!(!is_stuck(&None, 5));
+
+        let walk = RegisterItemConfig {
+            address: 0,
+            initial: 50.into(),
+            seed: Some(3),
+            update_ms: Some(1),
+            stuck: window(60_000, 60_000),
+            dynamics: Some(
+                toml::from_str("kind = \"random-walk\"\nmin = 0.0\nmax = 100.0\nstep = 5.0")
+                    .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let mut state = SimState::new(500, false, vec![], vec![], vec![], vec![walk]).unwrap();
+        for _ in 0..5 {
+            std::thread::sleep(Duration::from_millis(2));
+            state.tick().unwrap();
+            assert_eq!(state.read_input_registers(0, 1), Ok(vec![50]));
+        }
+    }
+
+    fn follower(address: u16, dynamics: &str) -> RegisterItemConfig {
+        RegisterItemConfig {
+            address,
+            update_ms: Some(1),
+            dynamics: Some(toml::from_str(dynamics).unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn followers_track_their_source_from_the_tick_start() {
+        let source = RegisterItemConfig {
+            address: 0,
+            initial: 100.into(),
+            ..Default::default()
+        };
+        let chain = vec![
+            follower(1, "kind = \"follow\"\nsource = 0\ntau_ms = 0"),
+            follower(
+                2,
+                "kind = \"follow\"\nsource = 1\ntau_ms = 0\nsource_space = \"holding_registers\"",
+            ),
+        ];
+        let mut state = SimState::new(500, false, vec![], vec![], chain, vec![source]).unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        state.tick().unwrap();
+        assert_eq!(state.read_holding_registers(1, 2), Ok(vec![100, 0]));
+        std::thread::sleep(Duration::from_millis(2));
+        state.tick().unwrap();
+        assert_eq!(state.read_holding_registers(1, 2), Ok(vec![100, 100]));
+    }
+
+    #[test]
+    fn mirrored_registers_fall_back_to_the_other_space() {
+        let register = |address, initial: u16| RegisterItemConfig {
+            address,
+            initial: initial.into(),
+            ..Default::default()
+        };
+        let holding = vec![register(0, 7)];
+        let input = vec![register(0, 9), register(1, 5)];
+        let state = SimState::new(500, false, vec![], vec![], holding, input).unwrap();
+        assert_eq!(state.read_holding_registers(0, 2), Ok(vec![7, 0]));
+
+        let state = state
+            .with_mirror_registers(true)
+            .with_block_bounded_reads(true);
+        assert_eq!(state.read_holding_registers(0, 3), Ok(vec![7, 5, 0]));
+        assert_eq!(state.read_input_registers(0, 2), Ok(vec![9, 5]));
+        assert_eq!(state.check_readable(Space::HoldingRegisters, 0, 2), Ok(()));
+        assert_eq!(
+            state.check_readable(Space::HoldingRegisters, 0, 3),
+            Err(Exception::IllegalDataAddress)
+        );
+    }
+
+    #[test]
+    fn follow_sources_must_exist_and_not_cycle() {
+        let missing = follower(1, "kind = \"follow\"\nsource = 9\ntau_ms = 10");
+        let err = SimState::new(500, false, vec![], vec![], vec![missing], vec![]).unwrap_err();
+        assert!(err.to_string().contains("input register 9"), "{err}");
+
+        let cycle = [(1, 2), (2, 3), (3, 1)].map(|(address, source)| {
+            follower(
+                address,
+                &format!(
+                    "kind = \"follow\"\nsource = {source}\ntau_ms = 10\nsource_space = \"holding_registers\""
+                ),
+            )
+        });
+        let err = SimState::new(500, false, vec![], vec![], cycle.to_vec(), vec![]).unwrap_err();
+        assert!(err.to_string().contains("cycle"), "{err}");
+    }
+
+    #[test]
+    fn reference_cycles_name_their_path() {
+        let follows = follower(
+            1,
+            "kind = \"follow\"\nsource = 2\ntau_ms = 10\nsource_space = \"holding_registers\"",
+        );
+        let controlled = follower(2, "kind = \"controlled\"\nsetpoint = 1\nkp = 1.0");
+        let err = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![follows, controlled],
+            vec![],
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "holding register 1: follows itself through a cycle, \
+             holding register 1 -> holding register 2 -> holding register 1"
+        );
+    }
+
+    #[test]
+    fn partially_invalid_multi_write_changes_nothing() {
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![register(0xFFFE, 1, None)],
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(
+            state.write_multiple_registers(0xFFFE, &[7, 8, 9]),
+            Err(Exception::IllegalDataAddress)
+        );
+        assert_eq!(
+            state.write_multiple_coils(0xFFFF, &[true, true]),
+            Err(Exception::IllegalDataAddress)
+        );
+        assert_eq!(state.holding_register_value(0xFFFE), 1);
+        assert!(!state.holding_registers.contains_key(&0xFFFF));
+        assert!(state.coils.is_empty());
+
+        assert_eq!(state.write_multiple_registers(0xFFFE, &[7, 8]), Ok(()));
+        assert_eq!(state.read_holding_registers(0xFFFE, 2), Ok(vec![7, 8]));
+    }
+
+    #[test]
+    fn bad_quality_returns_sentinel() {
+        let quality = QualityConfig {
+            period_ms: 1000,
+            bad_ms: 1000,
+            sentinel: Some(0xFFFF),
+        };
+        let state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![],
+            vec![register(0, 5, Some(quality)), register(1, 6, None)],
+        )
+        .unwrap();
+        assert_eq!(state.read_input_registers(0, 2), Ok(vec![0xFFFF, 6]));
+    }
+
+    #[test]
+    fn bad_quality_without_sentinel_raises_exception() {
+        let quality = QualityConfig {
+            period_ms: 1000,
+            bad_ms: 1000,
+            sentinel: None,
+        };
+        let state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![register(0, 5, Some(quality))],
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(
+            state.read_holding_registers(0, 1),
+            Err(Exception::ServerDeviceFailure)
+        );
+    }
+
+    #[test]
+    fn compare_and_swap_writes_on_match() {
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![register(0, 5, None)],
+            vec![],
+        )
+        .unwrap();
+        assert!(state.compare_and_swap_register(0, 5, 9));
+        assert_eq!(state.holding_register_value(0), 9);
+    }
+
+    #[test]
+    fn compare_and_swap_leaves_value_on_mismatch() {
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![register(0, 5, None)],
+            vec![],
+        )
+        .unwrap();
+        assert!(!state.compare_and_swap_register(0, 4, 9));
+        assert_eq!(state.holding_register_value(0), 5);
+    }
+
+    #[test]
+    fn update_rate_averages_intervals() {
+        let start = Instant::now();
+        let mut rate = UpdateRate::default();
+        rate.observe(start);
+        assert_eq!(rate.interval_ms, None);
+        rate.observe(start + Duration::from_millis(100));
+        assert_eq!(rate.interval_ms, Some(100.0));
+        rate.observe(start + Duration::from_millis(300));
+        assert_eq!(rate.interval_ms, Some(120.0));
+    }
+
+    #[test]
+    fn tick_floor_bounds_min_tick() {
+        let fast = RegisterItemConfig {
+            address: 0,
+            update_ms: Some(1),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![fast], vec![])
+            .unwrap()
+            .with_tick_floor(25);
+        assert_eq!(state.min_tick_ms(), 25);
+    }
+
+    #[test]
+    fn aggressive_update_rates_warn() {
+        let regs = (0..100)
+            .map(|address| RegisterItemConfig {
+                address,
+                update_ms: Some(1),
+                ..Default::default()
+            })
+            .collect();
+        let state = SimState::new(500, false, vec![], vec![], regs, vec![])
+            .unwrap()
+            .with_tick_floor(1);
+        let warnings = state.tick_rate_warnings(50, 10_000);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].starts_with("100 item(s)"));
+    }
+
+    #[test]
+    fn default_update_rates_do_not_warn() {
+        let state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![register(0, 5, None)],
+            vec![],
+        )
+        .unwrap();
+        assert!(state.tick_rate_warnings(50, 100_000).is_empty());
+    }
+
+    #[test]
+    fn range_warnings_flag_narrow_and_clamped_dynamics() {
+        let item = |address, dynamics: &str| RegisterItemConfig {
+            address,
+            dynamics: Some(toml::from_str(dynamics).unwrap()),
+            ..Default::default()
+        };
+        let narrow = "kind = \"sine\"\namplitude = 10.0\noffset = 5.0\nperiod_ms = 1000";
+        let mut quiet = item(4, narrow);
+        quiet.suppress_range_warning = true;
+        let state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![
+                item(0, narrow),
+                quiet,
+                item(
+                    5,
+                    "kind = \"sine\"\namplitude = 5.0\noffset = 10.0\nperiod_ms = 1000",
+                ),
+            ],
+            vec![
+                item(1, "kind = \"noise\"\nmin = -20.0\nmax = -10.0"),
+                item(
+                    2,
+                    "kind = \"ramp\"\nmin = 0.0\nmax = 1000.0\nperiod_ms = 1000",
+                ),
+                item(3, "kind = \"follow\"\nsource = 2\ntau_ms = 0"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            state.range_warnings(),
+            vec![
+                "holding register 0: `sine` dynamics produce -5..=15 and are clamped to 0..=65535"
+                    .to_string(),
+                "holding register 5: `sine` dynamics only produce 5..=15; a missing scale? Set suppress_range_warning to silence this"
+                    .to_string(),
+                "input register 1: `noise` dynamics produce -20..=-10 and are always clamped to 0..=65535"
+                    .to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn clock_drift_speeds_up_the_simulation_clock() {
+        let clock = |ppm| {
+            let state = SimState::new(500, false, vec![], vec![], vec![], vec![])
+                .unwrap()
+                .with_clock_drift(ppm);
+            std::thread::sleep(Duration::from_millis(100));
+            state.elapsed_ms()
+        };
+        // A million ppm runs the clock at twice the wall-clock rate.
+        let fast = clock(1_000_000.0);
+        assert!((200..300).contains(&fast), "{fast}");
+        let slow = clock(-500_000.0);
+        assert!((50..75).contains(&slow), "{slow}");
+    }
+
+    #[test]
+    fn resolution_snaps_dynamics_output() {
+        let item = |address, resolution| RegisterItemConfig {
+            address,
+            resolution,
+            dynamics: Some(toml::from_str("kind = \"script\"\nexpr = \"37.2\"").unwrap()),
+            ..Default::default()
+        };
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![item(0, None), item(1, Some(5.0)), item(2, Some(0.5))],
+            vec![],
+        )
+        .unwrap();
+        state.write_single_register(3, 38);
+        let mut eval = |address| {
+            let item = state.holding_registers.get_mut(&address).unwrap();
+            let startup = item.startup(0);
+            let shaping = item.shaping.clone();
+            eval_register(
+                item.value,
+                0.0,
+                &shaping,
+                &mut item.dynamics,
+                startup,
+                0.0,
+                None,
+            )
+        };
+        assert_eq!(eval(0), 37);
+        assert_eq!(eval(1), 35);
+        assert_eq!(eval(2), 37);
+        // Values without dynamics are left as written.
+        assert_eq!(eval(3), 38);
+
+        let err = SimState::new(500, false, vec![], vec![], vec![item(0, Some(0.0))], vec![])
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("resolution must be positive"));
+    }
+
+    #[test]
+    fn transforms_shape_dynamics_output_before_resolution() {
+        let item = |address, transform: &str, value: f64| {
+            toml::from_str::<RegisterItemConfig>(&format!(
+                "address = {address}\ninitial = 9\nupdate_ms = 1\nresolution = 2.0\n\
+                 transform = {transform}\n\
+                 dynamics = {{ kind = \"script\", expr = \"{value}\" }}"
+            ))
+            .unwrap()
+        };
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![
+                item(0, "\"sqrt\"", 49.0),
+                item(1, "\"square\"", 3.0),
+                item(2, "{ script = \"x\" }", 21.0),
+                item(3, "\"log\"", 0.0),
+            ],
+            vec![],
+        )
+        .unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        state.tick().unwrap();
+        // The log of zero is not a number, so register 3 holds its value.
+        assert_eq!(state.read_holding_registers(0, 4), Ok(vec![8, 10, 22, 9]));
+    }
+
+    fn noisy(address: u16, seed: Option<u64>) -> RegisterItemConfig {
+        RegisterItemConfig {
+            address,
+            seed,
+            dynamics: Some(toml::from_str("kind = \"noise\"\nmin = 0.0\nmax = 65535.0").unwrap()),
+            ..Default::default()
+        }
+    }
+
+    fn sample(state: &mut SimState, address: u16, count: usize) -> Vec<u16> {
+        (0..count)
+            .map(|_| {
+                let item = state.holding_registers.get_mut(&address).unwrap();
+                let startup = item.startup(0);
+                eval_register(
+                    item.value,
+                    0.0,
+                    &Shaping::default(),
+                    &mut item.dynamics,
+                    startup,
+                    0.0,
+                    None,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn item_seed_gives_stable_stream_regardless_of_other_items() {
+        let mut alone =
+            SimState::new(500, false, vec![], vec![], vec![noisy(0, Some(7))], vec![]).unwrap();
+        let mut crowded = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![noisy(1, None), noisy(0, Some(7))],
+            vec![],
+        )
+        .unwrap()
+        .with_seed(Some(99));
+        assert_eq!(sample(&mut alone, 0, 5), sample(&mut crowded, 0, 5));
+    }
+
+    #[test]
+    fn global_seed_derives_per_item_streams() {
+        let build = |regs| {
+            SimState::new(500, false, vec![], vec![], regs, vec![])
+                .unwrap()
+                .with_seed(Some(99))
+        };
+        let mut a = build(vec![noisy(0, None), noisy(1, None)]);
+        let mut b = build(vec![noisy(1, None)]);
+        assert_eq!(sample(&mut a, 1, 5), sample(&mut b, 1, 5));
+        assert_ne!(sample(&mut a, 0, 5), sample(&mut a, 1, 5));
+    }
+
+    #[test]
+    fn reload_dynamics_keeps_values_and_ignores_new_items() {
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![register(0, 5, None), register(1, 6, None)],
+            vec![],
+        )
+        .unwrap();
+        state.write_single_register(0, 77);
+
+        let device: DeviceConfig = toml::from_str(
+            r#"
+[[holding_registers]]
+address = 0
+initial = 1
+update_ms = 100
+dynamics = { kind = "clamp", min = 0.0, max = 10.0 }
+
+[[holding_registers]]
+address = 9
+"#,
+        )
+        .unwrap();
+        let changes = state.reload_dynamics(&device).unwrap();
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].starts_with("holding register 0: dynamics none -> clamp"));
+        let item = &state.holding_registers[&0];
+        assert_eq!(item.value, 77);
+        assert_eq!(item.update_ms, 100);
+        assert!(item.dynamics.is_some());
+        assert!(!state.holding_registers.contains_key(&9));
+        assert!(state.holding_registers.contains_key(&1));
+    }
+
+    #[test]
+    fn reload_reinitializes_registers_whose_encoding_changed() {
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![register(0, 5, None)],
+            vec![],
+        )
+        .unwrap();
+        state.write_single_register(0, 300);
+        let device: DeviceConfig = toml::from_str(
+            r#"
+[[holding_registers]]
+address = 0
+encoding = "f32"
+initial = 1.5
+"#,
+        )
+        .unwrap();
+        let changes = state.reload_dynamics(&device).unwrap();
+        assert_eq!(
+            changes,
+            vec!["holding register 0: encoding u16 -> f32, re-initialized to [16320, 0]"]
+        );
+        assert_eq!(state.read_holding_registers(0, 2), Ok(vec![0x3FC0, 0]));
+        assert_eq!(state.holding_registers[&0].encoding, Encoding::F32);
+        // Unchanged layouts are left alone.
+        assert!(state.reload_dynamics(&device).unwrap().is_empty());
+    }
+
+    #[test]
+    fn reload_can_reinterpret_the_logical_value() {
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![register(0, 5, None)],
+            vec![],
+        )
+        .unwrap();
+        state.write_single_register(0, 300);
+        let device: DeviceConfig = toml::from_str(
+            r#"
+encoding_reload = "reinterpret"
+
+[[holding_registers]]
+address = 0
+encoding = "i32"
+word_order = "little"
+"#,
+        )
+        .unwrap();
+        state.reload_dynamics(&device).unwrap();
+        assert_eq!(state.read_holding_registers(0, 2), Ok(vec![300, 0]));
+        let (_, value) = encoding::decode_item(&state.holding_registers, 0).unwrap();
+        assert_eq!(value, 300.0);
+    }
+
+    #[test]
+    fn reload_refuses_encodings_overlapping_other_items() {
+        let dynamic = RegisterItemConfig {
+            address: 1,
+            dynamics: Some(toml::from_str("kind = \"clamp\"\nmin = 0.0\nmax = 1.0").unwrap()),
+            ..Default::default()
+        };
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![register(0, 5, None), dynamic],
+            vec![],
+        )
+        .unwrap();
+        let device: DeviceConfig = toml::from_str(
+            r#"
+[[holding_registers]]
+address = 0
+encoding = "u32"
+"#,
+        )
+        .unwrap();
+        let err = state.reload_dynamics(&device).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("register 1 would be part"),
+            "{err:#}"
+        );
+        assert_eq!(state.holding_registers[&0].encoding, Encoding::U16);
+        assert_eq!(state.holding_register_value(0), 5);
+    }
+
+    #[test]
+    fn reload_dynamics_rejects_invalid_tables_atomically() {
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![register(0, 5, None), register(1, 6, None)],
+            vec![],
+        )
+        .unwrap();
+        let device: DeviceConfig = toml::from_str(
+            r#"
+[[holding_registers]]
+address = 0
+update_ms = 100
+
+[[holding_registers]]
+address = 1
+dynamics = { kind = "bogus" }
+"#,
+        )
+        .unwrap();
+        assert!(state.reload_dynamics(&device).is_err());
+        assert_eq!(state.holding_registers[&0].update_ms, 500);
+    }
+
+    #[test]
+    fn engineering_round_trip_is_lossless_within_rounding() {
+        let cfg = RegisterItemConfig {
+            address: 0,
+            scale: Some(0.1),
+            offset: Some(-40.0),
+            ..Default::default()
+        };
+        let mut state = SimState::new(500, false, vec![], vec![], vec![cfg], vec![]).unwrap();
+        for engineering in [-40.0, -12.34, 0.0, 23.45, 100.0] {
+            let raw = state.holding_registers[&0].to_raw(engineering);
+            state.write_single_register(0, raw);
+            let read = state.read_holding_registers(0, 1).unwrap()[0];
+            let back = state.holding_registers[&0].to_engineering(read as f64);
+            assert!(
+                (back - engineering).abs() <= 0.05 + 1e-9,
+                "{engineering} -> {back}"
+            );
+        }
+    }
+
+    #[test]
+    fn to_raw_clamps_to_register_range() {
+        let cfg = RegisterItemConfig {
+            address: 0,
+            scale: Some(0.5),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![cfg], vec![]).unwrap();
+        let item = &state.holding_registers[&0];
+        assert_eq!(item.to_raw(-10.0), 0);
+        assert_eq!(item.to_raw(1e9), u16::MAX);
+    }
+
+    #[test]
+    fn update_on_read_items_are_sampled_by_reads_not_ticks() {
+        let cfg = RegisterItemConfig {
+            address: 0,
+            update_ms: Some(0),
+            update_on_read: true,
+            dynamics: Some(toml::from_str("kind = \"script\"\nexpr = \"7\"").unwrap()),
+            ..Default::default()
+        };
+        let mut state = SimState::new(500, false, vec![], vec![], vec![], vec![cfg]).unwrap();
+        state.tick().unwrap();
+        assert_eq!(state.read_input_registers(0, 1), Ok(vec![0]));
+        state.sample_on_read(Space::InputRegisters, 0, 1);
+        assert_eq!(state.read_input_registers(0, 1), Ok(vec![7]));
+    }
+
+    #[test]
+    fn update_on_read_items_at_the_last_address_are_sampled() {
+        let cfg = RegisterItemConfig {
+            address: u16::MAX,
+            update_ms: Some(0),
+            update_on_read: true,
+            dynamics: Some(toml::from_str("kind = \"script\"\nexpr = \"7\"").unwrap()),
+            ..Default::default()
+        };
+        let mut state = SimState::new(500, false, vec![], vec![], vec![], vec![cfg]).unwrap();
+        assert!(state.samples_on_read(Space::InputRegisters, u16::MAX - 1, 2));
+        assert!(!state.samples_on_read(Space::InputRegisters, u16::MAX, 0));
+        state.sample_on_read(Space::InputRegisters, u16::MAX - 1, 2);
+        assert_eq!(state.read_input_registers(u16::MAX, 1), Ok(vec![7]));
+    }
+
+    #[test]
+    fn update_on_read_items_do_not_drive_the_tick() {
+        let cfg = RegisterItemConfig {
+            address: 0,
+            update_ms: Some(1),
+            update_on_read: true,
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![], vec![cfg]).unwrap();
+        assert_eq!(state.min_tick_ms(), 500);
+    }
+
+    #[test]
+    fn good_quality_outside_bad_window() {
+        let quality = QualityConfig {
+            period_ms: 60_000,
+            bad_ms: 2_000,
+            sentinel: Some(0xFFFF),
+        };
+        let state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![register(0, 5, Some(quality))],
+            vec![],
+        )
+        .unwrap();
+        assert_eq!(state.read_holding_registers(0, 1), Ok(vec![5]));
+    }
+
+    fn warming(global_ms: u64, warmup_ms: Option<u64>) -> SimState {
+        let item = RegisterItemConfig {
+            address: 0,
+            warmup_ms,
+            cold_value: Some(20.0),
+            dynamics: Some(
+                toml::from_str(
+                    "kind = \"sine\"\namplitude = 0.0\noffset = 100.0\nperiod_ms = 1000",
+                )
+                .unwrap(),
+            ),
+            ..Default::default()
+        };
+        SimState::new(500, false, vec![], vec![], vec![item], vec![])
+            .unwrap()
+            .with_warmup(global_ms)
+    }
+
+    fn eval_at(state: &mut SimState, elapsed: f64) -> u16 {
+        let warmup_ms = state.warmup_ms;
+        let item = state.holding_registers.get_mut(&0).unwrap();
+        let startup = item.startup(warmup_ms);
+        eval_register(
+            item.value,
+            0.0,
+            &item.shaping,
+            &mut item.dynamics,
+            startup,
+            elapsed,
+            None,
+        )
+    }
+
+    #[test]
+    fn warmup_blends_from_cold_value_to_dynamics() {
+        let mut state = warming(0, Some(1000));
+        assert_eq!(eval_at(&mut state, 0.0), 20);
+        assert_eq!(eval_at(&mut state, 0.5), 60);
+        assert_eq!(eval_at(&mut state, 1.0), 100);
+        assert_eq!(eval_at(&mut state, 5.0), 100);
+    }
+
+    #[test]
+    fn item_warmup_overrides_global() {
+        assert_eq!(eval_at(&mut warming(1000, None), 0.5), 60);
+        assert_eq!(eval_at(&mut warming(1000, Some(0)), 0.5), 100);
+        assert_eq!(eval_at(&mut warming(0, None), 0.0), 100);
+    }
+
+    #[test]
+    fn dynamics_delay_holds_the_initial_value() {
+        let item = RegisterItemConfig {
+            address: 0,
+            initial: 7.into(),
+            dynamics_delay_ms: 2000,
+            dynamics: Some(toml::from_str("kind = \"script\"\nexpr = \"t\"").unwrap()),
+            ..Default::default()
+        };
+        let mut state = SimState::new(500, false, vec![], vec![], vec![item], vec![]).unwrap();
+        assert_eq!(eval_at(&mut state, 0.0), 7);
+        assert_eq!(eval_at(&mut state, 1.9), 7);
+        // The dynamics' clock starts when the delay ends.
+        assert_eq!(eval_at(&mut state, 2.0), 0);
+        assert_eq!(eval_at(&mut state, 5.0), 3);
+    }
+
+    #[derive(Debug, Clone, serde::Deserialize)]
+    struct Increment {}
+
+    impl Dynamic for Increment {
+        fn eval(&mut self, ctx: &EvalContext) -> f64 {
+            ctx.current + 1.0
+        }
+    }
+
+    #[test]
+    fn wide_dynamics_run_on_the_logical_value() {
+        let mut registry = DynamicsRegistry::builtin();
+        registry.register("increment", dynamics::from_params::<Increment>);
+        let item = |address, encoding, word_order| RegisterItemConfig {
+            address,
+            initial: Initial::Int(0xffff_ffff),
+            encoding,
+            word_order,
+            update_ms: Some(0),
+            dynamics: Some(toml::from_str("kind = \"increment\"").unwrap()),
+            ..Default::default()
+        };
+        let regs = vec![
+            item(0, Encoding::U64, WordOrder::Big),
+            item(4, Encoding::U64, WordOrder::Little),
+        ];
+        let mut state =
+            SimState::with_registry(&registry, 500, false, vec![], vec![], regs, vec![]).unwrap();
+        let words = |state: &SimState, address| {
+            (address..address + 4)
+                .map(|address| state.holding_registers[&address].value)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(words(&state, 0), vec![0, 0, 0xffff, 0xffff]);
+        state.tick().unwrap();
+        // The carry crosses into the next word, in either word order.
+        assert_eq!(words(&state, 0), vec![0, 1, 0, 0]);
+        assert_eq!(words(&state, 4), vec![0, 0, 1, 0]);
+        let (_, value) = encoding::decode_item(&state.holding_registers, 4).unwrap();
+        assert_eq!(value, 4_294_967_296.0);
+    }
+
+    #[test]
+    fn wide_initial_values_claim_the_following_registers() {
+        let wide: RegisterItemConfig =
+            toml::from_str("address = 0\nencoding = \"u64\"\ninitial = \"18446744073709551615\"")
+                .unwrap();
+        let state = SimState::new(500, false, vec![], vec![], vec![wide.clone()], vec![]).unwrap();
+        let (raw, _) = encoding::decode_item(&state.holding_registers, 0).unwrap();
+        assert_eq!(raw, vec![u16::MAX; 4]);
+        // Resets return the created registers to their word too.
+        assert_eq!(state.holding_registers[&3].initial, u16::MAX);
+
+        let dynamic: RegisterItemConfig =
+            toml::from_str("address = 2\ndynamics = { kind = \"script\", expr = \"t\" }").unwrap();
+        let err =
+            SimState::new(500, false, vec![], vec![], vec![wide, dynamic], vec![]).unwrap_err();
+        assert!(format!("{err:#}").contains("register 2 is part of the u64 at 0"));
+    }
+
+    #[derive(Debug, Clone, serde::Deserialize)]
+    struct Overshoot {}
+
+    impl Dynamic for Overshoot {
+        fn eval(&mut self, _ctx: &EvalContext) -> f64 {
+            200.0
+        }
+    }
+
+    fn overshooting(assert_bounds: bool) -> SimState {
+        let mut registry = DynamicsRegistry::builtin();
+        registry.register("overshoot", dynamics::from_params::<Overshoot>);
+        let cfg = RegisterItemConfig {
+            address: 3,
+            update_ms: Some(0),
+            dynamics: Some(toml::from_str("kind = \"overshoot\"\nmin = 0\nmax = 100").unwrap()),
+            ..Default::default()
+        };
+        SimState::with_registry(&registry, 500, false, vec![], vec![], vec![cfg], vec![])
+            .unwrap()
+            .with_assert_bounds(assert_bounds)
+    }
+
+    #[derive(Debug, Clone, serde::Deserialize)]
+    struct Slow {}
+
+    impl Dynamic for Slow {
+        fn eval(&mut self, _ctx: &EvalContext) -> f64 {
+            std::thread::sleep(Duration::from_millis(25));
+            0.0
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_ticks_are_counted_as_overruns() {
+        let mut registry = DynamicsRegistry::builtin();
+        registry.register("slow", dynamics::from_params::<Slow>);
+        let cfg = RegisterItemConfig {
+            address: 0,
+            update_ms: Some(10),
+            dynamics: Some(toml::from_str("kind = \"slow\"").unwrap()),
+            ..Default::default()
+        };
+        let state =
+            SimState::with_registry(&registry, 500, false, vec![], vec![], vec![cfg], vec![])
+                .unwrap()
+                .with_tick_overrun_policy(TickOverrunPolicy::Skip);
+        let state = std::sync::Arc::new(std::sync::RwLock::new(state));
+        let task = tokio::spawn(spawn_simulator(std::sync::Arc::clone(&state)));
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        task.abort();
+        assert!(state.read().unwrap().tick_overruns > 0);
+    }
+
+    #[test]
+    fn assert_bounds_fails_the_tick_on_violation() {
+        let err = overshooting(true).tick().unwrap_err();
+        assert!(format!("{err:#}").starts_with("holding register 3: value 200 outside"));
+        assert!(overshooting(false).tick().is_ok());
+    }
+
+    #[test]
+    fn assert_bounds_fails_values_outside_the_encoding() {
+        let saturating = |encoding, expr: &str| {
+            let cfg = RegisterItemConfig {
+                address: 3,
+                update_ms: Some(0),
+                encoding,
+                dynamics: Some(
+                    toml::from_str(&format!("kind = \"script\"\nexpr = \"{expr}\"")).unwrap(),
+                ),
+                ..Default::default()
+            };
+            let mut state = SimState::new(500, false, vec![], vec![], vec![cfg], vec![])
+                .unwrap()
+                .with_assert_bounds(true);
+            state.tick().map_err(|err| format!("{err:#}"))
+        };
+        assert_eq!(
+            saturating(Encoding::U16, "70000").unwrap_err(),
+            "holding register 3: value 70000 of its `script` dynamics does not fit its u16 encoding"
+        );
+        assert!(
+            saturating(Encoding::I16, "0 - 40000")
+                .unwrap_err()
+                .ends_with("value -40000 of its `script` dynamics does not fit its i16 encoding")
+        );
+        assert_eq!(saturating(Encoding::I32, "0 - 40000"), Ok(()));
+        assert_eq!(saturating(Encoding::U16, "65535"), Ok(()));
+    }
+
+    fn periodic(address: u16, phase_ms: Option<u64>) -> RegisterItemConfig {
+        RegisterItemConfig {
+            address,
+            phase_ms,
+            dynamics: Some(
+                toml::from_str("kind = \"step\"\nlow = 1.0\nhigh = 9.0\nperiod_ms = 1000").unwrap(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn phases(state: &SimState) -> Vec<Option<u64>> {
+        state
+            .holding_registers
+            .values()
+            .map(|item| item.phase_ms)
+            .collect()
+    }
+
+    #[test]
+    fn phase_shifts_time_seen_by_dynamics() {
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![periodic(0, Some(500))],
+            vec![],
+        )
+        .unwrap();
+        let item = state.holding_registers.get_mut(&0).unwrap();
+        let startup = item.startup(0);
+        assert_eq!(
+            eval_register(
+                0,
+                0.0,
+                &Shaping::default(),
+                &mut item.dynamics,
+                startup,
+                0.0,
+                None
+            ),
+            9
+        );
+        assert_eq!(
+            eval_register(
+                0,
+                0.0,
+                &Shaping::default(),
+                &mut item.dynamics,
+                startup,
+                0.5,
+                None
+            ),
+            1
+        );
+    }
+
+    #[test]
+    fn random_phase_is_seeded_and_keeps_explicit_phases() {
+        let build = || {
+            let items = vec![periodic(0, None), periodic(1, None), periodic(2, Some(42))];
+            SimState::new(500, false, vec![], vec![], items, vec![])
+                .unwrap()
+                .with_seed(Some(7))
+                .with_random_phase(true)
+        };
+        let first = phases(&build());
+        assert_eq!(first, phases(&build()));
+        assert!(
+            first[..2]
+                .iter()
+                .all(|phase| phase.is_some_and(|p| p < 1000))
+        );
+        assert_ne!(first[0], first[1]);
+        assert_eq!(first[2], Some(42));
+
+        let off = SimState::new(500, false, vec![], vec![], vec![periodic(0, None)], vec![])
+            .unwrap()
+            .with_random_phase(false);
+        assert_eq!(phases(&off), vec![None]);
     }
 }
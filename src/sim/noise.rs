@@ -0,0 +1,80 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use super::{SimRegisterItem, SimState, derived_seed};
+
+/// Uniform noise of up to `amplitude` raw units either way, added to the
+/// output of every register with dynamics like electrical noise on all the
+/// channels of a device.
+#[derive(Debug, Clone)]
+pub(super) struct NoiseFloor {
+    amplitude: f64,
+    rng: StdRng,
+}
+
+impl NoiseFloor {
+    fn sample(&mut self) -> f64 {
+        self.rng.gen_range(-self.amplitude..=self.amplitude)
+    }
+}
+
+impl SimState {
+    /// Adds noise of up to `amplitude` raw units either way to every update
+    /// of the registers with dynamics that are not exempt; `0` disables it.
+    /// A global `seed` makes the noise reproducible.
+    pub fn with_noise_floor(mut self, amplitude: f64, seed: Option<u64>) -> Self {
+        self.noise_floor = (amplitude > 0.0).then(|| NoiseFloor {
+            amplitude,
+            // Table 4 keeps the stream apart from those of the items.
+            rng: seed.map_or_else(StdRng::from_entropy, |seed| {
+                StdRng::seed_from_u64(derived_seed(seed, 4, 0))
+            }),
+        });
+        self
+    }
+}
+
+/// Noise to add to the next update of `item`: none for items that are
+/// exempt or have no dynamics.
+pub(super) fn noise(noise_floor: &mut Option<NoiseFloor>, item: &SimRegisterItem) -> f64 {
+    match noise_floor {
+        Some(noise_floor) if item.dynamics.is_some() && !item.noise_floor_exempt => {
+            noise_floor.sample()
+        }
+        _ => 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::RegisterItemConfig;
+    use crate::sim::SimState;
+
+    #[test]
+    fn noise_floor_perturbs_without_drifting() {
+        let item = |address, noise_floor_exempt| RegisterItemConfig {
+            address,
+            initial: 100.into(),
+            update_ms: Some(0),
+            dynamics: Some(toml::from_str("kind = \"static\"").unwrap()),
+            noise_floor_exempt,
+            ..Default::default()
+        };
+        let regs = vec![item(0, false), item(1, true)];
+        let mut state = SimState::new(500, false, vec![], vec![], regs, vec![])
+            .unwrap()
+            .with_noise_floor(3.0, Some(7));
+        let mut seen = Vec::new();
+        for _ in 0..200 {
+            state.tick().unwrap();
+            seen.push(state.holding_registers[&0].value);
+            assert_eq!(state.holding_registers[&1].value, 100);
+        }
+        // A static value would random-walk away if it integrated the noise.
+        assert!(
+            seen.iter().all(|value| (97..=103).contains(value)),
+            "{seen:?}"
+        );
+        assert!(seen.iter().any(|value| *value != 100));
+    }
+}
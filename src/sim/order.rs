@@ -0,0 +1,187 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use super::{SimRegisterItem, SimState, Space, encoding};
+use crate::config::{TickEvaluation, TickOrder};
+
+/// What one tick evaluates its items against.
+pub(super) struct TickPass {
+    pub now: Instant,
+    /// Seconds on the simulation clock.
+    pub elapsed: f64,
+    pub elapsed_ms: u64,
+    /// Whether the device is in an outage.
+    pub outage: bool,
+    pub timestamp_ms: u64,
+    /// The values items read from others, taken before any item updates;
+    /// `None` when they read the current ones.
+    pub snapshot: Option<TickSnapshot>,
+    pub replay_held: Vec<(Space, u16)>,
+    /// Cross-talk to add to each register, by space and address.
+    pub crosstalk: BTreeMap<(Space, u16), f64>,
+}
+
+/// The values items read from others at the start of a two-pass tick.
+pub(super) struct TickSnapshot {
+    /// Source value of each following item, by space and address.
+    pub sources: BTreeMap<(Space, u16), f64>,
+    pub coils: BTreeMap<u16, bool>,
+}
+
+/// One register space through a tick.
+pub(super) struct RegisterPass {
+    /// Decoded values of the encoded registers from before the tick.
+    pub logical: BTreeMap<u16, (f64, Vec<u16>)>,
+    /// Words past the first of the values written, by address, applied
+    /// once every item has been evaluated.
+    pub spill: Vec<(u16, u16)>,
+}
+
+impl RegisterPass {
+    pub fn new(map: &BTreeMap<u16, SimRegisterItem>) -> Self {
+        Self {
+            logical: encoding::logical_values(map),
+            spill: Vec::new(),
+        }
+    }
+}
+
+impl SimState {
+    /// Sets the order a tick evaluates items in, and whether items reading
+    /// others see them as of the start of the tick or as updated so far.
+    pub fn with_tick_order(mut self, order: TickOrder, evaluation: TickEvaluation) -> Self {
+        self.tick_order = order;
+        self.tick_evaluation = evaluation;
+        self
+    }
+
+    /// Every item of the device, in the order a tick evaluates them.
+    pub(super) fn tick_items(&self) -> Vec<(Space, u16)> {
+        let bits = [
+            (Space::Coils, &self.coils),
+            (Space::DiscreteInputs, &self.discrete_inputs),
+        ];
+        let registers = [
+            (Space::HoldingRegisters, &self.holding_registers),
+            (Space::InputRegisters, &self.input_registers),
+        ];
+        let mut items: Vec<(Space, u16)> = bits
+            .iter()
+            .flat_map(|(space, map)| map.keys().map(move |address| (*space, *address)))
+            .chain(
+                registers
+                    .iter()
+                    .flat_map(|(space, map)| map.keys().map(move |address| (*space, *address))),
+            )
+            .collect();
+        if self.tick_order == TickOrder::ByAddress {
+            items.sort_by_key(|&(space, address)| (address, space));
+        }
+        items
+    }
+
+    pub(super) fn tick_snapshot(&self) -> TickSnapshot {
+        let spaces = [
+            Space::Coils,
+            Space::DiscreteInputs,
+            Space::HoldingRegisters,
+            Space::InputRegisters,
+        ];
+        TickSnapshot {
+            sources: spaces
+                .into_iter()
+                .flat_map(|space| {
+                    self.follow_snapshot(space, ..)
+                        .into_iter()
+                        .map(move |(address, value)| ((space, address), value))
+                })
+                .collect(),
+            coils: self
+                .coils
+                .iter()
+                .map(|(address, item)| (*address, item.value))
+                .collect(),
+        }
+    }
+
+    /// The value of the register the item at `space`/`address` follows, if
+    /// any: from the snapshot of a two-pass tick, else as it is now.
+    pub(super) fn tick_source(&self, pass: &TickPass, space: Space, address: u16) -> Option<f64> {
+        match &pass.snapshot {
+            Some(snapshot) => snapshot.sources.get(&(space, address)).copied(),
+            None => self
+                .follow_snapshot(space, address..=address)
+                .remove(&address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RegisterItemConfig;
+
+    /// Input register 0 copies holding register 1, which jumps from 0 to 42
+    /// on the first tick; returns what the copy shows after that tick.
+    fn copied(order: TickOrder, evaluation: TickEvaluation) -> u16 {
+        let dynamics = |text: &str| Some(toml::from_str(text).unwrap());
+        let source = RegisterItemConfig {
+            address: 1,
+            update_ms: Some(0),
+            dynamics: dynamics("kind = \"script\"\nexpr = \"42\""),
+            ..Default::default()
+        };
+        let copy = RegisterItemConfig {
+            address: 0,
+            update_ms: Some(0),
+            dynamics: dynamics(
+                "kind = \"follow\"\nsource = 1\ntau_ms = 0\nsource_space = \"holding_registers\"",
+            ),
+            ..Default::default()
+        };
+        let mut state = SimState::new(500, false, vec![], vec![], vec![source], vec![copy])
+            .unwrap()
+            .with_tick_order(order, evaluation);
+        state.tick().unwrap();
+        assert_eq!(state.holding_registers[&1].value, 42);
+        state.input_registers[&0].value
+    }
+
+    #[test]
+    fn tick_order_only_matters_to_sequential_evaluation() {
+        assert_eq!(copied(TickOrder::PerSpace, TickEvaluation::TwoPass), 0);
+        assert_eq!(copied(TickOrder::ByAddress, TickEvaluation::TwoPass), 0);
+        // Holding registers come before input registers...
+        assert_eq!(copied(TickOrder::PerSpace, TickEvaluation::Sequential), 42);
+        // ...but address 0 comes before address 1.
+        assert_eq!(copied(TickOrder::ByAddress, TickEvaluation::Sequential), 0);
+    }
+
+    #[test]
+    fn by_address_order_interleaves_the_spaces() {
+        let register = |address| RegisterItemConfig {
+            address,
+            ..Default::default()
+        };
+        let holding = vec![register(2)];
+        let input = vec![register(1), register(2)];
+        let state = SimState::new(500, false, vec![], vec![], holding, input).unwrap();
+        assert_eq!(
+            state.tick_items(),
+            [
+                (Space::HoldingRegisters, 2),
+                (Space::InputRegisters, 1),
+                (Space::InputRegisters, 2)
+            ]
+        );
+        let state = state.with_tick_order(TickOrder::ByAddress, TickEvaluation::TwoPass);
+        assert_eq!(
+            state.tick_items(),
+            [
+                (Space::InputRegisters, 1),
+                (Space::HoldingRegisters, 2),
+                (Space::InputRegisters, 2)
+            ]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+use tracing::info;
+
+use super::{SimState, Space, in_window};
+use crate::config::OutageConfig;
+
+/// Whether an outage schedule is inside its outage window.
+pub(super) fn in_outage(outage: &Option<OutageConfig>, elapsed_ms: u64) -> bool {
+    outage
+        .as_ref()
+        .is_some_and(|outage| in_window(outage.period_ms, outage.outage_ms, elapsed_ms))
+}
+
+impl SimState {
+    /// Sets the outage freezing every item of the device and prepares the
+    /// status registers of it and of the items' own outages. A status
+    /// register must not have dynamics; it is created if not configured.
+    pub fn with_outage(mut self, outage: Option<&OutageConfig>) -> Result<Self> {
+        self.outage = outage.cloned();
+        let mut statuses = Vec::new();
+        for (item, outage) in self.outages() {
+            let Some(register) = outage.status_register else {
+                continue;
+            };
+            let context = match item {
+                Some((space, address)) => format!("{} {address} outage", space.item_name()),
+                None => "device outage".to_string(),
+            };
+            if outage.status_bit > 15 {
+                bail!(
+                    "{context}: status_bit {} is not a register bit (0-15)",
+                    outage.status_bit
+                );
+            }
+            if self
+                .holding_registers
+                .get(&register)
+                .is_some_and(|item| item.dynamics.is_some())
+            {
+                bail!(
+                    "{context}: status register {register} has dynamics, which would overwrite the status bit"
+                );
+            }
+            statuses.push(register);
+        }
+        for register in statuses {
+            if !self.holding_registers.contains_key(&register) {
+                self.write_single_register(register, 0);
+            }
+        }
+        Ok(self)
+    }
+
+    /// The device outage and every item outage, with the item it belongs to.
+    fn outages(&self) -> impl Iterator<Item = (Option<(Space, u16)>, &OutageConfig)> {
+        let bools = [
+            (Space::Coils, &self.coils),
+            (Space::DiscreteInputs, &self.discrete_inputs),
+        ]
+        .into_iter()
+        .flat_map(|(space, map)| {
+            map.iter()
+                .filter_map(move |(address, item)| Some(((space, *address), item.outage.as_ref()?)))
+        });
+        let registers = [
+            (Space::HoldingRegisters, &self.holding_registers),
+            (Space::InputRegisters, &self.input_registers),
+        ]
+        .into_iter()
+        .flat_map(|(space, map)| {
+            map.iter()
+                .filter_map(move |(address, item)| Some(((space, *address), item.outage.as_ref()?)))
+        });
+        self.outage.iter().map(|outage| (None, outage)).chain(
+            bools
+                .chain(registers)
+                .map(|(item, outage)| (Some(item), outage)),
+        )
+    }
+
+    /// Sets every outage status bit while its outage lasts and clears it
+    /// otherwise. Outages sharing a bit set it while any of them lasts.
+    pub(super) fn update_outage_status(&mut self, elapsed_ms: u64) {
+        // Mask of the bits each register carries, and those of them to set.
+        let mut statuses: BTreeMap<u16, (u16, u16)> = BTreeMap::new();
+        for (_, outage) in self.outages() {
+            let Some(register) = outage.status_register else {
+                continue;
+            };
+            let mask = 1u16 << outage.status_bit.min(15);
+            let (bits, set) = statuses.entry(register).or_default();
+            *bits |= mask;
+            if in_window(outage.period_ms, outage.outage_ms, elapsed_ms) {
+                *set |= mask;
+            }
+        }
+        for (register, (bits, set)) in statuses {
+            let Some(item) = self.holding_registers.get_mut(&register) else {
+                continue;
+            };
+            let value = item.value & !bits | set;
+            if value != item.value {
+                item.last_value = item.value;
+                item.value = value;
+                if self.log_value_updates {
+                    info!(address = register, value, "outage status updated");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::config::{OutageConfig, RegisterItemConfig};
+    use crate::sim::SimState;
+
+    fn outage(text: &str) -> OutageConfig {
+        toml::from_str(text).unwrap()
+    }
+
+    fn walking(address: u16, outage: Option<OutageConfig>) -> RegisterItemConfig {
+        RegisterItemConfig {
+            address,
+            initial: 50.into(),
+            seed: Some(3),
+            update_ms: Some(1),
+            outage,
+            dynamics: Some(
+                toml::from_str("kind = \"random-walk\"\nmin = 0.0\nmax = 100.0\nstep = 5.0")
+                    .unwrap(),
+            ),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn device_outage_freezes_items_and_sets_status() {
+        let mut state = SimState::new(500, false, vec![], vec![], vec![], vec![walking(0, None)])
+            .unwrap()
+            .with_outage(Some(&outage(
+                "period_ms = 60000\noutage_ms = 60000\nstatus_register = 10\nstatus_bit = 2",
+            )))
+            .unwrap();
+        state.write_single_register(10, 0x0100);
+        for _ in 0..5 {
+            std::thread::sleep(Duration::from_millis(2));
+            state.tick().unwrap();
+            assert_eq!(state.read_input_registers(0, 1), Ok(vec![50]));
+        }
+        assert_eq!(state.holding_register_value(10), 0x0104);
+    }
+
+    #[test]
+    fn item_outage_only_freezes_its_item() {
+        let frozen = Some(outage("period_ms = 60000\noutage_ms = 60000"));
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![],
+            vec![],
+            vec![],
+            vec![walking(0, frozen), walking(1, None)],
+        )
+        .unwrap();
+        let mut moved = Vec::new();
+        for _ in 0..5 {
+            std::thread::sleep(Duration::from_millis(2));
+            state.tick().unwrap();
+            let values = state.read_input_registers(0, 2).unwrap();
+            assert_eq!(values[0], 50);
+            moved.push(values[1]);
+        }
+        assert!(moved.iter().any(|value| *value != 50), "{moved:?}");
+    }
+
+    #[test]
+    fn invalid_status_bits_are_rejected() {
+        let state = || {
+            let status = RegisterItemConfig {
+                address: 10,
+                dynamics: Some(toml::from_str("kind = \"static\"").unwrap()),
+                ..Default::default()
+            };
+            SimState::new(500, false, vec![], vec![], vec![status], vec![]).unwrap()
+        };
+        let err = state()
+            .with_outage(Some(&outage(
+                "period_ms = 1000\noutage_ms = 10\nstatus_register = 11\nstatus_bit = 16",
+            )))
+            .unwrap_err();
+        assert!(err.to_string().contains("status_bit 16"), "{err}");
+        let err = state()
+            .with_outage(Some(&outage(
+                "period_ms = 1000\noutage_ms = 10\nstatus_register = 10",
+            )))
+            .unwrap_err();
+        assert!(err.to_string().contains("has dynamics"), "{err}");
+    }
+}
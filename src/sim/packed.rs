@@ -0,0 +1,121 @@
+use anyhow::{Result, bail};
+
+use super::SimState;
+use crate::config::CoilRegisterMapConfig;
+
+impl SimState {
+    /// Aliases holding registers to coils: bit `i` of each register is coil
+    /// `coils[i]`, least significant bit first. The registers and coils are
+    /// created if not configured; the registers must not have dynamics.
+    pub fn with_coil_register_map(mut self, maps: &[CoilRegisterMapConfig]) -> Result<Self> {
+        for map in maps {
+            let context = format!("coil register map (holding register {})", map.register);
+            if map.coils.is_empty() || map.coils.len() > 16 {
+                bail!(
+                    "{context}: maps {} coils, expected 1 to 16",
+                    map.coils.len()
+                );
+            }
+            if self.packed_registers.contains_key(&map.register) {
+                bail!("{context}: the register is already mapped");
+            }
+            if self
+                .holding_registers
+                .get(&map.register)
+                .is_some_and(|item| item.dynamics.is_some())
+            {
+                bail!("{context}: the register has dynamics, which would fight the coils");
+            }
+            if !self.holding_registers.contains_key(&map.register) {
+                self.write_single_register(map.register, 0);
+            }
+            for &coil in &map.coils {
+                if !self.coils.contains_key(&coil) {
+                    self.write_single_coil(coil, false);
+                }
+            }
+            self.packed_registers
+                .insert(map.register, map.coils.clone());
+        }
+        Ok(self)
+    }
+
+    /// Replaces the mapped registers among `values`, read from `address`
+    /// on, by the bits of their coils.
+    pub fn pack_registers(&self, address: u16, values: &mut [u16]) {
+        for (register, value) in (address..=u16::MAX).zip(values.iter_mut()) {
+            if let Some(coils) = self.packed_registers.get(&register) {
+                *value = coils
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, coil)| self.coils.get(coil).is_some_and(|item| item.value))
+                    .fold(0, |packed, (bit, _)| packed | 1 << bit);
+            }
+        }
+    }
+
+    /// Writes the bits of the mapped registers among `values`, written from
+    /// `address` on, to their coils.
+    pub fn unpack_registers(&mut self, address: u16, values: &[u16]) {
+        for (register, &value) in (address..=u16::MAX).zip(values) {
+            let Some(coils) = self.packed_registers.get(&register).cloned() else {
+                continue;
+            };
+            for (bit, coil) in coils.into_iter().enumerate() {
+                self.write_single_coil(coil, value & (1 << bit) != 0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{BoolItemConfig, CoilRegisterMapConfig};
+    use crate::sim::SimState;
+
+    #[test]
+    fn mapped_registers_read_and_write_their_coils() {
+        let coil = |address, initial| BoolItemConfig {
+            address,
+            initial,
+            ..Default::default()
+        };
+        let map = CoilRegisterMapConfig {
+            register: 100,
+            coils: vec![0, 1, 7],
+        };
+        let mut state = SimState::new(
+            500,
+            false,
+            vec![coil(0, true), coil(1, false), coil(7, true)],
+            vec![],
+            vec![],
+            vec![],
+        )
+        .unwrap()
+        .with_coil_register_map(&[map])
+        .unwrap();
+
+        let mut values = state.read_holding_registers(99, 3).unwrap();
+        state.pack_registers(99, &mut values);
+        assert_eq!(values, vec![0, 0b101, 0]);
+
+        state.write_single_register(100, 0b110);
+        state.unpack_registers(100, &[0b110]);
+        assert_eq!(state.read_coils(0, 2), Ok(vec![false, true]));
+        assert_eq!(state.read_coils(7, 1), Ok(vec![true]));
+    }
+
+    #[test]
+    fn maps_need_one_to_sixteen_coils() {
+        let map = CoilRegisterMapConfig {
+            register: 0,
+            coils: (0..17).collect(),
+        };
+        let err = SimState::new(500, false, vec![], vec![], vec![], vec![])
+            .unwrap()
+            .with_coil_register_map(&[map])
+            .unwrap_err();
+        assert!(err.to_string().contains("expected 1 to 16"), "{err}");
+    }
+}
@@ -0,0 +1,407 @@
+use anyhow::{Context, Result, bail};
+use tracing::info;
+
+use super::{SimRegisterItem, SimState, Space, changes, eval_logical, frozen};
+use crate::config::{Encoding, ReplayConfig};
+
+/// A device capture played back into its registers: each row sets the
+/// registers of its columns at its time after the first row.
+#[derive(Debug, Clone)]
+pub struct Playback {
+    columns: Vec<(Space, u16)>,
+    /// Milliseconds after the first row, and the value of each column carried
+    /// forward over empty cells; `None` before a column's first value.
+    rows: Vec<(f64, Vec<Option<u16>>)>,
+    looped: bool,
+    time_scale: f64,
+    /// Whether the registers keep their dynamics, held until the capture
+    /// ends.
+    then_live: bool,
+    /// Whether the capture has ended and handed its registers over to their
+    /// dynamics.
+    live: bool,
+}
+
+impl Playback {
+    pub fn load(config: &ReplayConfig) -> Result<Self> {
+        if config.replay_then_live && config.looped {
+            bail!("replay_then_live needs a capture that ends; it cannot be combined with loop");
+        }
+        let text = std::fs::read_to_string(&config.path)
+            .with_context(|| format!("failed to read {}", config.path))?;
+        let playback = Self::parse(&text, config.looped, config.time_scale)
+            .with_context(|| format!("invalid replay file {}", config.path))?;
+        Ok(playback.then_live(config.replay_then_live))
+    }
+
+    /// Hands the registers over to their dynamics once the capture ends.
+    fn then_live(mut self, then_live: bool) -> Self {
+        self.then_live = then_live;
+        self
+    }
+
+    /// Parses a capture: a header of a timestamp column and
+    /// `holding_register:N` or `input_register:N` columns, then rows of a
+    /// timestamp in milliseconds and raw values. Empty cells keep the value
+    /// of the row before.
+    fn parse(text: &str, looped: bool, time_scale: f64) -> Result<Self> {
+        if !(time_scale > 0.0 && time_scale.is_finite()) {
+            bail!("time_scale must be positive, got {time_scale}");
+        }
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+        let Some((_, header)) = lines.next() else {
+            bail!("no header row");
+        };
+        let columns = header
+            .split(',')
+            .skip(1)
+            .map(parse_column)
+            .collect::<Result<Vec<_>>>()?;
+        if columns.is_empty() {
+            bail!("no register columns");
+        }
+        let mut rows: Vec<(f64, Vec<Option<u16>>)> = Vec::new();
+        let mut values = vec![None; columns.len()];
+        let mut start = None;
+        for (index, line) in lines {
+            let line_number = index + 1;
+            let mut cells = line.split(',').map(str::trim);
+            let timestamp = cells
+                .next()
+                .and_then(|cell| cell.parse::<f64>().ok())
+                .filter(|timestamp| timestamp.is_finite())
+                .with_context(|| format!("line {line_number}: invalid timestamp"))?;
+            let at = timestamp - *start.get_or_insert(timestamp);
+            if rows.last().is_some_and(|(last, _)| at < *last) {
+                bail!("line {line_number}: timestamp goes backwards");
+            }
+            let cells: Vec<&str> = cells.collect();
+            if cells.len() > columns.len() {
+                bail!(
+                    "line {line_number}: {} values for {} columns",
+                    cells.len(),
+                    columns.len()
+                );
+            }
+            for (value, cell) in values.iter_mut().zip(cells) {
+                if !cell.is_empty() {
+                    *value = Some(cell.parse().with_context(|| {
+                        format!("line {line_number}: invalid register value `{cell}`")
+                    })?);
+                }
+            }
+            rows.push((at, values.clone()));
+        }
+        if rows.is_empty() {
+            bail!("no rows");
+        }
+        Ok(Self {
+            columns,
+            rows,
+            looped,
+            time_scale,
+            then_live: false,
+            live: false,
+        })
+    }
+
+    /// Values of the row playing `elapsed_ms` into the playback. A looping
+    /// capture starts over at the time of its last row; otherwise the last
+    /// row stays.
+    fn row_at(&self, elapsed_ms: f64) -> &[Option<u16>] {
+        let mut at = elapsed_ms * self.time_scale;
+        let length = self.rows.last().map_or(0.0, |(last, _)| *last);
+        if self.looped && length > 0.0 {
+            at %= length;
+        }
+        let index = self.rows.partition_point(|(row_at, _)| *row_at <= at);
+        &self.rows[index.max(1) - 1].1
+    }
+
+    /// Holds the registers for the capture again, as it replays from its
+    /// first row after a reset.
+    pub(super) fn restart(&mut self) {
+        self.live = false;
+    }
+
+    /// Whether the last row has played, `elapsed_ms` into the playback.
+    fn ended(&self, elapsed_ms: f64) -> bool {
+        !self.looped
+            && self
+                .rows
+                .last()
+                .is_some_and(|(last, _)| elapsed_ms * self.time_scale >= *last)
+    }
+
+    /// Shortest time between two rows, on the simulation clock.
+    fn shortest_gap_ms(&self) -> Option<f64> {
+        self.rows
+            .windows(2)
+            .map(|pair| pair[1].0 - pair[0].0)
+            .filter(|gap| *gap > 0.0)
+            .min_by(f64::total_cmp)
+            .map(|gap| gap / self.time_scale)
+    }
+}
+
+fn parse_column(name: &str) -> Result<(Space, u16)> {
+    let name = name.trim();
+    let (space, address) = name
+        .split_once(':')
+        .with_context(|| format!("invalid column `{name}`, expected e.g. holding_register:10"))?;
+    let space = match space {
+        "holding_register" => Space::HoldingRegisters,
+        "input_register" => Space::InputRegisters,
+        _ => bail!("invalid column `{name}`: the space must be holding_register or input_register"),
+    };
+    let address = address
+        .parse()
+        .with_context(|| format!("invalid address in column `{name}`"))?;
+    Ok((space, address))
+}
+
+impl SimState {
+    /// Plays `playback` back into the registers it lists. They lose their
+    /// dynamics, unless the capture hands over to them when it ends, are
+    /// created if not configured, and update at least as often as the
+    /// capture's rows come.
+    pub fn with_playback(mut self, playback: Playback) -> Self {
+        let gap_ms = playback
+            .shortest_gap_ms()
+            .map(|gap| (gap.round() as u64).max(1));
+        let global_update_ms = self.global_update_ms;
+        for &(space, address) in &playback.columns {
+            let map = if space == Space::HoldingRegisters {
+                &mut self.holding_registers
+            } else {
+                &mut self.input_registers
+            };
+            let item = map
+                .entry(address)
+                .or_insert_with(|| SimRegisterItem::plain(0, global_update_ms));
+            if !playback.then_live {
+                item.dynamics = None;
+                item.dynamics_spec = None;
+            }
+            item.update_on_read = false;
+            if let Some(gap_ms) = gap_ms {
+                item.update_ms = item.update_ms.min(gap_ms);
+            }
+        }
+        self.playback = Some(playback);
+        self
+    }
+
+    /// Whether playback drives the register at `address`.
+    pub(super) fn replayed(&self, space: Space, address: u16) -> bool {
+        self.playback
+            .as_ref()
+            .is_some_and(|playback| playback.columns.contains(&(space, address)))
+    }
+
+    /// Registers whose dynamics wait for a `replay_then_live` capture to
+    /// end.
+    pub(super) fn replay_held(&self) -> Vec<(Space, u16)> {
+        match &self.playback {
+            Some(playback) if playback.then_live && !playback.live => playback.columns.clone(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Sets the played back registers to the row playing at `elapsed_ms` on
+    /// the simulation clock. Frozen and pulsing registers hold their value.
+    /// When a `replay_then_live` capture has played its last row, its
+    /// registers go live: their dynamics are offset like a step event so
+    /// they start from the last replayed value, and playback stops.
+    pub(super) fn apply_playback(&mut self, elapsed_ms: u64, outage: bool) {
+        let warmup_ms = self.warmup_ms;
+        let Some(playback) = &mut self.playback else {
+            return;
+        };
+        if playback.live {
+            return;
+        }
+        let timestamp_ms = changes::timestamp_ms();
+        let row = playback.row_at(elapsed_ms as f64);
+        for (&(space, address), value) in playback.columns.iter().zip(row) {
+            let map = if space == Space::HoldingRegisters {
+                &mut self.holding_registers
+            } else {
+                &mut self.input_registers
+            };
+            let (Some(value), Some(item)) = (*value, map.get_mut(&address)) else {
+                continue;
+            };
+            if item.events.pulsing() || frozen(outage, &item.stuck, &item.outage, elapsed_ms) {
+                continue;
+            }
+            item.last_value = item.value;
+            item.value = value;
+            if item.value != item.last_value {
+                let value = f64::from(item.value);
+                changes::publish(&self.change_feed, space, address, value, timestamp_ms);
+            }
+        }
+        if !playback.then_live || !playback.ended(elapsed_ms as f64) {
+            return;
+        }
+        playback.live = true;
+        let elapsed = elapsed_ms as f64 / 1000.0;
+        for &(space, address) in &playback.columns {
+            let map = if space == Space::HoldingRegisters {
+                &mut self.holding_registers
+            } else {
+                &mut self.input_registers
+            };
+            let Some(item) = map.get_mut(&address) else {
+                continue;
+            };
+            // Replayed words carry no logical value to offset encoded
+            // registers from; those start from the dynamics' own value.
+            if item.encoding != Encoding::U16 {
+                continue;
+            }
+            let current = item.value as f64;
+            let startup = item.startup(warmup_ms);
+            if let Some(produced) = eval_logical(
+                current,
+                item.events.step,
+                &item.shaping,
+                &mut item.dynamics,
+                startup,
+                elapsed,
+                None,
+            ) {
+                item.events.step += current - produced;
+            }
+        }
+        info!(
+            registers = playback.columns.len(),
+            elapsed_ms, "replay ended, dynamics take over"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Playback;
+    use crate::config::{RegisterItemConfig, ReplayConfig};
+    use crate::sim::{DynamicsState, SimState, Space};
+
+    const CAPTURE: &str = "\
+timestamp,holding_register:0,input_register:7
+1000,10,
+1500,,70
+3000,30,71
+";
+
+    #[test]
+    fn rows_play_at_their_recorded_times() {
+        let playback = Playback::parse(CAPTURE, false, 1.0).unwrap();
+        assert_eq!(playback.row_at(0.0), [Some(10), None]);
+        assert_eq!(playback.row_at(499.0), [Some(10), None]);
+        // Empty cells keep the value of the row before.
+        assert_eq!(playback.row_at(500.0), [Some(10), Some(70)]);
+        assert_eq!(playback.row_at(60_000.0), [Some(30), Some(71)]);
+
+        let looped = Playback::parse(CAPTURE, true, 2.0).unwrap();
+        assert_eq!(looped.row_at(250.0), [Some(10), Some(70)]);
+        assert_eq!(looped.row_at(1000.0), [Some(10), None]);
+        assert_eq!(looped.shortest_gap_ms(), Some(250.0));
+    }
+
+    #[test]
+    fn invalid_captures_are_rejected() {
+        for (capture, error) in [
+            ("timestamp,coil:1\n0,1\n", "space must be"),
+            ("timestamp,holding_register:1\n", "no rows"),
+            ("timestamp,holding_register:1\n5,1\n4,2\n", "backwards"),
+            (
+                "timestamp,holding_register:1\n0,1,2\n",
+                "2 values for 1 columns",
+            ),
+            (
+                "timestamp,holding_register:1\n0,70000\n",
+                "invalid register value",
+            ),
+        ] {
+            let err = Playback::parse(capture, false, 1.0).unwrap_err();
+            assert!(err.to_string().contains(error), "{err}");
+        }
+        assert!(Playback::parse(CAPTURE, false, 0.0).is_err());
+    }
+
+    #[test]
+    fn replayed_registers_lose_their_dynamics() {
+        let item = RegisterItemConfig {
+            address: 0,
+            dynamics: Some(toml::from_str("kind = \"script\"\nexpr = \"t\"").unwrap()),
+            ..Default::default()
+        };
+        let mut state = SimState::new(500, false, vec![], vec![], vec![item], vec![])
+            .unwrap()
+            .with_playback(Playback::parse(CAPTURE, false, 1.0).unwrap());
+        assert!(state.holding_registers[&0].dynamics.is_none());
+        assert_eq!(state.holding_registers[&0].update_ms, 500);
+        state.tick().unwrap();
+        assert_eq!(state.holding_register_value(0), 10);
+        assert_eq!(state.input_registers[&7].value, 0);
+    }
+
+    #[test]
+    fn replay_then_live_hands_over_from_the_last_value() {
+        let item = RegisterItemConfig {
+            address: 0,
+            update_ms: Some(1),
+            dynamics: Some(toml::from_str("kind = \"script\"\nexpr = \"t * 1000 + 500\"").unwrap()),
+            ..Default::default()
+        };
+        let playing = Playback::parse(CAPTURE, false, 1.0)
+            .unwrap()
+            .then_live(true);
+        let mut state = SimState::new(500, false, vec![], vec![], vec![item.clone()], vec![])
+            .unwrap()
+            .with_playback(playing);
+        state.tick().unwrap();
+        assert!(state.holding_registers[&0].dynamics.is_some());
+        assert_eq!(state.holding_register_value(0), 10);
+        assert_eq!(state.replay_held().len(), 2);
+
+        // A single row ends the capture at once.
+        let ended = Playback::parse("timestamp,holding_register:0\n0,10\n", false, 1.0)
+            .unwrap()
+            .then_live(true);
+        let mut state = SimState::new(500, false, vec![], vec![], vec![item], vec![])
+            .unwrap()
+            .with_playback(ended);
+        state.tick().unwrap();
+        assert_eq!(state.holding_register_value(0), 10);
+        assert!(state.replay_held().is_empty());
+        std::thread::sleep(Duration::from_millis(50));
+        state.tick().unwrap();
+        // The script runs on from 10 rather than jumping to 500 and more.
+        let value = state.holding_register_value(0);
+        assert!((50..300).contains(&value), "{value}");
+        assert_eq!(
+            state.dynamics_state(Space::HoldingRegisters, 0),
+            Some(DynamicsState::Running)
+        );
+    }
+
+    #[test]
+    fn replay_then_live_rejects_looping_captures() {
+        let config = ReplayConfig {
+            path: "capture.csv".to_string(),
+            looped: true,
+            time_scale: 1.0,
+            replay_then_live: true,
+        };
+        let err = Playback::load(&config).unwrap_err();
+        assert!(err.to_string().contains("combined with loop"), "{err}");
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt::Write;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+
+use super::{SimState, Space, encoding};
+
+/// Most samples a preview takes.
+const MAX_SAMPLES: u64 = 1_000_000;
+/// Columns of the bars `preview_plot` draws.
+const PLOT_WIDTH: usize = 60;
+
+impl SimState {
+    /// The values the item at `space`/`address` takes from the start of the
+    /// simulation clock through `duration`, every `step`: the clock is moved
+    /// forward by `step` and the device ticked, as `--self-test` does, and
+    /// the item's value read after each tick. Registers give their decoded
+    /// value. The other items of the device are simulated too, so items
+    /// that follow them preview as they run. Fails when the item is not
+    /// configured or the clock cannot be moved that far.
+    pub fn preview(
+        &mut self,
+        space: Space,
+        address: u16,
+        duration: Duration,
+        step: Duration,
+    ) -> Result<Vec<(Duration, f64)>> {
+        if self.item_value(space, address).is_none() {
+            bail!("{} {address} is not configured", space.item_name());
+        }
+        if step.is_zero() {
+            bail!("the step must be longer than zero");
+        }
+        let steps = (duration.as_nanos() / step.as_nanos()) as u64;
+        if steps >= MAX_SAMPLES {
+            bail!("more than {MAX_SAMPLES} samples; use a longer step");
+        }
+        let started_ms = self.elapsed_ms();
+        let mut samples = Vec::new();
+        for index in 0..=steps {
+            if index > 0 {
+                self.advance_clock(step);
+            }
+            self.tick()?;
+            let value = self.item_value(space, address).unwrap_or_default();
+            samples.push((step * index as u32, value));
+        }
+        let total_ms = (step * steps as u32).as_millis() as u64;
+        if self.elapsed_ms() < started_ms + total_ms {
+            bail!("failed to move the simulation clock {total_ms} ms ahead");
+        }
+        Ok(samples)
+    }
+
+    /// The current value of an item; `None` when it is not configured.
+    fn item_value(&self, space: Space, address: u16) -> Option<f64> {
+        let map = match space {
+            Space::Coils => return self.coils.get(&address).map(|item| bit(item.value)),
+            Space::DiscreteInputs => {
+                return self
+                    .discrete_inputs
+                    .get(&address)
+                    .map(|item| bit(item.value));
+            }
+            Space::HoldingRegisters => &self.holding_registers,
+            Space::InputRegisters => &self.input_registers,
+        };
+        let item = map.get(&address)?;
+        Some(encoding::decode_raw(map, address, item).map_or(item.value as f64, |(_, value)| value))
+    }
+}
+
+fn bit(value: bool) -> f64 {
+    f64::from(u8::from(value))
+}
+
+/// The samples of a preview as CSV: `elapsed_ms,value`, one row per sample.
+pub fn preview_csv(samples: &[(Duration, f64)]) -> String {
+    let mut csv = String::from("elapsed_ms,value\n");
+    for (elapsed, value) in samples {
+        let _ = writeln!(csv, "{},{value}", elapsed.as_millis());
+    }
+    csv
+}
+
+/// The samples of a preview as a bar per sample, scaled between the lowest
+/// and the highest value, for a look in the terminal.
+pub fn preview_plot(samples: &[(Duration, f64)]) -> String {
+    let values = samples.iter().map(|(_, value)| *value);
+    let low = values.clone().fold(f64::INFINITY, f64::min);
+    let high = values.fold(f64::NEG_INFINITY, f64::max);
+    let mut plot = format!("{low} .. {high}\n");
+    for (elapsed, value) in samples {
+        let fraction = if high > low {
+            (value - low) / (high - low)
+        } else {
+            0.0
+        };
+        let bar = "#".repeat(1 + (fraction * (PLOT_WIDTH - 1) as f64).round() as usize);
+        let _ = writeln!(
+            plot,
+            "{:>10} ms {bar:<PLOT_WIDTH$} {value}",
+            elapsed.as_millis()
+        );
+    }
+    plot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RegisterItemConfig;
+
+    #[test]
+    fn preview_samples_an_item_on_the_simulation_clock() {
+        let ramp = RegisterItemConfig {
+            address: 100,
+            update_ms: Some(0),
+            dynamics: Some(toml::from_str("kind = \"script\"\nexpr = \"floor(t) * 10\"").unwrap()),
+            ..Default::default()
+        };
+        let mut state = SimState::new(500, false, vec![], vec![], vec![ramp], vec![]).unwrap();
+        let samples = state
+            .preview(
+                Space::HoldingRegisters,
+                100,
+                Duration::from_secs(3),
+                Duration::from_secs(1),
+            )
+            .unwrap();
+        assert_eq!(
+            preview_csv(&samples),
+            "elapsed_ms,value\n0,0\n1000,10\n2000,20\n3000,30\n"
+        );
+        let plot = preview_plot(&samples);
+        assert!(plot.starts_with("0 .. 30\n         0 ms # "), "{plot}");
+        assert!(
+            plot.ends_with(&format!("      3000 ms {} 30\n", "#".repeat(60))),
+            "{plot}"
+        );
+
+        let err = state
+            .preview(
+                Space::InputRegisters,
+                100,
+                Duration::ZERO,
+                Duration::from_secs(1),
+            )
+            .unwrap_err();
+        assert_eq!(err.to_string(), "input register 100 is not configured");
+    }
+}
@@ -0,0 +1,130 @@
+use anyhow::{Result, bail};
+use tracing::{debug, warn};
+
+use super::{SimRegisterItem, encoding};
+use crate::config::{Encoding, RegisterItemConfig};
+
+/// Bound on how far a register may move in one update, whatever moves it:
+/// dynamics, noise or a decay.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    /// Largest change of the logical value per update, in raw units.
+    pub max_change: f64,
+    /// Whether the last update was clamped; only the first clamped update
+    /// of a run is logged as a warning.
+    engaged: bool,
+}
+
+impl RateLimit {
+    /// The limit `item` asks for, if any; it must be positive.
+    pub(super) fn new(item: &RegisterItemConfig) -> Result<Option<Self>> {
+        let Some(max_change) = item.max_change_per_tick else {
+            return Ok(None);
+        };
+        if !(max_change > 0.0 && max_change.is_finite()) {
+            bail!("max_change_per_tick must be positive, got {max_change}");
+        }
+        Ok(Some(Self {
+            max_change,
+            engaged: false,
+        }))
+    }
+}
+
+impl SimRegisterItem {
+    /// Clamps an update from `current` to `value`, shown as `words`, to the
+    /// item's `max_change_per_tick`, encoding the clamped value anew.
+    pub(super) fn limit_change(
+        &mut self,
+        name: &str,
+        address: u16,
+        current: f64,
+        (value, words): (f64, Vec<u16>),
+    ) -> (f64, Vec<u16>) {
+        let Some(limit) = &mut self.rate_limit else {
+            return (value, words);
+        };
+        let change = value - current;
+        if change.abs() <= limit.max_change {
+            limit.engaged = false;
+            return (value, words);
+        }
+        let clamped = current + change.clamp(-limit.max_change, limit.max_change);
+        if limit.engaged {
+            debug!(
+                address,
+                from = current,
+                to = value,
+                clamped,
+                "{name} change clamped"
+            );
+        } else {
+            warn!(
+                address,
+                from = current,
+                to = value,
+                clamped,
+                max_change_per_tick = limit.max_change,
+                "{name} change clamped"
+            );
+        }
+        limit.engaged = true;
+        if self.encoding == Encoding::U16 {
+            // Rounding toward `current` keeps the change within the limit.
+            let raw = (current + (clamped - current).trunc()).clamp(0.0, u16::MAX as f64) as u16;
+            return (raw as f64, vec![raw]);
+        }
+        let words = encoding::encode(self.encoding, self.word_order, self.byte_order, clamped);
+        (clamped, words)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::config::RegisterItemConfig;
+    use crate::sim::SimState;
+
+    #[test]
+    fn step_changes_are_rate_limited() {
+        let item = RegisterItemConfig {
+            address: 0,
+            initial: 100.into(),
+            update_ms: Some(1),
+            max_change_per_tick: Some(10.0),
+            dynamics: Some(
+                toml::from_str("kind = \"step\"\nlow = 100.0\nhigh = 150.0\nperiod_ms = 60000")
+                    .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let mut state = SimState::new(500, false, vec![], vec![], vec![], vec![item]).unwrap();
+        // Half a period in, where the step goes high.
+        state.start_time = state
+            .start_time
+            .checked_sub(Duration::from_secs(30))
+            .unwrap();
+        let mut seen = Vec::new();
+        for _ in 0..6 {
+            std::thread::sleep(Duration::from_millis(2));
+            state.tick().unwrap();
+            seen.push(state.input_registers[&0].value);
+        }
+        assert_eq!(seen, [110, 120, 130, 140, 150, 150]);
+    }
+
+    #[test]
+    fn limits_must_be_positive() {
+        let item = RegisterItemConfig {
+            address: 0,
+            max_change_per_tick: Some(0.0),
+            ..Default::default()
+        };
+        let err = SimState::new(500, false, vec![], vec![], vec![item], vec![]).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("max_change_per_tick"),
+            "{err:#}"
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use tracing::info;
+
+use super::{ItemEvents, SimState, build_dynamics, next_due, stream_seed};
+
+impl SimState {
+    /// Returns every item to its initial value and rebuilds its dynamics from
+    /// their spec, as at startup: random streams restart from their seeds and
+    /// integrating dynamics forget what they accumulated. The simulation
+    /// clock restarts too, so warmups, schedules and playback run again.
+    /// Pending and active events are dropped; counters and FIFO queues are
+    /// kept.
+    pub fn reset(&mut self) -> Result<()> {
+        // Everything is built before anything changes, so a failure leaves
+        // the state untouched.
+        let mut bools = Vec::new();
+        for (table, name, map) in [
+            (0, "coil", &self.coils),
+            (1, "discrete input", &self.discrete_inputs),
+        ] {
+            for (address, item) in map {
+                let seed = stream_seed(self.seed, item.seed, table, *address);
+                let dynamics = build_dynamics(&self.registry, &item.dynamics_spec, seed)
+                    .with_context(|| format!("{name} {address}"))?;
+                bools.push((table, *address, dynamics));
+            }
+        }
+        let mut registers = Vec::new();
+        for (table, name, map) in [
+            (2, "holding register", &self.holding_registers),
+            (3, "input register", &self.input_registers),
+        ] {
+            for (address, item) in map {
+                let seed = stream_seed(self.seed, item.seed, table, *address);
+                let dynamics = build_dynamics(&self.registry, &item.dynamics_spec, seed)
+                    .with_context(|| format!("{name} {address}"))?;
+                registers.push((table, *address, dynamics));
+            }
+        }
+
+        let watched = self.watch_all();
+        let now = Instant::now();
+        self.start_time = now;
+        if let Some(playback) = &mut self.playback {
+            playback.restart();
+        }
+        self.pending_events.clear();
+        self.last_writers.clear();
+        self.relock_all();
+        for (table, address, dynamics) in bools {
+            let map = if table == 0 {
+                &mut self.coils
+            } else {
+                &mut self.discrete_inputs
+            };
+            if let Some(item) = map.get_mut(&address) {
+                item.value = item.initial;
+                item.last_value = item.initial;
+                item.dynamics = dynamics;
+                item.events = ItemEvents::default();
+                item.reset_at = None;
+                item.last_write = None;
+                item.next_due = next_due(now, item.update_ms, item.align_to_clock);
+            }
+        }
+        for (table, address, dynamics) in registers {
+            let map = if table == 2 {
+                &mut self.holding_registers
+            } else {
+                &mut self.input_registers
+            };
+            if let Some(item) = map.get_mut(&address) {
+                item.value = item.initial;
+                item.last_value = item.initial;
+                item.dynamics = dynamics;
+                item.events = ItemEvents::default();
+                item.noise = 0.0;
+                if let Some(decay) = &mut item.decay {
+                    decay.restart();
+                }
+                item.next_due = next_due(now, item.update_ms, item.align_to_clock);
+            }
+        }
+        for ringbuffer in &mut self.ringbuffers {
+            ringbuffer.position = 0;
+            ringbuffer.next_due = now;
+        }
+        for watched in watched {
+            self.publish_watched(Some(watched));
+        }
+        info!(
+            items = self.coils.len()
+                + self.discrete_inputs.len()
+                + self.holding_registers.len()
+                + self.input_registers.len(),
+            "device reset"
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::config::{BoolItemConfig, RegisterItemConfig};
+    use crate::sim::SimState;
+
+    #[test]
+    fn reset_restores_initials_and_restarts_dynamics() {
+        let walk = RegisterItemConfig {
+            address: 0,
+            initial: 50.into(),
+            seed: Some(7),
+            update_ms: Some(1),
+            dynamics: Some(
+                toml::from_str("kind = \"random-walk\"\nmin = 0.0\nmax = 100.0\nstep = 5.0")
+                    .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let setpoint = RegisterItemConfig {
+            address: 1,
+            initial: 20.into(),
+            ..Default::default()
+        };
+        let coil = BoolItemConfig {
+            address: 0,
+            initial: true,
+            ..Default::default()
+        };
+        let mut state =
+            SimState::new(500, false, vec![coil], vec![], vec![setpoint], vec![walk]).unwrap();
+        let run = |state: &mut SimState| {
+            (0..5)
+                .map(|_| {
+                    std::thread::sleep(Duration::from_millis(2));
+                    state.tick().unwrap();
+                    state.input_registers[&0].value
+                })
+                .collect::<Vec<_>>()
+        };
+        let first = run(&mut state);
+        state.write_single_register(1, 99);
+        state.write_single_register(9, 5);
+        state.write_single_coil(0, false);
+
+        state.reset().unwrap();
+        assert_eq!(state.read_input_registers(0, 1), Ok(vec![50]));
+        assert_eq!(state.read_holding_registers(1, 1), Ok(vec![20]));
+        assert_eq!(state.read_holding_registers(9, 1), Ok(vec![0]));
+        assert_eq!(state.read_coils(0, 1), Ok(vec![true]));
+        assert_eq!(run(&mut state), first);
+    }
+}
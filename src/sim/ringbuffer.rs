@@ -0,0 +1,195 @@
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+
+use super::SimState;
+use crate::config::{RegisterSpace, RingBufferConfig};
+
+/// A block of holding registers receiving the recent values of a source
+/// register, oldest overwritten first.
+#[derive(Debug, Clone)]
+pub struct RingBuffer {
+    pub source_space: RegisterSpace,
+    pub source: u16,
+    pub address: u16,
+    pub length: u16,
+    pub head: u16,
+    /// Offset of the slot written next; mirrored into the `head` register.
+    pub position: u16,
+    pub interval_ms: u64,
+    pub next_due: Instant,
+}
+
+impl RingBuffer {
+    fn slots(&self) -> Range<u32> {
+        u32::from(self.address)..u32::from(self.address) + u32::from(self.length)
+    }
+
+    /// Whether `address` is one of the buffer's slots or its head.
+    fn occupies(&self, address: u16) -> bool {
+        address == self.head || self.slots().contains(&u32::from(address))
+    }
+}
+
+impl SimState {
+    /// Adds ring buffers. Their slot and head registers are created as plain
+    /// holding registers and must not be driven by dynamics or shared with
+    /// another ring buffer.
+    pub fn with_ringbuffers(mut self, ringbuffers: &[RingBufferConfig]) -> Result<Self> {
+        for (index, config) in ringbuffers.iter().enumerate() {
+            let context = format!("ring buffer {index} (holding registers {})", config.address);
+            if config.length == 0 {
+                bail!("{context}: length must be at least 1");
+            }
+            if u32::from(config.address) + u32::from(config.length) > 0x1_0000 {
+                bail!("{context}: {} slots run past address 65535", config.length);
+            }
+            let sources = match config.source_space {
+                RegisterSpace::HoldingRegisters => &self.holding_registers,
+                RegisterSpace::InputRegisters => &self.input_registers,
+            };
+            let Some(source) = sources.get(&config.source) else {
+                bail!(
+                    "{context}: source {} {} is not configured",
+                    config.source_space.name(),
+                    config.source
+                );
+            };
+            let interval_ms = config.interval_ms.unwrap_or(source.update_ms).max(1);
+            let ringbuffer = RingBuffer {
+                source_space: config.source_space,
+                source: config.source,
+                address: config.address,
+                length: config.length,
+                head: config.head,
+                position: 0,
+                interval_ms,
+                next_due: Instant::now(),
+            };
+            let slots = ringbuffer.slots();
+            if slots.contains(&u32::from(config.head)) {
+                bail!("{context}: head {} lies inside the slots", config.head);
+            }
+            if config.source_space == RegisterSpace::HoldingRegisters
+                && ringbuffer.occupies(config.source)
+            {
+                bail!("{context}: the source cannot be one of its own registers");
+            }
+            let driven = self
+                .holding_registers
+                .range(config.address..)
+                .take_while(|(address, _)| slots.contains(&u32::from(**address)))
+                .chain(self.holding_registers.get_key_value(&config.head))
+                .find(|(_, item)| item.dynamics.is_some());
+            if let Some((address, _)) = driven {
+                bail!("{context}: holding register {address} has dynamics");
+            }
+            if let Some(other) = self.ringbuffers.iter().find(|other| {
+                other.occupies(config.head)
+                    || ringbuffer.occupies(other.head)
+                    || other.slots().start < slots.end && slots.start < other.slots().end
+            }) {
+                bail!(
+                    "{context}: overlaps the ring buffer at holding register {}",
+                    other.address
+                );
+            }
+            for address in slots.chain([u32::from(config.head)]) {
+                let address = address as u16;
+                if !self.holding_registers.contains_key(&address) {
+                    self.write_single_register(address, 0);
+                }
+            }
+            self.ringbuffers.push(ringbuffer);
+        }
+        Ok(self)
+    }
+
+    /// Writes the current source value of every ring buffer that is due into
+    /// its next slot and advances its head.
+    pub(super) fn sample_ringbuffers(&mut self, now: Instant) {
+        for ringbuffer in &mut self.ringbuffers {
+            if now < ringbuffer.next_due {
+                continue;
+            }
+            ringbuffer.next_due = now + Duration::from_millis(ringbuffer.interval_ms);
+            let sources = match ringbuffer.source_space {
+                RegisterSpace::HoldingRegisters => &self.holding_registers,
+                RegisterSpace::InputRegisters => &self.input_registers,
+            };
+            let value = sources.get(&ringbuffer.source).map_or(0, |item| item.value);
+            let slot = ringbuffer.address + ringbuffer.position;
+            ringbuffer.position = (ringbuffer.position + 1) % ringbuffer.length;
+            for (address, value) in [(slot, value), (ringbuffer.head, ringbuffer.position)] {
+                if let Some(item) = self.holding_registers.get_mut(&address) {
+                    item.last_value = item.value;
+                    item.value = value;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{RegisterItemConfig, RingBufferConfig};
+    use crate::sim::SimState;
+
+    fn ringbuffer(text: &str) -> RingBufferConfig {
+        toml::from_str(text).unwrap()
+    }
+
+    fn state() -> SimState {
+        let source = RegisterItemConfig {
+            address: 0,
+            initial: 10.into(),
+            ..Default::default()
+        };
+        SimState::new(500, false, vec![], vec![], vec![], vec![source]).unwrap()
+    }
+
+    #[test]
+    fn samples_wrap_around_and_advance_the_head() {
+        let mut state = state()
+            .with_ringbuffers(&[ringbuffer(
+                "source = 0\naddress = 100\nlength = 3\nhead = 99\ninterval_ms = 1",
+            )])
+            .unwrap();
+        for value in [1, 2, 3, 4] {
+            state.write_input_registers(0, &[value]);
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            state.tick().unwrap();
+        }
+        assert_eq!(state.read_holding_registers(99, 4), Ok(vec![1, 4, 2, 3]));
+    }
+
+    #[test]
+    fn overlapping_or_misplaced_buffers_are_rejected() {
+        for (configs, message) in [
+            (
+                vec!["source = 0\naddress = 100\nlength = 0\nhead = 99"],
+                "at least 1",
+            ),
+            (
+                vec!["source = 0\naddress = 100\nlength = 3\nhead = 101"],
+                "inside the slots",
+            ),
+            (
+                vec!["source = 5\naddress = 100\nlength = 3\nhead = 99"],
+                "input register 5",
+            ),
+            (
+                vec![
+                    "source = 0\naddress = 100\nlength = 3\nhead = 99",
+                    "source = 0\naddress = 102\nlength = 3\nhead = 98",
+                ],
+                "overlaps",
+            ),
+        ] {
+            let configs: Vec<_> = configs.into_iter().map(ringbuffer).collect();
+            let err = state().with_ringbuffers(&configs).unwrap_err();
+            assert!(err.to_string().contains(message), "{err}");
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+
+use super::{SimState, Space, check_bounds, encoding, period_ms};
+
+/// Periods of the slowest periodic item a self-test runs through.
+pub const SELF_TEST_PERIODS: u64 = 3;
+/// Ticks per period of the fastest periodic item.
+const STEPS_PER_PERIOD: u64 = 16;
+/// Most ticks a self-test runs, however far apart the periods are.
+const MAX_STEPS: u64 = 100_000;
+
+/// The outcome of the self-test of one periodic item.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfTestResult {
+    pub space: Space,
+    pub address: u16,
+    /// Kind of the item's dynamics.
+    pub kind: String,
+    /// Why the item failed; `None` when it passed.
+    pub failure: Option<String>,
+}
+
+/// What an item did during a self-test.
+struct Observed {
+    first: f64,
+    varied: bool,
+    failure: Option<String>,
+}
+
+impl SimState {
+    /// Checks every item with periodic dynamics (those with a `period_ms`)
+    /// by moving the simulation clock through `periods` periods of the
+    /// slowest, ticking 16 times per period of the fastest: each item must
+    /// change, and registers must stay within the `min`/`max` of their
+    /// dynamics as `--assert-bounds` checks them. Items updating on read are
+    /// left out. Fails when the clock cannot be moved that far.
+    pub fn self_test(&mut self, periods: u64) -> Result<Vec<SelfTestResult>> {
+        let items = self.periodic_items();
+        let (Some(shortest), Some(longest)) = (
+            items.iter().map(|(_, _, period)| *period).min(),
+            items.iter().map(|(_, _, period)| *period).max(),
+        ) else {
+            return Ok(Vec::new());
+        };
+        let total_ms = longest * periods;
+        let step_ms = (shortest / STEPS_PER_PERIOD)
+            .max(total_ms.div_ceil(MAX_STEPS))
+            .max(1);
+        let started_ms = self.elapsed_ms();
+        // Bounds are collected per item instead of failing the tick.
+        let assert_bounds = std::mem::replace(&mut self.assert_bounds, false);
+        let mut observed = BTreeMap::new();
+        let mut ticked = Ok(());
+        for _ in 0..total_ms.div_ceil(step_ms) {
+            self.advance_clock(Duration::from_millis(step_ms));
+            ticked = self.tick();
+            if ticked.is_err() {
+                break;
+            }
+            self.observe(&items, &mut observed);
+        }
+        self.assert_bounds = assert_bounds;
+        ticked?;
+        if self.elapsed_ms() < started_ms + total_ms {
+            anyhow::bail!("failed to move the simulation clock {total_ms} ms ahead");
+        }
+        Ok(items
+            .into_iter()
+            .map(|(space, address, _)| {
+                let observed = &observed[&(space, address)];
+                let failure = observed
+                    .failure
+                    .clone()
+                    .or_else(|| (!observed.varied).then(|| format!("stuck at {}", observed.first)));
+                let kind = self
+                    .dynamics(space, address)
+                    .map(|(spec, _)| spec.kind.clone())
+                    .unwrap_or_default();
+                SelfTestResult {
+                    space,
+                    address,
+                    kind,
+                    failure,
+                }
+            })
+            .collect())
+    }
+
+    /// Every tick-scheduled item with periodic dynamics, with its period.
+    fn periodic_items(&self) -> Vec<(Space, u16, u64)> {
+        let bools = [
+            (Space::Coils, &self.coils),
+            (Space::DiscreteInputs, &self.discrete_inputs),
+        ]
+        .into_iter()
+        .flat_map(|(space, map)| {
+            map.iter()
+                .filter(|(_, item)| !item.update_on_read)
+                .filter_map(move |(&address, item)| {
+                    Some((space, address, period_ms(&item.dynamics_spec)?))
+                })
+        });
+        let registers = [
+            (Space::HoldingRegisters, &self.holding_registers),
+            (Space::InputRegisters, &self.input_registers),
+        ]
+        .into_iter()
+        .flat_map(|(space, map)| {
+            map.iter()
+                .filter(|(_, item)| !item.update_on_read)
+                .filter_map(move |(&address, item)| {
+                    Some((space, address, period_ms(&item.dynamics_spec)?))
+                })
+        });
+        bools.chain(registers).collect()
+    }
+
+    /// Records the current value of each of `items`, and the first time a
+    /// register is out of bounds.
+    fn observe(
+        &self,
+        items: &[(Space, u16, u64)],
+        observed: &mut BTreeMap<(Space, u16), Observed>,
+    ) {
+        let elapsed = self.sim_elapsed(Instant::now()).as_secs_f64();
+        for &(space, address, _) in items {
+            let (value, bounds) = match space {
+                Space::Coils => (f64::from(u8::from(self.coils[&address].value)), Ok(())),
+                Space::DiscreteInputs => (
+                    f64::from(u8::from(self.discrete_inputs[&address].value)),
+                    Ok(()),
+                ),
+                Space::HoldingRegisters | Space::InputRegisters => {
+                    let map = if space == Space::HoldingRegisters {
+                        &self.holding_registers
+                    } else {
+                        &self.input_registers
+                    };
+                    let item = &map[&address];
+                    let value = encoding::decode_raw(map, address, item)
+                        .map_or(item.value as f64, |(_, value)| value);
+                    let startup = item.startup(self.warmup_ms);
+                    (value, check_bounds(item, startup, value, elapsed))
+                }
+            };
+            let entry = observed.entry((space, address)).or_insert(Observed {
+                first: value,
+                varied: false,
+                failure: None,
+            });
+            entry.varied |= value != entry.first;
+            if entry.failure.is_none()
+                && let Err(err) = bounds
+            {
+                entry.failure = Some(err.to_string());
+            }
+        }
+    }
+}
+
+/// The self-test results of each named device in the Test Anything Protocol,
+/// which CI systems parse: an `ok` or `not ok` line per item, with the
+/// reason a failed item failed in a YAML block below it.
+pub fn self_test_tap(devices: &[(String, Vec<SelfTestResult>)]) -> String {
+    let count: usize = devices.iter().map(|(_, results)| results.len()).sum();
+    let mut tap = format!("TAP version 13\n1..{count}\n");
+    let results = devices
+        .iter()
+        .flat_map(|(device, results)| results.iter().map(move |result| (device, result)));
+    for (number, (device, result)) in (1..).zip(results) {
+        let status = if result.failure.is_some() {
+            "not ok"
+        } else {
+            "ok"
+        };
+        let _ = writeln!(
+            tap,
+            "{status} {number} - {device} {} {} ({})",
+            result.space.item_name(),
+            result.address,
+            result.kind
+        );
+        if let Some(failure) = &result.failure {
+            let _ = writeln!(tap, "  ---\n  message: {failure:?}\n  ...");
+        }
+    }
+    tap
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RegisterItemConfig, Transform};
+
+    fn item(address: u16, dynamics: &str) -> RegisterItemConfig {
+        RegisterItemConfig {
+            address,
+            update_ms: Some(0),
+            dynamics: Some(toml::from_str(dynamics).unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn periodic_items_must_vary_within_bounds() {
+        let sine = item(
+            0,
+            "kind = \"sine\"\namplitude = 100.0\noffset = 200.0\nperiod_ms = 1000",
+        );
+        let mut stuck = item(
+            1,
+            "kind = \"step\"\nlow = 1.0\nhigh = 9.0\nperiod_ms = 2000",
+        );
+        stuck.update_ms = Some(3_600_000);
+        let mut doubled = item(
+            2,
+            "kind = \"ramp\"\nmin = 0.0\nmax = 100.0\nperiod_ms = 1000",
+        );
+        doubled.transform = Some(Transform::Script("x * 2".to_string()));
+        let constant = item(3, "kind = \"static\"");
+        let items = vec![sine, stuck, doubled, constant];
+        let mut state = SimState::new(500, false, vec![], vec![], vec![], items).unwrap();
+
+        let results = state.self_test(SELF_TEST_PERIODS).unwrap();
+        let failures: Vec<_> = results
+            .iter()
+            .map(|result| (result.address, result.failure.as_deref()))
+            .collect();
+        assert_eq!(failures.len(), 3);
+        assert_eq!(failures[0], (0, None));
+        assert_eq!(failures[1], (1, Some("stuck at 0")));
+        assert!(
+            failures[2]
+                .1
+                .unwrap()
+                .contains("outside the bounds 0..=100"),
+            "{failures:?}"
+        );
+        assert!(state.elapsed_ms() >= 6000);
+
+        let tap = self_test_tap(&[("plc".to_string(), results)]);
+        assert!(tap.starts_with("TAP version 13\n1..3\nok 1 - plc input register 0 (sine)\n"));
+        assert!(tap.contains("not ok 2 - plc input register 1 (step)\n  ---\n  message: \"stuck"));
+    }
+}
@@ -0,0 +1,81 @@
+use anyhow::{Context, Result, bail};
+
+use super::dynamics::eval_expr;
+use crate::config::{RegisterItemConfig, Transform};
+
+/// What a register does to its dynamics output before showing it: the
+/// transform first, then snapping to the resolution.
+#[derive(Debug, Clone)]
+pub struct Shaping {
+    pub transform: Option<Transform>,
+    /// Step the output is snapped to, in raw units.
+    pub resolution: f64,
+}
+
+impl Default for Shaping {
+    fn default() -> Self {
+        Self {
+            transform: None,
+            resolution: 1.0,
+        }
+    }
+}
+
+impl Shaping {
+    /// The shaping `item` asks for; the resolution must be positive and a
+    /// script must parse.
+    pub(super) fn new(item: &RegisterItemConfig) -> Result<Self> {
+        let resolution = item.resolution.unwrap_or(1.0);
+        if !(resolution > 0.0 && resolution.is_finite()) {
+            bail!("resolution must be positive, got {resolution}");
+        }
+        if let Some(Transform::Script(expr)) = &item.transform {
+            evalexpr::build_operator_tree(expr).context("failed to parse transform script")?;
+        }
+        Ok(Self {
+            transform: item.transform.clone(),
+            resolution,
+        })
+    }
+
+    /// Shapes `value` at `elapsed` seconds, or `None` when the transform has
+    /// no finite result, e.g. the log of zero.
+    pub(super) fn apply(&self, value: f64, elapsed: f64) -> Option<f64> {
+        let value = match &self.transform {
+            None => value,
+            Some(Transform::Log) => value.ln(),
+            Some(Transform::Exp) => value.exp(),
+            Some(Transform::Sqrt) => value.sqrt(),
+            Some(Transform::Square) => value * value,
+            Some(Transform::Script(expr)) => eval_expr(expr, &[("x", value), ("t", elapsed)])?,
+        };
+        if !value.is_finite() {
+            return None;
+        }
+        Some((value / self.resolution).round() * self.resolution)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Shaping;
+    use crate::config::Transform;
+
+    #[test]
+    fn transforms_run_before_the_resolution() {
+        let shaping = |transform, resolution| Shaping {
+            transform: Some(transform),
+            resolution,
+        };
+        assert_eq!(shaping(Transform::Sqrt, 1.0).apply(49.0, 0.0), Some(7.0));
+        assert_eq!(shaping(Transform::Square, 10.0).apply(7.0, 0.0), Some(50.0));
+        assert_eq!(shaping(Transform::Exp, 1.0).apply(0.0, 0.0), Some(1.0));
+        assert_eq!(
+            shaping(Transform::Script("x".to_string()), 1.0).apply(12.0, 0.0),
+            Some(12.0)
+        );
+        // Results with no number hold the register.
+        assert_eq!(shaping(Transform::Log, 1.0).apply(0.0, 0.0), None);
+        assert_eq!(shaping(Transform::Sqrt, 1.0).apply(-1.0, 0.0), None);
+    }
+}
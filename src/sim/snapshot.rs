@@ -0,0 +1,221 @@
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Dynamic, SimState, Space};
+
+/// A savestate of a device: every item's value, the simulation clock and the
+/// internal state of stateful dynamics.
+///
+/// Dynamics state is kept in memory only; a snapshot that went through serde
+/// restores values and the clock but leaves dynamics as they are.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    /// Milliseconds since start when the snapshot was taken.
+    pub elapsed_ms: u64,
+    pub coils: Vec<(u16, bool)>,
+    pub discrete_inputs: Vec<(u16, bool)>,
+    pub holding_registers: Vec<(u16, u16)>,
+    pub input_registers: Vec<(u16, u16)>,
+    #[serde(skip)]
+    dynamics: Vec<(Space, u16, Box<dyn Dynamic>)>,
+}
+
+impl SimState {
+    pub fn capture(&self) -> StateSnapshot {
+        let mut dynamics = Vec::new();
+        let mut keep = |space, address: &u16, dynamic: &Option<Box<dyn Dynamic>>| {
+            if let Some(dynamic) = dynamic {
+                dynamics.push((space, *address, dynamic.clone()));
+            }
+        };
+        for (address, item) in &self.coils {
+            keep(Space::Coils, address, &item.dynamics);
+        }
+        for (address, item) in &self.discrete_inputs {
+            keep(Space::DiscreteInputs, address, &item.dynamics);
+        }
+        for (address, item) in &self.holding_registers {
+            keep(Space::HoldingRegisters, address, &item.dynamics);
+        }
+        for (address, item) in &self.input_registers {
+            keep(Space::InputRegisters, address, &item.dynamics);
+        }
+
+        StateSnapshot {
+            elapsed_ms: self.elapsed_ms(),
+            coils: self.coils.iter().map(|(a, i)| (*a, i.value)).collect(),
+            discrete_inputs: self
+                .discrete_inputs
+                .iter()
+                .map(|(a, i)| (*a, i.value))
+                .collect(),
+            holding_registers: self
+                .holding_registers
+                .iter()
+                .map(|(a, i)| (*a, i.value))
+                .collect(),
+            input_registers: self
+                .input_registers
+                .iter()
+                .map(|(a, i)| (*a, i.value))
+                .collect(),
+            dynamics,
+        }
+    }
+
+    /// Rewinds to `snapshot`. Coils and holding registers created by writes
+    /// since it was taken are removed again, and ones it holds but the state
+    /// lacks are recreated.
+    pub fn restore(&mut self, snapshot: &StateSnapshot) {
+        let coils: BTreeSet<u16> = snapshot.coils.iter().map(|(address, _)| *address).collect();
+        self.coils.retain(|address, _| coils.contains(address));
+        let registers: BTreeSet<u16> = snapshot
+            .holding_registers
+            .iter()
+            .map(|(address, _)| *address)
+            .collect();
+        self.holding_registers
+            .retain(|address, _| registers.contains(address));
+        for &(address, value) in &snapshot.coils {
+            if !self.coils.contains_key(&address) {
+                self.write_single_coil(address, value);
+            }
+        }
+        for &(address, value) in &snapshot.holding_registers {
+            if !self.holding_registers.contains_key(&address) {
+                self.write_single_register(address, value);
+            }
+        }
+
+        // Every item now exists, and its value is set directly: a client
+        // write would be filtered by debounce and would restart debounce and
+        // momentary timers.
+        let watched = self.watch_all();
+        for &(address, value) in &snapshot.coils {
+            if let Some(item) = self.coils.get_mut(&address) {
+                item.value = value;
+                item.last_value = value;
+            }
+        }
+        for &(address, value) in &snapshot.holding_registers {
+            if let Some(item) = self.holding_registers.get_mut(&address) {
+                item.value = value;
+                item.last_value = value;
+            }
+        }
+        for &(address, value) in &snapshot.discrete_inputs {
+            if let Some(item) = self.discrete_inputs.get_mut(&address) {
+                item.value = value;
+                item.last_value = value;
+            }
+        }
+        for &(address, value) in &snapshot.input_registers {
+            if let Some(item) = self.input_registers.get_mut(&address) {
+                item.value = value;
+                item.last_value = value;
+            }
+        }
+        for watched in watched {
+            self.publish_watched(Some(watched));
+        }
+
+        for (space, address, dynamic) in &snapshot.dynamics {
+            let slot = match space {
+                Space::Coils => self.coils.get_mut(address).map(|item| &mut item.dynamics),
+                Space::DiscreteInputs => self
+                    .discrete_inputs
+                    .get_mut(address)
+                    .map(|item| &mut item.dynamics),
+                Space::HoldingRegisters => self
+                    .holding_registers
+                    .get_mut(address)
+                    .map(|item| &mut item.dynamics),
+                Space::InputRegisters => self
+                    .input_registers
+                    .get_mut(address)
+                    .map(|item| &mut item.dynamics),
+            };
+            if let Some(slot) = slot {
+                *slot = Some(dynamic.clone());
+            }
+        }
+
+        let now = Instant::now();
+        self.start_time = now
+            .checked_sub(Duration::from_millis(snapshot.elapsed_ms).div_f64(self.clock_rate))
+            .unwrap_or(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{BoolItemConfig, RegisterItemConfig};
+    use crate::sim::{SimState, StateSnapshot};
+
+    #[test]
+    fn restore_rewinds_values_and_dynamics() {
+        let walk = RegisterItemConfig {
+            address: 0,
+            initial: 50.into(),
+            seed: Some(7),
+            dynamics: Some(
+                toml::from_str("kind = \"random-walk\"\nmin = 0.0\nmax = 100.0\nstep = 5.0")
+                    .unwrap(),
+            ),
+            ..Default::default()
+        };
+        let mut state = SimState::new(1, false, vec![], vec![], vec![], vec![walk]).unwrap();
+        state.write_single_register(3, 30);
+        let snapshot = state.capture();
+        let stored: StateSnapshot = toml::from_str(&toml::to_string(&snapshot).unwrap()).unwrap();
+        assert_eq!(stored.holding_registers, vec![(3, 30)]);
+        assert_eq!(stored.input_registers, vec![(0, 50)]);
+
+        let mut first = Vec::new();
+        for _ in 0..5 {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            state.tick().unwrap();
+            first.push(state.input_registers[&0].value);
+        }
+        state.write_single_register(3, 99);
+        state.write_single_register(4, 40);
+
+        state.restore(&snapshot);
+        assert_eq!(state.input_registers[&0].value, 50);
+        assert_eq!(state.holding_register_value(3), 30);
+        assert!(!state.holding_registers.contains_key(&4));
+
+        let mut second = Vec::new();
+        for _ in 0..5 {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            state.tick().unwrap();
+            second.push(state.input_registers[&0].value);
+        }
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn restored_coils_do_not_count_as_writes() {
+        let button = BoolItemConfig {
+            address: 2,
+            debounce_ms: 60_000,
+            momentary: true,
+            momentary_ms: Some(60_000),
+            ..Default::default()
+        };
+        let mut state = SimState::new(500, false, vec![button], vec![], vec![], vec![]).unwrap();
+        let snapshot = StateSnapshot {
+            coils: vec![(2, true)],
+            ..Default::default()
+        };
+        state.restore(&snapshot);
+        let coil = &state.coils[&2];
+        assert!(coil.value);
+        assert_eq!((coil.last_write, coil.reset_at), (None, None));
+        // Nothing is debounced, so a client write still lands.
+        state.write_single_coil(2, false);
+        assert!(!state.coils[&2].value);
+    }
+}
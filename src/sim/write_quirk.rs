@@ -0,0 +1,115 @@
+use anyhow::{Context, Result};
+
+use super::SimState;
+use super::dynamics::eval_expr;
+use crate::config::{RegisterItemConfig, WriteTransform};
+
+/// How a holding register mangles the values clients write to it: the
+/// transform first, then rounding and clamping to `0..=65535`, then the
+/// mask.
+#[derive(Debug, Clone)]
+pub struct WriteQuirk {
+    pub transform: Option<WriteTransform>,
+    /// Bits of the transformed value the register keeps.
+    pub mask: u16,
+}
+
+impl WriteQuirk {
+    /// The quirk `item` asks for, if any; a script must parse.
+    pub(super) fn new(item: &RegisterItemConfig) -> Result<Option<Self>> {
+        if item.write_mask.is_none() && item.write_transform.is_none() {
+            return Ok(None);
+        }
+        if let Some(WriteTransform::Script(expr)) = &item.write_transform {
+            evalexpr::build_operator_tree(expr)
+                .context("failed to parse write_transform script")?;
+        }
+        Ok(Some(Self {
+            transform: item.write_transform.clone(),
+            mask: item.write_mask.unwrap_or(u16::MAX),
+        }))
+    }
+
+    /// What a register holding `current` stores when `written` is written.
+    /// A script without a finite result leaves the register as it was.
+    fn store(&self, written: u16, current: u16) -> u16 {
+        let value = match &self.transform {
+            None => written,
+            Some(WriteTransform::SwapBytes) => written.swap_bytes(),
+            Some(WriteTransform::Script(expr)) => match eval_expr(expr, &[("x", written as f64)]) {
+                Some(value) if value.is_finite() => {
+                    value.round().clamp(0.0, u16::MAX as f64) as u16
+                }
+                _ => return current,
+            },
+        };
+        value & self.mask
+    }
+}
+
+impl SimState {
+    /// The values the holding registers from `address` on store when a
+    /// client writes `values` to them; as written unless an item has a
+    /// `write_mask` or `write_transform`.
+    pub fn stored_values(&self, address: u16, values: &[u16]) -> Vec<u16> {
+        (address..=u16::MAX)
+            .zip(values)
+            .map(|(address, &value)| {
+                self.holding_registers
+                    .get(&address)
+                    .and_then(|item| Some(item.write_quirk.as_ref()?.store(value, item.value)))
+                    .unwrap_or(value)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn register(
+        address: u16,
+        write_mask: Option<u16>,
+        write_transform: Option<WriteTransform>,
+    ) -> RegisterItemConfig {
+        RegisterItemConfig {
+            address,
+            initial: 7.into(),
+            write_mask,
+            write_transform,
+            ..Default::default()
+        }
+    }
+
+    fn script(expr: &str) -> Option<WriteTransform> {
+        Some(WriteTransform::Script(expr.to_string()))
+    }
+
+    #[test]
+    fn writes_are_transformed_then_masked() {
+        let items = vec![
+            register(0, Some(0x00FF), None),
+            register(1, None, Some(WriteTransform::SwapBytes)),
+            register(2, Some(0xFFF0), script("x * 2")),
+            register(3, None, script("x / 0")),
+            register(4, None, None),
+        ];
+        let state = SimState::new(500, false, vec![], vec![], items, vec![]).unwrap();
+        assert_eq!(
+            state.stored_values(0, &[0x1234, 0x1234, 40_000, 5, 0x1234, 0x1234]),
+            [0x0034, 0x3412, 0xFFF0, 7, 0x1234, 0x1234]
+        );
+    }
+
+    #[test]
+    fn quirks_are_validated() {
+        let item = register(0, Some(0x00FF), None);
+        let err = SimState::new(500, false, vec![], vec![], vec![], vec![item]).unwrap_err();
+        assert!(format!("{err:#}").contains("write_mask"), "{err:#}");
+
+        let item = register(0, None, script("x +"));
+        let err = SimState::new(500, false, vec![], vec![], vec![item], vec![]).unwrap_err();
+        assert!(format!("{err:#}").contains("write_transform"), "{err:#}");
+    }
+}
@@ -0,0 +1,486 @@
+//! Warm standby: a primary streams its item values to standbys, which apply
+//! them so clients can fail over to a standby with consistent state.
+//!
+//! The wire format is line-based text over TCP. On accepting a standby the
+//! primary sends the header line `modsim-standby 1`, then one batch every
+//! `interval_ms`: a line `<space> <address> <value>` per item changed since
+//! the last batch, followed by an empty line. Spaces are `coil`,
+//! `discrete_input`, `holding_register` and `input_register`; bits are `0`
+//! or `1`, and a register value spanning several registers sends all of
+//! them. The first batch holds every item, as does the batch after the
+//! primary lost track of its changes. An empty batch doubles as a heartbeat.
+//!
+//! The primary learns what changed from the device's change feed, which
+//! ticks and writes publish to. A standby suspends its own tick loop while
+//! it follows a primary, and resumes it when the primary is lost.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::TryRecvError;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+use crate::config::StandbyConfig;
+use crate::sim::{Change, SimState, Space};
+
+const HEADER: &str = "modsim-standby 1";
+
+/// Item values keyed by space and address; bits are 0 or 1.
+pub type Values = BTreeMap<(Space, u16), u16>;
+
+/// Which end of the replication channel a device is.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Role {
+    /// Serves its changes on this address.
+    Primary(String),
+    /// Follows the primary at this address.
+    Standby(String),
+}
+
+impl Role {
+    pub fn new(config: &StandbyConfig) -> Result<Self> {
+        match (&config.listen, &config.primary) {
+            (Some(listen), None) => Ok(Role::Primary(listen.clone())),
+            (None, Some(primary)) => Ok(Role::Standby(primary.clone())),
+            _ => bail!("standby: set either `listen` on the primary or `primary` on a standby"),
+        }
+    }
+}
+
+/// Current value of every item of `state`.
+pub fn values(state: &SimState) -> Values {
+    let bit = |space, address: &u16, value: bool| ((space, *address), u16::from(value));
+    let coils = state
+        .coils
+        .iter()
+        .map(|(address, item)| bit(Space::Coils, address, item.value));
+    let discrete_inputs = state
+        .discrete_inputs
+        .iter()
+        .map(|(address, item)| bit(Space::DiscreteInputs, address, item.value));
+    let holding_registers = state
+        .holding_registers
+        .iter()
+        .map(|(address, item)| ((Space::HoldingRegisters, *address), item.value));
+    let input_registers = state
+        .input_registers
+        .iter()
+        .map(|(address, item)| ((Space::InputRegisters, *address), item.value));
+    coils
+        .chain(discrete_inputs)
+        .chain(holding_registers)
+        .chain(input_registers)
+        .collect()
+}
+
+/// Current value of the `changed` items of `state`; a register with all
+/// the registers holding its value.
+pub fn changed_values(state: &SimState, changed: &BTreeSet<(Space, u16)>) -> Values {
+    let mut values = Values::new();
+    for &(space, address) in changed {
+        let registers = match space {
+            Space::Coils | Space::DiscreteInputs => {
+                let map = if space == Space::Coils {
+                    &state.coils
+                } else {
+                    &state.discrete_inputs
+                };
+                if let Some(item) = map.get(&address) {
+                    values.insert((space, address), u16::from(item.value));
+                }
+                continue;
+            }
+            Space::HoldingRegisters => &state.holding_registers,
+            Space::InputRegisters => &state.input_registers,
+        };
+        let Some(item) = registers.get(&address) else {
+            continue;
+        };
+        for address in (address..=u16::MAX).take(item.encoding.width().into()) {
+            if let Some(item) = registers.get(&address) {
+                values.insert((space, address), item.value);
+            }
+        }
+    }
+    values
+}
+
+/// The items `feed` reported changes to since it was last drained; `None`
+/// when it fell behind and lost some.
+fn drain(feed: &mut broadcast::Receiver<Change>) -> Option<BTreeSet<(Space, u16)>> {
+    let mut changed = BTreeSet::new();
+    loop {
+        match feed.try_recv() {
+            Ok(change) => {
+                changed.insert((change.space, change.address));
+            }
+            Err(TryRecvError::Lagged(_)) => return None,
+            Err(TryRecvError::Empty | TryRecvError::Closed) => return Some(changed),
+        }
+    }
+}
+
+/// One batch on the wire, its terminating empty line included.
+pub fn encode(batch: &[((Space, u16), u16)]) -> String {
+    let mut encoded = String::new();
+    for ((space, address), value) in batch {
+        encoded.push_str(&format!("{} {address} {value}\n", space_name(*space)));
+    }
+    encoded.push('\n');
+    encoded
+}
+
+/// Parses one change line of a batch.
+pub fn parse_change(line: &str) -> Result<((Space, u16), u16)> {
+    let invalid = || format!("invalid change `{line}`");
+    let words: Vec<&str> = line.split(' ').collect();
+    let [space, address, value] = words[..] else {
+        bail!(invalid());
+    };
+    let space = match space {
+        "coil" => Space::Coils,
+        "discrete_input" => Space::DiscreteInputs,
+        "holding_register" => Space::HoldingRegisters,
+        "input_register" => Space::InputRegisters,
+        _ => bail!(invalid()),
+    };
+    let address = address.parse().with_context(invalid)?;
+    let value: u16 = value.parse().with_context(invalid)?;
+    if matches!(space, Space::Coils | Space::DiscreteInputs) && value > 1 {
+        bail!(invalid());
+    }
+    Ok(((space, address), value))
+}
+
+fn space_name(space: Space) -> &'static str {
+    match space {
+        Space::Coils => "coil",
+        Space::DiscreteInputs => "discrete_input",
+        Space::HoldingRegisters => "holding_register",
+        Space::InputRegisters => "input_register",
+    }
+}
+
+/// Sets the items of a received batch. Coils and holding registers the
+/// standby lacks are created; other unknown items are skipped.
+pub fn apply(state: &mut SimState, batch: &[((Space, u16), u16)]) {
+    for &((space, address), value) in batch {
+        match space {
+            // Set the value directly: a client write would be filtered by
+            // debounce and would restart debounce and momentary timers.
+            Space::Coils => match state.coils.get_mut(&address) {
+                Some(item) => {
+                    item.value = value != 0;
+                    item.last_value = value != 0;
+                }
+                None => state.write_single_coil(address, value != 0),
+            },
+            Space::DiscreteInputs => state.write_discrete_inputs(address, &[value != 0]),
+            Space::HoldingRegisters => state.write_single_register(address, value),
+            Space::InputRegisters => state.write_input_registers(address, &[value]),
+        }
+    }
+}
+
+/// Runs the device's end of replication. A primary serves every standby
+/// that connects; a standby follows its primary, reconnecting whenever the
+/// connection fails or the primary stays silent for `timeout_ms`.
+pub async fn spawn_standby(
+    device: String,
+    config: StandbyConfig,
+    state: Arc<RwLock<SimState>>,
+) -> Result<()> {
+    let interval = Duration::from_millis(config.interval_ms.max(1));
+    let timeout = Duration::from_millis(config.timeout_ms.max(1));
+    match Role::new(&config)? {
+        Role::Primary(listen) => serve(device, &listen, interval, state).await,
+        Role::Standby(primary) => {
+            follow(device, &primary, interval, timeout, state).await;
+            Ok(())
+        }
+    }
+}
+
+async fn serve(
+    device: String,
+    listen: &str,
+    interval: Duration,
+    state: Arc<RwLock<SimState>>,
+) -> Result<()> {
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("standby: failed to bind {listen}"))?;
+    info!(device = %device, addr = %listen, "standby replication listening");
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!(device = %device, error = %err, "standby accept failed");
+                continue;
+            }
+        };
+        info!(device = %device, peer = %peer, "standby subscribed");
+        let device = device.clone();
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            let err = stream_changes(stream, interval, &state).await;
+            warn!(device = %device, peer = %peer, "{err:#}; standby unsubscribed");
+        });
+    }
+}
+
+/// Sends the header and then a batch every `interval` until writing fails,
+/// returning why.
+async fn stream_changes(
+    mut stream: TcpStream,
+    interval: Duration,
+    state: &RwLock<SimState>,
+) -> anyhow::Error {
+    if let Err(err) = stream.write_all(format!("{HEADER}\n").as_bytes()).await {
+        return err.into();
+    }
+    // Subscribing and taking every value under one lock misses no change.
+    let (mut feed, mut batch) = {
+        let mut state = state.write().unwrap();
+        (state.subscribe_changes(), values(&state))
+    };
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticks.tick().await;
+        let changed = drain(&mut feed);
+        {
+            let state = state.read().unwrap();
+            match changed {
+                Some(changed) => batch.extend(changed_values(&state, &changed)),
+                None => batch = values(&state),
+            }
+        }
+        let encoded = encode(&batch.into_iter().collect::<Vec<_>>());
+        if let Err(err) = stream.write_all(encoded.as_bytes()).await {
+            return err.into();
+        }
+        batch = Values::new();
+    }
+}
+
+async fn follow(
+    device: String,
+    primary: &str,
+    interval: Duration,
+    timeout: Duration,
+    state: Arc<RwLock<SimState>>,
+) {
+    let mut reported_down = false;
+    loop {
+        let lines = match subscribe(primary, timeout).await {
+            Ok(lines) => {
+                info!(device = %device, primary = %primary, "following primary");
+                reported_down = false;
+                lines
+            }
+            Err(err) => {
+                if !reported_down {
+                    warn!(device = %device, primary = %primary, "{err:#}; retrying");
+                    reported_down = true;
+                }
+                tokio::time::sleep(interval).await;
+                continue;
+            }
+        };
+        state.write().unwrap().following = true;
+        let err = receive(lines, timeout, &state).await;
+        state.write().unwrap().following = false;
+        warn!(device = %device, primary = %primary, "{err:#}; reconnecting");
+    }
+}
+
+async fn subscribe(primary: &str, timeout: Duration) -> Result<Lines<BufReader<TcpStream>>> {
+    let stream = tokio::time::timeout(timeout, TcpStream::connect(primary))
+        .await
+        .context("primary timed out")?
+        .with_context(|| format!("failed to connect to {primary}"))?;
+    let mut lines = BufReader::new(stream).lines();
+    let header = tokio::time::timeout(timeout, lines.next_line())
+        .await
+        .context("primary timed out")??;
+    if header.as_deref() != Some(HEADER) {
+        bail!("{primary} is not a modsim standby primary");
+    }
+    Ok(lines)
+}
+
+/// Applies batches, each under a single lock so clients never see half of
+/// one, until the connection fails; returns why.
+async fn receive(
+    mut lines: Lines<BufReader<TcpStream>>,
+    timeout: Duration,
+    state: &RwLock<SimState>,
+) -> anyhow::Error {
+    let mut batch = Vec::new();
+    loop {
+        let line = match tokio::time::timeout(timeout, lines.next_line()).await {
+            Err(_) => return anyhow!("primary silent for {} ms", timeout.as_millis()),
+            Ok(Err(err)) => return err.into(),
+            Ok(Ok(None)) => return anyhow!("primary closed the connection"),
+            Ok(Ok(Some(line))) => line,
+        };
+        if line.is_empty() {
+            apply(&mut state.write().unwrap(), &batch);
+            batch.clear();
+            continue;
+        }
+        match parse_change(&line) {
+            Ok(change) => batch.push(change),
+            Err(err) => return err,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DeviceConfig;
+
+    fn state(toml: &str) -> SimState {
+        let device: DeviceConfig = toml::from_str(toml).unwrap();
+        SimState::new(
+            500,
+            false,
+            device.coils,
+            device.discrete_inputs,
+            device.holding_registers,
+            device.input_registers,
+        )
+        .unwrap()
+    }
+
+    fn free_addr() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    #[test]
+    fn changes_round_trip_through_the_wire_format() {
+        let state = state(
+            "coils = [{ address = 3, initial = true }]\n\
+             input_registers = [{ address = 7, initial = 77 }]",
+        );
+        let batch: Vec<_> = values(&state).into_iter().collect();
+        let encoded = encode(&batch);
+        assert_eq!(encoded, "coil 3 1\ninput_register 7 77\n\n");
+        let parsed: Vec<_> = encoded
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| parse_change(line).unwrap())
+            .collect();
+        assert_eq!(parsed, batch);
+        let changed = BTreeSet::from([(Space::InputRegisters, 7), (Space::HoldingRegisters, 1)]);
+        assert_eq!(
+            changed_values(&state, &changed),
+            Values::from([((Space::InputRegisters, 7), 77)])
+        );
+        for line in [
+            "coil 3 2",
+            "register 1 1",
+            "holding_register 1",
+            "input_register x 1",
+        ] {
+            assert!(parse_change(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn applied_coils_skip_debounce_and_momentary_timers() {
+        let mut state = state(
+            "coils = [{ address = 3, debounce_ms = 60000, momentary = true, momentary_ms = 10 }]",
+        );
+        apply(&mut state, &[((Space::Coils, 3), 1)]);
+        apply(&mut state, &[((Space::Coils, 3), 0)]);
+        apply(&mut state, &[((Space::Coils, 3), 1)]);
+        let coil = &state.coils[&3];
+        assert!(coil.value);
+        assert_eq!((coil.last_write, coil.reset_at), (None, None));
+    }
+
+    #[test]
+    fn wide_registers_replicate_all_their_words() {
+        let mut state =
+            state("holding_registers = [{ address = 4, encoding = \"u32\", initial = 65536 }]");
+        let mut feed = state.subscribe_changes();
+        state.write_multiple_registers(4, &[2, 3]).unwrap();
+        let changed = drain(&mut feed).unwrap();
+        assert_eq!(
+            changed_values(&state, &changed),
+            Values::from([
+                ((Space::HoldingRegisters, 4), 2),
+                ((Space::HoldingRegisters, 5), 3)
+            ])
+        );
+    }
+
+    #[test]
+    fn role_needs_exactly_one_end() {
+        let config = |toml: &str| -> StandbyConfig { toml::from_str(toml).unwrap() };
+        assert_eq!(
+            Role::new(&config("listen = \"0.0.0.0:5030\"")).unwrap(),
+            Role::Primary("0.0.0.0:5030".to_string())
+        );
+        assert_eq!(
+            Role::new(&config("primary = \"10.0.0.1:5030\"")).unwrap(),
+            Role::Standby("10.0.0.1:5030".to_string())
+        );
+        assert!(Role::new(&config("")).is_err());
+        assert!(Role::new(&config("listen = \"a:1\"\nprimary = \"b:2\"")).is_err());
+    }
+
+    #[tokio::test]
+    async fn standby_follows_the_primary_once_it_comes_up() {
+        let items = "coils = [{ address = 3 }]\n\
+                     holding_registers = [{ address = 0 }, { address = 1 }]\n\
+                     input_registers = [{ address = 7 }]";
+        let addr = free_addr();
+        let standby = Arc::new(RwLock::new(state(items)));
+        let follower = tokio::spawn(spawn_standby(
+            "standby".to_string(),
+            toml::from_str(&format!(
+                "primary = \"{addr}\"\ninterval_ms = 20\ntimeout_ms = 200"
+            ))
+            .unwrap(),
+            Arc::clone(&standby),
+        ));
+        tokio::time::sleep(Duration::from_millis(60)).await;
+
+        let primary = Arc::new(RwLock::new(state(items)));
+        primary.write().unwrap().write_single_register(0, 11);
+        let server = tokio::spawn(spawn_standby(
+            "primary".to_string(),
+            toml::from_str(&format!("listen = \"{addr}\"\ninterval_ms = 20")).unwrap(),
+            Arc::clone(&primary),
+        ));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(standby.read().unwrap().holding_register_value(0), 11);
+        assert!(standby.read().unwrap().following);
+
+        {
+            let mut primary = primary.write().unwrap();
+            primary.write_single_coil(3, true);
+            primary.write_single_register(1, 12);
+            primary.write_input_registers(7, &[77]);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        server.abort();
+        follower.abort();
+
+        let standby = standby.read().unwrap();
+        assert_eq!(standby.read_coils(3, 1).unwrap(), vec![true]);
+        assert_eq!(standby.read_holding_registers(0, 2).unwrap(), vec![11, 12]);
+        assert_eq!(standby.read_input_registers(7, 1).unwrap(), vec![77]);
+    }
+}
@@ -0,0 +1,200 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use tokio_modbus::prelude::Request;
+use tracing::{info, warn};
+
+use crate::config::Encoding;
+use crate::control::Json;
+use crate::sim::SimState;
+use crate::sim::encoding::decode_item;
+
+/// A register item a write touched, decoded to its engineering value after
+/// the write.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decoded {
+    /// Address the item starts at, as the client addresses it.
+    pub address: u16,
+    pub raw: Vec<u16>,
+    pub value: f64,
+    pub unit: Option<String>,
+}
+
+/// Decodes the holding register items overlapping the `count` registers at
+/// `address` of `state`, leaving out plain `u16` items whose value is the raw
+/// word. `offset` is added back to report client addresses.
+pub fn decode_write(state: &SimState, address: u16, count: u16, offset: u16) -> Vec<Decoded> {
+    let last = address.saturating_add(count.saturating_sub(1));
+    state
+        .holding_registers
+        .range(..=last)
+        .filter(|(start, item)| {
+            u32::from(**start) + u32::from(item.encoding.width()) > u32::from(address)
+        })
+        .filter(|(_, item)| {
+            item.encoding != Encoding::U16
+                || item.scale != 1.0
+                || item.offset != 0.0
+                || item.unit.is_some()
+        })
+        .filter_map(|(start, item)| {
+            let (raw, value) = decode_item(&state.holding_registers, *start)?;
+            Some(Decoded {
+                address: start.saturating_add(offset),
+                raw,
+                value,
+                unit: item.unit.clone(),
+            })
+        })
+        .collect()
+}
+
+/// Records successful client writes, as `audit` log events and optionally as
+/// JSON lines appended to a file.
+#[derive(Debug, Clone, Default)]
+pub struct AuditLog {
+    file: Option<Arc<Mutex<File>>>,
+}
+
+impl AuditLog {
+    /// An audit log that also appends to `path`, when given.
+    pub fn open(path: Option<&str>) -> Result<Self> {
+        let file = match path {
+            Some(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .with_context(|| format!("failed to open audit file {path}"))?;
+                Some(Arc::new(Mutex::new(file)))
+            }
+            None => None,
+        };
+        Ok(Self { file })
+    }
+
+    /// Records `request` if it is a write, with the register items it
+    /// changed as `decoded`; other requests are ignored.
+    pub fn record(
+        &self,
+        peer: Option<SocketAddr>,
+        connection_id: Option<u64>,
+        unit: u8,
+        request: &Request,
+        decoded: &[Decoded],
+    ) {
+        let Some(record) = write_record(peer, connection_id, unit, request, decoded) else {
+            return;
+        };
+        info!(target: "audit", "{record}");
+        if let Some(file) = &self.file
+            && let Err(err) = writeln!(file.lock().unwrap(), "{record}")
+        {
+            warn!(error = %err, "failed to append to audit file");
+        }
+    }
+}
+
+fn write_record(
+    peer: Option<SocketAddr>,
+    connection_id: Option<u64>,
+    unit: u8,
+    request: &Request,
+    decoded: &[Decoded],
+) -> Option<Json> {
+    let (function, address, values): (u8, u16, Vec<Json>) = match request {
+        Request::WriteSingleCoil(address, value) => (0x05, *address, vec![Json::from(*value)]),
+        Request::WriteSingleRegister(address, value) => (0x06, *address, vec![Json::from(*value)]),
+        Request::WriteMultipleCoils(address, values) => (
+            0x0F,
+            *address,
+            values.iter().copied().map(Json::from).collect(),
+        ),
+        Request::WriteMultipleRegisters(address, values) => (
+            0x10,
+            *address,
+            values.iter().copied().map(Json::from).collect(),
+        ),
+        _ => return None,
+    };
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_millis() as u64);
+    Some(Json::object([
+        ("timestamp_ms", Json::from(timestamp_ms)),
+        (
+            "peer",
+            peer.map_or(Json::Null, |peer| Json::from(peer.to_string())),
+        ),
+        ("connection_id", Json::from(connection_id)),
+        ("unit", Json::from(u16::from(unit))),
+        ("function", Json::from(u16::from(function))),
+        ("address", Json::from(address)),
+        ("values", Json::Array(values)),
+        (
+            "decoded",
+            Json::Array(decoded.iter().map(decoded_json).collect()),
+        ),
+    ]))
+}
+
+fn decoded_json(decoded: &Decoded) -> Json {
+    let mut fields = vec![
+        ("address", Json::from(decoded.address)),
+        ("raw", Json::from(decoded.raw.clone())),
+        ("value", Json::from(decoded.value)),
+    ];
+    if let Some(unit) = &decoded.unit {
+        fields.push(("unit", Json::from(unit.clone())));
+    }
+    Json::object(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RegisterItemConfig;
+
+    #[test]
+    fn records_writes_only() {
+        let peer = "10.0.0.7:50200".parse().ok();
+        let request = Request::WriteMultipleCoils(8, vec![true, false]);
+        let record = write_record(peer, Some(5), 3, &request, &[])
+            .unwrap()
+            .to_string();
+        assert!(record.ends_with(
+            r#""peer":"10.0.0.7:50200","connection_id":5,"unit":3,"function":15,"address":8,"values":[true,false],"decoded":[]}"#
+        ));
+        assert!(write_record(peer, Some(5), 3, &Request::ReadCoils(0, 1), &[]).is_none());
+    }
+
+    #[test]
+    fn register_writes_are_decoded_to_engineering_units() {
+        let temperature: RegisterItemConfig =
+            toml::from_str("address = 10\nencoding = \"i16\"\nscale = 0.1\nunit = \"degC\"")
+                .unwrap();
+        let flow: RegisterItemConfig = toml::from_str("address = 11\nencoding = \"u32\"").unwrap();
+        let mut state =
+            SimState::new(500, false, vec![], vec![], vec![temperature, flow], vec![]).unwrap();
+        state.write_multiple_registers(10, &[0xFF9C, 1, 2]).unwrap();
+
+        // Writing only the low word of `flow` still decodes all of it.
+        let decoded = decode_write(&state, 12, 1, 0);
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(decoded[0].raw, vec![1, 2]);
+        assert_eq!(decoded[0].value, 65538.0);
+
+        let decoded = decode_write(&state, 10, 1, 40000);
+        let request = Request::WriteSingleRegister(40010, 0xFF9C);
+        let record = write_record(None, None, 1, &request, &decoded)
+            .unwrap()
+            .to_string();
+        assert!(record.ends_with(
+            r#""values":[65436],"decoded":[{"address":40010,"raw":[65436],"value":-10,"unit":"degC"}]}"#
+        ));
+    }
+}
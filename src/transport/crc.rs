@@ -0,0 +1,128 @@
+use crate::config::CrcAlgorithm;
+
+/// CRC-16/MODBUS generator polynomial, in normal notation.
+const MODBUS_POLYNOMIAL: u16 = 0x8005;
+
+/// A CRC-16 that RTU frames end with. tokio-modbus only frames with the
+/// standard Modbus CRC, so the RTU relay moves frames between this one and
+/// [`Crc::MODBUS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc {
+    /// Generator polynomial, bit-reversed for least significant bit first
+    /// processing.
+    reflected: u16,
+    init: u16,
+    xor_out: u16,
+    /// Whether the high byte of the CRC is sent first.
+    high_first: bool,
+}
+
+impl Crc {
+    pub const MODBUS: Crc = Crc {
+        reflected: MODBUS_POLYNOMIAL.reverse_bits(),
+        init: 0xFFFF,
+        xor_out: 0,
+        high_first: false,
+    };
+
+    /// The CRC of `algorithm`, with `polynomial` replacing its generator.
+    pub fn new(algorithm: CrcAlgorithm, polynomial: Option<u16>) -> Self {
+        let (init, xor_out, high_first) = match algorithm {
+            CrcAlgorithm::Modbus => (0xFFFF, 0, false),
+            CrcAlgorithm::ModbusSwapped => (0xFFFF, 0, true),
+            CrcAlgorithm::Arc => (0, 0, false),
+            CrcAlgorithm::Usb => (0xFFFF, 0xFFFF, false),
+            CrcAlgorithm::Maxim => (0, 0xFFFF, false),
+        };
+        Crc {
+            reflected: polynomial.unwrap_or(MODBUS_POLYNOMIAL).reverse_bits(),
+            init,
+            xor_out,
+            high_first,
+        }
+    }
+
+    pub fn is_standard(&self) -> bool {
+        *self == Crc::MODBUS
+    }
+
+    pub fn checksum(&self, data: &[u8]) -> u16 {
+        let mut crc = self.init;
+        for byte in data {
+            crc ^= u16::from(*byte);
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ self.reflected
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        crc ^ self.xor_out
+    }
+
+    /// The CRC bytes of `data` in the order they are sent.
+    fn trailer(&self, data: &[u8]) -> [u8; 2] {
+        let crc = self.checksum(data);
+        if self.high_first {
+            crc.to_be_bytes()
+        } else {
+            crc.to_le_bytes()
+        }
+    }
+
+    /// `frame` without its CRC, if the CRC is right.
+    pub fn verify<'a>(&self, frame: &'a [u8]) -> Option<&'a [u8]> {
+        let (data, crc) = frame.split_at(frame.len().checked_sub(2)?);
+        (crc == self.trailer(data)).then_some(data)
+    }
+
+    /// `data` followed by its CRC.
+    pub fn seal(&self, data: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(data.len() + 2);
+        frame.extend_from_slice(data);
+        frame.extend_from_slice(&self.trailer(data));
+        frame
+    }
+}
+
+/// `frame`, carrying a `from` CRC, with a `to` CRC instead; `None` when its
+/// CRC is wrong.
+pub fn reseal(frame: &[u8], from: &Crc, to: &Crc) -> Option<Vec<u8>> {
+    from.verify(frame).map(|data| to.seal(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn algorithms_match_their_check_values() {
+        for (algorithm, check) in [
+            (CrcAlgorithm::Modbus, 0x4B37),
+            (CrcAlgorithm::ModbusSwapped, 0x4B37),
+            (CrcAlgorithm::Arc, 0xBB3D),
+            (CrcAlgorithm::Usb, 0xB4C8),
+            (CrcAlgorithm::Maxim, 0x44C2),
+        ] {
+            let crc = Crc::new(algorithm, None);
+            assert_eq!(crc.checksum(b"123456789"), check, "{algorithm:?}");
+        }
+        assert!(Crc::new(CrcAlgorithm::Modbus, None).is_standard());
+        assert!(!Crc::new(CrcAlgorithm::Modbus, Some(0x3D65)).is_standard());
+    }
+
+    #[test]
+    fn frames_are_resealed_between_crcs() {
+        // Read holding register 1 of slave 1, as tokio-modbus frames it.
+        let standard = [0x01, 0x03, 0x00, 0x01, 0x00, 0x01, 0xD5, 0xCA];
+        assert_eq!(Crc::MODBUS.seal(&standard[..6]), standard);
+        let swapped = Crc::new(CrcAlgorithm::ModbusSwapped, None);
+        let frame = reseal(&standard, &Crc::MODBUS, &swapped).unwrap();
+        assert_eq!(frame[6..], [0xCA, 0xD5]);
+        assert_eq!(reseal(&frame, &swapped, &Crc::MODBUS).unwrap(), standard);
+
+        assert_eq!(reseal(&standard, &swapped, &Crc::MODBUS), None);
+        assert_eq!(Crc::MODBUS.verify(&[0x01]), None);
+    }
+}
@@ -0,0 +1,119 @@
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::{Level, trace};
+
+/// Logs the raw bytes read from and written to a connection at trace level,
+/// hex encoded. Below trace the bytes are only forwarded; nothing is
+/// formatted.
+#[derive(Debug)]
+pub struct FrameLog<S> {
+    inner: S,
+    peer: SocketAddr,
+}
+
+impl<S> FrameLog<S> {
+    pub fn new(inner: S, peer: SocketAddr) -> Self {
+        Self { inner, peer }
+    }
+}
+
+/// Whether raw bytes are logged, which an RTU bus only does through the
+/// relay.
+pub fn enabled() -> bool {
+    tracing::enabled!(Level::TRACE)
+}
+
+/// Logs bytes received from an RTU bus, as [`FrameLog`] does for TCP.
+pub fn rtu_received(bytes: &[u8]) {
+    trace!(bytes = %Hex(bytes), "modbus rtu frame received");
+}
+
+/// Logs bytes sent on an RTU bus, as [`FrameLog`] does for TCP.
+pub fn rtu_sent(bytes: &[u8]) {
+    trace!(bytes = %Hex(bytes), "modbus rtu frame sent");
+}
+
+/// Formats bytes as space-separated hex pairs without allocating.
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, byte) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(" ")?;
+            }
+            write!(f, "{byte:02x}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for FrameLog<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result
+            && buf.filled().len() > before
+        {
+            trace!(peer = %self.peer, bytes = %Hex(&buf.filled()[before..]), "modbus frame received");
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for FrameLog<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            trace!(peer = %self.peer, bytes = %Hex(&buf[..written]), "modbus frame sent");
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn hex_pairs_are_space_separated() {
+        assert_eq!(Hex(&[0x00, 0x01, 0xab]).to_string(), "00 01 ab");
+        assert_eq!(Hex(&[]).to_string(), "");
+    }
+
+    #[tokio::test]
+    async fn bytes_pass_through_unchanged() {
+        let (client, server) = tokio::io::duplex(64);
+        let mut server = FrameLog::new(server, "127.0.0.1:502".parse().unwrap());
+        let mut client = client;
+        client.write_all(&[0x00, 0x01, 0x02]).await.unwrap();
+        let mut buf = [0; 3];
+        server.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, [0x00, 0x01, 0x02]);
+        server.write_all(&[0x03]).await.unwrap();
+        client.read_exact(&mut buf[..1]).await.unwrap();
+        assert_eq!(buf[0], 0x03);
+    }
+}
@@ -0,0 +1,311 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+
+use super::tcp::{
+    MAX_READ_BITS, MAX_READ_REGISTERS, MAX_WRITE_BITS, MAX_WRITE_REGISTERS, check_range,
+};
+use crate::config::LineConfig;
+use crate::sim::{SimState, Space};
+
+const HELP: &str = "\
+commands:
+  GET <ref> [count]     read count (default 1) values from ref
+  SET <ref> <value>...  write coils (0xxxx) or holding registers (4xxxx) from ref
+  HELP                  show this help
+  QUIT                  close the connection
+refs: 0xxxx coils, 1xxxx discrete inputs, 3xxxx input registers, 4xxxx holding registers";
+
+/// One line-protocol command.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineCommand {
+    Get {
+        space: Space,
+        address: u16,
+        count: u16,
+    },
+    Set {
+        space: Space,
+        address: u16,
+        values: Vec<u16>,
+    },
+    Help,
+    Quit,
+}
+
+/// Parses a command line; command words are case-insensitive.
+pub fn parse(line: &str) -> Result<LineCommand> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let Some((command, args)) = words.split_first() else {
+        bail!("empty command, try HELP");
+    };
+    match (command.to_ascii_uppercase().as_str(), args) {
+        ("GET", [reference]) => {
+            let (space, address) = parse_reference(reference)?;
+            Ok(LineCommand::Get {
+                space,
+                address,
+                count: 1,
+            })
+        }
+        ("GET", [reference, count]) => {
+            let (space, address) = parse_reference(reference)?;
+            let count = count
+                .parse()
+                .with_context(|| format!("invalid count `{count}`"))?;
+            Ok(LineCommand::Get {
+                space,
+                address,
+                count,
+            })
+        }
+        ("SET", [reference, values @ ..]) if !values.is_empty() => {
+            let (space, address) = parse_reference(reference)?;
+            if !matches!(space, Space::Coils | Space::HoldingRegisters) {
+                bail!("`{reference}` is read-only");
+            }
+            let values = values
+                .iter()
+                .map(|value| {
+                    let parsed: u16 = value
+                        .parse()
+                        .with_context(|| format!("invalid value `{value}`"))?;
+                    if space == Space::Coils && parsed > 1 {
+                        bail!("invalid coil value `{value}` (0 or 1)");
+                    }
+                    Ok(parsed)
+                })
+                .collect::<Result<_>>()?;
+            Ok(LineCommand::Set {
+                space,
+                address,
+                values,
+            })
+        }
+        ("HELP", []) => Ok(LineCommand::Help),
+        ("QUIT", []) => Ok(LineCommand::Quit),
+        _ => bail!("unrecognised command, try HELP"),
+    }
+}
+
+/// Modbus reference number: a table digit followed by the 1-based address,
+/// five digits in all (`40001`) or six for addresses past 9999 (`465536`).
+fn parse_reference(word: &str) -> Result<(Space, u16)> {
+    let invalid = || format!("invalid reference `{word}`");
+    if !matches!(word.len(), 5 | 6) || !word.bytes().all(|b| b.is_ascii_digit()) {
+        bail!(invalid());
+    }
+    let space = match &word[..1] {
+        "0" => Space::Coils,
+        "1" => Space::DiscreteInputs,
+        "3" => Space::InputRegisters,
+        "4" => Space::HoldingRegisters,
+        _ => bail!(invalid()),
+    };
+    let number: u32 = word[1..].parse().with_context(invalid)?;
+    let address = number
+        .checked_sub(1)
+        .and_then(|address| u16::try_from(address).ok())
+        .with_context(invalid)?;
+    Ok((space, address))
+}
+
+/// Runs a command against `state` and returns the reply line: the values
+/// read, `OK` for writes, or `ERR` and the Modbus exception.
+pub fn execute(state: &mut SimState, command: &LineCommand, peer: Option<SocketAddr>) -> String {
+    let result = match command {
+        LineCommand::Get {
+            space,
+            address,
+            count,
+        } => {
+            let (space, address, count) = (*space, *address, *count);
+            let max = match space {
+                Space::Coils | Space::DiscreteInputs => MAX_READ_BITS,
+                Space::HoldingRegisters | Space::InputRegisters => MAX_READ_REGISTERS,
+            };
+            check_range(address, count.into(), max)
+                .and_then(|()| state.check_readable(space, address, count))
+                .and_then(|()| {
+                    state.sample_on_read(space, address, count);
+                    let values = match space {
+                        Space::Coils => state.read_coils(address, count).map(bits)?,
+                        Space::DiscreteInputs => {
+                            state.read_discrete_inputs(address, count).map(bits)?
+                        }
+                        Space::HoldingRegisters => {
+                            let mut values = state.read_holding_registers(address, count)?;
+                            state.pack_registers(address, &mut values);
+                            values
+                        }
+                        Space::InputRegisters => state.read_input_registers(address, count)?,
+                    };
+                    Ok(values
+                        .iter()
+                        .map(u16::to_string)
+                        .collect::<Vec<_>>()
+                        .join(" "))
+                })
+        }
+        LineCommand::Set {
+            space,
+            address,
+            values,
+        } => {
+            let (space, address) = (*space, *address);
+            let max = if space == Space::Coils {
+                MAX_WRITE_BITS
+            } else {
+                MAX_WRITE_REGISTERS
+            };
+            check_range(address, values.len(), max)
+                .and_then(|()| state.check_writable(space, address, values.len()))
+                .and_then(|()| state.check_unlocked(space, address, values.len()))
+                .and_then(|()| state.claim_write(space, address, values.len(), peer, None))
+                .and_then(|()| {
+                    if space == Space::Coils {
+                        let values: Vec<bool> = values.iter().map(|&value| value != 0).collect();
+                        state.write_multiple_coils(address, &values)
+                    } else {
+                        let stored = state.stored_values(address, values);
+                        state.write_multiple_registers(address, &stored)?;
+                        state.unpack_registers(address, &stored);
+                        Ok(())
+                    }
+                })
+                .map(|()| {
+                    state.update_write_locks(space, address, values.len());
+                    "OK".to_string()
+                })
+        }
+        LineCommand::Help => Ok(HELP.to_string()),
+        LineCommand::Quit => Ok(String::new()),
+    };
+    result.unwrap_or_else(|exception| format!("ERR {}", exception.name()))
+}
+
+fn bits(values: Vec<bool>) -> Vec<u16> {
+    values.into_iter().map(u16::from).collect()
+}
+
+/// Serves the line protocol on `line.bind` until the runtime shuts down.
+pub async fn start_line(
+    line: &LineConfig,
+    state: Arc<RwLock<SimState>>,
+    startup_delay: Duration,
+) -> Result<()> {
+    let addr: SocketAddr = line.bind.parse()?;
+    if !startup_delay.is_zero() {
+        tokio::time::sleep(startup_delay).await;
+    }
+    let listener = TcpListener::bind(addr).await?;
+    info!(addr = %addr, "line protocol listening");
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    tokio::spawn(serve(Arc::clone(&state), stream, peer));
+                }
+                Err(err) => warn!(error = %err, "line protocol accept failed"),
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn serve(state: Arc<RwLock<SimState>>, stream: TcpStream, peer: SocketAddr) {
+    let _client = state.read().unwrap().connect_client();
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(err) => {
+                debug!(peer = %peer, error = %err, "line protocol connection error");
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let reply = match parse(&line) {
+            Ok(LineCommand::Quit) => break,
+            Ok(command) => execute(&mut state.write().unwrap(), &command, Some(peer)),
+            Err(err) => format!("ERR {err}"),
+        };
+        if writer
+            .write_all(format!("{reply}\n").as_bytes())
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BoolItemConfig, RegisterItemConfig};
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            parse("get 40001").unwrap(),
+            LineCommand::Get {
+                space: Space::HoldingRegisters,
+                address: 0,
+                count: 1,
+            }
+        );
+        assert_eq!(
+            parse("GET 310010 3").unwrap(),
+            LineCommand::Get {
+                space: Space::InputRegisters,
+                address: 10009,
+                count: 3,
+            }
+        );
+        assert_eq!(
+            parse("SET 00002 1 0").unwrap(),
+            LineCommand::Set {
+                space: Space::Coils,
+                address: 1,
+                values: vec![1, 0],
+            }
+        );
+        assert!(parse("SET 30001 5").is_err());
+        assert!(parse("SET 00001 2").is_err());
+        assert!(parse("GET 40000").is_err());
+        assert!(parse("GET 20001").is_err());
+        assert!(parse("FETCH 40001").is_err());
+    }
+
+    #[test]
+    fn executes_against_the_state() {
+        let coil = BoolItemConfig {
+            address: 0,
+            ..Default::default()
+        };
+        let setpoint = RegisterItemConfig {
+            address: 0,
+            initial: 20.into(),
+            ..Default::default()
+        };
+        let mut state =
+            SimState::new(500, false, vec![coil], vec![], vec![setpoint], vec![]).unwrap();
+        let mut run = |line: &str| execute(&mut state, &parse(line).unwrap(), None);
+        assert_eq!(run("GET 40001 2"), "20 0");
+        assert_eq!(run("SET 40002 7 8"), "OK");
+        assert_eq!(run("GET 40001 3"), "20 7 8");
+        assert_eq!(run("SET 00001 1"), "OK");
+        assert_eq!(run("GET 00001"), "1");
+        assert_eq!(run("GET 465536 2"), "ERR illegal_data_address");
+    }
+}
@@ -0,0 +1,367 @@
+//! MBAP-level shim for Modbus/TCP connections.
+//!
+//! The tokio-modbus server decodes the MBAP header itself and only hands the
+//! PDU to the service. [`Mbap`] sits between the socket and its codec, sees
+//! each frame whole, header included, and may rewrite or drop it first.
+//! Features that act on the MBAP header, or on PDU fields the codec
+//! discards, belong here.
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::{debug, info};
+
+use crate::config::{ByteCountPolicy, CoilValuePolicy, ProtocolIdPolicy};
+use crate::fault::Truncation;
+
+/// Transaction id, protocol id and length; the length counts the bytes
+/// after it, unit id included.
+const HEADER_LEN: usize = 6;
+
+/// Applies the protocol id, byte count and coil value policies to the frames
+/// read from a connection, and the truncation fault to the frames written to
+/// it.
+#[derive(Debug)]
+pub struct Mbap<S> {
+    inner: S,
+    peer: SocketAddr,
+    protocol_id: ProtocolIdPolicy,
+    byte_count: ByteCountPolicy,
+    coil_value: CoilValuePolicy,
+    /// Bytes read but not yet a whole frame.
+    pending: Vec<u8>,
+    /// Frames passed, not yet read by the codec, and how far it got.
+    ready: Vec<u8>,
+    position: usize,
+    truncation: Option<Truncation>,
+    /// Bytes of the frame being written not yet passed on.
+    frame_left: usize,
+    /// What is left to send of a truncated frame, and the frame's length.
+    cut: Option<(Vec<u8>, usize)>,
+    /// Set once a truncated frame is sent; the connection then ends.
+    truncated: bool,
+}
+
+impl<S> Mbap<S> {
+    pub fn new(inner: S, peer: SocketAddr, protocol_id: ProtocolIdPolicy) -> Self {
+        Self {
+            inner,
+            peer,
+            protocol_id,
+            byte_count: ByteCountPolicy::default(),
+            coil_value: CoilValuePolicy::default(),
+            pending: Vec::new(),
+            ready: Vec::new(),
+            position: 0,
+            truncation: None,
+            frame_left: 0,
+            cut: None,
+            truncated: false,
+        }
+    }
+
+    pub fn with_byte_count(mut self, byte_count: ByteCountPolicy) -> Self {
+        self.byte_count = byte_count;
+        self
+    }
+
+    pub fn with_coil_value(mut self, coil_value: CoilValuePolicy) -> Self {
+        self.coil_value = coil_value;
+        self
+    }
+
+    /// Cuts off written frames as `truncation` draws, then ends the
+    /// connection as if the device had dropped it mid-response.
+    pub fn with_truncation(mut self, truncation: Option<Truncation>) -> Self {
+        self.truncation = truncation;
+        self
+    }
+
+    /// Moves the whole frames of `pending` to `ready`, applying the policy.
+    fn frame(&mut self) {
+        while self.pending.len() >= HEADER_LEN {
+            let length = u16::from_be_bytes([self.pending[4], self.pending[5]]);
+            let end = HEADER_LEN + usize::from(length);
+            if self.pending.len() < end {
+                break;
+            }
+            let mut frame: Vec<u8> = self.pending.drain(..end).collect();
+            let protocol_id = u16::from_be_bytes([frame[2], frame[3]]);
+            if protocol_id != 0 {
+                match self.protocol_id {
+                    ProtocolIdPolicy::Lenient => {
+                        debug!(peer = %self.peer, protocol_id, "modbus tcp frame with nonzero protocol id accepted");
+                        frame[2..4].fill(0);
+                    }
+                    ProtocolIdPolicy::Strict => {
+                        debug!(peer = %self.peer, protocol_id, "modbus tcp frame with nonzero protocol id dropped");
+                        continue;
+                    }
+                }
+            }
+            if self.byte_count == ByteCountPolicy::Strict
+                && byte_count_mismatch(&frame[HEADER_LEN + 1..])
+            {
+                debug!(peer = %self.peer, "modbus tcp write with a mismatched byte count refused");
+                // tokio-modbus drops the byte count while decoding, so the
+                // write is turned into one of zero quantity at the same
+                // address, which the service answers IllegalDataValue.
+                frame.truncate(HEADER_LEN + 7);
+                frame[HEADER_LEN + 4..].fill(0);
+                frame[4..6].copy_from_slice(&7u16.to_be_bytes());
+            }
+            if let Some(value) = non_canonical_coil(&frame[HEADER_LEN + 1..]) {
+                match self.coil_value {
+                    CoilValuePolicy::Close => {}
+                    CoilValuePolicy::Strict => {
+                        debug!(peer = %self.peer, value, "modbus tcp write single coil with a non-canonical value refused");
+                        // tokio-modbus would refuse to decode the value, so
+                        // the exception bit marks the request as refused; no
+                        // client sends such a function code, and the service
+                        // answers it IllegalDataValue.
+                        frame[HEADER_LEN + 1] |= 0x80;
+                    }
+                    CoilValuePolicy::Lenient => {
+                        debug!(peer = %self.peer, value, "modbus tcp write single coil with a non-canonical value written as on");
+                        frame[HEADER_LEN + 4..].copy_from_slice(&[0xFF, 0x00]);
+                    }
+                }
+            }
+            self.ready.extend_from_slice(&frame);
+        }
+    }
+}
+
+/// Whether `pdu` is a Write Multiple Coils or Registers request whose byte
+/// count is not the size of its quantity.
+fn byte_count_mismatch(pdu: &[u8]) -> bool {
+    let &[function, _, _, high, low, byte_count, ..] = pdu else {
+        return false;
+    };
+    let quantity = usize::from(u16::from_be_bytes([high, low]));
+    let expected = match function {
+        0x0F => quantity.div_ceil(8),
+        0x10 => 2 * quantity,
+        _ => return false,
+    };
+    usize::from(byte_count) != expected
+}
+
+/// The value of a Write Single Coil request `pdu` when it is neither 0xFF00
+/// nor 0x0000.
+fn non_canonical_coil(pdu: &[u8]) -> Option<u16> {
+    let &[0x05, _, _, high, low] = pdu else {
+        return None;
+    };
+    let value = u16::from_be_bytes([high, low]);
+    (value != 0xFF00 && value != 0x0000).then_some(value)
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Mbap<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if this.truncated {
+            // End of stream, so the server closes the connection.
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            if this.position < this.ready.len() {
+                let count = buf.remaining().min(this.ready.len() - this.position);
+                buf.put_slice(&this.ready[this.position..this.position + count]);
+                this.position += count;
+                if this.position == this.ready.len() {
+                    this.ready.clear();
+                    this.position = 0;
+                }
+                return Poll::Ready(Ok(()));
+            }
+            let mut chunk = [0; 512];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            if chunk.filled().is_empty() {
+                // End of stream: hand a trailing partial frame to the codec,
+                // which rejects it as it would without the shim.
+                if this.pending.is_empty() {
+                    return Poll::Ready(Ok(()));
+                }
+                this.ready = std::mem::take(&mut this.pending);
+                continue;
+            }
+            this.pending.extend_from_slice(chunk.filled());
+            this.frame();
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Mbap<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let Some(truncation) = &this.truncation else {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        };
+        if this.truncated {
+            return Poll::Ready(Ok(buf.len()));
+        }
+        if this.frame_left == 0 && this.cut.is_none() && buf.len() >= HEADER_LEN {
+            let length = u16::from_be_bytes([buf[4], buf[5]]);
+            let len = (HEADER_LEN + usize::from(length)).min(buf.len());
+            let function = buf.get(HEADER_LEN + 1).copied().unwrap_or_default();
+            let cut = truncation.applies_to(function).then(|| truncation.cut(len));
+            match cut.flatten() {
+                Some(kept) => {
+                    info!(peer = %this.peer, len, kept, "modbus tcp response truncated");
+                    this.cut = Some((buf[..kept].to_vec(), len));
+                }
+                None => this.frame_left = len,
+            }
+        }
+        if let Some((rest, len)) = &mut this.cut {
+            while !rest.is_empty() {
+                let written = ready!(Pin::new(&mut this.inner).poll_write(cx, rest))?;
+                rest.drain(..written);
+            }
+            let len = *len;
+            this.cut = None;
+            this.truncated = true;
+            return Poll::Ready(Ok(len));
+        }
+        // Frames are passed on one at a time, so the next write starts one.
+        let end = if this.frame_left == 0 {
+            buf.len()
+        } else {
+            this.frame_left.min(buf.len())
+        };
+        let written = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..end]))?;
+        this.frame_left = this.frame_left.saturating_sub(written);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    /// Read holding register 0 with transaction `id` and `protocol_id`.
+    fn request(id: u8, protocol_id: u8) -> [u8; 12] {
+        [0, id, 0, protocol_id, 0, 6, 1, 0x03, 0, 0, 0, 1]
+    }
+
+    async fn filtered(protocol_id: ProtocolIdPolicy, input: &[u8]) -> Vec<u8> {
+        let mut mbap = Mbap::new(input, "127.0.0.1:502".parse().unwrap(), protocol_id);
+        let mut out = Vec::new();
+        mbap.read_to_end(&mut out).await.unwrap();
+        out
+    }
+
+    #[tokio::test]
+    async fn nonzero_protocol_ids_are_rewritten_or_dropped() {
+        let input = [request(1, 0), request(2, 7), request(3, 0)].concat();
+        let lenient = filtered(ProtocolIdPolicy::Lenient, &input).await;
+        assert_eq!(
+            lenient,
+            [request(1, 0), request(2, 0), request(3, 0)].concat()
+        );
+        let strict = filtered(ProtocolIdPolicy::Strict, &input).await;
+        assert_eq!(strict, [request(1, 0), request(3, 0)].concat());
+    }
+
+    #[tokio::test]
+    async fn mismatched_byte_counts_become_zero_quantity_writes() {
+        // FC16 of two registers from 4 with a byte count of 6, and a
+        // well-formed FC15 of ten coils.
+        let mismatched = [0, 1, 0, 0, 0, 13, 1, 0x10, 0, 4, 0, 2, 6, 0, 1, 0, 2, 0, 3];
+        let coils = [0, 2, 0, 0, 0, 9, 1, 0x0F, 0, 0, 0, 10, 2, 0xFF, 0x03];
+        let input = [&mismatched[..], &coils].concat();
+        let mut strict = Mbap::new(
+            &input[..],
+            "127.0.0.1:502".parse().unwrap(),
+            ProtocolIdPolicy::Strict,
+        );
+        let mut out = Vec::new();
+        strict.read_to_end(&mut out).await.unwrap();
+        let refused = [0, 1, 0, 0, 0, 7, 1, 0x10, 0, 4, 0, 0, 0];
+        assert_eq!(out, [&refused[..], &coils].concat());
+
+        let mut lenient = Mbap::new(
+            &input[..],
+            "127.0.0.1:502".parse().unwrap(),
+            ProtocolIdPolicy::Strict,
+        )
+        .with_byte_count(ByteCountPolicy::Lenient);
+        let mut out = Vec::new();
+        lenient.read_to_end(&mut out).await.unwrap();
+        assert_eq!(out, input);
+    }
+
+    #[tokio::test]
+    async fn non_canonical_coil_values_are_marked_or_written_as_on() {
+        let coil = |value: [u8; 2]| [0, 1, 0, 0, 0, 6, 1, 0x05, 0, 3, value[0], value[1]];
+        let input = [coil([0x12, 0x34]), coil([0xFF, 0x00])].concat();
+        let bytes = &input[..];
+        let policy = |coil_value| async move {
+            let mut mbap = Mbap::new(
+                bytes,
+                "127.0.0.1:502".parse().unwrap(),
+                ProtocolIdPolicy::Strict,
+            )
+            .with_coil_value(coil_value);
+            let mut out = Vec::new();
+            mbap.read_to_end(&mut out).await.unwrap();
+            out
+        };
+        assert_eq!(policy(CoilValuePolicy::Close).await, input);
+        let mut refused = coil([0x12, 0x34]);
+        refused[7] = 0x85;
+        assert_eq!(
+            policy(CoilValuePolicy::Strict).await,
+            [refused, coil([0xFF, 0x00])].concat()
+        );
+        assert_eq!(
+            policy(CoilValuePolicy::Lenient).await,
+            [coil([0xFF, 0x00]), coil([0xFF, 0x00])].concat()
+        );
+    }
+
+    #[tokio::test]
+    async fn frames_split_across_reads_are_reassembled() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut mbap = Mbap::new(
+            server,
+            "127.0.0.1:502".parse().unwrap(),
+            ProtocolIdPolicy::Strict,
+        );
+        let frame = request(1, 0);
+        tokio::io::AsyncWriteExt::write_all(&mut client, &frame[..4])
+            .await
+            .unwrap();
+        let read = tokio::spawn(async move {
+            let mut buf = [0; 12];
+            mbap.read_exact(&mut buf).await.unwrap();
+            buf
+        });
+        tokio::io::AsyncWriteExt::write_all(&mut client, &frame[4..])
+            .await
+            .unwrap();
+        assert_eq!(read.await.unwrap(), frame);
+    }
+}
@@ -1,2 +1,10 @@
+pub mod audit;
+pub mod crc;
+pub mod frames;
+pub mod line;
+pub mod mbap;
+pub mod pdu;
+pub mod peers;
 pub mod rtu;
 pub mod tcp;
+pub mod transactions;
@@ -0,0 +1,137 @@
+use std::io::{self, ErrorKind};
+
+use tokio_modbus::prelude::Request;
+
+/// Decodes a request PDU (function code followed by its data) into the
+/// [`Request`] the transports hand to `ModbusService`.
+///
+/// Mirrors the tokio-modbus server codec, except that a FC0F quantity larger
+/// than the packed coil bytes is rejected instead of indexing past them.
+pub fn decode_request(pdu: &[u8]) -> io::Result<Request> {
+    let (&function, data) = pdu.split_first().ok_or_else(|| invalid("empty PDU"))?;
+    let mut reader = PduReader { data };
+    let request = match function {
+        0x01 => Request::ReadCoils(reader.u16()?, reader.u16()?),
+        0x02 => Request::ReadDiscreteInputs(reader.u16()?, reader.u16()?),
+        0x03 => Request::ReadHoldingRegisters(reader.u16()?, reader.u16()?),
+        0x04 => Request::ReadInputRegisters(reader.u16()?, reader.u16()?),
+        0x05 => Request::WriteSingleCoil(reader.u16()?, coil(reader.u16()?)?),
+        0x06 => Request::WriteSingleRegister(reader.u16()?, reader.u16()?),
+        0x0F => {
+            let address = reader.u16()?;
+            let quantity = reader.u16()?;
+            let bytes = reader.counted_bytes()?;
+            if usize::from(quantity) > bytes.len() * 8 {
+                return Err(invalid("coil quantity exceeds byte count"));
+            }
+            let coils = (0..usize::from(quantity))
+                .map(|i| (bytes[i / 8] >> (i % 8)) & 1 == 1)
+                .collect();
+            Request::WriteMultipleCoils(address, coils)
+        }
+        0x10 => {
+            let address = reader.u16()?;
+            let quantity = reader.u16()?;
+            let mut words = PduReader {
+                data: reader.counted_bytes()?,
+            };
+            let values = (0..quantity)
+                .map(|_| words.u16())
+                .collect::<io::Result<_>>()?;
+            Request::WriteMultipleRegisters(address, values)
+        }
+        0x16 => Request::MaskWriteRegister(reader.u16()?, reader.u16()?, reader.u16()?),
+        0x17 => {
+            let read_address = reader.u16()?;
+            let read_quantity = reader.u16()?;
+            let write_address = reader.u16()?;
+            let write_quantity = reader.u16()?;
+            let mut words = PduReader {
+                data: reader.counted_bytes()?,
+            };
+            let values = (0..write_quantity)
+                .map(|_| words.u16())
+                .collect::<io::Result<_>>()?;
+            Request::ReadWriteMultipleRegisters(read_address, read_quantity, write_address, values)
+        }
+        code if code < 0x80 => Request::Custom(code, data.to_vec()),
+        code => return Err(invalid(&format!("invalid function code 0x{code:02X}"))),
+    };
+    Ok(request)
+}
+
+struct PduReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> PduReader<'a> {
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < len {
+            return Err(io::Error::from(ErrorKind::UnexpectedEof));
+        }
+        let (head, tail) = self.data.split_at(len);
+        self.data = tail;
+        Ok(head)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// Reads a byte count followed by that many bytes.
+    fn counted_bytes(&mut self) -> io::Result<&'a [u8]> {
+        let count = self.take(1)?[0];
+        self.take(usize::from(count))
+            .map_err(|_| invalid("invalid byte count"))
+    }
+}
+
+fn coil(value: u16) -> io::Result<bool> {
+    match value {
+        0xFF00 => Ok(true),
+        0x0000 => Ok(false),
+        _ => Err(invalid(&format!("invalid coil value 0x{value:04X}"))),
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_read_and_write_requests() {
+        assert_eq!(
+            decode_request(&[0x03, 0x00, 0x0A, 0x00, 0x02]).unwrap(),
+            Request::ReadHoldingRegisters(10, 2)
+        );
+        assert_eq!(
+            decode_request(&[0x0F, 0x00, 0x00, 0x00, 0x03, 0x01, 0b101]).unwrap(),
+            Request::WriteMultipleCoils(0, vec![true, false, true])
+        );
+        assert_eq!(
+            decode_request(&[0x2B, 0x0E, 0x01]).unwrap(),
+            Request::Custom(0x2B, vec![0x0E, 0x01])
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_pdus() {
+        assert!(decode_request(&[]).is_err());
+        assert!(decode_request(&[0x03, 0x00]).is_err());
+        assert!(decode_request(&[0x05, 0x00, 0x00, 0x12, 0x34]).is_err());
+        assert!(decode_request(&[0x10, 0x00, 0x00, 0x00, 0x02, 0x04, 0x00]).is_err());
+        assert!(decode_request(&[0x83, 0x00]).is_err());
+    }
+
+    /// Regression: the upstream codec indexes past the coil bytes when the
+    /// quantity is larger than they can hold.
+    #[test]
+    fn coil_quantity_beyond_byte_count_is_rejected() {
+        assert!(decode_request(&[0x0F, 0x00, 0x00, 0x00, 0x10, 0x01, 0xFF]).is_err());
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Caps the connections open at once from each client IP.
+#[derive(Debug, Clone)]
+pub struct PeerLimit {
+    max: usize,
+    active: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl PeerLimit {
+    pub fn new(max: usize) -> Self {
+        Self {
+            max,
+            active: Arc::default(),
+        }
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Counts a new connection from `ip`, or returns `None` when it already
+    /// has `max` open. The connection counts until the slot is dropped.
+    pub fn acquire(&self, ip: IpAddr) -> Option<PeerSlot> {
+        let mut active = self.active.lock().unwrap();
+        let count = active.entry(ip).or_default();
+        if *count >= self.max {
+            return None;
+        }
+        *count += 1;
+        Some(PeerSlot {
+            ip,
+            active: Arc::clone(&self.active),
+        })
+    }
+}
+
+/// One open connection counted against its IP.
+#[derive(Debug)]
+pub struct PeerSlot {
+    ip: IpAddr,
+    active: Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+impl Drop for PeerSlot {
+    fn drop(&mut self) {
+        let mut active = self.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::PeerLimit;
+
+    #[test]
+    fn slots_are_counted_per_ip_until_dropped() {
+        let limit = PeerLimit::new(1);
+        let first = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let second = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let slot = limit.acquire(first).unwrap();
+        assert!(limit.acquire(first).is_none());
+        assert!(limit.acquire(second).is_some());
+        drop(slot);
+        assert!(limit.acquire(first).is_some());
+    }
+}
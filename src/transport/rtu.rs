@@ -1,28 +1,308 @@
-use std::sync::Arc;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_modbus::prelude::{Response, SlaveRequest};
+use tokio_modbus::server::Service;
 use tokio_modbus::server::rtu::Server;
-use tokio_serial::{DataBits, Parity, SerialPortBuilderExt, StopBits};
-use tracing::info;
+use tokio_serial::{DataBits, Parity, SerialPort, SerialPortBuilderExt, SerialStream, StopBits};
+use tracing::{debug, info};
 
 use crate::config::{Parity as ConfigParity, RtuConfig};
-use crate::sim::SimState;
-use crate::transport::tcp::ModbusService;
-
-pub async fn start_rtu(config: &RtuConfig, state: Arc<std::sync::RwLock<SimState>>) -> Result<()> {
-    let service = ModbusService::new(state);
-    // Only serial mode is supported now.
-    let device = config
-        .device
-        .as_ref()
-        .context("rtu.device is required for serial mode")?;
-    info!(device = %device, "modbus rtu serial listening");
-    let serial = build_serial(device, config)?;
+use crate::fault::{Collisions, Truncation};
+use crate::power::PowerSwitch;
+use crate::transport::crc::{Crc, reseal};
+use crate::transport::frames;
+use crate::transport::tcp::{ModbusService, RTU_ADU_OVERHEAD};
+
+pub async fn start_rtu(
+    config: &RtuConfig,
+    service: ModbusService,
+    power: PowerSwitch,
+    faults: BusFaults,
+    startup_delay: Duration,
+) -> Result<()> {
+    let device = match &config.device {
+        Some(device) => device.as_str(),
+        None if config.pty => "pty",
+        None => anyhow::bail!("rtu.device is required for serial mode, unless rtu.pty is set"),
+    };
+    if !startup_delay.is_zero() {
+        info!(device = %device, delay_ms = startup_delay.as_millis() as u64, "modbus rtu waiting for startup delay");
+        tokio::time::sleep(startup_delay).await;
+        info!(device = %device, "modbus rtu startup delay elapsed");
+    }
+    // The master's end of a pty is held open for as long as it is served.
+    let (serial, _pty_end) = if config.pty {
+        let (serial, pty_end, path) = open_pty()?;
+        info!(pty = %path, "modbus rtu listening on a pseudo-terminal");
+        (serial, Some(pty_end))
+    } else {
+        info!(device = %device, "modbus rtu serial listening");
+        (build_serial(device, config)?, None)
+    };
+    let service = service.with_adu_overhead(RTU_ADU_OVERHEAD);
+    let service = PoweredService { service, power };
+    let framing = Framing::new(config);
+    // The server owns the stream it serves, so only the relay sees the
+    // bytes to log.
+    if faults.collisions.is_none()
+        && faults.truncation.is_none()
+        && framing.crc.is_standard()
+        && framing.byte_time.is_none()
+        && !(cfg!(unix) && frames::enabled())
+    {
+        return serve_serial(serial, service).await;
+    }
+    serve_relayed(serial, service, faults, framing).await
+}
+
+/// How frames on the bus are delimited and checked, when the relay has to
+/// rewrite them.
+#[derive(Debug, Clone, Copy)]
+struct Framing {
+    crc: Crc,
+    /// Silence that ends a request frame: 3.5 character times, and at
+    /// least 1.75ms as the spec fixes above 19200 baud.
+    gap: Duration,
+    /// Time a character takes on the wire, when received bytes are paced
+    /// to it.
+    byte_time: Option<Duration>,
+}
+
+impl Framing {
+    const STANDARD: Framing = Framing {
+        crc: Crc::MODBUS,
+        gap: Duration::from_micros(1750),
+        byte_time: None,
+    };
+
+    fn new(config: &RtuConfig) -> Self {
+        // A character is a start bit, the data bits, the parity bit if any
+        // and the stop bits.
+        let parity_bits = u32::from(!matches!(config.parity, ConfigParity::None));
+        let char_bits = 1 + u32::from(config.data_bits) + parity_bits + u32::from(config.stop_bits);
+        let char_time = f64::from(char_bits) / f64::from(config.baud_rate.max(1));
+        Framing {
+            crc: Crc::new(config.crc, config.crc_polynomial),
+            gap: Duration::from_secs_f64(3.5 * char_time).max(Framing::STANDARD.gap),
+            byte_time: config
+                .pace_receive
+                .then(|| Duration::from_secs_f64(char_time)),
+        }
+    }
+}
+
+/// Holds received bytes back until they could have come in over the wire,
+/// one character time each, counted from when they arrived or the previous
+/// ones were through.
+struct Pacer {
+    byte_time: Duration,
+    ready_at: tokio::time::Instant,
+}
+
+impl Pacer {
+    fn new(byte_time: Duration) -> Self {
+        Self {
+            byte_time,
+            ready_at: tokio::time::Instant::now(),
+        }
+    }
+
+    async fn pace(&mut self, len: usize) {
+        let start = self.ready_at.max(tokio::time::Instant::now());
+        self.ready_at = start + self.byte_time * len as u32;
+        tokio::time::sleep_until(self.ready_at).await;
+    }
+}
+
+/// Faults applied to the response frames sent on an RTU bus.
+#[derive(Debug, Clone, Default)]
+pub struct BusFaults {
+    pub collisions: Option<Collisions>,
+    pub truncation: Option<Truncation>,
+}
+
+impl BusFaults {
+    /// The bytes of `frame` that reach the bus, logging any fault.
+    fn apply(&self, frame: &[u8]) -> Vec<u8> {
+        let len = frame.len();
+        let function = frame.get(1).copied().unwrap_or_default();
+        if let Some(kept) = self
+            .truncation
+            .as_ref()
+            .filter(|truncation| truncation.applies_to(function))
+            .and_then(|truncation| truncation.cut(len))
+        {
+            info!(len, kept, "modbus rtu response truncated");
+            return frame[..kept].to_vec();
+        }
+        if let Some((collision, garbled)) = self
+            .collisions
+            .as_ref()
+            .filter(|collisions| collisions.applies_to(function))
+            .and_then(|collisions| collisions.garble(frame))
+        {
+            info!(
+                collision = collision.as_str(),
+                len,
+                sent = garbled.len(),
+                "modbus rtu response collided"
+            );
+            return garbled;
+        }
+        frame.to_vec()
+    }
+}
+
+/// Serves Modbus RTU on an already opened serial stream until it closes.
+async fn serve_serial(serial: SerialStream, service: PoweredService) -> Result<()> {
     Server::new(serial).serve_forever(service).await?;
     Ok(())
 }
 
-fn build_serial(device: &str, config: &RtuConfig) -> Result<tokio_serial::SerialStream> {
+/// Serves Modbus RTU behind a relay that applies `faults` to responses,
+/// moves frames between the bus CRC of `framing` and the standard one,
+/// paces received bytes to its byte time and logs the bytes on the bus at
+/// trace level. The tokio-modbus server only
+/// writes whole frames to a serial stream, so it runs on one end of an
+/// internal pseudo-terminal pair and the relay copies requests in and
+/// responses out, one frame per read. Requests with a non-standard CRC are
+/// collected up to the silence ending them first; those whose CRC is wrong
+/// are dropped, like the server drops them.
+#[cfg(unix)]
+async fn serve_relayed(
+    bus: SerialStream,
+    service: PoweredService,
+    faults: BusFaults,
+    framing: Framing,
+) -> Result<()> {
+    let (relay, server_end) = SerialStream::pair().context("failed to open the fault relay")?;
+    let (mut bus_rx, mut bus_tx) = tokio::io::split(bus);
+    let (mut relay_rx, mut relay_tx) = tokio::io::split(relay);
+    let requests = async {
+        let mut pacer = framing.byte_time.map(Pacer::new);
+        let mut chunk = [0; 256];
+        loop {
+            let len = bus_rx.read(&mut chunk).await?;
+            if len == 0 {
+                return Ok::<_, io::Error>(());
+            }
+            frames::rtu_received(&chunk[..len]);
+            if let Some(pacer) = &mut pacer {
+                pacer.pace(len).await;
+            }
+            if framing.crc.is_standard() {
+                relay_tx.write_all(&chunk[..len]).await?;
+                continue;
+            }
+            let mut frame = chunk[..len].to_vec();
+            while let Ok(read) = tokio::time::timeout(framing.gap, bus_rx.read(&mut chunk)).await {
+                let len = read?;
+                if len == 0 {
+                    break;
+                }
+                frames::rtu_received(&chunk[..len]);
+                if let Some(pacer) = &mut pacer {
+                    pacer.pace(len).await;
+                }
+                frame.extend_from_slice(&chunk[..len]);
+            }
+            match reseal(&frame, &framing.crc, &Crc::MODBUS) {
+                Some(frame) => relay_tx.write_all(&frame).await?,
+                None => debug!(
+                    len = frame.len(),
+                    "modbus rtu request with a bad crc dropped"
+                ),
+            }
+        }
+    };
+    let responses = async {
+        let mut frame = [0; 256];
+        loop {
+            let len = relay_rx.read(&mut frame).await?;
+            if len == 0 {
+                return Ok::<_, io::Error>(());
+            }
+            let frame = &frame[..len];
+            let resealed = reseal(frame, &Crc::MODBUS, &framing.crc);
+            let sent = faults.apply(resealed.as_deref().unwrap_or(frame));
+            frames::rtu_sent(&sent);
+            bus_tx.write_all(&sent).await?;
+        }
+    };
+    tokio::select! {
+        result = serve_serial(server_end, service) => result,
+        result = requests => result.context("fault relay failed"),
+        result = responses => result.context("fault relay failed"),
+    }
+}
+
+#[cfg(not(unix))]
+async fn serve_relayed(
+    _bus: SerialStream,
+    _service: PoweredService,
+    _faults: BusFaults,
+    _framing: Framing,
+) -> Result<()> {
+    anyhow::bail!(
+        "RTU response faults, CRC variants and receive pacing are not supported on this platform"
+    )
+}
+
+/// Answers like `service` while the power is on and not at all while it is
+/// off, like a bus device without power.
+#[derive(Clone)]
+struct PoweredService {
+    service: ModbusService,
+    power: PowerSwitch,
+}
+
+impl From<ModbusService> for PoweredService {
+    fn from(service: ModbusService) -> Self {
+        Self {
+            service,
+            power: PowerSwitch::default(),
+        }
+    }
+}
+
+impl Service for PoweredService {
+    type Request = SlaveRequest;
+    type Response = Option<Response>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Option<Response>, io::Error>> + Send + Sync>>;
+
+    fn call(&self, req: SlaveRequest) -> Self::Future {
+        if !self.power.is_on() {
+            return Box::pin(std::future::ready(Ok(None)));
+        }
+        let response = self.service.call(req);
+        Box::pin(async move { response.await.map(Some) })
+    }
+}
+
+/// Opens a pseudo-terminal pair: the stream to serve on, the end masters
+/// open and its path. That end stays open here as well, because reads on
+/// the served side fail while no process has it open.
+#[cfg(unix)]
+fn open_pty() -> Result<(SerialStream, SerialStream, String)> {
+    let (serial, pty_end) = SerialStream::pair().context("failed to open a pseudo-terminal")?;
+    let path = pty_end.name().context("the pseudo-terminal has no path")?;
+    Ok((serial, pty_end, path))
+}
+
+#[cfg(not(unix))]
+fn open_pty() -> Result<(SerialStream, SerialStream, String)> {
+    anyhow::bail!("rtu.pty is not supported on this platform")
+}
+
+/// Out-of-range `data_bits`/`stop_bits` are rejected when the config is
+/// loaded; the fallbacks here only cover configs built in code.
+fn build_serial(device: &str, config: &RtuConfig) -> Result<SerialStream> {
     let mut builder = tokio_serial::new(device, config.baud_rate);
     builder = builder.data_bits(match config.data_bits {
         5 => DataBits::Five,
@@ -47,18 +327,285 @@ fn build_serial(device: &str, config: &RtuConfig) -> Result<tokio_serial::Serial
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{Parity as ConfigParity, RtuConfig};
+    use crate::config::{
+        CollisionConfig, CrcAlgorithm, Parity as ConfigParity, RegisterItemConfig, RtuConfig,
+        TruncationConfig,
+    };
+    use crate::harness::TestHarness;
+    use crate::sim::{Exception, SimState};
+    use std::sync::{Arc, RwLock};
+    use tokio_modbus::client::rtu as rtu_client;
+    use tokio_modbus::prelude::{Client, Reader, Request, Slave, Writer};
 
     #[test]
     fn build_serial_returns_error_for_nonexistent_device() {
         let cfg = RtuConfig {
             device: Some("/dev/doesnotexist".to_string()),
+            pty: false,
             baud_rate: 9600,
             data_bits: 8,
             parity: ConfigParity::None,
             stop_bits: 1,
+            crc: CrcAlgorithm::Modbus,
+            crc_polynomial: None,
+            pace_receive: false,
         };
         let res = build_serial("/dev/doesnotexist", &cfg);
         assert!(res.is_err());
     }
+
+    #[tokio::test]
+    async fn pty_is_served_at_its_path() {
+        let register = RegisterItemConfig {
+            address: 5,
+            initial: 42.into(),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![register], vec![]).unwrap();
+        let service = ModbusService::new(Arc::new(RwLock::new(state)));
+        let (serial, pty_end, path) = open_pty().unwrap();
+        tokio::spawn(async move {
+            let _pty_end = pty_end;
+            serve_serial(serial, service.into()).await
+        });
+        let config: RtuConfig = toml::from_str("pty = true").unwrap();
+        let master = build_serial(&path, &config).unwrap();
+        let mut rtu = rtu_client::attach_slave(master, Slave(1));
+        let read = rtu.read_holding_registers(5, 1);
+        let values = tokio::time::timeout(Duration::from_secs(1), read).await;
+        assert_eq!(values.unwrap().unwrap(), [42]);
+    }
+
+    /// Reads answered over RTU and TCP by the same `ModbusService`.
+    async fn read_outcomes(ctx: &mut tokio_modbus::client::Context) -> Vec<String> {
+        vec![
+            // configured register next to unconfigured ones: zero-filled
+            format!("{:?}", ctx.read_holding_registers(4, 3).await),
+            format!("{:?}", ctx.read_input_registers(100, 2).await),
+            format!("{:?}", ctx.read_coils(7, 3).await),
+            // past the end of the address space: IllegalDataAddress
+            format!("{:?}", ctx.read_holding_registers(0xFFFF, 2).await),
+        ]
+    }
+
+    #[tokio::test]
+    async fn rtu_is_silent_without_power() {
+        let state = SimState::new(500, false, vec![], vec![], vec![], vec![]).unwrap();
+        let power = PowerSwitch::default();
+        let service = PoweredService {
+            service: ModbusService::new(Arc::new(RwLock::new(state))),
+            power: power.clone(),
+        };
+        let (master, slave) = SerialStream::pair().unwrap();
+        tokio::spawn(serve_serial(slave, service));
+        let mut rtu = rtu_client::attach_slave(master, Slave(1));
+
+        power.power_off();
+        let read = rtu.read_holding_registers(0, 1);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), read)
+                .await
+                .is_err()
+        );
+    }
+
+    /// Reads register 5, holding 42, through a fault relay with a fresh
+    /// session, so a truncated frame cannot stall the next read.
+    async fn read_through_faults(faults: BusFaults) -> bool {
+        let register = RegisterItemConfig {
+            address: 5,
+            initial: 42.into(),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![register], vec![]).unwrap();
+        let service = ModbusService::new(Arc::new(RwLock::new(state)));
+        let (master, slave) = SerialStream::pair().unwrap();
+        tokio::spawn(serve_relayed(
+            slave,
+            service.into(),
+            faults,
+            Framing::STANDARD,
+        ));
+        let mut rtu = rtu_client::attach_slave(master, Slave(1));
+        let read = rtu.read_holding_registers(5, 1);
+        let outcome = tokio::time::timeout(Duration::from_millis(300), read).await;
+        matches!(outcome, Ok(Ok(values)) if values == [42])
+    }
+
+    #[tokio::test]
+    async fn received_bytes_are_paced_to_the_baud_rate() {
+        let config: RtuConfig =
+            toml::from_str("device = \"/dev/ttyS0\"\nbaud_rate = 1200\npace_receive = true")
+                .unwrap();
+        let framing = Framing::new(&config);
+        // 8N1 characters are 10 bits: 1/120 s each at 1200 baud.
+        assert_eq!(
+            framing.byte_time,
+            Some(Duration::from_secs_f64(1.0 / 120.0))
+        );
+
+        let register = RegisterItemConfig {
+            address: 5,
+            initial: 42.into(),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![register], vec![]).unwrap();
+        let service = ModbusService::new(Arc::new(RwLock::new(state)));
+        let (master, slave) = SerialStream::pair().unwrap();
+        tokio::spawn(serve_relayed(
+            slave,
+            service.into(),
+            BusFaults::default(),
+            framing,
+        ));
+        let mut rtu = rtu_client::attach_slave(master, Slave(1));
+        let started = std::time::Instant::now();
+        assert_eq!(rtu.read_holding_registers(5, 1).await.unwrap(), [42]);
+        // The 8 bytes of the request take 66.7 ms to come in.
+        assert!(
+            started.elapsed() >= Duration::from_millis(66),
+            "{:?}",
+            started.elapsed()
+        );
+    }
+
+    #[tokio::test]
+    async fn colliding_responses_do_not_reach_the_master_intact() {
+        let collisions = |probability, seed| BusFaults {
+            collisions: Some(
+                Collisions::new(
+                    &CollisionConfig {
+                        probability,
+                        function_codes: None,
+                    },
+                    Some(seed),
+                )
+                .unwrap(),
+            ),
+            truncation: None,
+        };
+        for seed in 0..3 {
+            assert!(read_through_faults(collisions(0.0, seed)).await);
+            assert!(!read_through_faults(collisions(1.0, seed)).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn truncated_responses_do_not_reach_the_master_intact() {
+        let truncation = |probability, bytes, function_codes| {
+            let config = TruncationConfig {
+                probability,
+                bytes,
+                function_codes,
+            };
+            BusFaults {
+                collisions: None,
+                truncation: Some(Truncation::new(&config, Some(5)).unwrap()),
+            }
+        };
+        assert!(read_through_faults(truncation(0.0, None, None)).await);
+        assert!(!read_through_faults(truncation(1.0, None, None)).await);
+        assert!(!read_through_faults(truncation(1.0, Some(3), None)).await);
+        assert!(read_through_faults(truncation(1.0, None, Some(vec![0x04]))).await);
+    }
+
+    #[tokio::test]
+    async fn relay_speaks_the_configured_crc_on_the_bus() {
+        let register = RegisterItemConfig {
+            address: 5,
+            initial: 42.into(),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![register], vec![]).unwrap();
+        let service = ModbusService::new(Arc::new(RwLock::new(state)));
+        let (mut master, slave) = SerialStream::pair().unwrap();
+        let framing = Framing {
+            crc: Crc::new(CrcAlgorithm::Arc, None),
+            ..Framing::STANDARD
+        };
+        tokio::spawn(serve_relayed(
+            slave,
+            service.into(),
+            BusFaults::default(),
+            framing,
+        ));
+
+        // Read holding register 5 of slave 1 with the standard CRC, which
+        // the bus does not accept, then with CRC-16/ARC.
+        let request = [0x01, 0x03, 0x00, 0x05, 0x00, 0x01];
+        let mut response = [0; 16];
+        master.write_all(&Crc::MODBUS.seal(&request)).await.unwrap();
+        let read = master.read(&mut response);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(200), read)
+                .await
+                .is_err()
+        );
+        master.write_all(&[0x01, 0x03, 0x00, 0x05]).await.unwrap();
+        master.write_all(&[0x00, 0x01, 0x94, 0x10]).await.unwrap();
+        let len = tokio::time::timeout(Duration::from_millis(300), master.read(&mut response))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(response[..len], [0x01, 0x03, 0x02, 0x00, 0x2A, 0x1D, 0x9B]);
+    }
+
+    #[tokio::test]
+    async fn rtu_and_tcp_answer_unconfigured_reads_alike() {
+        let register = RegisterItemConfig {
+            address: 5,
+            initial: 42.into(),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![register], vec![]).unwrap();
+        let mut tcp = TestHarness::start(state).await.unwrap();
+        let service = ModbusService::new(Arc::clone(&tcp.state));
+
+        let (master, slave) = SerialStream::pair().unwrap();
+        tokio::spawn(serve_serial(slave, service.into()));
+        let mut rtu = rtu_client::attach_slave(master, Slave(1));
+
+        let rtu_outcomes = read_outcomes(&mut rtu).await;
+        assert_eq!(rtu_outcomes[0], "Ok([0, 42, 0])");
+        assert!(rtu_outcomes[3].starts_with("Err"), "{}", rtu_outcomes[3]);
+        assert_eq!(rtu_outcomes, read_outcomes(&mut tcp.client).await);
+    }
+
+    /// Requests the service does not implement, then a read on the same
+    /// session. RTU frames only function codes whose length it knows, so
+    /// these are all standard ones.
+    async fn unsupported_outcomes(ctx: &mut tokio_modbus::client::Context) -> Vec<String> {
+        vec![
+            format!("{:?}", ctx.masked_write_register(0, 0xFF00, 0x00FF).await),
+            format!(
+                "{:?}",
+                ctx.read_write_multiple_registers(0, 1, 0, &[1]).await
+            ),
+            format!("{:?}", ctx.call(Request::Custom(0x11, vec![])).await),
+            format!("{:?}", ctx.read_holding_registers(0, 1).await),
+        ]
+    }
+
+    #[tokio::test]
+    async fn rtu_answers_unsupported_requests_and_keeps_serving() {
+        let state = SimState::new(500, false, vec![], vec![], vec![], vec![]).unwrap();
+        let mut tcp = TestHarness::start(state).await.unwrap();
+        let service = ModbusService::new(Arc::clone(&tcp.state));
+
+        let (master, slave) = SerialStream::pair().unwrap();
+        let session = tokio::spawn(serve_serial(slave, service.into()));
+        let mut rtu = rtu_client::attach_slave(master, Slave(1));
+
+        let rtu_outcomes = unsupported_outcomes(&mut rtu).await;
+        assert!(rtu_outcomes[0].starts_with("Err"), "{}", rtu_outcomes[0]);
+        assert!(rtu_outcomes[1].starts_with("Err"), "{}", rtu_outcomes[1]);
+        assert!(rtu_outcomes[2].starts_with("Err"), "{}", rtu_outcomes[2]);
+        assert_eq!(rtu_outcomes[3], "Ok([0])");
+        assert!(!session.is_finished());
+        assert_eq!(rtu_outcomes, unsupported_outcomes(&mut tcp.client).await);
+        assert_eq!(
+            tcp.state.read().unwrap().exception_counts[&Exception::IllegalFunction],
+            6
+        );
+    }
 }
@@ -1,81 +1,1095 @@
-use std::future::{Ready, ready};
+use std::collections::BTreeMap;
+use std::future::Future;
 use std::io;
 use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use anyhow::Result;
-use tokio::net::TcpListener;
-use tokio_modbus::prelude::{Request, Response};
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_modbus::prelude::{Request, Response, SlaveRequest};
 use tokio_modbus::server::Service;
 use tokio_modbus::server::tcp::{Server, accept_tcp_connection};
-use tracing::info;
+use tracing::{debug, info, warn};
 
-use crate::sim::SimState;
+use crate::config::{
+    AddressOffsets, FaultedAddressConfig, FunctionCodes, KeepaliveConfig, TcpConfig,
+};
+use crate::fault::{Latency, Truncation};
+use crate::power::PowerSwitch;
+use crate::sim::{ClientGuard, Exception, Exemplar, SimState, Space};
+use crate::transport::audit::{AuditLog, Decoded, decode_write};
+use crate::transport::frames::FrameLog;
+use crate::transport::mbap::Mbap;
+use crate::transport::pdu::decode_request;
+use crate::transport::peers::{PeerLimit, PeerSlot};
+use crate::transport::transactions::{describe_request, describe_response};
 
 #[derive(Clone)]
 pub struct ModbusService {
     state: Arc<std::sync::RwLock<SimState>>,
+    latency: Option<Latency>,
+    mei: Arc<BTreeMap<u8, Vec<u8>>>,
+    audit: Option<AuditLog>,
+    peer: Option<SocketAddr>,
+    connection_id: Option<u64>,
+    address_offsets: AddressOffsets,
+    max_adu_bytes: Option<usize>,
+    adu_overhead: usize,
+    request_ids: Arc<AtomicU64>,
+    faulted: Arc<BTreeMap<(Space, u16), Exception>>,
+    function_codes: Arc<FunctionCodes>,
+    log_transactions: bool,
+    fast_path: bool,
 }
 
 impl ModbusService {
     pub fn new(state: Arc<std::sync::RwLock<SimState>>) -> Self {
-        Self { state }
+        Self {
+            state,
+            latency: None,
+            mei: Arc::default(),
+            audit: None,
+            peer: None,
+            connection_id: None,
+            address_offsets: AddressOffsets::default(),
+            max_adu_bytes: None,
+            adu_overhead: TCP_ADU_OVERHEAD,
+            request_ids: Arc::default(),
+            faulted: Arc::default(),
+            function_codes: Arc::default(),
+            log_transactions: false,
+            fast_path: false,
+        }
+    }
+
+    /// Answers reads that sample no `update_on_read` item under the read
+    /// lock, and stops recording request durations, for benchmarking.
+    pub fn with_fast_path(mut self, enabled: bool) -> Self {
+        self.fast_path = enabled;
+        self
+    }
+
+    /// Logs every request and what it was answered with as one line at info
+    /// level, such as `FC03 addr=100 count=2 -> [7, 8]`.
+    pub fn with_transaction_log(mut self, enabled: bool) -> Self {
+        self.log_transactions = enabled;
+        self
+    }
+
+    /// Answers every request touching one of the `faulted` addresses with
+    /// its exception, before any check but the function code one. Addresses
+    /// are 0-based, like item addresses.
+    pub fn with_faulted(mut self, faulted: &[FaultedAddressConfig]) -> Self {
+        self.faulted = Arc::new(
+            faulted
+                .iter()
+                .map(|fault| ((fault.space, fault.address), fault.exception.into()))
+                .collect(),
+        );
+        self
+    }
+
+    /// Answers the function codes `function_codes` leaves out with
+    /// IllegalFunction before any other check, like a device that does not
+    /// implement them.
+    pub fn with_function_codes(mut self, function_codes: FunctionCodes) -> Self {
+        self.function_codes = Arc::new(function_codes);
+        self
+    }
+
+    /// Canned FC43 responses keyed by MEI type; unlisted types answer
+    /// IllegalDataValue, and FC43 answers IllegalFunction without any.
+    pub fn with_mei(mut self, mei: BTreeMap<u8, Vec<u8>>) -> Self {
+        self.mei = Arc::new(mei);
+        self
+    }
+
+    pub fn with_latency(mut self, latency: Option<Latency>) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Records successful client writes to `audit`.
+    pub fn with_audit(mut self, audit: Option<AuditLog>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    /// Maps client addresses onto the 0-based maps by subtracting a
+    /// per-space offset; addresses below the offset are IllegalDataAddress.
+    pub fn with_address_offsets(mut self, offsets: AddressOffsets) -> Self {
+        self.address_offsets = offsets;
+        self
+    }
+
+    /// Answers requests whose request or response ADU would be larger than
+    /// `max_adu_bytes` with IllegalDataValue, like a constrained gateway.
+    pub fn with_max_adu_bytes(mut self, max_adu_bytes: Option<u16>) -> Self {
+        self.max_adu_bytes = max_adu_bytes.map(usize::from);
+        self
+    }
+
+    /// Bytes an ADU adds around the PDU on this transport.
+    pub fn with_adu_overhead(mut self, overhead: usize) -> Self {
+        self.adu_overhead = overhead;
+        self
+    }
+
+    /// Client address reported in audit records of this connection and
+    /// identifying its writes under the reject-if-busy write policy.
+    pub fn with_peer(mut self, peer: SocketAddr) -> Self {
+        self.peer = Some(peer);
+        self
+    }
+
+    /// Number of the connection, counted from 1 per listener, reported in
+    /// request logs and audit records.
+    pub fn with_connection_id(mut self, connection_id: u64) -> Self {
+        self.connection_id = Some(connection_id);
+        self
     }
 }
 
 impl Service for ModbusService {
-    type Request = Request;
+    type Request = SlaveRequest;
     type Response = Response;
     type Error = io::Error;
-    type Future = Ready<Result<Response, io::Error>>;
+    type Future = ServiceFuture;
 
-    fn call(&self, req: Request) -> Self::Future {
-        let mut state = self.state.write().unwrap();
-        let response = match req {
-            Request::ReadCoils(addr, cnt) => Response::ReadCoils(state.read_coils(addr, cnt)),
+    fn call(&self, req: SlaveRequest) -> Self::Future {
+        let SlaveRequest { slave, request } = req;
+        let started = Instant::now();
+        let request_id = self.request_ids.fetch_add(1, Ordering::Relaxed) + 1;
+        let function = function_code(&request);
+        let address = request_address(&request);
+        let audited = self.audit.as_ref().map(|audit| (audit, request.clone()));
+        let response_delay = self.response_delay(&request);
+        let transaction = self.log_transactions.then(|| describe_request(&request));
+        let result = self.handle(request);
+        if let Some(transaction) = transaction {
+            info!(
+                connection_id = self.connection_id,
+                unit = slave,
+                peer = self.peer.map(tracing::field::display),
+                "{transaction} -> {}",
+                describe_response(&result)
+            );
+        }
+        if let (Some((audit, request)), Ok(response)) = (audited, &result)
+            && !is_exception(response)
+        {
+            let decoded = self.decoded_write(&request);
+            audit.record(self.peer, self.connection_id, slave, &request, &decoded);
+        }
+        let delay = self
+            .latency
+            .as_ref()
+            .filter(|latency| latency.applies_to(function))
+            .map_or(Duration::ZERO, Latency::sample)
+            + response_delay;
+        let answered = (!self.fast_path).then(|| Answered {
+            state: Arc::clone(&self.state),
+            started,
+            request_id,
+            connection_id: self.connection_id,
+            peer: self.peer,
+            function,
+            address,
+        });
+        if delay.is_zero() {
+            if let Some(answered) = answered {
+                answered.record();
+            }
+            return ServiceFuture::Ready(Some(result));
+        }
+        ServiceFuture::Delayed(Box::pin(async move {
+            tokio::time::sleep(delay).await;
+            if let Some(answered) = answered {
+                answered.record();
+            }
+            result
+        }))
+    }
+}
+
+/// The services of the devices sharing one TCP listener, by unit id. Each
+/// request reaches only the state of the unit it addresses; requests for
+/// other units answer GatewayTargetDeviceFailedToRespond. A listener serving
+/// a single device answers every unit id.
+#[derive(Clone)]
+pub struct UnitRouter {
+    units: BTreeMap<u8, ModbusService>,
+    /// Counts the connection against its client IP while it is served.
+    _slot: Option<Arc<PeerSlot>>,
+    /// Counts the connection as a client of each device while it is served.
+    _clients: Arc<Vec<ClientGuard>>,
+    power: PowerSwitch,
+    /// Boot of the power switch the connection was accepted in.
+    boot: u64,
+    exception_limit: Option<ExceptionLimit>,
+    truncation: Option<Truncation>,
+}
+
+impl UnitRouter {
+    pub fn new(units: BTreeMap<u8, ModbusService>) -> Self {
+        Self {
+            units,
+            _slot: None,
+            _clients: Arc::default(),
+            power: PowerSwitch::default(),
+            boot: 0,
+            exception_limit: None,
+            truncation: None,
+        }
+    }
+
+    /// Cuts off responses on this listener's connections as `truncation`
+    /// draws, closing each connection after its truncated response.
+    pub fn with_truncation(mut self, truncation: Option<Truncation>) -> Self {
+        self.truncation = truncation;
+        self
+    }
+
+    /// Closes a connection once `max` requests in a row were answered with
+    /// an exception, like a device defending itself against a broken
+    /// master. Any other response starts the count over.
+    pub fn with_exception_limit(mut self, max: Option<NonZeroU32>) -> Self {
+        self.exception_limit = max.map(|max| ExceptionLimit {
+            max,
+            consecutive: Arc::default(),
+            peer: None,
+            connection_id: None,
+        });
+        self
+    }
+
+    /// Refuses connections while `power` is off and drops the ones accepted
+    /// before it last came back on.
+    pub fn with_power(mut self, power: PowerSwitch) -> Self {
+        self.power = power;
+        self
+    }
+
+    fn route(&self, unit: u8) -> Option<&ModbusService> {
+        if self.units.len() == 1 {
+            return self.units.values().next();
+        }
+        self.units.get(&unit)
+    }
+
+    /// The router for one accepted connection.
+    fn with_connection(
+        &self,
+        peer: SocketAddr,
+        connection_id: u64,
+        slot: Option<PeerSlot>,
+    ) -> Self {
+        let units = self
+            .units
+            .iter()
+            .map(|(&unit, service)| {
+                let service = service
+                    .clone()
+                    .with_peer(peer)
+                    .with_connection_id(connection_id);
+                (unit, service)
+            })
+            .collect();
+        let clients = self
+            .units
+            .values()
+            .map(|service| service.state.read().unwrap().connect_client())
+            .collect();
+        let exception_limit = self.exception_limit.as_ref().map(|limit| ExceptionLimit {
+            max: limit.max,
+            consecutive: Arc::default(),
+            peer: Some(peer),
+            connection_id: Some(connection_id),
+        });
+        Self {
+            units,
+            _slot: slot.map(Arc::new),
+            _clients: Arc::new(clients),
+            power: self.power.clone(),
+            boot: self.power.boots(),
+            exception_limit,
+            truncation: self.truncation.clone(),
+        }
+    }
+}
+
+/// Count of the requests in a row a connection had answered with an
+/// exception.
+#[derive(Clone)]
+struct ExceptionLimit {
+    max: NonZeroU32,
+    consecutive: Arc<AtomicU32>,
+    peer: Option<SocketAddr>,
+    connection_id: Option<u64>,
+}
+
+impl ExceptionLimit {
+    /// Counts `result`, turning it into an error that closes the connection
+    /// when it is the exception that reaches the limit.
+    fn check(&self, result: Result<Response, io::Error>) -> Result<Response, io::Error> {
+        let Ok(response) = &result else {
+            return result;
+        };
+        if !is_exception(response) {
+            self.consecutive.store(0, Ordering::Relaxed);
+            return result;
+        }
+        let consecutive = self.consecutive.fetch_add(1, Ordering::Relaxed) + 1;
+        if consecutive < self.max.get() {
+            return result;
+        }
+        warn!(
+            peer = self.peer.map(tracing::field::display),
+            connection_id = self.connection_id,
+            consecutive,
+            "modbus tcp connection closed: too many consecutive exceptions"
+        );
+        Err(io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "too many consecutive exceptions",
+        ))
+    }
+}
+
+impl From<ModbusService> for UnitRouter {
+    fn from(service: ModbusService) -> Self {
+        Self::new(BTreeMap::from([(0, service)]))
+    }
+}
+
+impl Service for UnitRouter {
+    type Request = SlaveRequest;
+    type Response = Response;
+    type Error = io::Error;
+    type Future = ServiceFuture;
+
+    fn call(&self, req: SlaveRequest) -> Self::Future {
+        if !self.power.is_on() || self.power.boots() != self.boot {
+            // The device lost power since the connection was made; the
+            // error closes it.
+            return ServiceFuture::Ready(Some(Err(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "device power cycled",
+            ))));
+        }
+        let future = match self.route(req.slave) {
+            Some(service) => service.call(req),
+            None => {
+                debug!(
+                    unit = req.slave,
+                    "modbus request for a unit not served here"
+                );
+                let function = function_code(&req.request);
+                ServiceFuture::Ready(Some(Ok(exception_response(
+                    function,
+                    Exception::GatewayTargetDeviceFailedToRespond,
+                ))))
+            }
+        };
+        let Some(limit) = self.exception_limit.clone() else {
+            return future;
+        };
+        match future {
+            ServiceFuture::Ready(result) => {
+                ServiceFuture::Ready(result.map(|result| limit.check(result)))
+            }
+            ServiceFuture::Delayed(future) => {
+                ServiceFuture::Delayed(Box::pin(async move { limit.check(future.await) }))
+            }
+        }
+    }
+}
+
+/// Future of [`ModbusService::call`]. Responses without a delay to wait
+/// out are ready at once and skip the boxed future.
+pub enum ServiceFuture {
+    Ready(Option<Result<Response, io::Error>>),
+    Delayed(Pin<Box<dyn Future<Output = Result<Response, io::Error>> + Send + Sync>>),
+}
+
+impl Future for ServiceFuture {
+    type Output = Result<Response, io::Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.get_mut() {
+            ServiceFuture::Ready(result) => Poll::Ready(
+                result
+                    .take()
+                    .expect("ServiceFuture polled after completion"),
+            ),
+            ServiceFuture::Delayed(future) => future.as_mut().poll(cx),
+        }
+    }
+}
+
+/// A request whose duration is recorded once its response is sent off.
+struct Answered {
+    state: Arc<std::sync::RwLock<SimState>>,
+    started: Instant,
+    request_id: u64,
+    connection_id: Option<u64>,
+    peer: Option<SocketAddr>,
+    function: u8,
+    address: Option<u16>,
+}
+
+impl Answered {
+    fn record(self) {
+        let Answered {
+            state,
+            started,
+            request_id,
+            connection_id,
+            peer,
+            function,
+            address,
+        } = self;
+        let duration = started.elapsed().as_secs_f64();
+        debug!(
+            request_id,
+            connection_id,
+            peer = peer.map(tracing::field::display),
+            function,
+            ?address,
+            duration,
+            "modbus request answered"
+        );
+        let mut labels = vec![
+            ("request_id", request_id.to_string()),
+            ("function", function.to_string()),
+        ];
+        if let Some(address) = address {
+            labels.push(("address", address.to_string()));
+        }
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |since| since.as_secs_f64());
+        state
+            .write()
+            .unwrap()
+            .request_duration
+            .observe_with_exemplar(Exemplar {
+                labels,
+                value: duration,
+                timestamp,
+            });
+    }
+}
+
+impl ModbusService {
+    /// Runs a raw request PDU through the same path as a live connection, minus
+    /// the latency fault. Malformed PDUs fail like they do in the codec.
+    pub fn process_pdu(&self, pdu: &[u8]) -> Result<Response, io::Error> {
+        self.handle(decode_request(pdu)?)
+    }
+
+    /// Longest `response_delay_ms` among the items `req` touches, looked up
+    /// after the address offset is removed.
+    fn response_delay(&self, req: &Request) -> Duration {
+        let offset = address_offset(&self.address_offsets, function_code(req));
+        request_span(req)
+            .and_then(|(space, address, count)| Some((space, address.checked_sub(offset)?, count)))
+            .map_or(Duration::ZERO, |(space, address, count)| {
+                self.state
+                    .read()
+                    .unwrap()
+                    .response_delay(space, address, count)
+            })
+    }
+
+    /// The holding register items a register write changed, decoded for the
+    /// audit log.
+    fn decoded_write(&self, req: &Request) -> Vec<Decoded> {
+        if !matches!(
+            req,
+            Request::WriteSingleRegister(..) | Request::WriteMultipleRegisters(..)
+        ) {
+            return Vec::new();
+        }
+        let offset = address_offset(&self.address_offsets, function_code(req));
+        match request_span(req) {
+            Some((_, address, count)) if address >= offset => {
+                decode_write(&self.state.read().unwrap(), address - offset, count, offset)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn handle(&self, req: Request) -> Result<Response, io::Error> {
+        let function = function_code(&req);
+        let offset = address_offset(&self.address_offsets, function);
+        let checked = self
+            .check_function(function)
+            .and_then(|()| unshift_request(req, offset).ok_or(Exception::IllegalDataAddress))
+            .and_then(|req| self.check_faulted(&req).map(|()| req))
+            .and_then(|req| {
+                let sizes = [Some(request_pdu_len(&req)), response_pdu_len(&req)];
+                self.check_adu(sizes.into_iter().flatten()).map(|()| req)
+            });
+        let response = match checked {
+            Ok(req) => self.dispatch(req)?,
+            Err(exception) => self.reject(function, exception),
+        };
+        // Custom function responses are only sized once built; they are all
+        // reads, so rejecting them afterwards changes no state.
+        let response = match &response {
+            Response::Custom(_, data) if !is_exception(&response) => {
+                match self.check_adu([1 + data.len()]) {
+                    Ok(()) => response,
+                    Err(exception) => self.reject(function, exception),
+                }
+            }
+            _ => response,
+        };
+        Ok(shift_response(response, offset))
+    }
+
+    fn check_function(&self, function: u8) -> Result<(), Exception> {
+        if self.function_codes.is_enabled(function) {
+            Ok(())
+        } else {
+            Err(Exception::IllegalFunction)
+        }
+    }
+
+    /// The exception of the first faulted address `req` touches, if any.
+    fn check_faulted(&self, req: &Request) -> Result<(), Exception> {
+        let Some((space, address, count)) = request_span(req) else {
+            return Ok(());
+        };
+        let last = address.saturating_add(count.saturating_sub(1));
+        match self.faulted.range((space, address)..=(space, last)).next() {
+            Some((_, exception)) => Err(*exception),
+            None => Ok(()),
+        }
+    }
+
+    fn check_adu(&self, pdu_lens: impl IntoIterator<Item = usize>) -> Result<(), Exception> {
+        match self.max_adu_bytes {
+            Some(max)
+                if pdu_lens
+                    .into_iter()
+                    .any(|len| len + self.adu_overhead > max) =>
+            {
+                Err(Exception::IllegalDataValue)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn reject(&self, function: u8, exception: Exception) -> Response {
+        self.state.write().unwrap().record_exception(exception);
+        exception_response(function, exception)
+    }
+
+    /// Answers a read under the read lock when it samples no item. `None`
+    /// leaves the request, and any exception it raises, to [`Self::dispatch`].
+    fn read_shared(&self, req: &Request) -> Option<Response> {
+        let (space, address, count, max) = match *req {
+            Request::ReadCoils(addr, cnt) => (Space::Coils, addr, cnt, MAX_READ_BITS),
             Request::ReadDiscreteInputs(addr, cnt) => {
-                Response::ReadDiscreteInputs(state.read_discrete_inputs(addr, cnt))
+                (Space::DiscreteInputs, addr, cnt, MAX_READ_BITS)
             }
             Request::ReadHoldingRegisters(addr, cnt) => {
-                Response::ReadHoldingRegisters(state.read_holding_registers(addr, cnt))
+                (Space::HoldingRegisters, addr, cnt, MAX_READ_REGISTERS)
             }
             Request::ReadInputRegisters(addr, cnt) => {
-                Response::ReadInputRegisters(state.read_input_registers(addr, cnt))
+                (Space::InputRegisters, addr, cnt, MAX_READ_REGISTERS)
+            }
+            _ => return None,
+        };
+        check_range(address, count.into(), max).ok()?;
+        let state = self.state.read().unwrap();
+        if state.samples_on_read(space, address, count) {
+            return None;
+        }
+        state.check_readable(space, address, count).ok()?;
+        Some(match space {
+            Space::Coils => Response::ReadCoils(state.read_coils(address, count).ok()?),
+            Space::DiscreteInputs => {
+                Response::ReadDiscreteInputs(state.read_discrete_inputs(address, count).ok()?)
+            }
+            Space::HoldingRegisters => {
+                let mut values = state.read_holding_registers(address, count).ok()?;
+                state.pack_registers(address, &mut values);
+                Response::ReadHoldingRegisters(values)
+            }
+            Space::InputRegisters => {
+                Response::ReadInputRegisters(state.read_input_registers(address, count).ok()?)
             }
-            Request::WriteSingleCoil(addr, value) => {
-                state.write_single_coil(addr, value);
-                Response::WriteSingleCoil(addr, value)
+        })
+    }
+
+    fn dispatch(&self, req: Request) -> Result<Response, io::Error> {
+        if self.fast_path
+            && let Some(response) = self.read_shared(&req)
+        {
+            return Ok(response);
+        }
+        let function = function_code(&req);
+        let mut state = self.state.write().unwrap();
+        let result = match req {
+            Request::ReadCoils(addr, cnt) => check_range(addr, cnt.into(), MAX_READ_BITS)
+                .and_then(|()| state.check_readable(Space::Coils, addr, cnt))
+                .and_then(|()| {
+                    state.sample_on_read(Space::Coils, addr, cnt);
+                    state.read_coils(addr, cnt).map(Response::ReadCoils)
+                }),
+            Request::ReadDiscreteInputs(addr, cnt) => check_range(addr, cnt.into(), MAX_READ_BITS)
+                .and_then(|()| state.check_readable(Space::DiscreteInputs, addr, cnt))
+                .and_then(|()| {
+                    state.sample_on_read(Space::DiscreteInputs, addr, cnt);
+                    state
+                        .read_discrete_inputs(addr, cnt)
+                        .map(Response::ReadDiscreteInputs)
+                }),
+            Request::ReadHoldingRegisters(addr, cnt) => {
+                check_range(addr, cnt.into(), MAX_READ_REGISTERS)
+                    .and_then(|()| state.check_readable(Space::HoldingRegisters, addr, cnt))
+                    .and_then(|()| {
+                        state.sample_on_read(Space::HoldingRegisters, addr, cnt);
+                        state.read_holding_registers(addr, cnt).map(|mut values| {
+                            state.pack_registers(addr, &mut values);
+                            Response::ReadHoldingRegisters(values)
+                        })
+                    })
             }
-            Request::WriteSingleRegister(addr, value) => {
-                state.write_single_register(addr, value);
-                Response::WriteSingleRegister(addr, value)
+            Request::ReadInputRegisters(addr, cnt) => {
+                check_range(addr, cnt.into(), MAX_READ_REGISTERS)
+                    .and_then(|()| state.check_readable(Space::InputRegisters, addr, cnt))
+                    .and_then(|()| {
+                        state.sample_on_read(Space::InputRegisters, addr, cnt);
+                        state
+                            .read_input_registers(addr, cnt)
+                            .map(Response::ReadInputRegisters)
+                    })
             }
+            Request::WriteSingleCoil(addr, value) => state
+                .check_writable(Space::Coils, addr, 1)
+                .and_then(|()| {
+                    state.claim_write(Space::Coils, addr, 1, self.peer, self.connection_id)
+                })
+                .map(|()| {
+                    state.write_single_coil(addr, value);
+                    state.update_write_locks(Space::Coils, addr, 1);
+                    Response::WriteSingleCoil(addr, value)
+                }),
+            Request::WriteSingleRegister(addr, value) => state
+                .check_writable(Space::HoldingRegisters, addr, 1)
+                .and_then(|()| state.check_unlocked(Space::HoldingRegisters, addr, 1))
+                .and_then(|()| {
+                    state.claim_write(
+                        Space::HoldingRegisters,
+                        addr,
+                        1,
+                        self.peer,
+                        self.connection_id,
+                    )
+                })
+                .map(|()| {
+                    // The echo shows what was stored, as the device would.
+                    let value = state.stored_values(addr, &[value])[0];
+                    state.write_single_register(addr, value);
+                    state.unpack_registers(addr, &[value]);
+                    state.update_write_locks(Space::HoldingRegisters, addr, 1);
+                    Response::WriteSingleRegister(addr, value)
+                }),
             Request::WriteMultipleCoils(addr, values) => {
-                state.write_multiple_coils(addr, &values);
-                Response::WriteMultipleCoils(addr, values.len() as u16)
+                check_range(addr, values.len(), MAX_WRITE_BITS)
+                    .and_then(|()| state.check_writable(Space::Coils, addr, values.len()))
+                    .and_then(|()| {
+                        state.claim_write(
+                            Space::Coils,
+                            addr,
+                            values.len(),
+                            self.peer,
+                            self.connection_id,
+                        )
+                    })
+                    .and_then(|()| state.write_multiple_coils(addr, &values))
+                    .map(|()| {
+                        state.update_write_locks(Space::Coils, addr, values.len());
+                        Response::WriteMultipleCoils(addr, values.len() as u16)
+                    })
             }
             Request::WriteMultipleRegisters(addr, values) => {
-                state.write_multiple_registers(addr, &values);
-                Response::WriteMultipleRegisters(addr, values.len() as u16)
-            }
-            _ => {
-                return ready(Err(io::Error::other("unsupported request")));
+                check_range(addr, values.len(), MAX_WRITE_REGISTERS)
+                    .and_then(|()| {
+                        state.check_writable(Space::HoldingRegisters, addr, values.len())
+                    })
+                    .and_then(|()| {
+                        state.check_unlocked(Space::HoldingRegisters, addr, values.len())
+                    })
+                    .and_then(|()| {
+                        state.claim_write(
+                            Space::HoldingRegisters,
+                            addr,
+                            values.len(),
+                            self.peer,
+                            self.connection_id,
+                        )
+                    })
+                    .and_then(|()| {
+                        let stored = state.stored_values(addr, &values);
+                        state
+                            .write_multiple_registers(addr, &stored)
+                            .map(|()| stored)
+                    })
+                    .map(|stored| {
+                        state.unpack_registers(addr, &stored);
+                        state.update_write_locks(Space::HoldingRegisters, addr, values.len());
+                        Response::WriteMultipleRegisters(addr, values.len() as u16)
+                    })
             }
+            Request::Custom(READ_FIFO_QUEUE, data) => read_fifo_queue(&state, &data),
+            Request::Custom(ENCAPSULATED_INTERFACE_TRANSPORT, data) => self.encapsulated(&data),
+            // A request with the exception bit set was refused by the MBAP
+            // shim, as a client never sends one.
+            Request::Custom(code, _) if code & 0x80 != 0 => Err(Exception::IllegalDataValue),
+            // Answered rather than failed: an error would end the TCP
+            // connection or the whole RTU session.
+            _ => Err(Exception::IllegalFunction),
         };
 
-        ready(Ok(response))
+        Ok(result.unwrap_or_else(|exception| {
+            state.record_exception(exception);
+            exception_response(function, exception)
+        }))
+    }
+}
+
+/// MBAP header (transaction, protocol, length, unit id) of a TCP ADU.
+pub const TCP_ADU_OVERHEAD: usize = 7;
+/// Slave address and CRC of an RTU ADU.
+pub const RTU_ADU_OVERHEAD: usize = 3;
+
+/// PDU length of `req` as sent by the client, function code included.
+fn request_pdu_len(req: &Request) -> usize {
+    match req {
+        Request::WriteMultipleCoils(_, values) => 6 + values.len().div_ceil(8),
+        Request::WriteMultipleRegisters(_, values) => 6 + 2 * values.len(),
+        Request::Custom(_, data) => 1 + data.len(),
+        _ => 5,
+    }
+}
+
+/// PDU length of the successful response to `req`, when known before
+/// handling it.
+fn response_pdu_len(req: &Request) -> Option<usize> {
+    match req {
+        Request::ReadCoils(_, count) | Request::ReadDiscreteInputs(_, count) => {
+            Some(2 + usize::from(*count).div_ceil(8))
+        }
+        Request::ReadHoldingRegisters(_, count) | Request::ReadInputRegisters(_, count) => {
+            Some(2 + 2 * usize::from(*count))
+        }
+        Request::Custom(_, _) => None,
+        _ => Some(5),
+    }
+}
+
+/// Per-request quantity limits from the Modbus application protocol; larger
+/// responses would not fit in a PDU.
+pub(super) const MAX_READ_BITS: usize = 2000;
+pub(super) const MAX_READ_REGISTERS: usize = 125;
+pub(super) const MAX_WRITE_BITS: usize = 1968;
+pub(super) const MAX_WRITE_REGISTERS: usize = 123;
+
+/// Rejects zero or oversized quantities with IllegalDataValue and ranges
+/// running past address 0xFFFF with IllegalDataAddress.
+pub(super) fn check_range(address: u16, quantity: usize, max: usize) -> Result<(), Exception> {
+    if quantity == 0 || quantity > max {
+        return Err(Exception::IllegalDataValue);
+    }
+    if usize::from(address) + quantity > usize::from(u16::MAX) + 1 {
+        return Err(Exception::IllegalDataAddress);
+    }
+    Ok(())
+}
+
+const READ_FIFO_QUEUE: u8 = 0x18;
+const ENCAPSULATED_INTERFACE_TRANSPORT: u8 = 0x2B;
+/// The FC43 MEI type of Read Device Identification.
+const READ_DEVICE_IDENTIFICATION: u8 = 14;
+/// The private device identification object listing the answered function
+/// codes.
+const FUNCTION_CODES_OBJECT: u8 = 0x80;
+/// Function codes the service implements.
+const IMPLEMENTED_FUNCTIONS: [u8; 10] = [
+    0x01,
+    0x02,
+    0x03,
+    0x04,
+    0x05,
+    0x06,
+    0x0F,
+    0x10,
+    READ_FIFO_QUEUE,
+    ENCAPSULATED_INTERFACE_TRANSPORT,
+];
+
+/// Answers FC24: a byte count and a FIFO count, both two bytes, followed by
+/// the queued values.
+fn read_fifo_queue(state: &SimState, data: &[u8]) -> Result<Response, Exception> {
+    let values = match data {
+        [hi, lo] => state.read_fifo_queue(u16::from_be_bytes([*hi, *lo]))?,
+        _ if state.fifos.is_empty() => return Err(Exception::IllegalFunction),
+        _ => return Err(Exception::IllegalDataValue),
+    };
+    let count = values.len() as u16;
+    let mut response = Vec::with_capacity(4 + 2 * values.len());
+    response.extend_from_slice(&(2 + 2 * count).to_be_bytes());
+    response.extend_from_slice(&count.to_be_bytes());
+    for value in values {
+        response.extend_from_slice(&value.to_be_bytes());
+    }
+    Ok(Response::Custom(READ_FIFO_QUEUE, response))
+}
+
+impl ModbusService {
+    /// Answers FC43 from the canned responses. Without any the function is
+    /// unsupported; with some, an unlisted MEI type is an unsupported
+    /// subcode of a supported function. A device limiting its function codes
+    /// lists them in its device identification.
+    fn encapsulated(&self, data: &[u8]) -> Result<Response, Exception> {
+        if self.mei.is_empty() {
+            return Err(Exception::IllegalFunction);
+        }
+        let mei_type = *data.first().ok_or(Exception::IllegalDataValue)?;
+        let canned = self.mei.get(&mei_type).ok_or(Exception::IllegalDataValue)?;
+        let mut response = Vec::with_capacity(canned.len() + 1);
+        response.push(mei_type);
+        response.extend_from_slice(canned);
+        if mei_type == READ_DEVICE_IDENTIFICATION
+            && *self.function_codes != FunctionCodes::default()
+        {
+            append_function_codes(&mut response, &self.function_codes);
+        }
+        Ok(Response::Custom(ENCAPSULATED_INTERFACE_TRANSPORT, response))
+    }
+}
+
+/// Appends the implemented function codes `codes` enables, as the private
+/// object 0x80, to a Read Device Identification `response`: the MEI type,
+/// read device id code, conformity level, more follows, next object id,
+/// object count, then the objects. A response with more to follow is left
+/// as it is, as the object would not end it.
+fn append_function_codes(response: &mut Vec<u8>, codes: &FunctionCodes) {
+    let [_, _, _, 0x00, _, count, ..] = response[..] else {
+        return;
+    };
+    let Some(count) = count.checked_add(1) else {
+        return;
+    };
+    let enabled: Vec<u8> = IMPLEMENTED_FUNCTIONS
+        .into_iter()
+        .filter(|function| codes.is_enabled(*function))
+        .collect();
+    response[5] = count;
+    response.push(FUNCTION_CODES_OBJECT);
+    response.push(enabled.len() as u8);
+    response.extend_from_slice(&enabled);
+}
+
+/// The offset of the space `function` addresses; 0 for other functions.
+fn address_offset(offsets: &AddressOffsets, function: u8) -> u16 {
+    match function {
+        0x01 | 0x05 | 0x0F => offsets.coils,
+        0x02 => offsets.discrete_inputs,
+        0x03 | 0x06 | 0x10 => offsets.holding_registers,
+        0x04 => offsets.input_registers,
+        _ => 0,
     }
 }
 
-pub async fn start_tcp(bind: &str, state: Arc<std::sync::RwLock<SimState>>) -> Result<()> {
-    let addr: SocketAddr = bind.parse()?;
+/// Subtracts `offset` from the address of `req`; `None` when the client
+/// address lies below it.
+fn unshift_request(req: Request, offset: u16) -> Option<Request> {
+    Some(match req {
+        Request::ReadCoils(addr, cnt) => Request::ReadCoils(addr.checked_sub(offset)?, cnt),
+        Request::ReadDiscreteInputs(addr, cnt) => {
+            Request::ReadDiscreteInputs(addr.checked_sub(offset)?, cnt)
+        }
+        Request::ReadHoldingRegisters(addr, cnt) => {
+            Request::ReadHoldingRegisters(addr.checked_sub(offset)?, cnt)
+        }
+        Request::ReadInputRegisters(addr, cnt) => {
+            Request::ReadInputRegisters(addr.checked_sub(offset)?, cnt)
+        }
+        Request::WriteSingleCoil(addr, value) => {
+            Request::WriteSingleCoil(addr.checked_sub(offset)?, value)
+        }
+        Request::WriteSingleRegister(addr, value) => {
+            Request::WriteSingleRegister(addr.checked_sub(offset)?, value)
+        }
+        Request::WriteMultipleCoils(addr, values) => {
+            Request::WriteMultipleCoils(addr.checked_sub(offset)?, values)
+        }
+        Request::WriteMultipleRegisters(addr, values) => {
+            Request::WriteMultipleRegisters(addr.checked_sub(offset)?, values)
+        }
+        req => req,
+    })
+}
+
+/// Restores the client address in write echoes.
+fn shift_response(response: Response, offset: u16) -> Response {
+    match response {
+        Response::WriteSingleCoil(addr, value) => Response::WriteSingleCoil(addr + offset, value),
+        Response::WriteSingleRegister(addr, value) => {
+            Response::WriteSingleRegister(addr + offset, value)
+        }
+        Response::WriteMultipleCoils(addr, count) => {
+            Response::WriteMultipleCoils(addr + offset, count)
+        }
+        Response::WriteMultipleRegisters(addr, count) => {
+            Response::WriteMultipleRegisters(addr + offset, count)
+        }
+        response => response,
+    }
+}
+
+/// Encodes a Modbus exception as a raw PDU: the function code with the high bit
+/// set, followed by the exception code.
+fn exception_response(function: u8, exception: Exception) -> Response {
+    Response::Custom(function | 0x80, vec![exception.code()])
+}
+
+fn is_exception(response: &Response) -> bool {
+    matches!(response, Response::Custom(function, _) if function & 0x80 != 0)
+}
+
+/// Space, first address and quantity of the items `req` reads or writes.
+fn request_span(req: &Request) -> Option<(Space, u16, u16)> {
+    Some(match req {
+        Request::ReadCoils(addr, cnt) => (Space::Coils, *addr, *cnt),
+        Request::ReadDiscreteInputs(addr, cnt) => (Space::DiscreteInputs, *addr, *cnt),
+        Request::ReadHoldingRegisters(addr, cnt) => (Space::HoldingRegisters, *addr, *cnt),
+        Request::ReadInputRegisters(addr, cnt) => (Space::InputRegisters, *addr, *cnt),
+        Request::WriteSingleCoil(addr, _) => (Space::Coils, *addr, 1),
+        Request::WriteSingleRegister(addr, _) => (Space::HoldingRegisters, *addr, 1),
+        Request::WriteMultipleCoils(addr, values) => (Space::Coils, *addr, values.len() as u16),
+        Request::WriteMultipleRegisters(addr, values) => {
+            (Space::HoldingRegisters, *addr, values.len() as u16)
+        }
+        _ => return None,
+    })
+}
+
+/// Starting address of `req`, for requests that have one.
+fn request_address(req: &Request) -> Option<u16> {
+    match req {
+        Request::ReadCoils(addr, _)
+        | Request::ReadDiscreteInputs(addr, _)
+        | Request::ReadHoldingRegisters(addr, _)
+        | Request::ReadInputRegisters(addr, _)
+        | Request::WriteSingleCoil(addr, _)
+        | Request::WriteSingleRegister(addr, _)
+        | Request::WriteMultipleCoils(addr, _)
+        | Request::WriteMultipleRegisters(addr, _)
+        | Request::MaskWriteRegister(addr, _, _)
+        | Request::ReadWriteMultipleRegisters(addr, _, _, _) => Some(*addr),
+        _ => None,
+    }
+}
+
+fn function_code(req: &Request) -> u8 {
+    match req {
+        Request::ReadCoils(_, _) => 0x01,
+        Request::ReadDiscreteInputs(_, _) => 0x02,
+        Request::ReadHoldingRegisters(_, _) => 0x03,
+        Request::ReadInputRegisters(_, _) => 0x04,
+        Request::WriteSingleCoil(_, _) => 0x05,
+        Request::WriteSingleRegister(_, _) => 0x06,
+        Request::WriteMultipleCoils(_, _) => 0x0F,
+        Request::WriteMultipleRegisters(_, _) => 0x10,
+        Request::MaskWriteRegister(_, _, _) => 0x16,
+        Request::ReadWriteMultipleRegisters(_, _, _, _) => 0x17,
+        Request::Custom(code, _) => *code,
+        Request::Disconnect => 0x00,
+    }
+}
+
+pub async fn start_tcp(
+    tcp: &TcpConfig,
+    service: impl Into<UnitRouter>,
+    startup_delay: Duration,
+) -> Result<()> {
+    let router = service
+        .into()
+        .with_exception_limit(tcp.max_consecutive_exceptions);
+    let addr: SocketAddr = tcp.bind.parse()?;
+    let keepalive = tcp.keepalive.clone();
+    let connections = Arc::new(AtomicU64::new(0));
+    let peer_limit = tcp.max_connections_per_ip.map(PeerLimit::new);
+    let protocol_id = tcp.protocol_id;
+    let byte_count = tcp.byte_count;
+    let coil_value = tcp.coil_value;
+    if !startup_delay.is_zero() {
+        info!(addr = %addr, delay_ms = startup_delay.as_millis() as u64, "modbus tcp waiting for startup delay");
+        tokio::time::sleep(startup_delay).await;
+        info!(addr = %addr, "modbus tcp startup delay elapsed");
+    }
     info!(addr = %addr, "modbus tcp listening");
     let listener = TcpListener::bind(addr).await?;
     let server = Server::new(listener);
-    let service = ModbusService::new(state);
-    let on_connected = move |stream, socket_addr| {
-        let service = service.clone();
-        async move { accept_tcp_connection(stream, socket_addr, move |_| Ok(Some(service.clone()))) }
+    let on_connected = move |stream: TcpStream, socket_addr: SocketAddr| {
+        let slot = peer_limit
+            .as_ref()
+            .map(|limit| (limit.max(), limit.acquire(socket_addr.ip())));
+        let router = match slot {
+            _ if !router.power.is_on() => {
+                debug!(peer = %socket_addr, "modbus tcp connection refused: power off");
+                None
+            }
+            Some((max, None)) => {
+                warn!(
+                    ip = %socket_addr.ip(),
+                    max,
+                    "modbus tcp connection refused: too many connections from this ip"
+                );
+                None
+            }
+            slot => {
+                let connection_id = connections.fetch_add(1, Ordering::Relaxed) + 1;
+                debug!(connection_id, peer = %socket_addr, "modbus tcp connection accepted");
+                if let Some(keepalive) = &keepalive
+                    && let Err(err) = apply_keepalive(&stream, keepalive)
+                {
+                    warn!(peer = %socket_addr, error = %err, "failed to set tcp keepalive");
+                }
+                let slot = slot.and_then(|(_, slot)| slot);
+                Some(router.with_connection(socket_addr, connection_id, slot))
+            }
+        };
+        async move {
+            // Without a router the stream is dropped, closing the connection.
+            let Some(router) = router else {
+                return Ok(None);
+            };
+            let truncation = router.truncation.clone();
+            let accepted =
+                accept_tcp_connection(stream, socket_addr, move |_| Ok(Some(router.clone())))?;
+            Ok(accepted.map(|(service, stream)| {
+                let stream = FrameLog::new(stream, socket_addr);
+                let stream = Mbap::new(stream, socket_addr, protocol_id)
+                    .with_byte_count(byte_count)
+                    .with_coil_value(coil_value)
+                    .with_truncation(truncation);
+                (service, stream)
+            }))
+        }
     };
     let on_error = |err| {
         tracing::error!(error = %err, "modbus tcp connection error");
@@ -87,10 +1101,29 @@ pub async fn start_tcp(bind: &str, state: Arc<std::sync::RwLock<SimState>>) -> R
     Ok(())
 }
 
+fn apply_keepalive(stream: &TcpStream, keepalive: &KeepaliveConfig) -> io::Result<()> {
+    let socket = SockRef::from(stream);
+    if !keepalive.enabled {
+        return socket.set_keepalive(false);
+    }
+    let mut params = TcpKeepalive::new();
+    if let Some(idle_ms) = keepalive.idle_ms {
+        params = params.with_time(Duration::from_millis(idle_ms));
+    }
+    if let Some(interval_ms) = keepalive.interval_ms {
+        params = params.with_interval(Duration::from_millis(interval_ms));
+    }
+    socket.set_tcp_keepalive(&params)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::RegisterItemConfig;
+    use crate::config::{
+        CoilValuePolicy, FifoConfig, FunctionCodes, LatencyConfig, RegisterItemConfig, TcpConfig,
+        TruncationConfig,
+    };
+    use crate::harness::TestHarness;
     use crate::sim::SimState;
     use std::sync::{Arc, RwLock};
     use tokio_modbus::client::tcp as client_tcp;
@@ -98,38 +1131,871 @@ mod tests {
 
     #[tokio::test]
     async fn tcp_end_to_end_read_holding_registers() {
-        // reserve a free port
+        let reg_cfg = RegisterItemConfig {
+            address: 0,
+            initial: 123u16.into(),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![reg_cfg], vec![]).unwrap();
+        let mut harness = TestHarness::start(state).await.unwrap();
+        harness.assert_holding_registers(0, &[123]).await;
+    }
+
+    #[tokio::test]
+    async fn keepalive_is_applied_to_sockets() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let config: TcpConfig =
+            toml::from_str("keepalive = { idle_ms = \"30s\", interval_ms = 5000 }").unwrap();
+        apply_keepalive(&stream, config.keepalive.as_ref().unwrap()).unwrap();
+        assert!(SockRef::from(&stream).keepalive().unwrap());
+
+        let config: TcpConfig = toml::from_str("keepalive = { enabled = false }").unwrap();
+        apply_keepalive(&stream, config.keepalive.as_ref().unwrap()).unwrap();
+        assert!(!SockRef::from(&stream).keepalive().unwrap());
+    }
+
+    #[tokio::test]
+    async fn tcp_refuses_connections_during_startup_delay() {
         let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
         let port = listener.local_addr().unwrap().port();
         drop(listener);
         let bind = format!("127.0.0.1:{}", port);
 
-        // create a state with a known holding register
-        let reg_cfg = RegisterItemConfig {
-            address: 0,
-            initial: 123u16,
-            update_ms: None,
-            dynamics: None,
+        let service = service();
+        tokio::spawn(async move {
+            start_tcp(&TcpConfig::new(bind), service, Duration::from_millis(300)).await
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let socket_addr = format!("127.0.0.1:{}", port).parse().unwrap();
+        assert!(client_tcp::connect(socket_addr).await.is_err());
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert!(client_tcp::connect(socket_addr).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn tcp_limits_connections_per_ip() {
+        let mut tcp = TcpConfig::new(String::new());
+        tcp.max_connections_per_ip = Some(1);
+        let harness = TestHarness::start_with(empty_state(), tcp, UnitRouter::from)
+            .await
+            .unwrap();
+        let TestHarness {
+            client: mut first,
+            addr,
+            ..
+        } = harness;
+        assert!(first.read_holding_registers(0, 1).await.is_ok());
+        let mut second = client_tcp::connect(addr).await.unwrap();
+        assert!(second.read_holding_registers(0, 1).await.is_err());
+
+        // Closing the first connection frees its slot.
+        drop(first);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut third = client_tcp::connect(addr).await.unwrap();
+        assert!(third.read_holding_registers(0, 1).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn tcp_drops_connections_across_power_cycles() {
+        let power = PowerSwitch::default();
+        let route = |service| UnitRouter::from(service).with_power(power.clone());
+        let harness = TestHarness::start_with(empty_state(), TcpConfig::new(String::new()), route)
+            .await
+            .unwrap();
+        let TestHarness {
+            client: mut before,
+            addr,
+            ..
+        } = harness;
+        assert!(before.read_holding_registers(0, 1).await.is_ok());
+        power.power_off();
+        let mut during = client_tcp::connect(addr).await.unwrap();
+        assert!(during.read_holding_registers(0, 1).await.is_err());
+        power.power_on();
+        // The connection from before the power cycle is gone; new ones work.
+        assert!(before.read_holding_registers(0, 1).await.is_err());
+        let mut after = client_tcp::connect(addr).await.unwrap();
+        assert!(after.read_holding_registers(0, 1).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn consecutive_exceptions_close_the_connection() {
+        let mut config = TcpConfig::new(String::new());
+        config.max_consecutive_exceptions = NonZeroU32::new(2);
+        let harness = TestHarness::start_with(empty_state(), config, UnitRouter::from)
+            .await
+            .unwrap();
+        let TestHarness {
+            mut client, addr, ..
+        } = harness;
+        // A valid request between two exceptions starts the count over.
+        assert!(client.read_holding_registers(0xFFFF, 2).await.is_err());
+        assert!(client.read_holding_registers(0, 1).await.is_ok());
+        assert!(client.read_holding_registers(0xFFFF, 2).await.is_err());
+        assert!(client.read_holding_registers(0, 1).await.is_ok());
+        // Two in a row close it.
+        assert!(client.read_holding_registers(0xFFFF, 2).await.is_err());
+        assert!(client.read_holding_registers(0xFFFF, 2).await.is_err());
+        assert!(client.read_holding_registers(0, 1).await.is_err());
+        let mut other = client_tcp::connect(addr).await.unwrap();
+        assert!(other.read_holding_registers(0, 1).await.is_ok());
+    }
+
+    /// Sends two reads of holding register 0 and returns every byte the
+    /// listener sends back until it closes the connection or goes quiet.
+    async fn responses_with_truncation(
+        probability: f64,
+        function_codes: Option<Vec<u8>>,
+    ) -> Vec<u8> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let truncation = TruncationConfig {
+            probability,
+            bytes: Some(4),
+            function_codes,
+        };
+        let truncation = Truncation::new(&truncation, Some(1)).unwrap();
+        let route = |service| UnitRouter::from(service).with_truncation(Some(truncation));
+        let harness = TestHarness::start_with(empty_state(), TcpConfig::new(String::new()), route)
+            .await
+            .unwrap();
+
+        let mut stream = tokio::net::TcpStream::connect(harness.addr).await.unwrap();
+        let mut received = Vec::new();
+        for id in 1..=2 {
+            let request = [0, id, 0, 0, 0, 6, 1, 0x03, 0, 0, 0, 1];
+            if stream.write_all(&request).await.is_err() {
+                break;
+            }
+            let mut buf = [0; 64];
+            match tokio::time::timeout(Duration::from_millis(200), stream.read(&mut buf)).await {
+                Ok(Ok(read)) if read > 0 => received.extend_from_slice(&buf[..read]),
+                _ => break,
+            }
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn truncated_responses_close_the_connection() {
+        let whole = |id| [0, id, 0, 0, 0, 5, 1, 0x03, 2, 0, 0];
+        assert_eq!(
+            responses_with_truncation(0.0, None).await,
+            [whole(1), whole(2)].concat()
+        );
+        assert_eq!(responses_with_truncation(1.0, None).await, whole(1)[..4]);
+        // Truncating only ReadInputRegisters leaves these reads whole.
+        assert_eq!(
+            responses_with_truncation(1.0, Some(vec![0x04])).await,
+            [whole(1), whole(2)].concat()
+        );
+    }
+
+    /// tokio-modbus rejects WriteSingleCoil values other than 0xFF00/0x0000 while
+    /// decoding, so they never reach `ModbusService`: the connection is closed
+    /// and the coil keeps its value.
+    #[tokio::test]
+    async fn non_canonical_single_coil_value_closes_connection() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let harness = TestHarness::start(empty_state()).await.unwrap();
+        let state = &harness.state;
+        let mut stream = tokio::net::TcpStream::connect(harness.addr).await.unwrap();
+        // MBAP: transaction 1, protocol 0, length 6, unit 1; PDU: FC05, address 0, value 0x1234.
+        let frame = [
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x06, 0x01, 0x05, 0x00, 0x00, 0x12, 0x34,
+        ];
+        stream.write_all(&frame).await.unwrap();
+        let mut buf = [0u8; 16];
+        let read = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf))
+            .await
+            .expect("connection should be closed");
+        assert!(matches!(read, Ok(0) | Err(_)));
+        assert_eq!(state.read().unwrap().read_coils(0, 1), Ok(vec![false]));
+    }
+
+    /// Sends Write Single Coil of 0x1234 to coil 0 under `coil_value` and
+    /// returns the response and the coil afterwards.
+    async fn write_non_canonical_coil(coil_value: CoilValuePolicy) -> (Vec<u8>, bool) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let tcp = TcpConfig {
+            coil_value,
+            ..TcpConfig::new(String::new())
         };
-        let state = Arc::new(RwLock::new(SimState::new(
+        let harness = TestHarness::start_with(empty_state(), tcp, UnitRouter::from)
+            .await
+            .unwrap();
+        let state = &harness.state;
+        let mut stream = tokio::net::TcpStream::connect(harness.addr).await.unwrap();
+        let frame = [0, 1, 0, 0, 0, 6, 1, 0x05, 0, 0, 0x12, 0x34];
+        stream.write_all(&frame).await.unwrap();
+        let mut buf = [0u8; 16];
+        let read = tokio::time::timeout(Duration::from_secs(1), stream.read(&mut buf))
+            .await
+            .expect("the write should be answered")
+            .unwrap();
+        let coil = state.read().unwrap().read_coils(0, 1).unwrap()[0];
+        (buf[..read].to_vec(), coil)
+    }
+
+    #[tokio::test]
+    async fn non_canonical_single_coil_values_follow_the_policy() {
+        assert_eq!(
+            write_non_canonical_coil(CoilValuePolicy::Strict).await,
+            (vec![0, 1, 0, 0, 0, 3, 1, 0x85, 0x03], false)
+        );
+        assert_eq!(
+            write_non_canonical_coil(CoilValuePolicy::Lenient).await,
+            (vec![0, 1, 0, 0, 0, 6, 1, 0x05, 0, 0, 0xFF, 0x00], true)
+        );
+    }
+
+    /// Strict byte count checking answers a write whose byte count disagrees
+    /// with its quantity, keeping the connection open.
+    #[tokio::test]
+    async fn mismatched_byte_count_answers_illegal_data_value() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let harness = TestHarness::start(empty_state()).await.unwrap();
+        let state = &harness.state;
+        let mut stream = tokio::net::TcpStream::connect(harness.addr).await.unwrap();
+        // FC16 of two registers from 0 carrying a byte count of 6 and 6 bytes.
+        let frame = [
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x0D, 0x01, 0x10, 0x00, 0x00, 0x00, 0x02, 0x06, 0x00,
+            0x01, 0x00, 0x02, 0x00, 0x03,
+        ];
+        stream.write_all(&frame).await.unwrap();
+        let mut response = [0u8; 9];
+        tokio::time::timeout(Duration::from_secs(1), stream.read_exact(&mut response))
+            .await
+            .expect("the write should be answered")
+            .unwrap();
+        assert_eq!(response, [0, 1, 0, 0, 0, 3, 1, 0x90, 0x03]);
+        assert_eq!(
+            state.read().unwrap().read_holding_registers(0, 2),
+            Ok(vec![0, 0])
+        );
+    }
+
+    fn empty_state() -> SimState {
+        SimState::new(500, false, vec![], vec![], vec![], vec![]).unwrap()
+    }
+
+    fn service() -> ModbusService {
+        ModbusService::new(Arc::new(RwLock::new(empty_state())))
+    }
+
+    #[test]
+    fn configured_mei_type_returns_canned_response() {
+        let service = service().with_mei(BTreeMap::from([(13, vec![0xAA, 0xBB])]));
+        let response = service
+            .handle(Request::Custom(0x2B, vec![13, 0x00]))
+            .unwrap();
+        assert_eq!(response, Response::Custom(0x2B, vec![13, 0xAA, 0xBB]));
+    }
+
+    #[test]
+    fn device_identification_lists_limited_function_codes() {
+        // Basic identification, complete, holding the vendor name "ACM".
+        let canned = vec![0x01, 0x01, 0x00, 0x00, 0x01, 0x00, 0x03, b'A', b'C', b'M'];
+        let mei = BTreeMap::from([(14, canned.clone()), (13, vec![0xAA])]);
+        let service = service().with_mei(mei.clone());
+        let response = service.handle(Request::Custom(0x2B, vec![14])).unwrap();
+        assert_eq!(
+            response,
+            Response::Custom(0x2B, [vec![14], canned].concat())
+        );
+
+        let codes = FunctionCodes {
+            enabled: Some(vec![0x03, 0x04, 0x2B]),
+            disabled: vec![0x04],
+        };
+        let service = service().with_mei(mei).with_function_codes(codes);
+        let response = service.handle(Request::Custom(0x2B, vec![14])).unwrap();
+        let objects = [0x00, 0x03, b'A', b'C', b'M', 0x80, 0x02, 0x03, 0x2B];
+        assert_eq!(
+            response,
+            Response::Custom(
+                0x2B,
+                [&[14, 0x01, 0x01, 0x00, 0x00, 0x02][..], &objects].concat()
+            )
+        );
+        // Other MEI types stay as configured.
+        let response = service.handle(Request::Custom(0x2B, vec![13])).unwrap();
+        assert_eq!(response, Response::Custom(0x2B, vec![13, 0xAA]));
+    }
+
+    #[test]
+    fn client_writes_reach_the_change_feed() {
+        let mut state = SimState::new(500, false, vec![], vec![], vec![], vec![]).unwrap();
+        let mut changes = state.subscribe_changes();
+        let service = ModbusService::new(Arc::new(RwLock::new(state)));
+        service
+            .handle(Request::WriteMultipleRegisters(4, vec![42, 0]))
+            .unwrap();
+        service.handle(Request::WriteSingleCoil(2, true)).unwrap();
+        let mut received = Vec::new();
+        while let Ok(change) = changes.try_recv() {
+            received.push((change.space, change.address, change.value));
+        }
+        assert_eq!(
+            received,
+            [
+                (Space::HoldingRegisters, 4, 42.0),
+                (Space::HoldingRegisters, 5, 0.0),
+                (Space::Coils, 2, 1.0)
+            ]
+        );
+        // Writing the same value again changes nothing.
+        service.handle(Request::WriteSingleRegister(4, 42)).unwrap();
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[test]
+    fn mapped_registers_alias_coils() {
+        let map = toml::from_str("register = 10\ncoils = [3, 4]").unwrap();
+        let state = SimState::new(500, false, vec![], vec![], vec![], vec![])
+            .unwrap()
+            .with_coil_register_map(&[map])
+            .unwrap();
+        let service = ModbusService::new(Arc::new(RwLock::new(state)));
+        service
+            .handle(Request::WriteSingleRegister(10, 0b10))
+            .unwrap();
+        let response = service.handle(Request::ReadCoils(3, 2)).unwrap();
+        assert_eq!(response, Response::ReadCoils(vec![false, true]));
+        service.handle(Request::WriteSingleCoil(3, true)).unwrap();
+        let response = service
+            .handle(Request::ReadHoldingRegisters(10, 1))
+            .unwrap();
+        assert_eq!(response, Response::ReadHoldingRegisters(vec![0b11]));
+    }
+
+    #[test]
+    fn faulted_addresses_answer_their_exception() {
+        for (name, code) in [
+            ("illegal_function", 0x01),
+            ("illegal_data_address", 0x02),
+            ("illegal_data_value", 0x03),
+            ("server_device_failure", 0x04),
+            ("server_device_busy", 0x06),
+            ("gateway_target_device_failed_to_respond", 0x0B),
+        ] {
+            let fault: FaultedAddressConfig = toml::from_str(&format!(
+                "address = 50\nspace = \"holding_registers\"\nexception = \"{name}\""
+            ))
+            .unwrap();
+            let service = service().with_faulted(&[fault]);
+            assert_eq!(
+                service
+                    .handle(Request::ReadHoldingRegisters(48, 3))
+                    .unwrap(),
+                Response::Custom(0x83, vec![code]),
+                "{name}"
+            );
+            assert_eq!(
+                service.handle(Request::WriteSingleRegister(50, 1)).unwrap(),
+                Response::Custom(0x86, vec![code]),
+                "{name}"
+            );
+            assert_eq!(service.state.read().unwrap().holding_register_value(50), 0);
+        }
+    }
+
+    #[test]
+    fn faults_apply_only_to_their_space_and_address() {
+        let fault =
+            toml::from_str("address = 50\nspace = \"coils\"\nexception = \"server_device_busy\"")
+                .unwrap();
+        let service = service().with_faulted(&[fault]);
+        assert_eq!(
+            service.handle(Request::ReadCoils(50, 1)).unwrap(),
+            Response::Custom(0x81, vec![0x06])
+        );
+        assert_eq!(
+            service.handle(Request::ReadCoils(51, 8)).unwrap(),
+            Response::ReadCoils(vec![false; 8])
+        );
+        assert_eq!(
+            service.handle(Request::ReadDiscreteInputs(50, 1)).unwrap(),
+            Response::ReadDiscreteInputs(vec![false])
+        );
+    }
+
+    #[test]
+    fn fast_path_answers_like_the_locked_path() {
+        let registers = vec![
+            RegisterItemConfig {
+                address: 0,
+                initial: 7.into(),
+                ..Default::default()
+            },
+            RegisterItemConfig {
+                address: 1,
+                update_on_read: true,
+                dynamics: Some(
+                    toml::from_str("kind = \"step\"\nlow = 1.0\nhigh = 9.0\nperiod_ms = 1000")
+                        .unwrap(),
+                ),
+                ..Default::default()
+            },
+        ];
+        let state = SimState::new(500, false, vec![], vec![], registers, vec![]).unwrap();
+        let service = ModbusService::new(Arc::new(RwLock::new(state))).with_fast_path(true);
+        assert_eq!(
+            service.read_shared(&Request::ReadHoldingRegisters(0, 1)),
+            Some(Response::ReadHoldingRegisters(vec![7]))
+        );
+        // Sampling and exceptions take the write-locked path.
+        assert_eq!(
+            service.read_shared(&Request::ReadHoldingRegisters(0, 2)),
+            None
+        );
+        assert_eq!(service.read_shared(&Request::ReadCoils(0, 0)), None);
+        assert_eq!(
+            service.read_shared(&Request::ReadHoldingRegisters(0, 0)),
+            None
+        );
+        assert_eq!(
+            service.handle(Request::ReadCoils(0, 0)).unwrap(),
+            Response::Custom(0x81, vec![0x03])
+        );
+        assert_eq!(
+            service.handle(Request::ReadHoldingRegisters(0, 1)).unwrap(),
+            Response::ReadHoldingRegisters(vec![7])
+        );
+    }
+
+    #[test]
+    fn unconfigured_mei_type_is_illegal_function() {
+        let response = service().handle(Request::Custom(0x2B, vec![13])).unwrap();
+        assert_eq!(response, Response::Custom(0xAB, vec![0x01]));
+    }
+
+    #[test]
+    fn unlisted_mei_type_of_supported_fc43_is_illegal_data_value() {
+        let service = service().with_mei(BTreeMap::from([(13, vec![0xAA])]));
+        for data in [vec![14], vec![]] {
+            let response = service.handle(Request::Custom(0x2B, data)).unwrap();
+            assert_eq!(response, Response::Custom(0xAB, vec![0x03]));
+        }
+    }
+
+    #[tokio::test]
+    async fn successful_writes_are_audited_to_file() {
+        let path = std::env::temp_dir().join(format!("modsim-audit-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let service = service()
+            .with_audit(Some(AuditLog::open(Some(path)).unwrap()))
+            .with_peer("127.0.0.1:40000".parse().unwrap())
+            .with_connection_id(3);
+        for request in [
+            Request::WriteSingleRegister(4, 99),
+            Request::ReadHoldingRegisters(4, 1),
+            Request::WriteMultipleRegisters(0xFFFF, vec![1, 2]),
+        ] {
+            service
+                .call(SlaveRequest { slave: 2, request })
+                .await
+                .unwrap();
+        }
+        let audit = std::fs::read_to_string(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        let lines: Vec<&str> = audit.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with(
+            r#""peer":"127.0.0.1:40000","connection_id":3,"unit":2,"function":6,"address":4,"values":[99],"decoded":[]}"#
+        ));
+    }
+
+    #[test]
+    fn exception_responses_are_counted() {
+        let service = service();
+        service
+            .process_pdu(&[0x01, 0x00, 0x00, 0x00, 0x00])
+            .unwrap();
+        service
+            .process_pdu(&[0x01, 0x00, 0x00, 0x00, 0x00])
+            .unwrap();
+        service
+            .process_pdu(&[0x03, 0xFF, 0xFF, 0x00, 0x02])
+            .unwrap();
+        service
+            .process_pdu(&[0x03, 0x00, 0x00, 0x00, 0x01])
+            .unwrap();
+        let state = service.state.read().unwrap();
+        assert_eq!(
+            state.exception_counts,
+            BTreeMap::from([
+                (Exception::IllegalDataAddress, 1),
+                (Exception::IllegalDataValue, 2),
+            ])
+        );
+    }
+
+    #[test]
+    fn strict_devices_refuse_writes_to_unconfigured_addresses() {
+        let holding = RegisterItemConfig {
+            address: 4,
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![holding], vec![])
+            .unwrap()
+            .with_dynamic_create(false);
+        let service = ModbusService::new(Arc::new(RwLock::new(state)));
+        // FC06 to the configured register 4, then to unconfigured 5.
+        assert_eq!(
+            service
+                .process_pdu(&[0x06, 0x00, 0x04, 0x00, 0x07])
+                .unwrap(),
+            Response::WriteSingleRegister(4, 7)
+        );
+        assert_eq!(
+            service
+                .process_pdu(&[0x06, 0x00, 0x05, 0x00, 0x07])
+                .unwrap(),
+            Response::Custom(0x86, vec![0x02])
+        );
+        // FC10 over 4 and 5 is refused as a whole.
+        assert_eq!(
+            service
+                .process_pdu(&[0x10, 0x00, 0x04, 0x00, 0x02, 0x04, 0x00, 0x01, 0x00, 0x02])
+                .unwrap(),
+            Response::Custom(0x90, vec![0x02])
+        );
+        // FC05 to a coil that does not exist.
+        assert_eq!(
+            service
+                .process_pdu(&[0x05, 0x00, 0x00, 0xFF, 0x00])
+                .unwrap(),
+            Response::Custom(0x85, vec![0x02])
+        );
+        let state = service.state.read().unwrap();
+        assert_eq!(state.read_holding_registers(4, 1), Ok(vec![7]));
+        assert!(!state.holding_registers.contains_key(&5));
+        assert!(state.coils.is_empty());
+    }
+
+    #[test]
+    fn disabled_function_codes_answer_illegal_function() {
+        let holding = RegisterItemConfig {
+            address: 4,
+            initial: 7u16.into(),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![holding], vec![]).unwrap();
+        let codes = FunctionCodes {
+            enabled: None,
+            disabled: vec![0x0F, 0x10],
+        };
+        let service = ModbusService::new(Arc::new(RwLock::new(state))).with_function_codes(codes);
+        // FC10 and FC0F are refused whatever they address.
+        assert_eq!(
+            service
+                .process_pdu(&[0x10, 0x00, 0x04, 0x00, 0x01, 0x02, 0x00, 0x09])
+                .unwrap(),
+            Response::Custom(0x90, vec![0x01])
+        );
+        assert_eq!(
+            service
+                .process_pdu(&[0x0F, 0x00, 0x00, 0x00, 0x01, 0x01, 0x01])
+                .unwrap(),
+            Response::Custom(0x8F, vec![0x01])
+        );
+        // Other functions are served as usual.
+        assert_eq!(
+            service
+                .process_pdu(&[0x03, 0x00, 0x04, 0x00, 0x01])
+                .unwrap(),
+            Response::ReadHoldingRegisters(vec![7])
+        );
+        let state = service.state.read().unwrap();
+        assert_eq!(state.read_holding_registers(4, 1), Ok(vec![7]));
+        assert!(state.coils.is_empty());
+    }
+
+    #[test]
+    fn write_echo_shows_the_stored_value() {
+        let holding = RegisterItemConfig {
+            address: 4,
+            write_mask: Some(0x00FF),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![holding], vec![]).unwrap();
+        let service = ModbusService::new(Arc::new(RwLock::new(state)));
+        assert_eq!(
+            service
+                .process_pdu(&[0x06, 0x00, 0x04, 0x12, 0x34])
+                .unwrap(),
+            Response::WriteSingleRegister(4, 0x0034)
+        );
+        // FC10 stores the masked value at 4 and 5 as written.
+        assert_eq!(
+            service
+                .process_pdu(&[0x10, 0x00, 0x04, 0x00, 0x02, 0x04, 0xAB, 0xCD, 0xAB, 0xCD])
+                .unwrap(),
+            Response::WriteMultipleRegisters(4, 2)
+        );
+        let state = service.state.read().unwrap();
+        assert_eq!(state.read_holding_registers(4, 2), Ok(vec![0x00CD, 0xABCD]));
+    }
+
+    /// Regressions for the request path: each used to overflow the address
+    /// arithmetic or build a response the codec cannot encode.
+    #[test]
+    fn out_of_range_requests_answer_exceptions() {
+        let service = service();
+        // FC03 at 0xFFFF for two registers.
+        assert_eq!(
+            service
+                .process_pdu(&[0x03, 0xFF, 0xFF, 0x00, 0x02])
+                .unwrap(),
+            Response::Custom(0x83, vec![0x02])
+        );
+        // FC01 for zero coils.
+        assert_eq!(
+            service
+                .process_pdu(&[0x01, 0x00, 0x00, 0x00, 0x00])
+                .unwrap(),
+            Response::Custom(0x81, vec![0x03])
+        );
+        // FC04 for 0xFFFF registers.
+        assert_eq!(
+            service
+                .process_pdu(&[0x04, 0x00, 0x00, 0xFF, 0xFF])
+                .unwrap(),
+            Response::Custom(0x84, vec![0x03])
+        );
+        // FC10 writing two registers from 0xFFFF.
+        assert_eq!(
+            service
+                .process_pdu(&[0x10, 0xFF, 0xFF, 0x00, 0x02, 0x04, 0, 1, 0, 2])
+                .unwrap(),
+            Response::Custom(0x90, vec![0x02])
+        );
+        // FC10 of zero registers, as a mismatched byte count is rewritten.
+        assert_eq!(
+            service
+                .process_pdu(&[0x10, 0x00, 0x04, 0x00, 0x00, 0x00])
+                .unwrap(),
+            Response::Custom(0x90, vec![0x03])
+        );
+    }
+
+    #[test]
+    fn read_fifo_queue_returns_contents() {
+        let fifo = FifoConfig {
+            address: 0x10,
+            values: vec![0x01B8, 0x1284],
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![], vec![])
+            .unwrap()
+            .with_fifos(&[fifo]);
+        let service = ModbusService::new(Arc::new(RwLock::new(state)));
+        assert_eq!(
+            service.process_pdu(&[0x18, 0x00, 0x10]).unwrap(),
+            Response::Custom(0x18, vec![0x00, 0x06, 0x00, 0x02, 0x01, 0xB8, 0x12, 0x84])
+        );
+        assert_eq!(
+            service.process_pdu(&[0x18, 0x00, 0x11]).unwrap(),
+            Response::Custom(0x98, vec![0x02])
+        );
+    }
+
+    #[test]
+    fn read_fifo_queue_without_fifos_is_illegal_function() {
+        assert_eq!(
+            service().process_pdu(&[0x18, 0x00, 0x10]).unwrap(),
+            Response::Custom(0x98, vec![0x01])
+        );
+    }
+
+    #[test]
+    fn address_offsets_map_conventional_addresses() {
+        let register = RegisterItemConfig {
+            address: 1,
+            initial: 42.into(),
+            ..Default::default()
+        };
+        let state = SimState::new(500, false, vec![], vec![], vec![register], vec![]).unwrap();
+        let service =
+            ModbusService::new(Arc::new(RwLock::new(state))).with_address_offsets(AddressOffsets {
+                holding_registers: 40000,
+                ..Default::default()
+            });
+        // 40001 = 0x9C41
+        assert_eq!(
+            service
+                .process_pdu(&[0x03, 0x9C, 0x41, 0x00, 0x01])
+                .unwrap(),
+            Response::ReadHoldingRegisters(vec![42])
+        );
+        assert_eq!(
+            service
+                .process_pdu(&[0x06, 0x9C, 0x42, 0x00, 0x07])
+                .unwrap(),
+            Response::WriteSingleRegister(40002, 7)
+        );
+        assert_eq!(service.state.read().unwrap().holding_register_value(2), 7);
+        // below the offset
+        assert_eq!(
+            service
+                .process_pdu(&[0x03, 0x00, 0x01, 0x00, 0x01])
+                .unwrap(),
+            Response::Custom(0x83, vec![0x02])
+        );
+        // other spaces are unaffected
+        assert_eq!(
+            service
+                .process_pdu(&[0x04, 0x00, 0x01, 0x00, 0x01])
+                .unwrap(),
+            Response::ReadInputRegisters(vec![0])
+        );
+    }
+
+    #[test]
+    fn slowest_addressed_item_sets_the_response_delay() {
+        let register = |address, response_delay_ms| RegisterItemConfig {
+            address,
+            response_delay_ms,
+            ..Default::default()
+        };
+        let state = SimState::new(
             500,
             false,
             vec![],
             vec![],
-            vec![reg_cfg],
+            vec![
+                register(5, Some(200)),
+                register(6, Some(50)),
+                register(7, None),
+            ],
             vec![],
-        )));
+        )
+        .unwrap();
+        let service =
+            ModbusService::new(Arc::new(RwLock::new(state))).with_address_offsets(AddressOffsets {
+                holding_registers: 100,
+                ..Default::default()
+            });
+        let delay = |req| service.response_delay(&req).as_millis();
+        assert_eq!(delay(Request::ReadHoldingRegisters(104, 3)), 200);
+        assert_eq!(delay(Request::WriteSingleRegister(106, 1)), 50);
+        assert_eq!(delay(Request::ReadHoldingRegisters(107, 10)), 0);
+        assert_eq!(delay(Request::ReadInputRegisters(5, 1)), 0);
+        assert_eq!(delay(Request::ReadHoldingRegisters(5, 1)), 0);
+    }
 
-        // start the TCP server (spawned inside start_tcp)
-        start_tcp(&bind, Arc::clone(&state)).await.unwrap();
+    #[tokio::test]
+    async fn units_sharing_a_listener_are_isolated() {
+        let unit = || {
+            ModbusService::new(Arc::new(RwLock::new(
+                SimState::new(500, false, vec![], vec![], vec![], vec![]).unwrap(),
+            )))
+        };
+        let router = UnitRouter::new(BTreeMap::from([(1, unit()), (2, unit())]));
+        let call = |slave, request| router.call(SlaveRequest { slave, request });
+        call(1, Request::WriteMultipleRegisters(0, vec![11, 12]))
+            .await
+            .unwrap();
+        call(2, Request::WriteSingleRegister(1, 22)).await.unwrap();
+        assert_eq!(
+            call(1, Request::ReadHoldingRegisters(0, 2)).await.unwrap(),
+            Response::ReadHoldingRegisters(vec![11, 12])
+        );
+        assert_eq!(
+            call(2, Request::ReadHoldingRegisters(0, 2)).await.unwrap(),
+            Response::ReadHoldingRegisters(vec![0, 22])
+        );
+        assert_eq!(
+            call(3, Request::ReadHoldingRegisters(0, 1)).await.unwrap(),
+            Response::Custom(0x83, vec![0x0B])
+        );
 
-        // give the server a moment to start
-        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        // A lone device answers whatever unit id the client sends.
+        let router = UnitRouter::from(unit());
+        let response = router
+            .call(SlaveRequest {
+                slave: 9,
+                request: Request::ReadHoldingRegisters(0, 1),
+            })
+            .await
+            .unwrap();
+        assert_eq!(response, Response::ReadHoldingRegisters(vec![0]));
+    }
 
-        // connect as a client and read the register
-        let socket_addr = format!("127.0.0.1:{}", port).parse().unwrap();
-        let mut ctx = client_tcp::connect(socket_addr).await.unwrap();
-        let regs = ctx.read_holding_registers(0u16, 1u16).await.unwrap();
-        assert_eq!(regs[0], 123u16);
+    #[tokio::test]
+    async fn undelayed_responses_are_ready_at_once() {
+        let service = service();
+        let request = |request| SlaveRequest { slave: 1, request };
+        let future = service.call(request(Request::ReadHoldingRegisters(0, 1)));
+        assert!(matches!(future, ServiceFuture::Ready(_)));
+        assert!(future.await.is_ok());
+        assert_eq!(service.state.read().unwrap().request_duration.count, 1);
+
+        let service = service.with_latency(Some(
+            Latency::new(
+                &LatencyConfig {
+                    mean_ms: 5.0,
+                    stddev_ms: 0.0,
+                    profile: None,
+                    function_codes: None,
+                },
+                None,
+            )
+            .unwrap(),
+        ));
+        let future = service.call(request(Request::ReadHoldingRegisters(0, 1)));
+        assert!(matches!(future, ServiceFuture::Delayed(_)));
+        assert!(future.await.is_ok());
+        assert_eq!(service.state.read().unwrap().request_duration.count, 2);
+    }
+
+    #[test]
+    fn responses_larger_than_max_adu_are_rejected() {
+        let service = service().with_max_adu_bytes(Some(20));
+        // 7 + 2 + 2 * 5 = 19 bytes
+        assert_eq!(
+            service
+                .process_pdu(&[0x03, 0x00, 0x00, 0x00, 0x05])
+                .unwrap(),
+            Response::ReadHoldingRegisters(vec![0; 5])
+        );
+        // 7 + 2 + 2 * 6 = 21 bytes
+        assert_eq!(
+            service
+                .process_pdu(&[0x03, 0x00, 0x00, 0x00, 0x06])
+                .unwrap(),
+            Response::Custom(0x83, vec![0x03])
+        );
+        // request of 7 + 6 + 2 * 4 = 21 bytes
+        assert_eq!(
+            service
+                .process_pdu(&[0x10, 0x00, 0x00, 0x00, 0x04, 0x08, 0, 1, 0, 2, 0, 3, 0, 4])
+                .unwrap(),
+            Response::Custom(0x90, vec![0x03])
+        );
+        assert_eq!(service.state.read().unwrap().holding_register_value(0), 0);
+        let rtu = service.with_adu_overhead(RTU_ADU_OVERHEAD);
+        assert_eq!(
+            rtu.process_pdu(&[0x03, 0x00, 0x00, 0x00, 0x07]).unwrap(),
+            Response::ReadHoldingRegisters(vec![0; 7])
+        );
+    }
+
+    #[test]
+    fn read_at_last_address_succeeds() {
+        assert_eq!(
+            service()
+                .process_pdu(&[0x03, 0xFF, 0xFF, 0x00, 0x01])
+                .unwrap(),
+            Response::ReadHoldingRegisters(vec![0])
+        );
     }
 }
@@ -0,0 +1,111 @@
+use std::fmt::Display;
+use std::io;
+
+use tokio_modbus::prelude::{Request, Response};
+
+use super::frames::Hex;
+
+/// Values a transaction line lists before eliding the rest.
+const MAX_LISTED: usize = 16;
+
+/// One-line summary of a request, such as `FC03 addr=100 count=10`.
+pub fn describe_request(req: &Request) -> String {
+    match req {
+        Request::ReadCoils(addr, count) => format!("FC01 addr={addr} count={count}"),
+        Request::ReadDiscreteInputs(addr, count) => format!("FC02 addr={addr} count={count}"),
+        Request::ReadHoldingRegisters(addr, count) => format!("FC03 addr={addr} count={count}"),
+        Request::ReadInputRegisters(addr, count) => format!("FC04 addr={addr} count={count}"),
+        Request::WriteSingleCoil(addr, value) => format!("FC05 addr={addr} value={value}"),
+        Request::WriteSingleRegister(addr, value) => format!("FC06 addr={addr} value={value}"),
+        Request::WriteMultipleCoils(addr, values) => {
+            format!("FC0F addr={addr} values={}", list(values))
+        }
+        Request::WriteMultipleRegisters(addr, values) => {
+            format!("FC10 addr={addr} values={}", list(values))
+        }
+        Request::MaskWriteRegister(addr, and, or) => {
+            format!("FC16 addr={addr} and={and:#06x} or={or:#06x}")
+        }
+        Request::ReadWriteMultipleRegisters(read, count, write, values) => format!(
+            "FC17 read_addr={read} count={count} write_addr={write} values={}",
+            list(values)
+        ),
+        Request::Custom(function, data) => format!("FC{function:02X} data=[{}]", Hex(data)),
+        Request::Disconnect => "disconnect".to_string(),
+    }
+}
+
+/// Summary of what a request was answered with: the values read, `ok` for
+/// writes, or the exception code.
+pub fn describe_response(result: &Result<Response, io::Error>) -> String {
+    match result {
+        Ok(Response::ReadCoils(values) | Response::ReadDiscreteInputs(values)) => list(values),
+        Ok(
+            Response::ReadHoldingRegisters(values)
+            | Response::ReadInputRegisters(values)
+            | Response::ReadWriteMultipleRegisters(values),
+        ) => list(values),
+        Ok(Response::Custom(function, data)) if function & 0x80 != 0 => match data.first() {
+            Some(code) => format!("exception {code:#04x}"),
+            None => "exception".to_string(),
+        },
+        Ok(Response::Custom(_, data)) => format!("data=[{}]", Hex(data)),
+        Ok(_) => "ok".to_string(),
+        Err(err) => format!("error: {err}"),
+    }
+}
+
+/// `[a, b, ...]`, eliding values past [`MAX_LISTED`].
+fn list<T: Display>(values: &[T]) -> String {
+    let listed: Vec<String> = values
+        .iter()
+        .take(MAX_LISTED)
+        .map(ToString::to_string)
+        .collect();
+    match values.len().checked_sub(MAX_LISTED) {
+        Some(more) if more > 0 => format!("[{}, ... {more} more]", listed.join(", ")),
+        _ => format!("[{}]", listed.join(", ")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_and_responses_are_summarized() {
+        assert_eq!(
+            describe_request(&Request::ReadHoldingRegisters(100, 3)),
+            "FC03 addr=100 count=3"
+        );
+        assert_eq!(
+            describe_response(&Ok(Response::ReadHoldingRegisters(vec![1, 2, 3]))),
+            "[1, 2, 3]"
+        );
+        assert_eq!(
+            describe_request(&Request::WriteMultipleCoils(4, vec![true, false])),
+            "FC0F addr=4 values=[true, false]"
+        );
+        assert_eq!(
+            describe_response(&Ok(Response::WriteMultipleCoils(4, 2))),
+            "ok"
+        );
+        assert_eq!(
+            describe_request(&Request::Custom(0x18, vec![0x00, 0x10])),
+            "FC18 data=[00 10]"
+        );
+        assert_eq!(
+            describe_response(&Ok(Response::Custom(0x83, vec![0x02]))),
+            "exception 0x02"
+        );
+    }
+
+    #[test]
+    fn long_value_lists_are_truncated() {
+        let values: Vec<u16> = (0..20).collect();
+        assert_eq!(
+            describe_response(&Ok(Response::ReadInputRegisters(values))),
+            "[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, ... 4 more]"
+        );
+    }
+}